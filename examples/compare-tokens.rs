@@ -31,10 +31,10 @@ async fn main() -> Result<()> {
         let serialized_pk = (pk,).serialized()?.into_owned();
         let t = Murmur3Partitioner::hash(prepared.compute_partition_key(&serialized_pk)?).value;
 
-        let statement_info = scylla::transport::load_balancing::Statement {
-            token: Some(scylla::routing::Token { value: t }),
-            keyspace: Some("ks"),
-        };
+        let statement_info = scylla::transport::load_balancing::Statement::new(
+            Some(scylla::routing::Token { value: t }),
+            Some("ks"),
+        );
         println!(
             "Estimated replicas for query: {:?}",
             session
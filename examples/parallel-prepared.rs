@@ -1,9 +1,7 @@
 use anyhow::Result;
+use futures::StreamExt;
 use scylla::{Session, SessionBuilder};
 use std::env;
-use std::sync::Arc;
-
-use tokio::sync::Semaphore;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -12,7 +10,6 @@ async fn main() -> Result<()> {
     println!("Connecting to {} ...", uri);
 
     let session: Session = SessionBuilder::new().known_node(uri).build().await?;
-    let session = Arc::new(session);
 
     session.query("CREATE KEYSPACE IF NOT EXISTS ks WITH REPLICATION = {'class' : 'SimpleStrategy', 'replication_factor' : 1}", &[]).await?;
 
@@ -23,37 +20,21 @@ async fn main() -> Result<()> {
         )
         .await?;
 
-    let prepared = Arc::new(
-        session
-            .prepare("INSERT INTO ks.t2 (a, b, c) VALUES (?, ?, 'abc')")
-            .await?,
-    );
+    let prepared = session
+        .prepare("INSERT INTO ks.t2 (a, b, c) VALUES (?, ?, 'abc')")
+        .await?;
     println!("Prepared statement: {:#?}", prepared);
 
     let parallelism = 256;
-    let sem = Arc::new(Semaphore::new(parallelism));
 
-    for i in 0..100_000usize {
-        if i % 1000 == 0 {
-            println!("{}", i);
+    // Runs at most `parallelism` requests at once and streams back their results
+    let mut results =
+        session.execute_concurrent(&prepared, (0..100_000_i32).map(|i| (i, 2 * i)), parallelism);
+    while let Some(item) = results.next().await {
+        if item.index % 1000 == 0 {
+            println!("{}", item.index);
         }
-        let session = session.clone();
-        let prepared = prepared.clone();
-        let permit = sem.clone().acquire_owned().await;
-        tokio::task::spawn(async move {
-            let i = i;
-            session
-                .execute(&prepared, (i as i32, 2 * i as i32))
-                .await
-                .unwrap();
-
-            let _permit = permit;
-        });
-    }
-
-    // Wait for all in-flight requests to finish
-    for _ in 0..parallelism {
-        sem.acquire().await.unwrap().forget();
+        item.result?;
     }
 
     println!("Ok.");
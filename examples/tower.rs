@@ -1,47 +1,31 @@
+use scylla::tower::{ConcurrencyLimitLayer, MetricsLayer, Request, SessionService, TimeoutLayer};
 use std::env;
-use std::future::Future;
-use std::pin::Pin;
-use std::sync::Arc;
-use std::task::Context;
-use std::task::Poll;
-use tower::Service;
-
-struct SessionService {
-    session: Arc<scylla::Session>,
-}
-
-// A trivial service implementation for sending parameterless simple string requests to Scylla.
-impl Service<scylla::query::Query> for SessionService {
-    type Response = scylla::QueryResult;
-    type Error = scylla::transport::errors::QueryError;
-    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
-
-    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
-    }
-
-    fn call(&mut self, req: scylla::query::Query) -> Self::Future {
-        let session = self.session.clone();
-        Box::pin(async move { session.query(req, &[]).await })
-    }
-}
+use std::time::Duration;
+use tower::{Service, ServiceBuilder};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let uri = env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
 
     println!("Connecting to {} ...", uri);
-    let mut session: SessionService = SessionService {
-        session: Arc::new(
-            scylla::SessionBuilder::new()
-                .known_node(uri)
-                .build()
-                .await?,
-        ),
-    };
+    let session = scylla::SessionBuilder::new()
+        .known_node(uri)
+        .build()
+        .await?;
 
-    let resp = session
-        .call("SELECT keyspace_name, table_name FROM system_schema.tables;".into())
+    let metrics = MetricsLayer::new();
+    let mut service = ServiceBuilder::new()
+        .layer(metrics.clone())
+        .layer(ConcurrencyLimitLayer::new(128))
+        .layer(TimeoutLayer::new(Duration::from_secs(5)))
+        .service(SessionService::new(session));
+
+    futures::future::poll_fn(|cx| service.poll_ready(cx)).await?;
+    let resp = service
+        .call(Request::query(
+            "SELECT keyspace_name, table_name FROM system_schema.tables;",
+            (),
+        )?)
         .await?;
 
     let print_text = |t: &Option<scylla::frame::response::result::CqlValue>| {
@@ -66,5 +50,6 @@ async fn main() -> anyhow::Result<()> {
             .collect::<Vec<String>>()
             .join("\n")
     );
+    println!("Requests: {}", metrics.get_metrics().get_queries_num());
     Ok(())
 }
@@ -8,8 +8,10 @@ use std::io::ErrorKind;
 use std::sync::Arc;
 use thiserror::Error;
 
-/// Error that occurred during query execution
+/// Error that occurred during query execution.\
+/// New variants may be added in minor releases, so matches on it need a wildcard arm.
 #[derive(Error, Debug, Clone)]
+#[non_exhaustive]
 pub enum QueryError {
     /// Database sent a response containing some error with a message
     #[error("Database returned an error: {0}, Error message: {1}")]
@@ -44,6 +46,10 @@ pub enum QueryError {
     /// Client timeout occurred before any response arrived
     #[error("Request timeout: {0}")]
     RequestTimeout(String),
+
+    /// The session was closed, it doesn't accept new requests
+    #[error("Session is closed")]
+    SessionClosed,
 }
 
 /// An error sent from the database in response to a query
@@ -400,6 +406,11 @@ impl From<QueryError> for NewSessionError {
             }
             QueryError::UnableToAllocStreamId => NewSessionError::UnableToAllocStreamId,
             QueryError::RequestTimeout(msg) => NewSessionError::RequestTimeout(msg),
+            // Requests sent while creating a session never see it closed
+            QueryError::SessionClosed => NewSessionError::IoError(Arc::new(std::io::Error::new(
+                ErrorKind::NotConnected,
+                "Session is closed",
+            ))),
         }
     }
 }
@@ -43,4 +43,6 @@ pub enum ParseError {
     SerializeValuesError(#[from] SerializeValuesError),
     #[error(transparent)]
     CqlTypeError(#[from] CqlTypeError),
+    #[error("Result metadata was skipped, but no matching metadata is known for a result with {0} columns")]
+    ResultMetadataMismatch(usize),
 }
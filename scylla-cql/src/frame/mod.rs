@@ -14,6 +14,7 @@ use bytes::{Buf, BufMut, Bytes};
 use tokio::io::{AsyncRead, AsyncReadExt};
 use uuid::Uuid;
 
+use std::collections::HashMap;
 use std::convert::TryFrom;
 
 use request::Request;
@@ -64,22 +65,28 @@ impl SerializedRequest {
         req: &R,
         compression: Option<Compression>,
         tracing: bool,
+        custom_payload: Option<&HashMap<String, Bytes>>,
     ) -> Result<SerializedRequest, FrameError> {
         let mut flags = 0;
         let mut data = vec![0; HEADER_SIZE];
 
         if let Some(compression) = compression {
             flags |= FLAG_COMPRESSION;
-            let body = req.to_bytes()?;
+            let mut body = Vec::new();
+            Self::serialize_body(req, custom_payload, &mut body)?;
             compress_append(&body, compression, &mut data)?;
         } else {
-            req.serialize(&mut data)?;
+            Self::serialize_body(req, custom_payload, &mut data)?;
         }
 
         if tracing {
             flags |= FLAG_TRACING;
         }
 
+        if custom_payload.is_some() {
+            flags |= FLAG_CUSTOM_PAYLOAD;
+        }
+
         data[0] = 4; // We only support version 4 for now
         data[1] = flags;
         // Leave space for the stream number
@@ -91,6 +98,19 @@ impl SerializedRequest {
         Ok(Self { data })
     }
 
+    // The custom payload, if present, precedes the request body
+    fn serialize_body<R: Request>(
+        req: &R,
+        custom_payload: Option<&HashMap<String, Bytes>>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), FrameError> {
+        if let Some(custom_payload) = custom_payload {
+            types::write_bytes_map(custom_payload, buf)?;
+        }
+        req.serialize(buf)?;
+        Ok(())
+    }
+
     pub fn set_stream(&mut self, stream: i16) {
         self.data[2..4].copy_from_slice(&stream.to_be_bytes());
     }
@@ -167,6 +187,7 @@ pub async fn read_response_frame(
 pub struct ResponseBodyWithExtensions {
     pub trace_id: Option<Uuid>,
     pub warnings: Vec<String>,
+    pub custom_payload: Option<HashMap<String, Bytes>>,
     pub body: Bytes,
 }
 
@@ -203,19 +224,26 @@ pub fn parse_response_body_extensions(
         Vec::new()
     };
 
-    if flags & FLAG_CUSTOM_PAYLOAD != 0 {
-        // TODO: Do something useful with the custom payload map
-        // For now, just skip it
+    let custom_payload = if flags & FLAG_CUSTOM_PAYLOAD != 0 {
         let body_len = body.len();
         let buf = &mut &*body;
-        types::read_bytes_map(buf)?;
+        let payload_map = types::read_bytes_map(buf)?;
         let buf_len = buf.len();
         body.advance(body_len - buf_len);
-    }
+        Some(
+            payload_map
+                .into_iter()
+                .map(|(key, value)| (key, Bytes::from(value)))
+                .collect(),
+        )
+    } else {
+        None
+    };
 
     Ok(ResponseBodyWithExtensions {
         trace_id,
         warnings,
+        custom_payload,
         body,
     })
 }
@@ -286,4 +314,42 @@ mod test {
         assert_eq!(32, comp_body.len());
         assert_eq!(uncomp_body.as_bytes(), result);
     }
+
+    #[test]
+    fn test_custom_payload_precedes_body() {
+        let mut custom_payload = HashMap::new();
+        custom_payload.insert("key".to_string(), Bytes::from_static(b"value"));
+        let request = request::Options {};
+
+        let serialized =
+            SerializedRequest::make(&request, None, false, Some(&custom_payload)).unwrap();
+        let data = serialized.get_data();
+        assert_eq!(data[1], FLAG_CUSTOM_PAYLOAD);
+
+        let mut body = &data[HEADER_SIZE..];
+        let read_payload = types::read_bytes_map(&mut body).unwrap();
+        assert_eq!(read_payload.get("key").unwrap(), b"value");
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn test_parse_warnings_and_custom_payload() {
+        let mut custom_payload = HashMap::new();
+        custom_payload.insert("key".to_string(), Bytes::from_static(b"value"));
+        let warnings = vec!["Batch is too large".to_string()];
+
+        // Warnings precede the custom payload, which precedes the body
+        let mut body = Vec::new();
+        types::write_string_list(&warnings, &mut body).unwrap();
+        types::write_bytes_map(&custom_payload, &mut body).unwrap();
+        body.extend_from_slice(b"body");
+
+        let parsed =
+            parse_response_body_extensions(FLAG_WARNING | FLAG_CUSTOM_PAYLOAD, None, body.into())
+                .unwrap();
+        assert_eq!(parsed.trace_id, None);
+        assert_eq!(parsed.warnings, warnings);
+        assert_eq!(parsed.custom_payload, Some(custom_payload));
+        assert_eq!(&parsed.body[..], b"body");
+    }
 }
@@ -1,11 +1,14 @@
 use std::collections::HashMap;
 
 const RATE_LIMIT_ERROR_EXTENSION: &str = "SCYLLA_RATE_LIMIT_ERROR";
+const LWT_ADD_METADATA_MARK_EXTENSION: &str = "SCYLLA_LWT_ADD_METADATA_MARK";
+const LWT_OPTIMIZATION_META_BIT_MASK_KEY: &str = "LWT_OPTIMIZATION_META_BIT_MASK";
 
 #[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
 #[non_exhaustive]
 pub struct ProtocolFeatures {
     pub rate_limit_error: Option<i32>,
+    pub lwt_optimization_meta_bit_mask: Option<u32>,
 }
 
 // TODO: Log information about options which failed to parse
@@ -14,6 +17,9 @@ impl ProtocolFeatures {
     pub fn parse_from_supported(supported: &HashMap<String, Vec<String>>) -> Self {
         Self {
             rate_limit_error: Self::maybe_parse_rate_limit_error(supported),
+            lwt_optimization_meta_bit_mask: Self::maybe_parse_lwt_optimization_meta_bit_mask(
+                supported,
+            ),
         }
     }
 
@@ -23,6 +29,15 @@ impl ProtocolFeatures {
         code_str.parse::<i32>().ok()
     }
 
+    fn maybe_parse_lwt_optimization_meta_bit_mask(
+        supported: &HashMap<String, Vec<String>>,
+    ) -> Option<u32> {
+        let vals = supported.get(LWT_ADD_METADATA_MARK_EXTENSION)?;
+        let mask_str =
+            Self::get_cql_extension_field(vals.as_slice(), LWT_OPTIMIZATION_META_BIT_MASK_KEY)?;
+        mask_str.parse::<u32>().ok()
+    }
+
     // Looks up a field which starts with `key=` and returns the rest
     fn get_cql_extension_field<'a>(vals: &'a [String], key: &str) -> Option<&'a str> {
         vals.iter()
@@ -33,5 +48,46 @@ impl ProtocolFeatures {
         if self.rate_limit_error.is_some() {
             options.insert(RATE_LIMIT_ERROR_EXTENSION.to_string(), String::new());
         }
+        if let Some(mask) = self.lwt_optimization_meta_bit_mask {
+            options.insert(
+                LWT_ADD_METADATA_MARK_EXTENSION.to_string(),
+                format!("{}={}", LWT_OPTIMIZATION_META_BIT_MASK_KEY, mask),
+            );
+        }
+    }
+
+    // Returns whether flags of the metadata of a PREPARED response mark the statement as an LWT
+    pub fn prepared_flags_contain_lwt_mark(&self, flags: u32) -> bool {
+        match self.lwt_optimization_meta_bit_mask {
+            Some(mask) => flags & mask == mask,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lwt_mark() {
+        let mut supported = HashMap::new();
+        supported.insert(
+            LWT_ADD_METADATA_MARK_EXTENSION.to_string(),
+            vec![format!("{}=2147483648", LWT_OPTIMIZATION_META_BIT_MASK_KEY)],
+        );
+        let features = ProtocolFeatures::parse_from_supported(&supported);
+        assert_eq!(features.lwt_optimization_meta_bit_mask, Some(0x8000_0000));
+
+        let mut options = HashMap::new();
+        features.add_startup_options(&mut options);
+        assert_eq!(
+            options[LWT_ADD_METADATA_MARK_EXTENSION],
+            "LWT_OPTIMIZATION_META_BIT_MASK=2147483648"
+        );
+
+        assert!(features.prepared_flags_contain_lwt_mark(0x8000_0001));
+        assert!(!features.prepared_flags_contain_lwt_mark(0x0000_0001));
+        assert!(!ProtocolFeatures::default().prepared_flags_contain_lwt_mark(0x8000_0001));
     }
 }
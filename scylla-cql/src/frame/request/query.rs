@@ -10,7 +10,7 @@ use crate::{
 // Query flags
 // Unused flags are commented out so that they don't trigger warnings
 const FLAG_VALUES: u8 = 0x01;
-// const FLAG_SKIP_METADATA: u8 = 0x02;
+const FLAG_SKIP_METADATA: u8 = 0x02;
 const FLAG_PAGE_SIZE: u8 = 0x04;
 const FLAG_WITH_PAGING_STATE: u8 = 0x08;
 const FLAG_WITH_SERIAL_CONSISTENCY: u8 = 0x10;
@@ -38,6 +38,9 @@ pub struct QueryParameters<'a> {
     pub timestamp: Option<i64>,
     pub page_size: Option<i32>,
     pub paging_state: Option<Bytes>,
+    // Asks the server to omit the result metadata in the response.
+    // Only makes sense for EXECUTE, the driver must know the metadata already.
+    pub skip_metadata: bool,
     pub values: &'a SerializedValues,
 }
 
@@ -49,6 +52,7 @@ impl Default for QueryParameters<'_> {
             timestamp: None,
             page_size: None,
             paging_state: None,
+            skip_metadata: false,
             values: SerializedValues::EMPTY,
         }
     }
@@ -63,6 +67,10 @@ impl QueryParameters<'_> {
             flags |= FLAG_VALUES;
         }
 
+        if self.skip_metadata {
+            flags |= FLAG_SKIP_METADATA;
+        }
+
         if self.page_size.is_some() {
             flags |= FLAG_PAGE_SIZE;
         }
@@ -9,6 +9,7 @@ use crate::{errors::QueryError, frame::frame_errors::ParseError};
 use num_enum::TryFromPrimitive;
 
 use crate::frame::protocol_features::ProtocolFeatures;
+use crate::frame::response::result::ResultMetadata;
 pub use error::Error;
 pub use supported::Supported;
 
@@ -42,6 +43,18 @@ impl Response {
         features: &ProtocolFeatures,
         opcode: ResponseOpcode,
         buf: &mut &[u8],
+    ) -> Result<Response, ParseError> {
+        Self::deserialize_with_result_metadata(features, opcode, buf, None)
+    }
+
+    /// Same as [`deserialize`](Response::deserialize), but rows results sent without metadata
+    /// (see `skip_metadata` in [`QueryParameters`](crate::frame::request::query::QueryParameters))
+    /// are deserialized using the given result metadata.
+    pub fn deserialize_with_result_metadata(
+        features: &ProtocolFeatures,
+        opcode: ResponseOpcode,
+        buf: &mut &[u8],
+        result_metadata: Option<&ResultMetadata>,
     ) -> Result<Response, ParseError> {
         let response = match opcode {
             ResponseOpcode::Error => Response::Error(Error::deserialize(features, buf)?),
@@ -50,7 +63,9 @@ impl Response {
                 Response::Authenticate(authenticate::Authenticate::deserialize(buf)?)
             }
             ResponseOpcode::Supported => Response::Supported(Supported::deserialize(buf)?),
-            ResponseOpcode::Result => Response::Result(result::deserialize(buf)?),
+            ResponseOpcode::Result => {
+                Response::Result(result::deserialize_with_metadata(buf, result_metadata)?)
+            }
             ResponseOpcode::Event => Response::Event(event::Event::deserialize(buf)?),
             ResponseOpcode::AuthChallenge => {
                 Response::AuthChallenge(authenticate::AuthChallenge::deserialize(buf)?)
@@ -334,7 +334,7 @@ pub struct ColumnSpec {
     pub typ: ColumnType,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct ResultMetadata {
     col_count: usize,
     pub paging_state: Option<Bytes>,
@@ -349,8 +349,11 @@ pub struct PartitionKeyIndex {
     pub sequence: u16,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
 pub struct PreparedMetadata {
+    /// Flags of the metadata, may contain protocol extension marks (e.g. the LWT mark)
+    pub flags: i32,
     pub col_count: usize,
     /// pk_indexes are sorted by `index` and can be reordered in partition key order
     /// using `sequence` field
@@ -550,6 +553,7 @@ fn deser_prepared_metadata(buf: &mut &[u8]) -> StdResult<PreparedMetadata, Parse
     let col_specs = deser_col_specs(buf, &global_table_spec, col_count)?;
 
     Ok(PreparedMetadata {
+        flags,
         col_count,
         pk_indexes,
         col_specs,
@@ -829,14 +833,33 @@ fn deser_cql_value(typ: &ColumnType, buf: &mut &[u8]) -> StdResult<CqlValue, Par
     })
 }
 
-fn deser_rows(buf: &mut &[u8]) -> StdResult<Rows, ParseError> {
-    let metadata = deser_result_metadata(buf)?;
+impl ResultMetadata {
+    /// Returns the number of columns in the result
+    pub fn col_count(&self) -> usize {
+        self.col_count
+    }
+}
 
-    // TODO: the protocol allows an optimization (which must be explicitly requested on query by
-    // the driver) where the column metadata is not sent with the result.
-    // Implement this optimization. We'll then need to take the column types by a parameter.
-    // Beware of races; our column types may be outdated.
-    assert!(metadata.col_count == metadata.col_specs.len());
+fn deser_rows(
+    buf: &mut &[u8],
+    cached_metadata: Option<&ResultMetadata>,
+) -> StdResult<Rows, ParseError> {
+    let mut metadata = deser_result_metadata(buf)?;
+
+    // The metadata is skipped when explicitly requested by the driver, which then provides
+    // the metadata cached when preparing the statement. The cached metadata may be outdated
+    // after a schema change - a mismatch in the number of columns is reported to the driver,
+    // which can re-prepare the statement.
+    if metadata.col_count != metadata.col_specs.len() {
+        match cached_metadata {
+            Some(cached)
+                if metadata.col_specs.is_empty() && cached.col_count == metadata.col_count =>
+            {
+                metadata.col_specs = cached.col_specs.clone();
+            }
+            _ => return Err(ParseError::ResultMetadataMismatch(metadata.col_count)),
+        }
+    }
 
     let rows_count: usize = types::read_int(buf)?.try_into()?;
 
@@ -887,10 +910,18 @@ fn deser_schema_change(buf: &mut &[u8]) -> StdResult<SchemaChange, ParseError> {
 }
 
 pub fn deserialize(buf: &mut &[u8]) -> StdResult<Result, ParseError> {
+    deserialize_with_metadata(buf, None)
+}
+
+/// Deserializes a result, using the given metadata for rows sent without metadata
+pub fn deserialize_with_metadata(
+    buf: &mut &[u8],
+    cached_metadata: Option<&ResultMetadata>,
+) -> StdResult<Result, ParseError> {
     use self::Result::*;
     Ok(match types::read_int(buf)? {
         0x0001 => Void,
-        0x0002 => Rows(deser_rows(buf)?),
+        0x0002 => Rows(deser_rows(buf, cached_metadata)?),
         0x0003 => SetKeyspace(deser_set_keyspace(buf)?),
         0x0004 => Prepared(deser_prepared(buf)?),
         0x0005 => SchemaChange(deser_schema_change(buf)?),
@@ -1434,4 +1465,48 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_deserialize_rows_with_skipped_metadata() {
+        use super::{ColumnSpec, ResultMetadata, TableSpec};
+        use crate::frame::frame_errors::ParseError;
+
+        // Rows result: no_metadata flag, 1 column, 2 rows with int values 1 and NULL
+        let mut buf: Vec<u8> = vec![0, 0, 0, 2, 0, 0, 0, 4, 0, 0, 0, 1, 0, 0, 0, 2];
+        buf.extend_from_slice(&[0, 0, 0, 4, 0, 0, 0, 1]);
+        buf.extend_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+
+        let cached_metadata = ResultMetadata {
+            col_count: 1,
+            paging_state: None,
+            col_specs: vec![ColumnSpec {
+                table_spec: TableSpec {
+                    ks_name: "ks".to_string(),
+                    table_name: "t".to_string(),
+                },
+                name: "a".to_string(),
+                typ: ColumnType::Int,
+            }],
+        };
+        let result = super::deserialize_with_metadata(&mut &buf[..], Some(&cached_metadata));
+        match result {
+            Ok(super::Result::Rows(rows)) => {
+                assert_eq!(rows.metadata.col_specs[0].name, "a");
+                assert_eq!(rows.rows[0].columns, vec![Some(CqlValue::Int(1))]);
+                assert_eq!(rows.rows[1].columns, vec![None]);
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // Cached metadata of a statement with different columns is rejected
+        let outdated_metadata = ResultMetadata {
+            col_count: 0,
+            paging_state: None,
+            col_specs: vec![],
+        };
+        let result = super::deserialize_with_metadata(&mut &buf[..], Some(&outdated_metadata));
+        assert!(matches!(result, Err(ParseError::ResultMetadataMismatch(1))));
+        let result = super::deserialize(&mut &buf[..]);
+        assert!(matches!(result, Err(ParseError::ResultMetadataMismatch(1))));
+    }
 }
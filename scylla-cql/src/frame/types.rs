@@ -12,7 +12,7 @@ use std::net::SocketAddr;
 use std::str;
 use uuid::Uuid;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, TryFromPrimitive)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, TryFromPrimitive)]
 #[repr(i16)]
 pub enum Consistency {
     Any = 0x0000,
@@ -26,7 +26,7 @@ pub enum Consistency {
     LocalOne = 0x000A,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, TryFromPrimitive)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, TryFromPrimitive)]
 #[repr(i16)]
 pub enum SerialConsistency {
     Serial = 0x0008,
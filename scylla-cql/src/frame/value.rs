@@ -190,6 +190,11 @@ impl SerializedValues {
     pub fn len(&self) -> i16 {
         self.values_num
     }
+
+    /// Returns the size of the serialized values in bytes
+    pub fn size(&self) -> usize {
+        self.serialized_values.len()
+    }
 }
 
 #[derive(Clone, Copy)]
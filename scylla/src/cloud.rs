@@ -0,0 +1,558 @@
+//! Connecting to clusters behind an SNI proxy, e.g. Scylla Cloud or Kubernetes ingresses.
+//!
+//! Such clusters are described by a cloud connection bundle - a YAML file with the addresses
+//! of the proxies of each datacenter, their certificate authorities, and the credentials of the client.
+//! Every connection is opened to the proxy of the node's datacenter, which routes it
+//! to the node using the server name sent in the TLS SNI extension - `<host id>.<node domain>`.
+//!
+//! Requires the `cloud` feature, which enables the `rustls` TLS backend.
+//! The `ssl` feature can be enabled as well to connect using OpenSSL.
+
+use crate::statement::Consistency;
+use crate::transport::tls::{TlsBackend, TlsConfig, TlsContext, TlsError, TlsStream};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tokio::net::TcpStream;
+use uuid::Uuid;
+
+/// Error returned when a cloud connection bundle can't be loaded
+#[derive(Error, Debug)]
+pub enum CloudConfigError {
+    #[error("Failed to read cloud config: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to parse cloud config: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("Invalid base64 data in cloud config: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error("Invalid TLS configuration in cloud config: {0}")]
+    Tls(#[from] TlsError),
+    #[error("Invalid cloud config: {0}")]
+    Validation(String),
+}
+
+/// Configuration of a cluster reachable through SNI proxies, loaded from a cloud connection bundle.
+///
+/// # Example
+/// ```rust
+/// # use scylla::cloud::CloudConfig;
+/// # use scylla::transport::tls::TlsBackend;
+/// # use scylla::{Session, SessionBuilder};
+/// # use std::sync::Arc;
+/// # #[cfg(feature = "rustls")]
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let cloud_config = CloudConfig::from_file("config_data.yaml", TlsBackend::Rustls)?;
+///
+/// let session: Session = SessionBuilder::new()
+///     .cloud_config(Arc::new(cloud_config))
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct CloudConfig {
+    datacenters: HashMap<String, CloudDatacenter>,
+    current_datacenter: String,
+    username: Option<String>,
+    password: Option<String>,
+    default_consistency: Option<Consistency>,
+}
+
+/// Datacenter of a cloud cluster, reachable through its SNI proxy
+#[derive(Debug)]
+pub struct CloudDatacenter {
+    server: String,
+    node_domain: String,
+    tls_server_name: Option<String>,
+    tls_context: TlsContext,
+}
+
+/// Node behind a proxy, identified by its host id
+#[derive(Clone, Debug)]
+pub(crate) struct CloudNode {
+    pub(crate) host_id: Uuid,
+    pub(crate) datacenter: Option<String>,
+}
+
+impl CloudConfig {
+    /// Loads the cloud connection bundle from a YAML file.
+    /// Paths in the bundle are relative to the directory of the file.
+    pub fn from_file(
+        path: impl AsRef<Path>,
+        backend: TlsBackend,
+    ) -> Result<CloudConfig, CloudConfigError> {
+        let path = path.as_ref();
+        let yaml = std::fs::read_to_string(path)?;
+        Self::from_yaml(&yaml, path.parent(), backend)
+    }
+
+    /// Loads the cloud connection bundle from YAML.
+    /// Paths in the bundle are relative to `base_dir`, or to the current directory if it's `None`.
+    pub fn from_yaml(
+        yaml: &str,
+        base_dir: Option<&Path>,
+        backend: TlsBackend,
+    ) -> Result<CloudConfig, CloudConfigError> {
+        let raw: RawCloudConfig = serde_yaml::from_str(yaml)?;
+        let base_dir = base_dir.unwrap_or_else(|| Path::new(""));
+
+        let context = raw.contexts.get(&raw.current_context).ok_or_else(|| {
+            CloudConfigError::Validation(format!(
+                "current context {} is not defined",
+                raw.current_context
+            ))
+        })?;
+        if !raw.datacenters.contains_key(&context.datacenter_name) {
+            return Err(CloudConfigError::Validation(format!(
+                "datacenter {} of the current context is not defined",
+                context.datacenter_name
+            )));
+        }
+        let auth_info = raw.auth_infos.get(&context.auth_info_name).ok_or_else(|| {
+            CloudConfigError::Validation(format!(
+                "auth info {} of the current context is not defined",
+                context.auth_info_name
+            ))
+        })?;
+
+        let client_certificate = read_data_or_path(
+            &auth_info.client_certificate_data,
+            &auth_info.client_certificate_path,
+            base_dir,
+        )?;
+        let client_key = read_data_or_path(
+            &auth_info.client_key_data,
+            &auth_info.client_key_path,
+            base_dir,
+        )?;
+        let client_identity = match (client_certificate, client_key) {
+            (Some(certificate), Some(key)) => Some((certificate, key)),
+            (None, None) => None,
+            _ => {
+                return Err(CloudConfigError::Validation(
+                    "client certificate and client key have to be provided together".to_string(),
+                ))
+            }
+        };
+
+        let mut datacenters = HashMap::with_capacity(raw.datacenters.len());
+        for (name, datacenter) in raw.datacenters {
+            if datacenter.server.is_empty() || datacenter.node_domain.is_empty() {
+                return Err(CloudConfigError::Validation(format!(
+                    "server and node domain of datacenter {} have to be provided",
+                    name
+                )));
+            }
+
+            let mut tls_config =
+                TlsConfig::new().verify_certificates(!datacenter.insecure_skip_tls_verify);
+            if let Some(certificate_authority) = read_data_or_path(
+                &datacenter.certificate_authority_data,
+                &datacenter.certificate_authority_path,
+                base_dir,
+            )? {
+                tls_config = tls_config.add_root_certificates_pem(certificate_authority);
+            }
+            if let Some((certificate, key)) = &client_identity {
+                tls_config = tls_config.client_identity_pem(certificate.clone(), key.clone());
+            }
+
+            datacenters.insert(
+                name,
+                CloudDatacenter {
+                    server: datacenter.server,
+                    node_domain: datacenter.node_domain,
+                    tls_server_name: datacenter.tls_server_name,
+                    tls_context: tls_config.build(backend)?,
+                },
+            );
+        }
+
+        let default_consistency = match raw
+            .parameters
+            .as_ref()
+            .and_then(|parameters| parameters.default_consistency.as_deref())
+        {
+            Some(consistency) => Some(parse_consistency(consistency)?),
+            None => None,
+        };
+
+        Ok(CloudConfig {
+            datacenters,
+            current_datacenter: context.datacenter_name.clone(),
+            username: auth_info.username.clone(),
+            password: auth_info.password.clone(),
+            default_consistency,
+        })
+    }
+
+    /// Returns the datacenters of the cluster, by name
+    pub fn get_datacenters(&self) -> &HashMap<String, CloudDatacenter> {
+        &self.datacenters
+    }
+
+    /// Returns the name of the datacenter of the current context,
+    /// whose proxy is used for the initial connection
+    pub fn get_current_datacenter(&self) -> &str {
+        &self.current_datacenter
+    }
+
+    /// Returns the username used to authenticate, if the bundle contains credentials
+    pub fn get_username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    /// Returns the password used to authenticate, if the bundle contains credentials
+    pub fn get_password(&self) -> Option<&str> {
+        self.password.as_deref()
+    }
+
+    /// Returns the default consistency set in the bundle
+    pub fn get_default_consistency(&self) -> Option<Consistency> {
+        self.default_consistency
+    }
+
+    /// Address of the proxy used for the initial connection
+    pub(crate) fn get_contact_point(&self) -> &str {
+        &self.current().server
+    }
+
+    fn current(&self) -> &CloudDatacenter {
+        // Existence of the current datacenter is verified when the config is loaded
+        &self.datacenters[&self.current_datacenter]
+    }
+
+    /// Opens the connection to the node through the proxy of its datacenter.
+    /// Without a node (i.e. before the host ids are known) the connection is opened
+    /// through the proxy of the current datacenter to any of its nodes.
+    pub(crate) async fn connect(
+        &self,
+        node: Option<&CloudNode>,
+        tcp_nodelay: bool,
+    ) -> Result<TlsStream, io::Error> {
+        let (datacenter, server_name) = match node {
+            Some(node) => {
+                let datacenter = node
+                    .datacenter
+                    .as_ref()
+                    .and_then(|name| self.datacenters.get(name))
+                    .unwrap_or_else(|| self.current());
+                let server_name = format!("{}.{}", node.host_id, datacenter.node_domain);
+                (datacenter, server_name)
+            }
+            None => {
+                let datacenter = self.current();
+                let server_name = datacenter
+                    .tls_server_name
+                    .clone()
+                    .unwrap_or_else(|| datacenter.node_domain.clone());
+                (datacenter, server_name)
+            }
+        };
+
+        let stream = TcpStream::connect(datacenter.server.as_str()).await?;
+        stream.set_nodelay(tcp_nodelay)?;
+        let address = stream.peer_addr()?;
+
+        datacenter
+            .tls_context
+            .connect_with_server_name(address, Some(server_name), stream)
+            .await
+    }
+}
+
+impl CloudDatacenter {
+    /// Returns the address of the SNI proxy, as `host:port`
+    pub fn get_server(&self) -> &str {
+        &self.server
+    }
+
+    /// Returns the domain of the nodes, their server names are `<host id>.<node domain>`
+    pub fn get_node_domain(&self) -> &str {
+        &self.node_domain
+    }
+
+    /// Returns the server name used for connections to any node of the datacenter
+    pub fn get_tls_server_name(&self) -> Option<&str> {
+        self.tls_server_name.as_deref()
+    }
+}
+
+// Reads PEM data given either inline as base64 or as a path to a file
+fn read_data_or_path(
+    data: &Option<String>,
+    path: &Option<PathBuf>,
+    base_dir: &Path,
+) -> Result<Option<Vec<u8>>, CloudConfigError> {
+    match (data, path) {
+        (Some(data), _) => Ok(Some(base64::decode(data.trim())?)),
+        (None, Some(path)) => Ok(Some(std::fs::read(base_dir.join(path))?)),
+        (None, None) => Ok(None),
+    }
+}
+
+fn parse_consistency(consistency: &str) -> Result<Consistency, CloudConfigError> {
+    Ok(match consistency.to_uppercase().as_str() {
+        "ANY" => Consistency::Any,
+        "ONE" => Consistency::One,
+        "TWO" => Consistency::Two,
+        "THREE" => Consistency::Three,
+        "QUORUM" => Consistency::Quorum,
+        "ALL" => Consistency::All,
+        "LOCAL_QUORUM" => Consistency::LocalQuorum,
+        "EACH_QUORUM" => Consistency::EachQuorum,
+        "LOCAL_ONE" => Consistency::LocalOne,
+        _ => {
+            return Err(CloudConfigError::Validation(format!(
+                "unknown consistency {}",
+                consistency
+            )))
+        }
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawCloudConfig {
+    datacenters: HashMap<String, RawDatacenter>,
+    #[serde(default)]
+    auth_infos: HashMap<String, RawAuthInfo>,
+    contexts: HashMap<String, RawContext>,
+    current_context: String,
+    parameters: Option<RawParameters>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawDatacenter {
+    certificate_authority_data: Option<String>,
+    certificate_authority_path: Option<PathBuf>,
+    server: String,
+    tls_server_name: Option<String>,
+    node_domain: String,
+    #[serde(default)]
+    insecure_skip_tls_verify: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawAuthInfo {
+    client_certificate_data: Option<String>,
+    client_certificate_path: Option<PathBuf>,
+    client_key_data: Option<String>,
+    client_key_path: Option<PathBuf>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawContext {
+    datacenter_name: String,
+    auth_info_name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawParameters {
+    default_consistency: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CloudConfig, CloudConfigError, CloudNode};
+    use crate::statement::Consistency;
+    use crate::transport::tls::TlsBackend;
+    use std::path::Path;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+    use uuid::Uuid;
+
+    fn backend() -> TlsBackend {
+        TlsBackend::Rustls
+    }
+
+    fn bundle(current_context: &str) -> String {
+        format!(
+            r#"
+apiVersion: cqlclient.scylla.scylladb.com/v1alpha1
+kind: CQLConnectionConfig
+datacenters:
+  eu-west-1:
+    certificateAuthorityPath: ca.crt
+    server: eu-west-1.proxy.example.com:443
+    nodeDomain: cql.eu-west-1.example.com
+  us-east-1:
+    certificateAuthorityData: {}
+    server: us-east-1.proxy.example.com:443
+    tlsServerName: any.cql.us-east-1.example.com
+    nodeDomain: cql.us-east-1.example.com
+    insecureSkipTlsVerify: true
+authInfos:
+  admin:
+    clientCertificatePath: client.crt
+    clientKeyPath: client.key
+    username: scylla
+    password: secret
+contexts:
+  default:
+    datacenterName: eu-west-1
+    authInfoName: admin
+  missing-datacenter:
+    datacenterName: ap-south-1
+    authInfoName: admin
+currentContext: {}
+parameters:
+  defaultConsistency: LOCAL_QUORUM
+"#,
+            base64::encode(include_bytes!("../../test/tls/ca.crt")),
+            current_context
+        )
+    }
+
+    fn test_dir() -> &'static Path {
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../test/tls"))
+    }
+
+    #[test]
+    fn load_bundle() {
+        let config =
+            CloudConfig::from_yaml(&bundle("default"), Some(test_dir()), backend()).unwrap();
+
+        assert_eq!(config.get_current_datacenter(), "eu-west-1");
+        assert_eq!(
+            config.get_contact_point(),
+            "eu-west-1.proxy.example.com:443"
+        );
+        assert_eq!(config.get_username(), Some("scylla"));
+        assert_eq!(config.get_password(), Some("secret"));
+        assert_eq!(
+            config.get_default_consistency(),
+            Some(Consistency::LocalQuorum)
+        );
+
+        let datacenters = config.get_datacenters();
+        assert_eq!(datacenters.len(), 2);
+        let us_east = &datacenters["us-east-1"];
+        assert_eq!(us_east.get_server(), "us-east-1.proxy.example.com:443");
+        assert_eq!(us_east.get_node_domain(), "cql.us-east-1.example.com");
+        assert_eq!(
+            us_east.get_tls_server_name(),
+            Some("any.cql.us-east-1.example.com")
+        );
+    }
+
+    #[test]
+    fn invalid_bundles_are_rejected() {
+        assert!(matches!(
+            CloudConfig::from_yaml(&bundle("missing-datacenter"), Some(test_dir()), backend()),
+            Err(CloudConfigError::Validation(_))
+        ));
+        assert!(matches!(
+            CloudConfig::from_yaml(&bundle("unknown"), Some(test_dir()), backend()),
+            Err(CloudConfigError::Validation(_))
+        ));
+        // Paths are relative to the directory of the bundle
+        assert!(matches!(
+            CloudConfig::from_yaml(&bundle("default"), None, backend()),
+            Err(CloudConfigError::Io(_))
+        ));
+        assert!(matches!(
+            CloudConfig::from_yaml("datacenters: []", None, backend()),
+            Err(CloudConfigError::Yaml(_))
+        ));
+    }
+
+    // Returns the server name sent in the SNI extension of the TLS handshake started
+    // by `CloudConfig::connect`, with a bundle whose proxies listen on a local socket
+    async fn sent_server_name(node: Option<&CloudNode>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let yaml = format!(
+            r#"
+datacenters:
+  eu-west-1:
+    certificateAuthorityPath: ca.crt
+    server: {0}
+    nodeDomain: cql.eu-west-1.example.com
+  us-east-1:
+    certificateAuthorityPath: ca.crt
+    server: {0}
+    tlsServerName: any.cql.us-east-1.example.com
+    nodeDomain: cql.us-east-1.example.com
+authInfos:
+  admin:
+    username: scylla
+contexts:
+  default:
+    datacenterName: us-east-1
+    authInfoName: admin
+currentContext: default
+"#,
+            listener.local_addr().unwrap()
+        );
+        let config = CloudConfig::from_yaml(&yaml, Some(test_dir()), backend()).unwrap();
+
+        let (accepted, _) = tokio::join!(listener.accept(), async {
+            // The handshake never completes, so the connection is dropped after the client hello
+            let _ = tokio::time::timeout(
+                std::time::Duration::from_millis(500),
+                config.connect(node, true),
+            )
+            .await;
+        });
+        let mut client_hello = vec![0; 4096];
+        let len = accepted.unwrap().0.read(&mut client_hello).await.unwrap();
+        client_hello.truncate(len);
+
+        // The server name is sent in plaintext, prefixed with its length
+        ["any.cql.us-east-1.example.com".to_string()]
+            .into_iter()
+            .chain(node.map(|node| {
+                format!(
+                    "{}.cql.{}.example.com",
+                    node.host_id,
+                    node.datacenter.as_deref().unwrap_or("us-east-1")
+                )
+            }))
+            .find(|name| {
+                let mut prefixed = (name.len() as u16).to_be_bytes().to_vec();
+                prefixed.extend_from_slice(name.as_bytes());
+                client_hello
+                    .windows(prefixed.len())
+                    .any(|window| window == prefixed)
+            })
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn connect_sends_node_server_name() {
+        // Without a node, any node of the current datacenter is reached using its tlsServerName
+        assert_eq!(
+            sent_server_name(None).await,
+            "any.cql.us-east-1.example.com"
+        );
+
+        let host_id = Uuid::from_u128(0x1d3b_5a1e_88f4_4e2c_9b1a_2f6c_0d7e_3a45);
+        let node = CloudNode {
+            host_id,
+            datacenter: Some("eu-west-1".to_string()),
+        };
+        assert_eq!(
+            sent_server_name(Some(&node)).await,
+            format!("{}.cql.eu-west-1.example.com", host_id)
+        );
+
+        // Nodes of unknown datacenters are reached through the proxy of the current one
+        let node = CloudNode {
+            host_id,
+            datacenter: None,
+        };
+        assert_eq!(
+            sent_server_name(Some(&node)).await,
+            format!("{}.cql.us-east-1.example.com", host_id)
+        );
+    }
+}
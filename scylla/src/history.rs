@@ -1,10 +1,10 @@
 //! Collecting history of query executions - retries, speculative, etc.
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
     fmt::{Debug, Display},
     net::SocketAddr,
     sync::Mutex,
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
 use crate::retry_policy::RetryDecision;
@@ -244,17 +244,383 @@ impl HistoryListener for HistoryCollector {
     }
 }
 
+/// Events of queries, grouped by the query they belong to.\
+/// Allows to drop all events of a single query, which is needed by listeners with bounded memory.
+#[derive(Debug)]
+struct QueryEvents {
+    queries: BTreeMap<QueryId, Vec<(HistoryEvent, TimePoint)>>,
+    attempt_queries: HashMap<AttemptId, QueryId>,
+    next_query_id: QueryId,
+    next_speculative_fiber_id: SpeculativeId,
+    next_attempt_id: AttemptId,
+}
+
+impl Default for QueryEvents {
+    fn default() -> QueryEvents {
+        QueryEvents {
+            queries: BTreeMap::new(),
+            attempt_queries: HashMap::new(),
+            next_query_id: QueryId(0),
+            next_speculative_fiber_id: SpeculativeId(0),
+            next_attempt_id: AttemptId(0),
+        }
+    }
+}
+
+impl QueryEvents {
+    fn start_query(&mut self) -> QueryId {
+        let query_id: QueryId = self.next_query_id;
+        self.next_query_id.0 += 1;
+        self.queries.insert(query_id, Vec::new());
+        self.add_event(query_id, HistoryEvent::NewQuery(query_id));
+        query_id
+    }
+
+    fn new_speculative_fiber(&mut self, query_id: QueryId) -> SpeculativeId {
+        let speculative_id: SpeculativeId = self.next_speculative_fiber_id;
+        self.next_speculative_fiber_id.0 += 1;
+        self.add_event(
+            query_id,
+            HistoryEvent::NewSpeculativeFiber(speculative_id, query_id),
+        );
+        speculative_id
+    }
+
+    fn new_attempt(
+        &mut self,
+        query_id: QueryId,
+        speculative_id: Option<SpeculativeId>,
+        node_addr: SocketAddr,
+    ) -> AttemptId {
+        let attempt_id: AttemptId = self.next_attempt_id;
+        self.next_attempt_id.0 += 1;
+        if self.queries.contains_key(&query_id) {
+            self.attempt_queries.insert(attempt_id, query_id);
+            self.add_event(
+                query_id,
+                HistoryEvent::NewAttempt(attempt_id, query_id, speculative_id, node_addr),
+            );
+        }
+        attempt_id
+    }
+
+    fn add_attempt_event(&mut self, attempt_id: AttemptId, event: HistoryEvent) {
+        if let Some(query_id) = self.attempt_queries.get(&attempt_id).copied() {
+            self.add_event(query_id, event);
+        }
+    }
+
+    // Events of queries which were already removed are dropped
+    fn add_event(&mut self, query_id: QueryId, event: HistoryEvent) {
+        if let Some(events) = self.queries.get_mut(&query_id) {
+            let event_time: TimePoint = SystemTime::now().into();
+            events.push((event, event_time));
+        }
+    }
+
+    fn remove_query(&mut self, query_id: QueryId) -> Option<Vec<(HistoryEvent, TimePoint)>> {
+        let events = self.queries.remove(&query_id)?;
+        for (event, _) in &events {
+            if let HistoryEvent::NewAttempt(attempt_id, _, _, _) = event {
+                self.attempt_queries.remove(attempt_id);
+            }
+        }
+        Some(events)
+    }
+
+    fn remove_oldest_query(&mut self) {
+        if let Some(oldest_id) = self.queries.keys().next().copied() {
+            self.remove_query(oldest_id);
+        }
+    }
+
+    fn to_collector_data(
+        &self,
+        events: impl IntoIterator<Item = (HistoryEvent, TimePoint)>,
+    ) -> HistoryCollectorData {
+        HistoryCollectorData {
+            events: events.into_iter().collect(),
+            next_query_id: self.next_query_id,
+            next_speculative_fiber_id: self.next_speculative_fiber_id,
+            next_attempt_id: self.next_attempt_id,
+        }
+    }
+}
+
+/// Locks a mutex guarding query events, avoiding panics on a poisoned mutex.
+fn do_with_events<OpRetType>(
+    events: &Mutex<QueryEvents>,
+    do_fn: impl FnOnce(&mut QueryEvents) -> OpRetType,
+) -> OpRetType {
+    match events.lock() {
+        Ok(mut events) => do_fn(&mut events),
+        Err(poison_error) => {
+            // Same as in HistoryCollector - history isn't important enough to panic.
+            warn!("History listener - mutex poisoned! Error: {}", poison_error);
+            do_fn(&mut QueryEvents::default())
+        }
+    }
+}
+
+/// RingBufferHistoryCollector is a HistoryListener which keeps the history of
+/// a limited number of most recent queries.\
+/// When a new query starts and the buffer is full, the history of the oldest query is dropped,
+/// even if that query is still running.
+#[derive(Debug)]
+pub struct RingBufferHistoryCollector {
+    capacity: usize,
+    events: Mutex<QueryEvents>,
+}
+
+impl RingBufferHistoryCollector {
+    /// Creates a collector keeping history of at most `capacity` queries.
+    pub fn new(capacity: usize) -> RingBufferHistoryCollector {
+        RingBufferHistoryCollector {
+            capacity,
+            events: Mutex::new(QueryEvents::default()),
+        }
+    }
+
+    /// Returns the maximum number of queries kept by the collector.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Clones the collected events of all kept queries and converts them to StructuredHistory.
+    pub fn clone_structured_history(&self) -> StructuredHistory {
+        do_with_events(&self.events, |events| {
+            let all_events = events.queries.values().flatten().cloned();
+            StructuredHistory::from(&events.to_collector_data(all_events))
+        })
+    }
+
+    /// Takes out the collected events of all kept queries and converts them to StructuredHistory.
+    /// Events of queries which are still running, that come afterwards, are dropped.
+    pub fn take_structured_history(&self) -> StructuredHistory {
+        do_with_events(&self.events, |events| {
+            let all_events: Vec<(HistoryEvent, TimePoint)> = std::mem::take(&mut events.queries)
+                .into_values()
+                .flatten()
+                .collect();
+            events.attempt_queries.clear();
+            StructuredHistory::from(&events.to_collector_data(all_events))
+        })
+    }
+}
+
+impl HistoryListener for RingBufferHistoryCollector {
+    fn log_query_start(&self) -> QueryId {
+        do_with_events(&self.events, |events| {
+            while !events.queries.is_empty() && events.queries.len() >= self.capacity {
+                events.remove_oldest_query();
+            }
+            let query_id = events.start_query();
+            if self.capacity == 0 {
+                events.remove_query(query_id);
+            }
+            query_id
+        })
+    }
+
+    fn log_query_success(&self, query_id: QueryId) {
+        do_with_events(&self.events, |events| {
+            events.add_event(query_id, HistoryEvent::QuerySuccess(query_id))
+        })
+    }
+
+    fn log_query_error(&self, query_id: QueryId, error: &QueryError) {
+        do_with_events(&self.events, |events| {
+            events.add_event(query_id, HistoryEvent::QueryError(query_id, error.clone()))
+        })
+    }
+
+    fn log_new_speculative_fiber(&self, query_id: QueryId) -> SpeculativeId {
+        do_with_events(&self.events, |events| {
+            events.new_speculative_fiber(query_id)
+        })
+    }
+
+    fn log_attempt_start(
+        &self,
+        query_id: QueryId,
+        speculative_id: Option<SpeculativeId>,
+        node_addr: SocketAddr,
+    ) -> AttemptId {
+        do_with_events(&self.events, |events| {
+            events.new_attempt(query_id, speculative_id, node_addr)
+        })
+    }
+
+    fn log_attempt_success(&self, attempt_id: AttemptId) {
+        do_with_events(&self.events, |events| {
+            events.add_attempt_event(attempt_id, HistoryEvent::AttemptSuccess(attempt_id))
+        })
+    }
+
+    fn log_attempt_error(
+        &self,
+        attempt_id: AttemptId,
+        error: &QueryError,
+        retry_decision: &RetryDecision,
+    ) {
+        do_with_events(&self.events, |events| {
+            events.add_attempt_event(
+                attempt_id,
+                HistoryEvent::AttemptError(attempt_id, error.clone(), retry_decision.clone()),
+            )
+        })
+    }
+}
+
+/// SlowQueryLogger is a HistoryListener which logs queries that failed
+/// or took longer than the given threshold, together with all their attempts.\
+/// Queries are logged with `tracing::warn!` once they finish.
+/// Only the history of queries which are still running is kept in memory.\
+/// Queries which never finish, e.g. because their future was dropped, would be kept forever,
+/// so the history of at most `max_unfinished_queries` queries is kept - the oldest ones are dropped without being logged.
+#[derive(Debug)]
+pub struct SlowQueryLogger {
+    threshold: Duration,
+    max_unfinished_queries: usize,
+    events: Mutex<QueryEvents>,
+}
+
+impl SlowQueryLogger {
+    /// Default number of unfinished queries whose history is kept
+    pub const DEFAULT_MAX_UNFINISHED_QUERIES: usize = 10_000;
+
+    /// Creates a logger of queries which failed or took at least `threshold` to finish.
+    pub fn new(threshold: Duration) -> SlowQueryLogger {
+        SlowQueryLogger::with_max_unfinished_queries(
+            threshold,
+            SlowQueryLogger::DEFAULT_MAX_UNFINISHED_QUERIES,
+        )
+    }
+
+    /// Creates a logger keeping the history of at most `max_unfinished_queries` running queries.
+    pub fn with_max_unfinished_queries(
+        threshold: Duration,
+        max_unfinished_queries: usize,
+    ) -> SlowQueryLogger {
+        SlowQueryLogger {
+            threshold,
+            max_unfinished_queries,
+            events: Mutex::new(QueryEvents::default()),
+        }
+    }
+
+    fn finish_query(&self, query_id: QueryId, final_event: HistoryEvent) {
+        let history = do_with_events(&self.events, |events| {
+            events.add_event(query_id, final_event);
+            events.remove_query(query_id).map(|query_events| {
+                StructuredHistory::from(&events.to_collector_data(query_events))
+            })
+        });
+
+        // Events of queries which were already logged are dropped
+        let history: StructuredHistory = match history {
+            Some(history) => history,
+            None => return,
+        };
+        let query: &QueryHistory = match history.queries.first() {
+            Some(query) => query,
+            None => return,
+        };
+
+        let (end_time, failed) = match &query.result {
+            Some(QueryHistoryResult::Success(time)) => (*time, false),
+            Some(QueryHistoryResult::Error(time, _)) => (*time, true),
+            None => return,
+        };
+        let elapsed: Duration = (end_time - query.start_time)
+            .to_std()
+            .unwrap_or(Duration::ZERO);
+
+        if failed {
+            warn!("Query failed after {}ms\n{}", elapsed.as_millis(), history);
+        } else if elapsed >= self.threshold {
+            warn!("Slow query took {}ms\n{}", elapsed.as_millis(), history);
+        }
+    }
+}
+
+impl HistoryListener for SlowQueryLogger {
+    fn log_query_start(&self) -> QueryId {
+        do_with_events(&self.events, |events| {
+            while !events.queries.is_empty() && events.queries.len() >= self.max_unfinished_queries
+            {
+                events.remove_oldest_query();
+            }
+            let query_id = events.start_query();
+            if self.max_unfinished_queries == 0 {
+                events.remove_query(query_id);
+            }
+            query_id
+        })
+    }
+
+    fn log_query_success(&self, query_id: QueryId) {
+        self.finish_query(query_id, HistoryEvent::QuerySuccess(query_id))
+    }
+
+    fn log_query_error(&self, query_id: QueryId, error: &QueryError) {
+        self.finish_query(query_id, HistoryEvent::QueryError(query_id, error.clone()))
+    }
+
+    fn log_new_speculative_fiber(&self, query_id: QueryId) -> SpeculativeId {
+        do_with_events(&self.events, |events| {
+            events.new_speculative_fiber(query_id)
+        })
+    }
+
+    fn log_attempt_start(
+        &self,
+        query_id: QueryId,
+        speculative_id: Option<SpeculativeId>,
+        node_addr: SocketAddr,
+    ) -> AttemptId {
+        do_with_events(&self.events, |events| {
+            events.new_attempt(query_id, speculative_id, node_addr)
+        })
+    }
+
+    fn log_attempt_success(&self, attempt_id: AttemptId) {
+        do_with_events(&self.events, |events| {
+            events.add_attempt_event(attempt_id, HistoryEvent::AttemptSuccess(attempt_id))
+        })
+    }
+
+    fn log_attempt_error(
+        &self,
+        attempt_id: AttemptId,
+        error: &QueryError,
+        retry_decision: &RetryDecision,
+    ) {
+        do_with_events(&self.events, |events| {
+            events.add_attempt_event(
+                attempt_id,
+                HistoryEvent::AttemptError(attempt_id, error.clone(), retry_decision.clone()),
+            )
+        })
+    }
+}
+
 /// Structured representation of queries history.\
 /// HistoryCollector collects raw events which later can be converted
 /// to this pretty representation.\
-/// It has a `Display` impl which can be used for printing pretty query history.
+/// It has a `Display` impl which can be used for printing pretty query history.\
+/// With the `serde` feature enabled it implements `serde::Serialize`, which allows to export it e.g. as JSON.
+/// Time points are then serialized in the RFC 3339 format, errors and retry decisions as text.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct StructuredHistory {
     pub queries: Vec<QueryHistory>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct QueryHistory {
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_time"))]
     pub start_time: TimePoint,
     pub non_speculative_fiber: FiberHistory,
     pub speculative_fibers: Vec<FiberHistory>,
@@ -262,28 +628,65 @@ pub struct QueryHistory {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum QueryHistoryResult {
-    Success(TimePoint),
-    Error(TimePoint, QueryError),
+    Success(#[cfg_attr(feature = "serde", serde(serialize_with = "serialize_time"))] TimePoint),
+    Error(
+        #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_time"))] TimePoint,
+        #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_display"))] QueryError,
+    ),
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct FiberHistory {
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_time"))]
     pub start_time: TimePoint,
     pub attempts: Vec<AttemptHistory>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct AttemptHistory {
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_time"))]
     pub send_time: TimePoint,
     pub node_addr: SocketAddr,
     pub result: Option<AttemptResult>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum AttemptResult {
-    Success(TimePoint),
-    Error(TimePoint, QueryError, RetryDecision),
+    Success(#[cfg_attr(feature = "serde", serde(serialize_with = "serialize_time"))] TimePoint),
+    Error(
+        #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_time"))] TimePoint,
+        #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_display"))] QueryError,
+        #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_debug"))] RetryDecision,
+    ),
+}
+
+#[cfg(feature = "serde")]
+fn serialize_time<S: serde::Serializer>(
+    time: &TimePoint,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&time.to_rfc3339())
+}
+
+#[cfg(feature = "serde")]
+fn serialize_display<S: serde::Serializer>(
+    value: &impl Display,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+#[cfg(feature = "serde")]
+fn serialize_debug<S: serde::Serializer>(
+    value: &impl Debug,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&format_args!("{:?}", value))
 }
 
 impl From<&HistoryCollectorData> for StructuredHistory {
@@ -456,6 +859,7 @@ mod tests {
     use std::{
         net::{IpAddr, Ipv4Addr, SocketAddr},
         sync::Arc,
+        time::Duration,
     };
 
     use crate::{
@@ -465,7 +869,7 @@ mod tests {
 
     use super::{
         AttemptId, AttemptResult, HistoryCollector, HistoryListener, QueryHistoryResult, QueryId,
-        SpeculativeId, StructuredHistory, TimePoint,
+        RingBufferHistoryCollector, SlowQueryLogger, SpeculativeId, StructuredHistory, TimePoint,
     };
     use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
     use futures::StreamExt;
@@ -910,6 +1314,153 @@ mod tests {
         assert_eq!(displayed, format!("{}", set_one_time(history)));
     }
 
+    #[test]
+    fn ring_buffer_keeps_most_recent_queries() {
+        let history_collector = RingBufferHistoryCollector::new(2);
+
+        let mut query_ids: Vec<QueryId> = Vec::new();
+        for _ in 0..3 {
+            let query_id: QueryId = history_collector.log_query_start();
+            let attempt_id: AttemptId =
+                history_collector.log_attempt_start(query_id, None, node1_addr());
+            history_collector.log_attempt_success(attempt_id);
+            query_ids.push(query_id);
+        }
+
+        // Events of the evicted query are dropped
+        history_collector.log_query_success(query_ids[0]);
+        history_collector.log_query_error(query_ids[2], &timeout_error());
+
+        let history: StructuredHistory = history_collector.clone_structured_history();
+
+        let displayed = "Queries History:
+=== Query #0 ===
+| start_time: 2022-02-22 20:22:22 UTC
+| Non-speculative attempts:
+| - Attempt #0 sent to 127.0.0.1:19042
+|   request send time: 2022-02-22 20:22:22 UTC
+|   Success at 2022-02-22 20:22:22 UTC
+|
+| Query still running - no final result yet
+=================
+=== Query #1 ===
+| start_time: 2022-02-22 20:22:22 UTC
+| Non-speculative attempts:
+| - Attempt #0 sent to 127.0.0.1:19042
+|   request send time: 2022-02-22 20:22:22 UTC
+|   Success at 2022-02-22 20:22:22 UTC
+|
+| Query failed at 2022-02-22 20:22:22 UTC
+| Error: Timeout Error
+=================
+";
+        assert_eq!(displayed, format!("{}", set_one_time(history)));
+
+        assert_eq!(history_collector.take_structured_history().queries.len(), 2);
+        assert!(history_collector
+            .clone_structured_history()
+            .queries
+            .is_empty());
+    }
+
+    #[test]
+    fn slow_query_logger_forgets_finished_queries() {
+        let logger = SlowQueryLogger::new(Duration::from_secs(1));
+
+        let query_id: QueryId = logger.log_query_start();
+        let speculative_id: SpeculativeId = logger.log_new_speculative_fiber(query_id);
+        let attempt_id: AttemptId = logger.log_attempt_start(query_id, None, node1_addr());
+        let spec_attempt_id: AttemptId =
+            logger.log_attempt_start(query_id, Some(speculative_id), node2_addr());
+        logger.log_attempt_success(attempt_id);
+        logger.log_query_success(query_id);
+
+        // Late events of a finished speculative fiber don't bring the query back
+        logger.log_attempt_error(spec_attempt_id, &timeout_error(), &RetryDecision::DontRetry);
+        let late_attempt_id: AttemptId =
+            logger.log_attempt_start(query_id, Some(speculative_id), node3_addr());
+        logger.log_attempt_success(late_attempt_id);
+
+        let events = logger.events.lock().unwrap();
+        assert!(events.queries.is_empty());
+        assert!(events.attempt_queries.is_empty());
+    }
+
+    #[test]
+    fn slow_query_logger_bounds_unfinished_queries() {
+        let logger = SlowQueryLogger::with_max_unfinished_queries(Duration::from_secs(1), 2);
+
+        // Queries whose futures were dropped never finish
+        let query_ids: Vec<QueryId> = (0..5)
+            .map(|_| {
+                let query_id: QueryId = logger.log_query_start();
+                logger.log_attempt_start(query_id, None, node1_addr());
+                query_id
+            })
+            .collect();
+
+        {
+            let events = logger.events.lock().unwrap();
+            assert_eq!(
+                events.queries.keys().copied().collect::<Vec<QueryId>>(),
+                query_ids[3..].to_vec()
+            );
+            assert_eq!(events.attempt_queries.len(), 2);
+        }
+
+        // Finishing an evicted query doesn't bring it back
+        logger.log_query_success(query_ids[0]);
+        logger.log_query_error(query_ids[4], &timeout_error());
+        let events = logger.events.lock().unwrap();
+        assert_eq!(
+            events.queries.keys().copied().collect::<Vec<QueryId>>(),
+            vec![query_ids[3]]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_structured_history() {
+        let history_collector = HistoryCollector::new();
+
+        let query_id: QueryId = history_collector.log_query_start();
+        let attempt_id: AttemptId =
+            history_collector.log_attempt_start(query_id, None, node1_addr());
+        history_collector.log_attempt_error(
+            attempt_id,
+            &timeout_error(),
+            &RetryDecision::RetryNextNode(Consistency::Quorum),
+        );
+        history_collector.log_query_error(query_id, &timeout_error());
+
+        let history: StructuredHistory = set_one_time(history_collector.clone_structured_history());
+
+        let serialized = serde_json::json!({
+            "queries": [{
+                "start_time": "2022-02-22T20:22:22+00:00",
+                "non_speculative_fiber": {
+                    "start_time": "2022-02-22T20:22:22+00:00",
+                    "attempts": [{
+                        "send_time": "2022-02-22T20:22:22+00:00",
+                        "node_addr": "127.0.0.1:19042",
+                        "result": {
+                            "Error": [
+                                "2022-02-22T20:22:22+00:00",
+                                "Timeout Error",
+                                "RetryNextNode(Quorum)"
+                            ]
+                        }
+                    }]
+                },
+                "speculative_fibers": [],
+                "result": {
+                    "Error": ["2022-02-22T20:22:22+00:00", "Timeout Error"]
+                }
+            }]
+        });
+        assert_eq!(serde_json::to_value(&history).unwrap(), serialized);
+    }
+
     #[tokio::test]
     async fn successful_query_history() {
         let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
@@ -1014,6 +1565,45 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn session_history_listener() {
+        let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+        let session_collector = Arc::new(RingBufferHistoryCollector::new(16));
+        let session = SessionBuilder::new()
+            .known_node(uri)
+            .history_listener(session_collector.clone())
+            .build()
+            .await
+            .unwrap();
+
+        session
+            .query("SELECT * FROM system.local", ())
+            .await
+            .unwrap();
+        let mut rows_iterator = session
+            .query_iter("SELECT * FROM system.local", ())
+            .await
+            .unwrap();
+        while let Some(_row) = rows_iterator.next().await {
+            // Receive rows...
+        }
+
+        // A listener set on the statement takes precedence over the session one
+        let mut query = Query::new("SELECT * FROM system.local");
+        let statement_collector = Arc::new(HistoryCollector::new());
+        query.set_history_listener(statement_collector.clone());
+        session.query(query, ()).await.unwrap();
+
+        assert_eq!(
+            session_collector.clone_structured_history().queries.len(),
+            2
+        );
+        assert_eq!(
+            statement_collector.clone_structured_history().queries.len(),
+            1
+        );
+    }
+
     #[tokio::test]
     async fn iterator_query_history() {
         let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
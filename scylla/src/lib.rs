@@ -96,9 +96,13 @@
 pub use scylla_cql::frame;
 pub use scylla_cql::macros::{self, *};
 
+#[cfg(feature = "cloud")]
+pub mod cloud;
 pub mod history;
 pub mod routing;
 pub mod statement;
+#[cfg(feature = "tower")]
+pub mod tower;
 pub mod tracing;
 pub mod transport;
 
@@ -112,11 +116,14 @@ pub use frame::response::cql_to_rust;
 pub use frame::response::cql_to_rust::FromRow;
 
 pub use transport::caching_session::CachingSession;
-pub use transport::query_result::QueryResult;
+pub use transport::query_result::{LwtResult, QueryResult};
 pub use transport::session::{IntoTypedRows, Session, SessionConfig};
 pub use transport::session_builder::SessionBuilder;
+pub use transport::session_like::SessionLike;
 
+pub use transport::host_filter;
 pub use transport::load_balancing;
+pub use transport::reconnection_policy;
 pub use transport::retry_policy;
 pub use transport::speculative_execution;
 
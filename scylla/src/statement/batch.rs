@@ -1,3 +1,5 @@
+use bytes::Bytes;
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::history::HistoryListener;
@@ -128,6 +130,18 @@ impl Batch {
     pub fn remove_history_listener(&mut self) -> Option<Arc<dyn HistoryListener>> {
         self.config.history_listener.take()
     }
+
+    /// Sets the custom payload sent along with this statement.\
+    /// Custom payload is a map of arbitrary values which is passed to the server side
+    /// and can be read by e.g. a query handler or a proxy.
+    pub fn set_custom_payload(&mut self, custom_payload: Option<HashMap<String, Bytes>>) {
+        self.config.custom_payload = custom_payload;
+    }
+
+    /// Gets the custom payload sent along with this statement.
+    pub fn get_custom_payload(&self) -> Option<&HashMap<String, Bytes>> {
+        self.config.custom_payload.as_ref()
+    }
 }
 
 impl Default for Batch {
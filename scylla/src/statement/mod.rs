@@ -1,4 +1,6 @@
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use bytes::Bytes;
 
 use crate::transport::speculative_execution::SpeculativeExecutionPolicy;
 use crate::{history::HistoryListener, transport::retry_policy::RetryPolicy};
@@ -9,6 +11,22 @@ pub mod query;
 
 pub use crate::frame::types::{Consistency, SerialConsistency};
 
+/// Kind of the statement which is being executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum StatementKind {
+    /// Unprepared [`Query`](query::Query)
+    Query,
+    /// [`PreparedStatement`](prepared_statement::PreparedStatement)
+    Prepared,
+    /// [`Batch`](batch::Batch) of statements
+    Batch,
+    /// [`PreparedStatement`](prepared_statement::PreparedStatement) confirmed by the database
+    /// to be a lightweight transaction, see [`is_confirmed_lwt`](prepared_statement::PreparedStatement::is_confirmed_lwt)\
+    /// Unprepared queries and batches are never reported as LWT, even if they contain conditions
+    Lwt,
+}
+
 #[derive(Debug)]
 pub struct StatementConfig {
     pub consistency: Option<Consistency>,
@@ -24,6 +42,8 @@ pub struct StatementConfig {
     pub request_timeout: Option<Duration>,
 
     pub history_listener: Option<Arc<dyn HistoryListener>>,
+
+    pub custom_payload: Option<HashMap<String, Bytes>>,
 }
 
 impl Default for StatementConfig {
@@ -38,6 +58,7 @@ impl Default for StatementConfig {
             timestamp: None,
             request_timeout: None,
             history_listener: None,
+            custom_payload: None,
         }
     }
 }
@@ -51,6 +72,7 @@ impl Clone for StatementConfig {
                 .map(|policy| policy.clone_boxed()),
             speculative_execution_policy: self.speculative_execution_policy.clone(),
             history_listener: self.history_listener.clone(),
+            custom_payload: self.custom_payload.clone(),
             ..*self
         }
     }
@@ -1,13 +1,15 @@
+use arc_swap::ArcSwap;
 use bytes::{BufMut, Bytes, BytesMut};
 use smallvec::{smallvec, SmallVec};
+use std::collections::HashMap;
 use std::convert::TryInto;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use std::time::Duration;
 use thiserror::Error;
 use uuid::Uuid;
 
-use super::StatementConfig;
-use crate::frame::response::result::PreparedMetadata;
+use super::{StatementConfig, StatementKind};
+use crate::frame::response::result::{PreparedMetadata, ResultMetadata};
 use crate::frame::types::{Consistency, SerialConsistency};
 use crate::frame::value::SerializedValues;
 use crate::history::HistoryListener;
@@ -22,9 +24,15 @@ pub struct PreparedStatement {
 
     id: Bytes,
     metadata: PreparedMetadata,
+    // Shared between clones, so that metadata refreshed after a re-prepare is visible to all of them
+    result_metadata: Arc<ArcSwap<ResultMetadata>>,
+    use_cached_result_metadata: bool,
     statement: String,
     page_size: Option<i32>,
     partitioner_name: PartitionerName,
+    is_confirmed_lwt: bool,
+    // Shared between clones, allows to check whether any clone of the statement is still in use
+    liveness: Arc<()>,
 }
 
 impl Clone for PreparedStatement {
@@ -34,9 +42,13 @@ impl Clone for PreparedStatement {
             prepare_tracing_ids: Vec::new(),
             id: self.id.clone(),
             metadata: self.metadata.clone(),
+            result_metadata: self.result_metadata.clone(),
+            use_cached_result_metadata: self.use_cached_result_metadata,
             statement: self.statement.clone(),
             page_size: self.page_size,
             partitioner_name: self.partitioner_name.clone(),
+            is_confirmed_lwt: self.is_confirmed_lwt,
+            liveness: self.liveness.clone(),
         }
     }
 }
@@ -45,6 +57,7 @@ impl PreparedStatement {
     pub(crate) fn new(
         id: Bytes,
         metadata: PreparedMetadata,
+        result_metadata: ResultMetadata,
         statement: String,
         page_size: Option<i32>,
         config: StatementConfig,
@@ -52,11 +65,15 @@ impl PreparedStatement {
         Self {
             id,
             metadata,
+            result_metadata: Arc::new(ArcSwap::from_pointee(result_metadata)),
+            use_cached_result_metadata: false,
             statement,
             prepare_tracing_ids: Vec::new(),
             page_size,
             config,
             partitioner_name: Default::default(),
+            is_confirmed_lwt: false,
+            liveness: Arc::new(()),
         }
     }
 
@@ -270,11 +287,83 @@ impl PreparedStatement {
             .unwrap_or_default();
     }
 
+    /// Returns true if the database marked this statement as a lightweight transaction (LWT).\
+    /// Only Scylla marks LWT statements, for other databases this is always false.
+    ///
+    /// LWT statements are routed to replicas in the same order by every client,
+    /// which reduces contention between Paxos rounds of concurrent transactions.
+    pub fn is_confirmed_lwt(&self) -> bool {
+        self.is_confirmed_lwt
+    }
+
+    // Kind reported to policies, sampling and spans
+    pub(crate) fn statement_kind(&self) -> StatementKind {
+        if self.is_confirmed_lwt {
+            StatementKind::Lwt
+        } else {
+            StatementKind::Prepared
+        }
+    }
+
+    pub(crate) fn set_is_confirmed_lwt(&mut self, is_confirmed_lwt: bool) {
+        self.is_confirmed_lwt = is_confirmed_lwt;
+    }
+
     /// Access metadata about this prepared statement as returned by the database
     pub fn get_prepared_metadata(&self) -> &PreparedMetadata {
         &self.metadata
     }
 
+    /// Access metadata about the result of this prepared statement as returned by the database.\
+    /// The metadata is refreshed when the statement is re-prepared, e.g. after a schema change.
+    pub fn get_result_metadata(&self) -> Arc<ResultMetadata> {
+        self.result_metadata.load_full()
+    }
+
+    /// Sets whether the database should skip sending the result metadata along with every result
+    /// of this statement, the metadata returned when preparing the statement being used instead.
+    /// Disabled by default.\
+    /// This saves network bandwidth and parsing work, especially for small, frequent reads.
+    /// The cached metadata is refreshed when the number of columns in the result doesn't match
+    /// (e.g. after `SELECT *` on an altered table) or when the database invalidates the statement.
+    /// Other schema changes, like a column type changing without the statement being invalidated,
+    /// can't be detected and results would be deserialized with outdated types - enable it only
+    /// for statements whose result schema is not going to change.
+    pub fn set_use_cached_result_metadata(&mut self, use_cached_result_metadata: bool) {
+        self.use_cached_result_metadata = use_cached_result_metadata;
+    }
+
+    /// Gets whether the result metadata cached in this statement is used, see
+    /// [`set_use_cached_result_metadata`](PreparedStatement::set_use_cached_result_metadata).
+    pub fn get_use_cached_result_metadata(&self) -> bool {
+        self.use_cached_result_metadata
+    }
+
+    // Returns the metadata which can be used instead of the one sent by the database,
+    // or None if the database should send the metadata
+    pub(crate) fn cached_result_metadata(&self) -> Option<Arc<ResultMetadata>> {
+        if !self.use_cached_result_metadata {
+            return None;
+        }
+        let result_metadata = self.result_metadata.load_full();
+        // Statements which don't return rows, or whose result columns are not known
+        // in advance (e.g. LWT), have no metadata to reuse
+        if result_metadata.col_specs.is_empty() {
+            None
+        } else {
+            Some(result_metadata)
+        }
+    }
+
+    pub(crate) fn update_result_metadata(&self, result_metadata: Arc<ResultMetadata>) {
+        self.result_metadata.store(result_metadata);
+    }
+
+    // Weak reference which can be upgraded as long as any clone of this statement exists
+    pub(crate) fn downgrade(&self) -> Weak<()> {
+        Arc::downgrade(&self.liveness)
+    }
+
     /// Get the name of the partitioner used for this statement.
     pub(crate) fn get_partitioner_name(&self) -> &PartitionerName {
         &self.partitioner_name
@@ -289,6 +378,18 @@ impl PreparedStatement {
     pub fn remove_history_listener(&mut self) -> Option<Arc<dyn HistoryListener>> {
         self.config.history_listener.take()
     }
+
+    /// Sets the custom payload sent along with this statement.\
+    /// Custom payload is a map of arbitrary values which is passed to the server side
+    /// and can be read by e.g. a query handler or a proxy.
+    pub fn set_custom_payload(&mut self, custom_payload: Option<HashMap<String, Bytes>>) {
+        self.config.custom_payload = custom_payload;
+    }
+
+    /// Gets the custom payload sent along with this statement.
+    pub fn get_custom_payload(&self) -> Option<&HashMap<String, Bytes>> {
+        self.config.custom_payload.as_ref()
+    }
 }
 
 #[derive(Debug, Error, PartialEq, Eq, PartialOrd, Ord)]
@@ -2,6 +2,8 @@ use super::StatementConfig;
 use crate::frame::types::{Consistency, SerialConsistency};
 use crate::history::HistoryListener;
 use crate::transport::retry_policy::RetryPolicy;
+use bytes::Bytes;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -143,6 +145,18 @@ impl Query {
     pub fn remove_history_listener(&mut self) -> Option<Arc<dyn HistoryListener>> {
         self.config.history_listener.take()
     }
+
+    /// Sets the custom payload sent along with this statement.\
+    /// Custom payload is a map of arbitrary values which is passed to the server side
+    /// and can be read by e.g. a query handler or a proxy.
+    pub fn set_custom_payload(&mut self, custom_payload: Option<HashMap<String, Bytes>>) {
+        self.config.custom_payload = custom_payload;
+    }
+
+    /// Gets the custom payload sent along with this statement.
+    pub fn get_custom_payload(&self) -> Option<&HashMap<String, Bytes>> {
+        self.config.custom_payload.as_ref()
+    }
 }
 
 impl From<String> for Query {
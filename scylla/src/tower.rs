@@ -0,0 +1,546 @@
+//! Integration with [tower](https://docs.rs/tower), enabled by the `tower` feature.
+//!
+//! [`SessionService`] implements [`Service`] for [`Session`] and [`CachingSession`],
+//! executing [`Request`]s. The layers in this module wrap any service executing requests:
+//! * [`TimeoutLayer`] - fails requests which take too long
+//! * [`ConcurrencyLimitLayer`] - limits the number of requests executed concurrently
+//! * [`MetricsLayer`] - collects the number of requests, errors and latencies
+//!
+//! # Example
+//! ```rust
+//! # use scylla::Session;
+//! # use std::error::Error;
+//! # async fn check_only_compiles(session: Session) -> Result<(), Box<dyn Error>> {
+//! use scylla::tower::{ConcurrencyLimitLayer, MetricsLayer, Request, SessionService, TimeoutLayer};
+//! use futures::future::poll_fn;
+//! use std::time::Duration;
+//! use tower_layer::Layer;
+//! use tower_service::Service;
+//!
+//! let metrics = MetricsLayer::new();
+//! let service = SessionService::new(session);
+//! let service = TimeoutLayer::new(Duration::from_secs(5)).layer(service);
+//! let service = ConcurrencyLimitLayer::new(1024).layer(service);
+//! let mut service = metrics.layer(service);
+//!
+//! poll_fn(|cx| service.poll_ready(cx)).await?;
+//! let result = service
+//!     .call(Request::query("SELECT a, b FROM ks.tab", &[])?)
+//!     .await?;
+//!
+//! println!("Requests: {}", metrics.get_metrics().get_queries_num());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::batch::Batch;
+use crate::frame::value::{
+    BatchValues, BatchValuesIterator, SerializeValuesError, SerializedValues, ValueList,
+};
+use crate::prepared_statement::PreparedStatement;
+use crate::query::Query;
+use crate::transport::errors::QueryError;
+use crate::{CachingSession, Metrics, QueryResult, Session};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Request executed by a [`SessionService`]
+#[derive(Clone)]
+pub enum Request {
+    /// Executes an unprepared query, like [`Session::query`].\
+    /// [`CachingSession`] executes the query using the prepared statement cache.
+    Query {
+        query: Query,
+        values: SerializedValues,
+    },
+    /// Executes a prepared statement, like [`Session::execute`].\
+    /// [`CachingSession`] applies its statement configuration template to the statement.
+    Execute {
+        prepared: PreparedStatement,
+        values: SerializedValues,
+    },
+    /// Executes a batch, like [`Session::batch`]
+    Batch {
+        batch: Batch,
+        values: Vec<SerializedValues>,
+    },
+}
+
+impl Request {
+    /// Creates a request executing an unprepared query
+    pub fn query(
+        query: impl Into<Query>,
+        values: impl ValueList,
+    ) -> Result<Request, SerializeValuesError> {
+        Ok(Request::Query {
+            query: query.into(),
+            values: values.serialized()?.into_owned(),
+        })
+    }
+
+    /// Creates a request executing a prepared statement
+    pub fn execute(
+        prepared: impl Into<PreparedStatement>,
+        values: impl ValueList,
+    ) -> Result<Request, SerializeValuesError> {
+        Ok(Request::Execute {
+            prepared: prepared.into(),
+            values: values.serialized()?.into_owned(),
+        })
+    }
+
+    /// Creates a request executing a batch
+    pub fn batch(batch: Batch, values: impl BatchValues) -> Result<Request, SerializeValuesError> {
+        let mut values_iter = values.batch_values_iter();
+        let mut serialized = Vec::with_capacity(values.len());
+        while let Some(statement_values) = values_iter.next_serialized() {
+            serialized.push(statement_values?.into_owned());
+        }
+
+        Ok(Request::Batch {
+            batch,
+            values: serialized,
+        })
+    }
+}
+
+/// [`Service`] executing [`Request`]s using a [`Session`] or a [`CachingSession`].
+///
+/// The service is ready when at least one node has a working connection
+/// which can accept another request, i.e. has a free stream id.
+/// Otherwise the task is woken up when a stream id is freed or connections change.
+/// After the session is closed, the service fails with [`QueryError::SessionClosed`].
+pub struct SessionService<S> {
+    session: Arc<S>,
+    // Resolves when a connection of the session might have become available
+    availability_change: Option<BoxFuture<'static, ()>>,
+}
+
+impl<S> SessionService<S> {
+    /// Creates a service executing requests using the session
+    pub fn new(session: impl Into<Arc<S>>) -> Self {
+        Self {
+            session: session.into(),
+            availability_change: None,
+        }
+    }
+
+    /// Returns the session used by the service
+    pub fn get_session(&self) -> &Arc<S> {
+        &self.session
+    }
+
+    fn poll_session_ready(
+        &mut self,
+        session: &Session,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), QueryError>> {
+        loop {
+            if session.is_closed() {
+                self.availability_change = None;
+                return Poll::Ready(Err(QueryError::SessionClosed));
+            }
+            if session.has_available_connections() {
+                self.availability_change = None;
+                return Poll::Ready(Ok(()));
+            }
+
+            match &mut self.availability_change {
+                Some(availability_change) => match availability_change.poll_unpin(cx) {
+                    Poll::Ready(()) => self.availability_change = None,
+                    Poll::Pending => return Poll::Pending,
+                },
+                None => {
+                    // The waiter is registered by the first poll, before the availability is checked
+                    // again, so that a change in between is not missed
+                    let notify = session.get_availability_notify().clone();
+                    let mut availability_change = async move { notify.notified().await }.boxed();
+                    if availability_change.poll_unpin(cx).is_pending() {
+                        self.availability_change = Some(availability_change);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<S> Clone for SessionService<S> {
+    fn clone(&self) -> Self {
+        Self::new(self.session.clone())
+    }
+}
+
+impl Service<Request> for SessionService<Session> {
+    type Response = QueryResult;
+    type Error = QueryError;
+    type Future = BoxFuture<'static, Result<QueryResult, QueryError>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), QueryError>> {
+        let session = self.session.clone();
+        self.poll_session_ready(&session, cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let session = self.session.clone();
+        async move {
+            match request {
+                Request::Query { query, values } => session.query(query, values).await,
+                Request::Execute { prepared, values } => session.execute(&prepared, values).await,
+                Request::Batch { batch, values } => session.batch(&batch, values).await,
+            }
+        }
+        .boxed()
+    }
+}
+
+impl Service<Request> for SessionService<CachingSession> {
+    type Response = QueryResult;
+    type Error = QueryError;
+    type Future = BoxFuture<'static, Result<QueryResult, QueryError>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), QueryError>> {
+        let session = self.session.clone();
+        self.poll_session_ready(&session.session, cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let session = self.session.clone();
+        async move {
+            match request {
+                Request::Query { query, values } => session.execute(query, values).await,
+                Request::Execute { prepared, values } => {
+                    let prepared = session.apply_statement_config_to_prepared(prepared);
+                    session.session.execute(&prepared, values).await
+                }
+                Request::Batch { batch, values } => session.batch(&batch, values).await,
+            }
+        }
+        .boxed()
+    }
+}
+
+/// [`Layer`] failing requests which don't complete within the timeout
+/// with [`QueryError::RequestTimeout`]
+#[derive(Clone, Copy, Debug)]
+pub struct TimeoutLayer {
+    timeout: Duration,
+}
+
+impl TimeoutLayer {
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = Timeout<S>;
+
+    fn layer(&self, inner: S) -> Timeout<S> {
+        Timeout {
+            inner,
+            timeout: self.timeout,
+        }
+    }
+}
+
+/// Service created by [`TimeoutLayer`]
+#[derive(Clone, Debug)]
+pub struct Timeout<S> {
+    inner: S,
+    timeout: Duration,
+}
+
+impl<S, R> Service<R> for Timeout<S>
+where
+    S: Service<R, Error = QueryError>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = QueryError;
+    type Future = BoxFuture<'static, Result<S::Response, QueryError>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), QueryError>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let response = self.inner.call(request);
+        let timeout = self.timeout;
+        async move {
+            match tokio::time::timeout(timeout, response).await {
+                Ok(result) => result,
+                Err(_) => Err(QueryError::RequestTimeout(format!(
+                    "Request took longer than {}ms",
+                    timeout.as_millis()
+                ))),
+            }
+        }
+        .boxed()
+    }
+}
+
+/// [`Layer`] limiting the number of requests executed concurrently.\
+/// Services created by the same layer share the limit. A service is not ready
+/// until a request can be executed without exceeding the limit.
+#[derive(Clone, Debug)]
+pub struct ConcurrencyLimitLayer {
+    semaphore: Arc<Semaphore>,
+}
+
+impl ConcurrencyLimitLayer {
+    pub fn new(max_concurrent_requests: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent_requests)),
+        }
+    }
+}
+
+impl<S> Layer<S> for ConcurrencyLimitLayer {
+    type Service = ConcurrencyLimit<S>;
+
+    fn layer(&self, inner: S) -> ConcurrencyLimit<S> {
+        ConcurrencyLimit {
+            inner,
+            semaphore: self.semaphore.clone(),
+            permit: None,
+            acquire: None,
+        }
+    }
+}
+
+/// Service created by [`ConcurrencyLimitLayer`]
+pub struct ConcurrencyLimit<S> {
+    inner: S,
+    semaphore: Arc<Semaphore>,
+    // Acquired in poll_ready, released when the response future completes
+    permit: Option<OwnedSemaphorePermit>,
+    acquire: Option<BoxFuture<'static, OwnedSemaphorePermit>>,
+}
+
+impl<S: Clone> Clone for ConcurrencyLimit<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            semaphore: self.semaphore.clone(),
+            permit: None,
+            acquire: None,
+        }
+    }
+}
+
+impl<S, R> Service<R> for ConcurrencyLimit<S>
+where
+    S: Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<S::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        if self.permit.is_none() {
+            let semaphore = self.semaphore.clone();
+            let acquire = self.acquire.get_or_insert_with(|| {
+                async move {
+                    // The semaphore is never closed
+                    semaphore.acquire_owned().await.unwrap()
+                }
+                .boxed()
+            });
+            match acquire.poll_unpin(cx) {
+                Poll::Ready(permit) => {
+                    self.permit = Some(permit);
+                    self.acquire = None;
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let permit = self
+            .permit
+            .take()
+            .expect("ConcurrencyLimit::call called without poll_ready returning Ready");
+        let response = self.inner.call(request);
+        async move {
+            let result = response.await;
+            drop(permit);
+            result
+        }
+        .boxed()
+    }
+}
+
+/// [`Layer`] collecting the number of requests, errors and request latencies.\
+/// Services created by the same layer share the [`Metrics`].
+#[derive(Clone, Debug, Default)]
+pub struct MetricsLayer {
+    metrics: Arc<Metrics>,
+}
+
+impl MetricsLayer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the metrics collected by the services created by this layer.
+    /// Requests and errors are counted as nonpaged queries.
+    pub fn get_metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> MetricsService<S> {
+        MetricsService {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+/// Service created by [`MetricsLayer`]
+#[derive(Clone, Debug)]
+pub struct MetricsService<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S, R> Service<R> for MetricsService<S>
+where
+    S: Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<S::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let metrics = self.metrics.clone();
+        let start = Instant::now();
+        metrics.inc_total_nonpaged_queries();
+        let response = self.inner.call(request);
+        async move {
+            let result = response.await;
+            match &result {
+                Ok(_) => {
+                    let _ = metrics.log_query_latency(start.elapsed().as_millis() as u64);
+                }
+                Err(_) => metrics.inc_failed_nonpaged_queries(),
+            }
+            result
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConcurrencyLimitLayer, MetricsLayer, TimeoutLayer};
+    use crate::transport::errors::QueryError;
+    use futures::future::{self, BoxFuture};
+    use futures::FutureExt;
+    use std::future::Future;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use tower_layer::Layer;
+    use tower_service::Service;
+
+    // Service which responds after the requested number of milliseconds,
+    // failing for requests of 0 milliseconds
+    #[derive(Clone)]
+    struct SleepService;
+
+    impl Service<u64> for SleepService {
+        type Response = ();
+        type Error = QueryError;
+        type Future = BoxFuture<'static, Result<(), QueryError>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), QueryError>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, millis: u64) -> Self::Future {
+            async move {
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                if millis == 0 {
+                    Err(QueryError::TimeoutError)
+                } else {
+                    Ok(())
+                }
+            }
+            .boxed()
+        }
+    }
+
+    fn ready<S: Service<u64>>(service: &mut S) -> impl Future<Output = Result<(), S::Error>> + '_ {
+        future::poll_fn(move |cx| service.poll_ready(cx))
+    }
+
+    #[tokio::test]
+    async fn timeout_layer() {
+        let mut service = TimeoutLayer::new(Duration::from_millis(50)).layer(SleepService);
+
+        ready(&mut service).await.unwrap();
+        assert!(service.call(1).await.is_ok());
+
+        ready(&mut service).await.unwrap();
+        assert!(matches!(
+            service.call(10_000).await,
+            Err(QueryError::RequestTimeout(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn concurrency_limit_layer() {
+        let layer = ConcurrencyLimitLayer::new(1);
+        let mut first = layer.layer(SleepService);
+        let mut second = layer.layer(SleepService);
+
+        ready(&mut first).await.unwrap();
+        let response = first.call(10);
+
+        // The only permit is held until the response completes
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        assert!(second.poll_ready(&mut cx).is_pending());
+
+        response.await.unwrap();
+        ready(&mut second).await.unwrap();
+        second.call(1).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn metrics_layer() {
+        let layer = MetricsLayer::new();
+        let mut service = layer.layer(SleepService);
+
+        for millis in [1, 1, 0].iter() {
+            ready(&mut service).await.unwrap();
+            let _ = service.call(*millis).await;
+        }
+
+        let metrics = layer.get_metrics();
+        assert_eq!(metrics.get_queries_num(), 3);
+        assert_eq!(metrics.get_errors_num(), 1);
+
+        // Requests are counted when they are sent, even if the response is never awaited
+        ready(&mut service).await.unwrap();
+        drop(service.call(1));
+        assert_eq!(metrics.get_queries_num(), 4);
+    }
+}
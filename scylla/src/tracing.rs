@@ -1,4 +1,4 @@
-use crate::statement::Consistency;
+use crate::statement::{Consistency, StatementKind};
 use itertools::Itertools;
 use std::collections::HashMap;
 use std::net::IpAddr;
@@ -8,6 +8,9 @@ use uuid::Uuid;
 
 use crate::cql_to_rust::{FromRow, FromRowError};
 use crate::frame::response::result::Row;
+use crate::transport::errors::QueryError;
+use crate::IntoTypedRows;
+use crate::QueryResult;
 
 /// Tracing info retrieved from `system_traces.sessions`
 /// with all events from `system_traces.events`
@@ -70,6 +73,93 @@ impl Default for GetTracingConfig {
     }
 }
 
+/// Decides which requests are traced automatically.\
+/// Requests which have tracing enabled explicitly (e.g. with [`Query::set_tracing`](crate::query::Query::set_tracing))
+/// are not affected - their tracing info still has to be fetched with
+/// [`Session::get_tracing_info`](crate::Session::get_tracing_info).
+///
+/// Sampling is applied to `Session::query`, `Session::execute` and `Session::batch`,
+/// paged iterators are never sampled.
+pub trait TracingSamplingPolicy: std::fmt::Debug + Send + Sync {
+    /// Decides whether tracing should be enabled for a request which is about to be sent
+    fn should_trace(&self, info: &SamplingInfo) -> bool;
+
+    /// Decides whether tracing info of a traced request, which finished successfully
+    /// after `latency`, should be fetched and passed to the [`TraceSink`].\
+    /// By default all traces are collected.
+    fn should_collect(&self, _info: &SamplingInfo, _latency: Duration) -> bool {
+        true
+    }
+}
+
+/// Information about a request passed to [`TracingSamplingPolicy`]
+#[derive(Debug, Clone, Copy)]
+pub struct SamplingInfo<'a> {
+    /// Kind of the statement
+    pub statement_kind: StatementKind,
+    /// Text of the statement, for batches - text of the first statement
+    pub statement: &'a str,
+}
+
+/// Traces a random fraction of requests
+#[derive(Debug, Clone)]
+pub struct ProbabilisticTracingSampling {
+    /// Probability of tracing a request, between 0.0 and 1.0
+    pub probability: f64,
+}
+
+impl TracingSamplingPolicy for ProbabilisticTracingSampling {
+    fn should_trace(&self, _info: &SamplingInfo) -> bool {
+        rand::random::<f64>() < self.probability
+    }
+}
+
+/// Collects traces of slow requests only.
+///
+/// Whether a request will be slow is not known upfront, so a fraction of requests
+/// is traced (all of them with `probability` equal to 1.0), but only traces of requests
+/// which took at least `threshold` are fetched and passed to the [`TraceSink`].\
+/// Keep in mind that tracing puts additional load on the cluster, even if the trace is never fetched.
+#[derive(Debug, Clone)]
+pub struct SlowQueryTracingSampling {
+    /// Minimum latency of a request whose trace is collected
+    pub threshold: Duration,
+    /// Probability of tracing a request, between 0.0 and 1.0
+    pub probability: f64,
+}
+
+impl TracingSamplingPolicy for SlowQueryTracingSampling {
+    fn should_trace(&self, _info: &SamplingInfo) -> bool {
+        rand::random::<f64>() < self.probability
+    }
+
+    fn should_collect(&self, _info: &SamplingInfo, latency: Duration) -> bool {
+        latency >= self.threshold
+    }
+}
+
+/// Trace of a sampled request, fetched in the background
+#[derive(Debug, Clone)]
+pub struct CollectedTrace {
+    pub tracing_id: Uuid,
+    pub statement_kind: StatementKind,
+    /// Text of the statement, for batches - text of the first statement
+    pub statement: String,
+    /// Client-side latency of the request
+    pub latency: Duration,
+    pub tracing_info: TracingInfo,
+}
+
+/// Receives traces of requests sampled by [`TracingSamplingPolicy`].\
+/// Methods are called from a background task of the `Session`, they should not block.
+pub trait TraceSink: std::fmt::Debug + Send + Sync {
+    /// Called with tracing info fetched for a sampled request
+    fn on_trace(&self, trace: CollectedTrace);
+
+    /// Called when tracing info of a sampled request could not be fetched
+    fn on_fetch_error(&self, _tracing_id: Uuid, _error: QueryError) {}
+}
+
 // A query used to query TracingInfo from system_traces.sessions
 pub(crate) const TRACES_SESSION_QUERY_STR: &str =
     "SELECT client, command, coordinator, duration, parameters, request, started_at \
@@ -80,6 +170,51 @@ pub(crate) const TRACES_EVENTS_QUERY_STR: &str =
     "SELECT event_id, activity, source, source_elapsed, thread \
     FROM system_traces.events WHERE session_id = ?";
 
+// Builds TracingInfo from results of TRACES_SESSION_QUERY_STR and TRACES_EVENTS_QUERY_STR
+// If the queries return 0 rows then returns None - the information didn't reach the node yet
+pub(crate) fn parse_tracing_info(
+    traces_session_res: QueryResult,
+    traces_events_res: QueryResult,
+) -> Result<Option<TracingInfo>, QueryError> {
+    // Get tracing info
+    let tracing_info_row_res: Option<Result<TracingInfo, _>> = traces_session_res
+        .rows
+        .ok_or(QueryError::ProtocolError(
+            "Response to system_traces.sessions query was not Rows",
+        ))?
+        .into_typed::<TracingInfo>()
+        .next();
+
+    let mut tracing_info: TracingInfo = match tracing_info_row_res {
+        Some(tracing_info_row_res) => tracing_info_row_res.map_err(|_| {
+            QueryError::ProtocolError("Columns from system_traces.session have an unexpected type")
+        })?,
+        None => return Ok(None),
+    };
+
+    // Get tracing events
+    let tracing_event_rows = traces_events_res
+        .rows
+        .ok_or(QueryError::ProtocolError(
+            "Response to system_traces.events query was not Rows",
+        ))?
+        .into_typed::<TracingEvent>();
+
+    for event in tracing_event_rows {
+        let tracing_event: TracingEvent = event.map_err(|_| {
+            QueryError::ProtocolError("Columns from system_traces.events have an unexpected type")
+        })?;
+
+        tracing_info.events.push(tracing_event);
+    }
+
+    if tracing_info.events.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(tracing_info))
+}
+
 // Converts a row received by performing TRACES_SESSION_QUERY_STR to TracingInfo
 impl FromRow for TracingInfo {
     fn from_row(row: Row) -> Result<TracingInfo, FromRowError> {
@@ -127,3 +262,35 @@ impl FromRow for TracingEvent {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: SamplingInfo = SamplingInfo {
+        statement_kind: StatementKind::Query,
+        statement: "SELECT * FROM ks.t",
+    };
+
+    #[test]
+    fn probabilistic_sampling_bounds() {
+        let never = ProbabilisticTracingSampling { probability: 0.0 };
+        let always = ProbabilisticTracingSampling { probability: 1.0 };
+        for _ in 0..100 {
+            assert!(!never.should_trace(&INFO));
+            assert!(always.should_trace(&INFO));
+        }
+        assert!(always.should_collect(&INFO, Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn slow_query_sampling_collects_slow_requests_only() {
+        let policy = SlowQueryTracingSampling {
+            threshold: Duration::from_millis(100),
+            probability: 1.0,
+        };
+        assert!(policy.should_trace(&INFO));
+        assert!(!policy.should_collect(&INFO, Duration::from_millis(99)));
+        assert!(policy.should_collect(&INFO, Duration::from_millis(100)));
+    }
+}
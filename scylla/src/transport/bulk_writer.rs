@@ -0,0 +1,487 @@
+//! Writing large amounts of rows, grouped by the replica and shard owning them
+
+use std::collections::HashMap;
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::join_all;
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::trace;
+
+use crate::batch::{Batch, BatchStatement, BatchType};
+use crate::frame::value::{SerializedValues, ValueList};
+use crate::routing::{Shard, Token};
+use crate::statement::prepared_statement::PreparedStatement;
+use crate::transport::errors::QueryError;
+use crate::transport::session::Session;
+
+/// How rows of one replica and shard are sent to the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushMode {
+    /// All rows are sent in a single unlogged batch.\
+    /// If the batch fails, all of its rows are reported as failed.
+    UnloggedBatch,
+    /// Every row is sent in a separate EXECUTE request, all of them at once.\
+    /// Requests are pipelined on the connection to the replica's shard and fail independently.
+    Pipelined,
+}
+
+/// Called after every flush, see [`FlushReport`]
+pub type FlushCallback = Arc<dyn Fn(&FlushReport) + Send + Sync>;
+
+/// Configuration of a [`BulkWriter`]
+#[derive(Clone)]
+pub struct BulkWriterConfig {
+    /// How buffered rows are sent\
+    /// Default: [`FlushMode::UnloggedBatch`]
+    pub flush_mode: FlushMode,
+
+    /// Rows of a replica and shard are flushed once there are this many of them\
+    /// Default: 64
+    pub max_flush_rows: usize,
+
+    /// Rows of a replica and shard are flushed once their serialized values take this many bytes\
+    /// Default: 64 KiB
+    pub max_flush_bytes: usize,
+
+    /// All buffered rows are flushed at least this often, even if the size limits weren't reached\
+    /// Default: 100ms
+    pub flush_interval: Duration,
+
+    /// Maximum number of flushes running at the same time.\
+    /// When it's reached, [`BulkWriter::write`] waits until one of them finishes.\
+    /// Zero is treated as one, values above `u32::MAX` as `u32::MAX`.\
+    /// Default: 64
+    pub max_concurrent_flushes: usize,
+
+    /// Number of rows that can wait to be grouped before [`BulkWriter::write`] starts waiting\
+    /// Default: 1024
+    pub queue_capacity: usize,
+
+    /// Called after every flush with the number of written rows and the failed ones.\
+    /// May be called from many flushes at the same time.\
+    /// Default: None
+    pub on_flush: Option<FlushCallback>,
+}
+
+impl Default for BulkWriterConfig {
+    fn default() -> Self {
+        Self {
+            flush_mode: FlushMode::UnloggedBatch,
+            max_flush_rows: 64,
+            max_flush_bytes: 64 * 1024,
+            flush_interval: Duration::from_millis(100),
+            max_concurrent_flushes: 64,
+            queue_capacity: 1024,
+            on_flush: None,
+        }
+    }
+}
+
+/// Rows written so far by a [`BulkWriter`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BulkWriterProgress {
+    /// Number of rows written successfully
+    pub written_rows: u64,
+    /// Number of rows which failed to be written
+    pub failed_rows: u64,
+}
+
+/// A row which couldn't be written
+#[derive(Debug)]
+pub struct FailedRow {
+    /// Number of the row, as returned by [`BulkWriter::write`]
+    pub row_number: u64,
+    /// Values of the row, can be used to retry it
+    pub values: SerializedValues,
+    pub error: QueryError,
+}
+
+/// Passed to [`BulkWriterConfig::on_flush`] after every flush
+#[derive(Debug)]
+pub struct FlushReport<'a> {
+    /// Number of rows written successfully in this flush
+    pub written_rows: usize,
+    /// Rows which failed in this flush
+    pub failed_rows: &'a [FailedRow],
+    /// Progress of the whole writer, including this flush
+    pub progress: BulkWriterProgress,
+}
+
+/// Writes lots of rows with a single prepared statement, e.g. during data migrations.
+///
+/// Rows are buffered and grouped by the replica and shard owning their partition,
+/// computed with [`PreparedStatement::compute_partition_key`] and
+/// [`ClusterData::get_token_endpoints`](crate::transport::ClusterData::get_token_endpoints).
+/// Each group is flushed when it reaches [`max_flush_rows`](BulkWriterConfig::max_flush_rows)
+/// or [`max_flush_bytes`](BulkWriterConfig::max_flush_bytes), and all groups are flushed
+/// every [`flush_interval`](BulkWriterConfig::flush_interval).
+///
+/// At most [`max_concurrent_flushes`](BulkWriterConfig::max_concurrent_flushes) flushes run at once.
+/// When the database can't keep up, [`write`](BulkWriter::write) waits, so memory usage stays bounded.
+///
+/// Failed rows don't stop the writer, they are reported through [`on_flush`](BulkWriterConfig::on_flush)
+/// and counted in [`BulkWriterProgress`].
+///
+/// # Example
+/// ```rust
+/// # use scylla::Session;
+/// # use std::error::Error;
+/// # use std::sync::Arc;
+/// # async fn check_only_compiles(session: Arc<Session>) -> Result<(), Box<dyn Error>> {
+/// use scylla::transport::bulk_writer::{BulkWriter, BulkWriterConfig};
+///
+/// let prepared = session.prepare("INSERT INTO ks.tab (a, b) VALUES(?, ?)").await?;
+/// let writer = BulkWriter::new(session, prepared, BulkWriterConfig::default());
+///
+/// for i in 0..1_000_000_i32 {
+///     writer.write((i, 2 * i)).await?;
+/// }
+///
+/// let progress = writer.finish().await;
+/// println!("Written {}, failed {}", progress.written_rows, progress.failed_rows);
+/// # Ok(())
+/// # }
+/// ```
+pub struct BulkWriter {
+    session: Arc<Session>,
+    prepared: Arc<PreparedStatement>,
+    sender: mpsc::Sender<Message>,
+    next_row_number: AtomicU64,
+    progress: Arc<ProgressCounters>,
+    worker: JoinHandle<()>,
+}
+
+struct Row {
+    number: u64,
+    values: SerializedValues,
+    token: Option<Token>,
+}
+
+enum Message {
+    Row(Row),
+    Flush(oneshot::Sender<()>),
+}
+
+#[derive(Default)]
+struct ProgressCounters {
+    written_rows: AtomicU64,
+    failed_rows: AtomicU64,
+}
+
+impl ProgressCounters {
+    fn add(&self, written_rows: usize, failed_rows: usize) -> BulkWriterProgress {
+        BulkWriterProgress {
+            written_rows: self
+                .written_rows
+                .fetch_add(written_rows as u64, Ordering::Relaxed)
+                + written_rows as u64,
+            failed_rows: self
+                .failed_rows
+                .fetch_add(failed_rows as u64, Ordering::Relaxed)
+                + failed_rows as u64,
+        }
+    }
+
+    fn get(&self) -> BulkWriterProgress {
+        self.add(0, 0)
+    }
+}
+
+impl BulkWriter {
+    /// Creates a writer executing `prepared` for every row.\
+    /// Must be called within a tokio runtime, as it spawns a task grouping and flushing the rows.
+    pub fn new(
+        session: Arc<Session>,
+        prepared: PreparedStatement,
+        mut config: BulkWriterConfig,
+    ) -> Self {
+        // Zero would never allow any flush, and waiting for all flushes acquires the permits at once,
+        // which is limited to u32::MAX
+        config.max_concurrent_flushes = config.max_concurrent_flushes.clamp(1, u32::MAX as usize);
+
+        let prepared = Arc::new(prepared);
+        let progress = Arc::new(ProgressCounters::default());
+        let (sender, receiver) = mpsc::channel(config.queue_capacity.max(1));
+
+        let worker = BulkWriterWorker {
+            session: session.clone(),
+            prepared: prepared.clone(),
+            progress: progress.clone(),
+            flush_permits: Arc::new(Semaphore::new(config.max_concurrent_flushes)),
+            groups: HashMap::new(),
+            config,
+        };
+
+        Self {
+            session,
+            prepared,
+            sender,
+            next_row_number: AtomicU64::new(0),
+            progress,
+            worker: tokio::spawn(worker.work(receiver)),
+        }
+    }
+
+    /// Adds a row to the buffer and returns its number, which identifies the row in [`FailedRow`].
+    ///
+    /// Waits if too many rows are waiting to be written.
+    /// Returns an error only if the values couldn't be serialized or routed,
+    /// errors of writing the row are reported through [`BulkWriterConfig::on_flush`].
+    pub async fn write(&self, values: impl ValueList) -> Result<u64, QueryError> {
+        let values = values.serialized()?.into_owned();
+        let token = self.session.calculate_token(&self.prepared, &values)?;
+        let number = self.next_row_number.fetch_add(1, Ordering::Relaxed);
+
+        self.send(Message::Row(Row {
+            number,
+            values,
+            token,
+        }))
+        .await?;
+        Ok(number)
+    }
+
+    /// Flushes all buffered rows and waits until all flushes finish
+    pub async fn flush(&self) -> Result<(), QueryError> {
+        let (flushed_sender, flushed_receiver) = oneshot::channel();
+        self.send(Message::Flush(flushed_sender)).await?;
+        flushed_receiver.await.map_err(|_| worker_stopped_error())
+    }
+
+    /// Returns the number of rows written and failed so far
+    pub fn progress(&self) -> BulkWriterProgress {
+        self.progress.get()
+    }
+
+    /// Flushes all buffered rows, waits until they are written and returns the final progress
+    pub async fn finish(self) -> BulkWriterProgress {
+        drop(self.sender);
+        if let Err(err) = self.worker.await {
+            // The worker is never cancelled, so it could only panic
+            std::panic::resume_unwind(err.into_panic());
+        }
+        self.progress.get()
+    }
+
+    async fn send(&self, message: Message) -> Result<(), QueryError> {
+        self.sender
+            .send(message)
+            .await
+            .map_err(|_| worker_stopped_error())
+    }
+}
+
+fn worker_stopped_error() -> QueryError {
+    QueryError::IoError(Arc::new(std::io::Error::new(
+        std::io::ErrorKind::BrokenPipe,
+        "BulkWriter worker stopped",
+    )))
+}
+
+// Rows are grouped by the primary replica of their token and the shard owning it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct GroupKey {
+    replica: Option<SocketAddr>,
+    shard: Option<Shard>,
+}
+
+#[derive(Default)]
+struct Group {
+    rows: Vec<Row>,
+    bytes: usize,
+}
+
+struct BulkWriterWorker {
+    session: Arc<Session>,
+    prepared: Arc<PreparedStatement>,
+    config: BulkWriterConfig,
+    progress: Arc<ProgressCounters>,
+    flush_permits: Arc<Semaphore>,
+    groups: HashMap<GroupKey, Group>,
+}
+
+impl BulkWriterWorker {
+    async fn work(mut self, mut receiver: mpsc::Receiver<Message>) {
+        // Zero period is not allowed by tokio
+        let flush_interval = self.config.flush_interval.max(Duration::from_millis(1));
+        let mut flush_interval = tokio::time::interval(flush_interval);
+        // Flushes can be delayed by backpressure, missed ticks shouldn't flush tiny groups in a burst
+        flush_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                message = receiver.recv() => match message {
+                    Some(Message::Row(row)) => self.add_row(row).await,
+                    Some(Message::Flush(flushed_sender)) => {
+                        self.flush_all().await;
+                        self.wait_for_flushes().await;
+                        let _ = flushed_sender.send(());
+                    }
+                    // The writer was finished
+                    None => break,
+                },
+                _ = flush_interval.tick() => self.flush_all().await,
+            }
+        }
+
+        self.flush_all().await;
+        self.wait_for_flushes().await;
+    }
+
+    fn group_key(&self, token: Option<Token>) -> GroupKey {
+        let token = match token {
+            Some(token) => token,
+            None => {
+                return GroupKey {
+                    replica: None,
+                    shard: None,
+                }
+            }
+        };
+
+        let cluster_data = self.session.get_cluster_data();
+        let replicas = match self.prepared.get_keyspace_name() {
+            Some(keyspace) => cluster_data.get_token_endpoints(keyspace, token),
+            None => Vec::new(),
+        };
+        match replicas.first() {
+            Some(replica) => GroupKey {
+                replica: Some(replica.address),
+                shard: replica.sharder().map(|sharder| sharder.shard_of(token)),
+            },
+            None => GroupKey {
+                replica: None,
+                shard: None,
+            },
+        }
+    }
+
+    async fn add_row(&mut self, row: Row) {
+        let key = self.group_key(row.token);
+        let group = self.groups.entry(key).or_default();
+        group.bytes += row.values.size();
+        group.rows.push(row);
+
+        if group.rows.len() >= self.config.max_flush_rows
+            || group.bytes >= self.config.max_flush_bytes
+        {
+            let rows = mem::take(&mut group.rows);
+            group.bytes = 0;
+            self.flush(rows).await;
+        }
+    }
+
+    async fn flush_all(&mut self) {
+        let groups: Vec<Vec<Row>> = self
+            .groups
+            .values_mut()
+            .filter(|group| !group.rows.is_empty())
+            .map(|group| {
+                group.bytes = 0;
+                mem::take(&mut group.rows)
+            })
+            .collect();
+
+        for rows in groups {
+            self.flush(rows).await;
+        }
+    }
+
+    // Waits for a free flush permit first, which stops receiving new rows when the database can't keep up
+    async fn flush(&self, rows: Vec<Row>) {
+        let permit = self
+            .flush_permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("flush permits are never closed");
+
+        let session = self.session.clone();
+        let prepared = self.prepared.clone();
+        let flush_mode = self.config.flush_mode;
+        let progress = self.progress.clone();
+        let on_flush = self.config.on_flush.clone();
+
+        tokio::spawn(async move {
+            trace!("Flushing {} rows", rows.len());
+            let rows_count = rows.len();
+            let failed_rows = flush_rows(&session, &prepared, flush_mode, rows).await;
+
+            let written_rows = rows_count - failed_rows.len();
+            let progress = progress.add(written_rows, failed_rows.len());
+            if let Some(on_flush) = on_flush {
+                on_flush(&FlushReport {
+                    written_rows,
+                    failed_rows: &failed_rows,
+                    progress,
+                });
+            }
+            drop(permit);
+        });
+    }
+
+    async fn wait_for_flushes(&self) {
+        // Clamped to u32::MAX when the writer was created
+        let permits_count = self.config.max_concurrent_flushes as u32;
+        let _all_permits = self
+            .flush_permits
+            .acquire_many(permits_count)
+            .await
+            .expect("flush permits are never closed");
+    }
+}
+
+// Returns the rows which failed
+async fn flush_rows(
+    session: &Session,
+    prepared: &PreparedStatement,
+    flush_mode: FlushMode,
+    rows: Vec<Row>,
+) -> Vec<FailedRow> {
+    match flush_mode {
+        FlushMode::UnloggedBatch => {
+            let statements = vec![BatchStatement::PreparedStatement(prepared.clone()); rows.len()];
+            let mut batch = Batch::new_with_statements(BatchType::Unlogged, statements);
+            batch.config = prepared.config.clone();
+            let (row_numbers, values): (Vec<u64>, Vec<SerializedValues>) =
+                rows.into_iter().map(|row| (row.number, row.values)).unzip();
+
+            match session.batch(&batch, &values).await {
+                Ok(_) => Vec::new(),
+                Err(error) => row_numbers
+                    .into_iter()
+                    .zip(values)
+                    .map(|(row_number, values)| FailedRow {
+                        row_number,
+                        values,
+                        error: error.clone(),
+                    })
+                    .collect(),
+            }
+        }
+        FlushMode::Pipelined => {
+            let results = join_all(
+                rows.iter()
+                    .map(|row| session.execute(prepared, &row.values)),
+            )
+            .await;
+
+            rows.into_iter()
+                .zip(results)
+                .filter_map(|(row, result)| match result {
+                    Ok(_) => None,
+                    Err(error) => Some(FailedRow {
+                        row_number: row.number,
+                        values: row.values,
+                        error,
+                    }),
+                })
+                .collect()
+        }
+    }
+}
@@ -1,34 +1,229 @@
 use crate::batch::{Batch, BatchStatement};
+use crate::frame::response::event::SchemaChangeEvent;
 use crate::frame::value::{BatchValues, ValueList};
 use crate::prepared_statement::PreparedStatement;
 use crate::query::Query;
+use crate::statement::{Consistency, SerialConsistency, StatementConfig};
 use crate::transport::errors::QueryError;
 use crate::transport::iterator::RowIterator;
-use crate::{QueryResult, Session};
+use crate::transport::paging_state::PagingState;
+use crate::transport::ClusterData;
+use crate::{Metrics, QueryResult, Session};
 use bytes::Bytes;
-use dashmap::DashMap;
 use futures::future::try_join_all;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tracing::debug;
 
 /// Provides auto caching while executing queries
 #[derive(Debug)]
 pub struct CachingSession {
     pub session: Session,
     /// The prepared statement cache size
-    /// If a prepared statement is added while the limit is reached, the oldest prepared statement
-    /// is removed from the cache
+    /// If a prepared statement is added while the limit is reached, the least recently used
+    /// prepared statement is removed from the cache
     pub max_capacity: usize,
-    pub cache: DashMap<String, PreparedStatement>,
+    /// Time after which a cached prepared statement is prepared again
+    ttl: Option<Duration>,
+    /// Configuration used for the options which are not set on the executed query
+    statement_config: StatementConfig,
+    /// Idempotence and tracing set on all executed statements, `None` keeps the ones of the statement
+    idempotence: Option<bool>,
+    tracing: Option<bool>,
+    cache: Mutex<StatementCache>,
+    metrics: CacheMetrics,
 }
 
 impl CachingSession {
     pub fn from(session: Session, cache_size: usize) -> Self {
+        let schema_changes = session.subscribe_schema_changes();
         Self {
             session,
             max_capacity: cache_size,
-            cache: Default::default(),
+            ttl: None,
+            statement_config: StatementConfig::default(),
+            idempotence: None,
+            tracing: None,
+            cache: Mutex::new(StatementCache::new(schema_changes)),
+            metrics: Default::default(),
         }
     }
 
+    /// Sets the time after which a cached prepared statement expires and is prepared again.
+    /// By default cached statements don't expire.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Sets the configuration template of the executed statements.
+    ///
+    /// Options which are not set on the executed query (consistency, serial consistency,
+    /// timestamp, request timeout, policies, history listener and custom payload) are taken
+    /// from the template. Tracing and idempotence enabled in the template are enabled for all
+    /// statements, which is the same as calling [`with_tracing`](CachingSession::with_tracing)
+    /// and [`with_idempotence`](CachingSession::with_idempotence) with `Some(true)`.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use scylla::{CachingSession, Session};
+    /// # fn example(session: Session) {
+    /// use scylla::statement::{Consistency, StatementConfig};
+    /// use std::time::Duration;
+    ///
+    /// let mut config = StatementConfig::default();
+    /// config.consistency = Some(Consistency::Quorum);
+    /// config.request_timeout = Some(Duration::from_secs(5));
+    ///
+    /// let session = CachingSession::from(session, 100).with_statement_config(config);
+    /// # }
+    /// ```
+    pub fn with_statement_config(mut self, config: StatementConfig) -> Self {
+        // Flags which are not enabled are treated as not set
+        self.idempotence = if config.is_idempotent {
+            Some(true)
+        } else {
+            None
+        };
+        self.tracing = if config.tracing { Some(true) } else { None };
+        self.statement_config = config;
+        self
+    }
+
+    /// Sets idempotence of all executed statements, overriding the one set on the statement.\
+    /// `None`, the default, keeps the idempotence of the statement.
+    pub fn with_idempotence(mut self, is_idempotent: Option<bool>) -> Self {
+        self.idempotence = is_idempotent;
+        self
+    }
+
+    /// Enables or disables tracing of all executed statements, overriding the statement setting.\
+    /// `None`, the default, keeps the tracing setting of the statement.
+    pub fn with_tracing(mut self, tracing: Option<bool>) -> Self {
+        self.tracing = tracing;
+        self
+    }
+
+    /// Returns the configuration template of the executed statements
+    pub fn get_statement_config(&self) -> &StatementConfig {
+        &self.statement_config
+    }
+
+    /// Returns the maximal number of cached prepared statements
+    pub fn get_max_capacity(&self) -> usize {
+        self.max_capacity
+    }
+
+    /// Returns the time after which a cached prepared statement expires
+    pub fn get_ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    /// Returns the number of prepared statements in the cache
+    pub fn get_cache_size(&self) -> usize {
+        self.cache.lock().unwrap().entries.len()
+    }
+
+    /// Returns a snapshot of the cached prepared statements, by their contents.\
+    /// Statements with the same contents, but executed with a different configuration,
+    /// are returned once.
+    #[deprecated(
+        note = "the cache is no longer a public field, use `is_cached` and `get_cache_size` instead"
+    )]
+    pub fn cache(&self) -> HashMap<String, PreparedStatement> {
+        self.cache
+            .lock()
+            .unwrap()
+            .entries
+            .values()
+            .map(|entry| {
+                (
+                    entry.prepared.get_statement().to_owned(),
+                    entry.prepared.clone(),
+                )
+            })
+            .collect()
+    }
+
+    /// Returns true if a prepared statement for the query is in the cache
+    pub fn is_cached(&self, query: &Query) -> bool {
+        let key = CacheKey::new(&self.apply_statement_config(query));
+        self.cache.lock().unwrap().contains(&key, self.ttl)
+    }
+
+    /// Removes all prepared statements from the cache
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    /// Returns the number of queries for which the prepared statement was found in the cache
+    pub fn get_cache_hits_num(&self) -> u64 {
+        self.metrics.hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of queries which had to be prepared, because they were not in the cache
+    pub fn get_cache_misses_num(&self) -> u64 {
+        self.metrics.misses.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of prepared statements removed from the cache, because it was full
+    pub fn get_cache_evictions_num(&self) -> u64 {
+        self.metrics.evictions.load(Ordering::Relaxed)
+    }
+
+    /// Does the same thing as [`Session::query`] but applies the statement configuration template
+    pub async fn query(
+        &self,
+        query: impl Into<Query>,
+        values: impl ValueList,
+    ) -> Result<QueryResult, QueryError> {
+        let query = self.apply_statement_config(&query.into());
+        self.session.query(query, values).await
+    }
+
+    /// Does the same thing as [`Session::query_paged`] but applies the statement configuration template
+    pub async fn query_paged(
+        &self,
+        query: impl Into<Query>,
+        values: impl ValueList,
+        paging_state: Option<Bytes>,
+    ) -> Result<QueryResult, QueryError> {
+        let query = self.apply_statement_config(&query.into());
+        self.session.query_paged(query, values, paging_state).await
+    }
+
+    /// Does the same thing as [`Session::query_iter`] but applies the statement configuration template
+    pub async fn query_iter(
+        &self,
+        query: impl Into<Query>,
+        values: impl ValueList,
+    ) -> Result<RowIterator, QueryError> {
+        let query = self.apply_statement_config(&query.into());
+        self.session.query_iter(query, values).await
+    }
+
+    /// Does the same thing as [`Session::resume_query_iter`] but applies the statement configuration template
+    pub async fn resume_query_iter(
+        &self,
+        query: impl Into<Query>,
+        values: impl ValueList,
+        paging_state: PagingState,
+    ) -> Result<RowIterator, QueryError> {
+        let query = self.apply_statement_config(&query.into());
+        self.session
+            .resume_query_iter(query, values, paging_state)
+            .await
+    }
+
+    /// Does the same thing as [`Session::prepare`] but uses the prepared statement cache
+    pub async fn prepare(&self, query: impl Into<Query>) -> Result<PreparedStatement, QueryError> {
+        let query = query.into();
+        self.add_prepared_statement(&query).await
+    }
+
     /// Does the same thing as [`Session::execute`] but uses the prepared statement cache
     pub async fn execute(
         &self,
@@ -53,6 +248,21 @@ impl CachingSession {
         self.session.execute_iter(prepared, values.clone()).await
     }
 
+    /// Does the same thing as [`Session::resume_execute_iter`] but uses the prepared statement cache
+    pub async fn resume_execute_iter(
+        &self,
+        query: impl Into<Query>,
+        values: impl ValueList,
+        paging_state: PagingState,
+    ) -> Result<RowIterator, QueryError> {
+        let query = query.into();
+        let prepared = self.add_prepared_statement(&query).await?;
+        let values = values.serialized()?;
+        self.session
+            .resume_execute_iter(prepared, values.clone(), paging_state)
+            .await
+    }
+
     /// Does the same thing as [`Session::execute_paged`] but uses the prepared statement cache
     pub async fn execute_paged(
         &self,
@@ -112,41 +322,414 @@ impl CachingSession {
         Ok(prepared_batch)
     }
 
+    /// See [`Session::await_schema_agreement`]
+    pub async fn await_schema_agreement(&self) -> Result<(), QueryError> {
+        self.session.await_schema_agreement().await
+    }
+
+    /// See [`Session::await_timed_schema_agreement`]
+    pub async fn await_timed_schema_agreement(
+        &self,
+        timeout_duration: Duration,
+    ) -> Result<bool, QueryError> {
+        self.session
+            .await_timed_schema_agreement(timeout_duration)
+            .await
+    }
+
+    /// See [`Session::check_schema_agreement`]
+    pub async fn check_schema_agreement(&self) -> Result<bool, QueryError> {
+        self.session.check_schema_agreement().await
+    }
+
+    /// See [`Session::refresh_metadata`]
+    pub async fn refresh_metadata(&self) -> Result<(), QueryError> {
+        self.session.refresh_metadata().await
+    }
+
+    /// See [`Session::get_metrics`]
+    pub fn get_metrics(&self) -> Arc<Metrics> {
+        self.session.get_metrics()
+    }
+
+    /// See [`Session::get_cluster_data`]
+    pub fn get_cluster_data(&self) -> Arc<ClusterData> {
+        self.session.get_cluster_data()
+    }
+
     /// Adds a prepared statement to the cache
+    ///
+    /// Statements are cached separately for each page size and configuration
+    /// (consistency, timestamp, timeout etc.) of the query, and the whitespace outside
+    /// of literals doesn't matter. Policies, the history listener and the custom payload
+    /// of the query are not a part of the cache key - they are always taken from the query.
+    /// Options not set on the query are taken from the statement configuration template.
     pub async fn add_prepared_statement(
         &self,
         query: impl Into<&Query>,
     ) -> Result<PreparedStatement, QueryError> {
-        let query = query.into();
+        let query = &self.apply_statement_config(query.into());
+        let key = CacheKey::new(query);
+
+        let cached = self.cache.lock().unwrap().get(&key, self.ttl);
+        if let Some(mut prepared) = cached {
+            self.metrics.hits.fetch_add(1, Ordering::Relaxed);
+            prepared.config.retry_policy = query
+                .config
+                .retry_policy
+                .as_ref()
+                .map(|policy| policy.clone_boxed());
+            prepared.config.speculative_execution_policy =
+                query.config.speculative_execution_policy.clone();
+            prepared.config.history_listener = query.config.history_listener.clone();
+            prepared.config.custom_payload = query.config.custom_payload.clone();
+            return Ok(prepared);
+        }
+
+        self.metrics.misses.fetch_add(1, Ordering::Relaxed);
+        let prepared = self.session.prepare(query.clone()).await?;
+
+        if self.max_capacity > 0 {
+            let evicted =
+                self.cache
+                    .lock()
+                    .unwrap()
+                    .insert(key, prepared.clone(), self.max_capacity);
+            self.metrics.evictions.fetch_add(evicted, Ordering::Relaxed);
+        }
+
+        Ok(prepared)
+    }
+
+    // Returns the query with the options it doesn't set taken from the template
+    fn apply_statement_config(&self, query: &Query) -> Query {
+        let mut query = query.clone();
+        self.apply_statement_config_to(&mut query.config);
+        query
+    }
+
+    // Returns the prepared statement with the options it doesn't set taken from the template
+    pub(crate) fn apply_statement_config_to_prepared(
+        &self,
+        prepared: impl Into<PreparedStatement>,
+    ) -> PreparedStatement {
+        let mut prepared = prepared.into();
+        self.apply_statement_config_to(&mut prepared.config);
+        prepared
+    }
+
+    fn apply_statement_config_to(&self, config: &mut StatementConfig) {
+        let template = &self.statement_config;
+
+        config.consistency = config.consistency.or(template.consistency);
+        config.serial_consistency = config.serial_consistency.or(template.serial_consistency);
+        if let Some(is_idempotent) = self.idempotence {
+            config.is_idempotent = is_idempotent;
+        }
+        if let Some(tracing) = self.tracing {
+            config.tracing = tracing;
+        }
+        config.timestamp = config.timestamp.or(template.timestamp);
+        config.request_timeout = config.request_timeout.or(template.request_timeout);
+        if config.retry_policy.is_none() {
+            config.retry_policy = template
+                .retry_policy
+                .as_ref()
+                .map(|policy| policy.clone_boxed());
+        }
+        if config.speculative_execution_policy.is_none() {
+            config.speculative_execution_policy = template.speculative_execution_policy.clone();
+        }
+        if config.history_listener.is_none() {
+            config.history_listener = template.history_listener.clone();
+        }
+        if config.custom_payload.is_none() {
+            config.custom_payload = template.custom_payload.clone();
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct CacheMetrics {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
 
-        if let Some(prepared) = self.cache.get(&query.contents) {
-            // Clone, because else the value is mutably borrowed and the execute method gives a compile error
-            Ok(prepared.clone())
+// Parts of the query which influence the prepared statement created from it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    contents: String,
+    page_size: Option<i32>,
+    consistency: Option<Consistency>,
+    serial_consistency: Option<SerialConsistency>,
+    is_idempotent: bool,
+    tracing: bool,
+    timestamp: Option<i64>,
+    request_timeout: Option<Duration>,
+}
+
+impl CacheKey {
+    fn new(query: &Query) -> Self {
+        Self {
+            contents: normalize_statement(&query.contents),
+            page_size: query.get_page_size(),
+            consistency: query.config.consistency,
+            serial_consistency: query.config.serial_consistency,
+            is_idempotent: query.config.is_idempotent,
+            tracing: query.config.tracing,
+            timestamp: query.config.timestamp,
+            request_timeout: query.config.request_timeout,
+        }
+    }
+}
+
+// Collapses whitespace and removes comments outside of string literals and quoted identifiers,
+// and removes trailing semicolons
+fn normalize_statement(contents: &str) -> String {
+    let chars: Vec<char> = contents.chars().collect();
+    let mut normalized = String::with_capacity(contents.len());
+    let mut pending_space = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        // Comments are treated as whitespace
+        if (c == '-' && next == Some('-')) || (c == '/' && next == Some('/')) {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            pending_space = true;
+            continue;
+        }
+        if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i = (i + 2).min(chars.len());
+            pending_space = true;
+            continue;
+        }
+        if c.is_whitespace() {
+            pending_space = true;
+            i += 1;
+            continue;
+        }
+
+        if pending_space && !normalized.is_empty() {
+            normalized.push(' ');
+        }
+        pending_space = false;
+
+        // String literals and quoted identifiers are copied verbatim
+        let literal_end = if c == '$' && next == Some('$') {
+            let mut end = i + 2;
+            while end < chars.len() && !(chars[end] == '$' && chars.get(end + 1) == Some(&'$')) {
+                end += 1;
+            }
+            (end + 2).min(chars.len())
+        } else if c == '\'' || c == '"' {
+            // Escaped quotes ('' and "") close and immediately reopen the literal
+            let mut end = i + 1;
+            while end < chars.len() && chars[end] != c {
+                end += 1;
+            }
+            (end + 1).min(chars.len())
         } else {
-            let prepared = self.session.prepare(query.clone()).await?;
-
-            if self.max_capacity == self.cache.len() {
-                // Cache is full, remove the first entry
-                // Don't hold a reference into the map (that's why the to_string() is called)
-                // This is because the documentation of the remove fn tells us that it may deadlock
-                // when holding some sort of reference into the map
-                let query = self.cache.iter().next().map(|c| c.key().to_string());
-
-                // Don't inline this: https://stackoverflow.com/questions/69873846/an-owned-value-is-still-references-somehow
-                if let Some(q) = query {
-                    self.cache.remove(&q);
-                }
+            i + 1
+        };
+        normalized.extend(&chars[i..literal_end]);
+        i = literal_end;
+    }
+
+    while normalized.ends_with(';') || normalized.ends_with(' ') {
+        normalized.pop();
+    }
+    normalized
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    prepared: PreparedStatement,
+    inserted_at: Instant,
+    // Position in the usage order
+    last_used: u64,
+    // Keyspace and table, used for invalidation after schema changes
+    keyspace: Option<String>,
+    table: Option<String>,
+}
+
+impl CacheEntry {
+    fn is_expired(&self, ttl: Option<Duration>) -> bool {
+        matches!(ttl, Some(ttl) if self.inserted_at.elapsed() >= ttl)
+    }
+}
+
+// LRU cache of prepared statements
+#[derive(Debug)]
+struct StatementCache {
+    entries: HashMap<CacheKey, CacheEntry>,
+    // Keys ordered by last usage, the least recently used first
+    usage_order: BTreeMap<u64, CacheKey>,
+    next_usage: u64,
+    schema_changes: broadcast::Receiver<SchemaChangeEvent>,
+}
+
+impl StatementCache {
+    fn new(schema_changes: broadcast::Receiver<SchemaChangeEvent>) -> Self {
+        Self {
+            entries: HashMap::new(),
+            usage_order: BTreeMap::new(),
+            next_usage: 0,
+            schema_changes,
+        }
+    }
+
+    fn get(&mut self, key: &CacheKey, ttl: Option<Duration>) -> Option<PreparedStatement> {
+        self.apply_schema_changes();
+
+        if self.entries.get(key)?.is_expired(ttl) {
+            self.remove(key);
+            return None;
+        }
+
+        let next_usage = self.next_usage;
+        let entry = self.entries.get_mut(key)?;
+        self.usage_order.remove(&entry.last_used);
+        entry.last_used = next_usage;
+        self.usage_order.insert(next_usage, key.clone());
+        self.next_usage += 1;
+
+        Some(entry.prepared.clone())
+    }
+
+    fn contains(&mut self, key: &CacheKey, ttl: Option<Duration>) -> bool {
+        self.apply_schema_changes();
+        matches!(self.entries.get(key), Some(entry) if !entry.is_expired(ttl))
+    }
+
+    // Returns the number of entries evicted to make space for the new one
+    fn insert(&mut self, key: CacheKey, prepared: PreparedStatement, capacity: usize) -> u64 {
+        self.apply_schema_changes();
+        self.remove(&key);
+
+        let mut evicted = 0;
+        while self.entries.len() >= capacity {
+            let least_recently_used = match self.usage_order.keys().next() {
+                Some(usage) => *usage,
+                None => break,
+            };
+            if let Some(key) = self.usage_order.remove(&least_recently_used) {
+                self.entries.remove(&key);
+                evicted += 1;
             }
+        }
+
+        let (keyspace, table) = statement_table(&prepared);
+        self.usage_order.insert(self.next_usage, key.clone());
+        self.entries.insert(
+            key,
+            CacheEntry {
+                prepared,
+                inserted_at: Instant::now(),
+                last_used: self.next_usage,
+                keyspace,
+                table,
+            },
+        );
+        self.next_usage += 1;
+
+        evicted
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.usage_order.remove(&entry.last_used);
+        }
+    }
 
-            self.cache.insert(query.contents.clone(), prepared.clone());
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.usage_order.clear();
+    }
 
-            Ok(prepared)
+    fn remove_matching(&mut self, mut predicate: impl FnMut(&CacheEntry) -> bool) {
+        let keys: Vec<CacheKey> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| predicate(entry))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            self.remove(&key);
         }
     }
+
+    // Statements of tables whose schema changed are prepared again on the next use,
+    // so that their metadata is up to date
+    fn apply_schema_changes(&mut self) {
+        loop {
+            let event = match self.schema_changes.try_recv() {
+                Ok(event) => event,
+                Err(broadcast::error::TryRecvError::Lagged(_)) => {
+                    // Some changes were missed, so any statement could be affected
+                    debug!("Missed schema changes, clearing the prepared statement cache");
+                    self.clear();
+                    continue;
+                }
+                Err(_) => return,
+            };
+
+            match event {
+                SchemaChangeEvent::TableChange {
+                    keyspace_name,
+                    object_name,
+                    ..
+                } => self.remove_matching(|entry| {
+                    entry.keyspace.as_deref() == Some(keyspace_name.as_str())
+                        && entry.table.as_deref() == Some(object_name.as_str())
+                }),
+                SchemaChangeEvent::KeyspaceChange { keyspace_name, .. }
+                | SchemaChangeEvent::TypeChange { keyspace_name, .. } => {
+                    self.remove_matching(|entry| {
+                        entry.keyspace.as_deref() == Some(keyspace_name.as_str())
+                    })
+                }
+                SchemaChangeEvent::FunctionChange { .. }
+                | SchemaChangeEvent::AggregateChange { .. } => (),
+            }
+        }
+    }
+}
+
+// Table of the statement is known from the metadata of its bound values or of its result
+fn statement_table(prepared: &PreparedStatement) -> (Option<String>, Option<String>) {
+    if let (Some(keyspace), Some(table)) = (prepared.get_keyspace_name(), prepared.get_table_name())
+    {
+        return (Some(keyspace.to_owned()), Some(table.to_owned()));
+    }
+    match prepared.get_result_metadata().col_specs.first() {
+        Some(col_spec) => (
+            Some(col_spec.table_spec.ks_name.clone()),
+            Some(col_spec.table_spec.table_name.clone()),
+        ),
+        None => (None, None),
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{normalize_statement, CacheKey, StatementCache};
+    use crate::frame::response::event::{SchemaChangeEvent, SchemaChangeType};
+    use crate::frame::response::result::{
+        ColumnSpec, ColumnType, PreparedMetadata, ResultMetadata, TableSpec,
+    };
+    use crate::query::Query;
+    use crate::statement::{Consistency, StatementConfig};
+    use crate::transport::session_like::SessionLike;
     use crate::utils::test_utils::unique_keyspace_name;
     use crate::{
         batch::{Batch, BatchStatement},
@@ -155,6 +738,8 @@ mod tests {
     };
     use futures::TryStreamExt;
     use std::collections::BTreeSet;
+    use std::time::Duration;
+    use tokio::sync::broadcast;
 
     async fn new_for_test() -> Session {
         let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
@@ -199,15 +784,15 @@ mod tests {
             .unwrap();
 
         // Clear the cache because it now contains an insert
-        assert_eq!(session.cache.len(), 1);
+        assert_eq!(session.get_cache_size(), 1);
 
-        session.cache.clear();
+        session.clear_cache();
 
         session
     }
 
     /// Test that when the cache is full and a different query comes in, that query will be added
-    /// to the cache and a random query is removed
+    /// to the cache and the least recently used query is removed
     #[tokio::test]
     async fn test_full() {
         let session = create_caching_session().await;
@@ -224,21 +809,52 @@ mod tests {
             .add_prepared_statement(&middle_query.into())
             .await
             .unwrap();
+        // Makes the middle query the least recently used one
+        session
+            .add_prepared_statement(&first_query.into())
+            .await
+            .unwrap();
         session
             .add_prepared_statement(&last_query.into())
             .await
             .unwrap();
 
-        assert_eq!(2, session.cache.len());
+        assert_eq!(2, session.get_cache_size());
+        assert_eq!(1, session.get_cache_hits_num());
+        assert_eq!(3, session.get_cache_misses_num());
+        assert_eq!(1, session.get_cache_evictions_num());
 
-        // This query should be in the cache
-        assert!(session.cache.get(last_query).is_some());
+        assert!(session.is_cached(&last_query.into()));
+        assert!(session.is_cached(&first_query.into()));
+        assert!(!session.is_cached(&middle_query.into()));
+    }
+
+    /// Checks that statements of a table are removed from the cache after the table is altered
+    #[tokio::test]
+    async fn test_schema_change_invalidation() {
+        let session = create_caching_session().await;
+
+        let query = "select * from test_table";
+        session.execute(query, &[]).await.unwrap();
+        assert!(session.is_cached(&query.into()));
+
+        session
+            .session
+            .query("ALTER TABLE test_table ADD c int", &[])
+            .await
+            .unwrap();
 
-        // Either the first or middle query should be removed
-        let first_query_removed = session.cache.get(first_query).is_none();
-        let middle_query_removed = session.cache.get(middle_query).is_none();
+        // Schema change events are received asynchronously
+        for _ in 0..50 {
+            if !session.is_cached(&query.into()) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert!(!session.is_cached(&query.into()));
 
-        assert!(first_query_removed || middle_query_removed);
+        let result = session.execute(query, &[]).await.unwrap();
+        assert_eq!(3, result.col_specs.len());
     }
 
     /// Checks that the same prepared statement is reused when executing the same query twice
@@ -250,7 +866,7 @@ mod tests {
             .await
             .unwrap();
 
-        assert_eq!(1, session.cache.len());
+        assert_eq!(1, session.get_cache_size());
         assert_eq!(1, result.rows.unwrap().len());
 
         let result = session
@@ -258,7 +874,7 @@ mod tests {
             .await
             .unwrap();
 
-        assert_eq!(1, session.cache.len());
+        assert_eq!(1, session.get_cache_size());
         assert_eq!(1, result.rows.unwrap().len());
     }
 
@@ -267,7 +883,7 @@ mod tests {
     async fn test_execute_iter_cached() {
         let session = create_caching_session().await;
 
-        assert!(session.cache.is_empty());
+        assert_eq!(0, session.get_cache_size());
 
         let iter = session
             .execute_iter("select * from test_table", &[])
@@ -277,7 +893,7 @@ mod tests {
         let rows = iter.try_collect::<Vec<_>>().await.unwrap().len();
 
         assert_eq!(1, rows);
-        assert_eq!(1, session.cache.len());
+        assert_eq!(1, session.get_cache_size());
     }
 
     /// Checks that caching works with execute_paged
@@ -285,17 +901,100 @@ mod tests {
     async fn test_execute_paged_cached() {
         let session = create_caching_session().await;
 
-        assert!(session.cache.is_empty());
+        assert_eq!(0, session.get_cache_size());
 
         let result = session
             .execute_paged("select * from test_table", &[], None)
             .await
             .unwrap();
 
-        assert_eq!(1, session.cache.len());
+        assert_eq!(1, session.get_cache_size());
         assert_eq!(1, result.rows.unwrap().len());
     }
 
+    /// Checks that the options not set on the query are taken from the statement configuration template
+    #[tokio::test]
+    async fn test_statement_config_template() {
+        let config = StatementConfig {
+            consistency: Some(Consistency::One),
+            tracing: true,
+            request_timeout: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let session = create_caching_session().await.with_statement_config(config);
+
+        let query = "select * from test_table";
+        let prepared = session.prepare(query).await.unwrap();
+        assert_eq!(prepared.get_consistency(), Some(Consistency::One));
+        assert!(prepared.get_tracing());
+        assert_eq!(
+            prepared.get_request_timeout(),
+            Some(Duration::from_secs(10))
+        );
+
+        // Options set on the query take precedence over the template
+        let mut quorum_query = Query::new(query);
+        quorum_query.set_consistency(Consistency::Quorum);
+        let prepared = session.prepare(quorum_query.clone()).await.unwrap();
+        assert_eq!(prepared.get_consistency(), Some(Consistency::Quorum));
+        assert_eq!(
+            prepared.get_request_timeout(),
+            Some(Duration::from_secs(10))
+        );
+
+        assert!(session.is_cached(&query.into()));
+        assert!(session.is_cached(&quorum_query));
+        assert_eq!(2, session.get_cache_size());
+
+        let result = session.query(query, &[]).await.unwrap();
+        assert!(result.tracing_id.is_some());
+
+        // The template is applied to prepared statements executed through SessionLike
+        let unprepared = session.session.prepare(query).await.unwrap();
+        let result = SessionLike::execute(&session, &unprepared, ())
+            .await
+            .unwrap();
+        assert!(result.tracing_id.is_some());
+
+        // The template can turn tracing off, even if it's enabled on the query
+        let session = session.with_tracing(Some(false));
+        let mut traced_query = Query::new(query);
+        traced_query.set_tracing(true);
+        let result = session.query(traced_query, &[]).await.unwrap();
+        assert!(result.tracing_id.is_none());
+        let prepared = session.prepare(query).await.unwrap();
+        assert!(!prepared.get_tracing());
+    }
+
+    async fn count_rows(session: &impl SessionLike) -> usize {
+        let prepared = session
+            .prepare("select a, b from test_table")
+            .await
+            .unwrap();
+        session
+            .execute_iter_typed::<(i32, i32)>(prepared, &[])
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+            .len()
+    }
+
+    /// Checks that code generic over SessionLike works with both Session and CachingSession
+    #[tokio::test]
+    async fn test_session_like() {
+        let session = create_caching_session().await;
+
+        assert_eq!(1, count_rows(&session).await);
+        assert_eq!(1, count_rows(&session).await);
+        assert_eq!(1, session.get_cache_size());
+        assert_eq!(1, session.get_cache_hits_num());
+
+        assert_eq!(1, count_rows(&session.session).await);
+        assert!(SessionLike::check_schema_agreement(&session).await.unwrap());
+    }
+
     async fn assert_test_batch_table_rows_contain(
         sess: &CachingSession,
         expected_rows: &[(i32, i32)],
@@ -435,4 +1134,108 @@ mod tests {
             assert!(session.prepare_batch(&bad_batch).await.is_err());
         }
     }
+
+    fn prepared(id: &'static [u8]) -> PreparedStatement {
+        let mut metadata = PreparedMetadata::default();
+        metadata.col_count = 1;
+        metadata.col_specs = vec![ColumnSpec {
+            table_spec: TableSpec {
+                ks_name: "ks".to_string(),
+                table_name: "t".to_string(),
+            },
+            name: "a".to_string(),
+            typ: ColumnType::Int,
+        }];
+
+        PreparedStatement::new(
+            bytes::Bytes::from_static(id),
+            metadata,
+            ResultMetadata::default(),
+            String::new(),
+            None,
+            StatementConfig::default(),
+        )
+    }
+
+    #[test]
+    fn test_normalize_statement() {
+        assert_eq!(
+            normalize_statement("  SELECT a,\n\tb  FROM ks.t WHERE a = ? ; "),
+            "SELECT a, b FROM ks.t WHERE a = ?"
+        );
+        assert_eq!(
+            normalize_statement("INSERT INTO t (a) VALUES ('x  ''y''  z')"),
+            "INSERT INTO t (a) VALUES ('x  ''y''  z')"
+        );
+        assert_eq!(
+            normalize_statement("SELECT \"My  Column\"   FROM t"),
+            "SELECT \"My  Column\" FROM t"
+        );
+        assert_eq!(
+            normalize_statement(
+                "SELECT a -- the key\nFROM t /* multi\n line */ WHERE a = 1; // end"
+            ),
+            "SELECT a FROM t WHERE a = 1"
+        );
+        assert_eq!(
+            normalize_statement("INSERT INTO t (a) VALUES ($$ -- not  a comment; $$)"),
+            "INSERT INTO t (a) VALUES ($$ -- not  a comment; $$)"
+        );
+        assert_eq!(
+            normalize_statement("SELECT '/* not  a comment */' FROM t"),
+            "SELECT '/* not  a comment */' FROM t"
+        );
+
+        // Statements differing only in whitespace share the key, unlike ones with different page sizes
+        let query = Query::new("SELECT a FROM t");
+        assert_eq!(
+            CacheKey::new(&query),
+            CacheKey::new(&Query::new("SELECT  a\nFROM t;"))
+        );
+        assert_ne!(
+            CacheKey::new(&query),
+            CacheKey::new(&query.with_page_size(10))
+        );
+    }
+
+    #[test]
+    fn test_statement_cache_eviction() {
+        let (sender, receiver) = broadcast::channel(4);
+        let mut cache = StatementCache::new(receiver);
+        let key = |contents: &str| CacheKey::new(&Query::new(contents));
+
+        assert_eq!(cache.insert(key("a"), prepared(b"a"), 2), 0);
+        assert_eq!(cache.insert(key("b"), prepared(b"b"), 2), 0);
+        // Makes "b" the least recently used entry
+        assert!(cache.get(&key("a"), None).is_some());
+        assert_eq!(cache.insert(key("c"), prepared(b"c"), 2), 1);
+        assert!(cache.contains(&key("a"), None));
+        assert!(!cache.contains(&key("b"), None));
+        assert!(cache.contains(&key("c"), None));
+
+        // Expired entries are removed on access
+        assert!(cache.get(&key("a"), Some(Duration::ZERO)).is_none());
+        assert!(!cache.contains(&key("a"), None));
+
+        // Entries of the altered table are invalidated
+        cache.insert(key("d"), prepared(b"d"), 2);
+        sender
+            .send(SchemaChangeEvent::TableChange {
+                change_type: SchemaChangeType::Updated,
+                keyspace_name: "ks".to_string(),
+                object_name: "other".to_string(),
+            })
+            .unwrap();
+        assert_eq!(cache.entries.len(), 2);
+        sender
+            .send(SchemaChangeEvent::TableChange {
+                change_type: SchemaChangeType::Updated,
+                keyspace_name: "ks".to_string(),
+                object_name: "t".to_string(),
+            })
+            .unwrap();
+        assert!(!cache.contains(&key("c"), None));
+        assert!(cache.entries.is_empty());
+        assert!(cache.usage_order.is_empty());
+    }
 }
@@ -1,16 +1,19 @@
 /// Cluster manages up to date information and connections to database nodes
-use crate::frame::response::event::{Event, StatusChangeEvent};
+use crate::frame::response::event::{Event, SchemaChangeEvent, StatusChangeEvent};
 use crate::frame::value::ValueList;
 use crate::load_balancing::TokenAwarePolicy;
+use crate::prepared_statement::PreparedStatement;
 use crate::routing::Token;
 use crate::transport::{
     connection::{Connection, VerifiedKeyspaceName},
     connection_pool::PoolConfig,
     errors::QueryError,
-    node::Node,
+    host_filter::HostFilter,
+    node::{Node, NodeState},
     partitioner::PartitionerName,
+    prepared_statement_registry::PreparedStatementRegistry,
     session::AddressTranslator,
-    topology::{Keyspace, Metadata, MetadataReader},
+    topology::{ContactPointHostnames, Keyspace, Metadata, MetadataReader},
 };
 
 use arc_swap::ArcSwap;
@@ -18,6 +21,7 @@ use bytes::{BufMut, Bytes, BytesMut};
 use futures::future::join_all;
 use futures::{future::RemoteHandle, FutureExt};
 use itertools::Itertools;
+use rand::Rng;
 use scylla_cql::errors::BadQuery;
 use std::collections::{BTreeMap, HashMap};
 use std::net::SocketAddr;
@@ -34,6 +38,16 @@ pub struct Cluster {
 
     refresh_channel: tokio::sync::mpsc::Sender<RefreshRequest>,
     use_keyspace_channel: tokio::sync::mpsc::Sender<UseKeyspaceRequest>,
+    close_channel: tokio::sync::mpsc::Sender<tokio::sync::oneshot::Sender<()>>,
+
+    // Statements to prepare on nodes which come back up or join the cluster
+    prepared_statements: Option<Arc<PreparedStatementRegistry>>,
+
+    // Used to subscribe to schema changes reported by the database
+    schema_change_sender: tokio::sync::broadcast::Sender<SchemaChangeEvent>,
+
+    // Used to subscribe to changes noticed by the driver
+    cluster_event_sender: tokio::sync::broadcast::Sender<ClusterEvent>,
 
     _worker_handle: RemoteHandle<()>,
 }
@@ -50,6 +64,25 @@ impl<'a> std::fmt::Debug for ClusterNeatDebug<'a> {
     }
 }
 
+/// Changes of the cluster state noticed by the driver
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ClusterEvent {
+    /// The control connection, used to fetch metadata and receive server events,
+    /// was moved to another node, e.g. because the previous one stopped responding
+    ControlConnectionChanged {
+        previous: SocketAddr,
+        current: SocketAddr,
+    },
+    /// The state of a node, as seen by the driver, has changed.\
+    /// See [NodeState] for the meaning of each state.
+    NodeStateChanged {
+        address: SocketAddr,
+        previous: NodeState,
+        current: NodeState,
+    },
+}
+
 #[derive(Clone, Debug)]
 pub struct Datacenter {
     pub nodes: Vec<Arc<Node>>,
@@ -99,17 +132,35 @@ struct ClusterWorker {
     metadata_reader: MetadataReader,
     pool_config: PoolConfig,
 
+    // Decides which nodes get a connection pool
+    host_filter: Option<Arc<dyn HostFilter>>,
+
     // To listen for refresh requests
     refresh_channel: tokio::sync::mpsc::Receiver<RefreshRequest>,
 
     // Channel used to receive use keyspace requests
     use_keyspace_channel: tokio::sync::mpsc::Receiver<UseKeyspaceRequest>,
 
+    // Channel used to receive the request to close the cluster
+    close_channel: tokio::sync::mpsc::Receiver<tokio::sync::oneshot::Sender<()>>,
+
     // Channel used to receive server events
     server_events_channel: tokio::sync::mpsc::Receiver<Event>,
 
     // Keyspace send in "USE <keyspace name>" when opening each connection
     used_keyspace: Option<VerifiedKeyspaceName>,
+
+    // Statements to prepare on nodes which come back up or join the cluster
+    prepared_statements: Option<Arc<PreparedStatementRegistry>>,
+
+    // Channel used to notify about schema changes
+    schema_change_sender: tokio::sync::broadcast::Sender<SchemaChangeEvent>,
+
+    // Channel used to notify about changes noticed by the driver
+    cluster_event_sender: tokio::sync::broadcast::Sender<ClusterEvent>,
+
+    // Last known state of every node, used to report state transitions
+    node_states: HashMap<SocketAddr, NodeState>,
 }
 
 #[derive(Debug)]
@@ -126,40 +177,61 @@ struct UseKeyspaceRequest {
 impl Cluster {
     pub async fn new(
         initial_peers: &[SocketAddr],
+        contact_points: ContactPointHostnames,
         pool_config: PoolConfig,
         fetch_schema_metadata: bool,
         address_translator: &Option<Arc<dyn AddressTranslator>>,
+        host_filter: Option<Arc<dyn HostFilter>>,
+        reprepare_on_node_up: bool,
     ) -> Result<Cluster, QueryError> {
         let (refresh_sender, refresh_receiver) = tokio::sync::mpsc::channel(32);
         let (use_keyspace_sender, use_keyspace_receiver) = tokio::sync::mpsc::channel(32);
+        let (close_sender, close_receiver) = tokio::sync::mpsc::channel(1);
         let (server_events_sender, server_events_receiver) = tokio::sync::mpsc::channel(32);
+        let (schema_change_sender, _) = tokio::sync::broadcast::channel(32);
+        let (cluster_event_sender, _) = tokio::sync::broadcast::channel(32);
 
         let mut metadata_reader = MetadataReader::new(
             initial_peers,
-            pool_config.connection_config.clone(),
-            pool_config.keepalive_interval,
+            pool_config.clone(),
             server_events_sender,
             fetch_schema_metadata,
             address_translator,
+            host_filter.clone(),
+            contact_points,
         );
 
         let metadata = metadata_reader.read_metadata(true).await?;
-        let cluster_data = ClusterData::new(metadata, &pool_config, &HashMap::new(), &None);
+        let cluster_data =
+            ClusterData::new(metadata, &pool_config, &HashMap::new(), &None, &host_filter);
         cluster_data.wait_until_all_pools_are_initialized().await;
         let cluster_data: Arc<ArcSwap<ClusterData>> =
             Arc::new(ArcSwap::from(Arc::new(cluster_data)));
+        let prepared_statements = if reprepare_on_node_up {
+            Some(Arc::new(PreparedStatementRegistry::new()))
+        } else {
+            None
+        };
 
         let worker = ClusterWorker {
             cluster_data: cluster_data.clone(),
 
             metadata_reader,
             pool_config,
+            host_filter,
 
             refresh_channel: refresh_receiver,
             server_events_channel: server_events_receiver,
 
             use_keyspace_channel: use_keyspace_receiver,
             used_keyspace: None,
+
+            close_channel: close_receiver,
+
+            prepared_statements: prepared_statements.clone(),
+            schema_change_sender: schema_change_sender.clone(),
+            cluster_event_sender: cluster_event_sender.clone(),
+            node_states: HashMap::new(),
         };
 
         let (fut, worker_handle) = worker.work().remote_handle();
@@ -169,6 +241,10 @@ impl Cluster {
             data: cluster_data,
             refresh_channel: refresh_sender,
             use_keyspace_channel: use_keyspace_sender,
+            close_channel: close_sender,
+            prepared_statements,
+            schema_change_sender,
+            cluster_event_sender,
             _worker_handle: worker_handle,
         };
 
@@ -179,6 +255,11 @@ impl Cluster {
         self.data.load_full()
     }
 
+    // Shared handle to the cluster data, always pointing at the current ClusterData
+    pub(crate) fn get_data_source(&self) -> Arc<ArcSwap<ClusterData>> {
+        self.data.clone()
+    }
+
     pub async fn refresh_metadata(&self) -> Result<(), QueryError> {
         let (response_sender, response_receiver) = tokio::sync::oneshot::channel();
 
@@ -187,8 +268,8 @@ impl Cluster {
                 response_chan: response_sender,
             })
             .await
-            .expect("Bug in Cluster::refresh_metadata sending");
-        // Other end of this channel is in ClusterWorker, can't be dropped while we have &self to Cluster with _worker_handle
+            .map_err(|_| QueryError::SessionClosed)?;
+        // Other end of this channel is in ClusterWorker, which stops only when the cluster is closed
 
         response_receiver
             .await
@@ -208,12 +289,22 @@ impl Cluster {
                 response_chan: response_sender,
             })
             .await
-            .expect("Bug in Cluster::use_keyspace sending");
-        // Other end of this channel is in ClusterWorker, can't be dropped while we have &self to Cluster with _worker_handle
+            .map_err(|_| QueryError::SessionClosed)?;
+        // Other end of this channel is in ClusterWorker, which stops only when the cluster is closed
 
         response_receiver.await.unwrap() // ClusterWorker always responds
     }
 
+    // Stops the worker, closes the control connection and connections to all nodes
+    pub(crate) async fn close(&self) {
+        let (response_sender, response_receiver) = tokio::sync::oneshot::channel();
+
+        // The worker is already stopped if the cluster was closed before
+        if self.close_channel.send(response_sender).await.is_ok() {
+            let _ = response_receiver.await;
+        }
+    }
+
     /// Returns nonempty list of working connections to all shards
     pub async fn get_working_connections(&self) -> Result<Vec<Arc<Connection>>, QueryError> {
         let cluster_data: Arc<ClusterData> = self.get_data();
@@ -223,6 +314,7 @@ impl Cluster {
 
         let mut last_error: Option<QueryError> = None;
 
+        // Nodes rejected by the host filter return an error, which is used only when there are no other nodes
         for node in peers.values() {
             match node.get_working_connections() {
                 Ok(conns) => result.extend(conns),
@@ -236,6 +328,57 @@ impl Cluster {
 
         Ok(result)
     }
+
+    /// Returns working connections to a single node, chosen randomly
+    pub(crate) fn get_working_connections_to_random_node(
+        &self,
+    ) -> Result<Vec<Arc<Connection>>, QueryError> {
+        let cluster_data: Arc<ClusterData> = self.get_data();
+        let nodes = &cluster_data.all_nodes;
+        if nodes.is_empty() {
+            return Err(QueryError::IoError(Arc::new(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "No nodes are known to the driver",
+            ))));
+        }
+        let offset = rand::thread_rng().gen_range(0..nodes.len());
+
+        let mut last_error: Option<QueryError> = None;
+        for node in nodes.iter().cycle().skip(offset).take(nodes.len()) {
+            match node.get_working_connections() {
+                Ok(conns) => return Ok(conns),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error.unwrap()) // By invariant nodes is nonempty
+    }
+
+    /// Subscribes to schema changes reported by the database
+    pub(crate) fn subscribe_schema_changes(
+        &self,
+    ) -> tokio::sync::broadcast::Receiver<SchemaChangeEvent> {
+        self.schema_change_sender.subscribe()
+    }
+
+    /// Subscribes to changes of the cluster state noticed by the driver
+    pub(crate) fn subscribe_cluster_events(
+        &self,
+    ) -> tokio::sync::broadcast::Receiver<ClusterEvent> {
+        self.cluster_event_sender.subscribe()
+    }
+
+    /// Remembers the statement, so that it's prepared on nodes which come back up or join the cluster
+    pub(crate) fn register_prepared_statement(&self, prepared: &PreparedStatement) {
+        if let Some(prepared_statements) = &self.prepared_statements {
+            prepared_statements.register(prepared);
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn get_prepared_statement_registry(&self) -> Option<&PreparedStatementRegistry> {
+        self.prepared_statements.as_deref()
+    }
 }
 
 impl ClusterData {
@@ -268,11 +411,13 @@ impl ClusterData {
 
     /// Creates new ClusterData using information about topology held in `metadata`.
     /// Uses provided `known_peers` hashmap to recycle nodes if possible.
+    /// Nodes rejected by `host_filter` are kept in the metadata, but get no connection pool.
     pub(crate) fn new(
         metadata: Metadata,
         pool_config: &PoolConfig,
         known_peers: &HashMap<SocketAddr, Arc<Node>>,
         used_keyspace: &Option<VerifiedKeyspaceName>,
+        host_filter: &Option<Arc<dyn HostFilter>>,
     ) -> Self {
         // Create new updated known_peers and ring
         let mut new_known_peers: HashMap<SocketAddr, Arc<Node>> =
@@ -282,19 +427,31 @@ impl ClusterData {
         let mut all_nodes: Vec<Arc<Node>> = Vec::with_capacity(metadata.peers.len());
 
         for peer in metadata.peers {
+            let enabled = match host_filter {
+                Some(filter) => filter.accept(&peer),
+                None => true,
+            };
+
             // Take existing Arc<Node> if possible, otherwise create new one
             // Changing rack/datacenter but not ip address seems improbable
             // so we can just create new node and connections then
             let node: Arc<Node> = match known_peers.get(&peer.address) {
-                Some(node) if node.datacenter == peer.datacenter && node.rack == peer.rack => {
+                Some(node)
+                    if node.host_id == peer.host_id
+                        && node.datacenter == peer.datacenter
+                        && node.rack == peer.rack
+                        && node.is_enabled() == enabled =>
+                {
                     node.clone()
                 }
                 _ => Arc::new(Node::new(
                     peer.address,
+                    peer.host_id,
                     pool_config.clone(),
                     peer.datacenter,
                     peer.rack,
                     used_keyspace.clone(),
+                    enabled,
                 )),
             };
 
@@ -421,6 +578,12 @@ impl ClusterWorker {
         let refresh_duration = Duration::from_secs(60); // Refresh topology every 60 seconds
         let mut last_refresh_time = Instant::now();
 
+        // Node states depend on connectivity and error rates, which change without any events,
+        // so they have to be checked periodically
+        self.report_node_state_changes();
+        let mut node_state_interval = tokio::time::interval(Duration::from_secs(1));
+        let mut control_connection_failover_attempted = false;
+
         loop {
             let mut cur_request: Option<RefreshRequest> = None;
 
@@ -434,6 +597,21 @@ impl ClusterWorker {
 
             tokio::select! {
                 _ = sleep_future => {},
+                _ = node_state_interval.tick() => {
+                    self.report_node_state_changes();
+
+                    // Fail over right away instead of waiting for the next refresh,
+                    // but only once until the control connection works again
+                    if !self.metadata_reader.is_control_connection_broken() {
+                        control_connection_failover_attempted = false;
+                        continue;
+                    }
+                    if control_connection_failover_attempted {
+                        continue;
+                    }
+                    control_connection_failover_attempted = true;
+                    debug!("The control connection is broken, failing over");
+                }
                 recv_res = self.refresh_channel.recv() => {
                     match recv_res {
                         Some(request) => cur_request = Some(request),
@@ -451,11 +629,20 @@ impl ClusterWorker {
 
                                 match status {
                                     StatusChangeEvent::Down(addr) => self.change_node_down_marker(addr, true),
-                                    StatusChangeEvent::Up(addr) => self.change_node_down_marker(addr, false),
+                                    StatusChangeEvent::Up(addr) => {
+                                        self.change_node_down_marker(addr, false);
+                                        // A restarted node doesn't know the statements prepared before
+                                        self.prepare_statements_on_node(addr);
+                                    }
                                 }
+                                self.report_node_state_changes();
                                 continue;
                             },
-                            _ => continue, // Don't go to refreshing
+                            Event::SchemaChange(schema_change) => {
+                                // Nobody might be subscribed, which is fine
+                                let _ = self.schema_change_sender.send(schema_change);
+                                continue;
+                            }
                         }
                     } else {
                         // If server_events_channel was closed, than TopologyReader was dropped,
@@ -467,6 +654,9 @@ impl ClusterWorker {
                     match recv_res {
                         Some(request) => {
                             self.used_keyspace = Some(request.keyspace_name.clone());
+                            if let Some(prepared_statements) = &self.prepared_statements {
+                                prepared_statements.set_used_keyspace(request.keyspace_name.clone());
+                            }
 
                             let cluster_data = self.cluster_data.load_full();
                             let use_keyspace_future = Self::handle_use_keyspace_request(cluster_data, request);
@@ -477,12 +667,21 @@ impl ClusterWorker {
 
                     continue; // Don't go to refreshing, wait for the next event
                 }
+                Some(response_chan) = self.close_channel.recv() => {
+                    debug!("Closing the cluster");
+                    let cluster_data = self.cluster_data.load_full();
+                    join_all(cluster_data.all_nodes.iter().map(|node| node.close_pool())).await;
+                    // The control connection is closed when the worker is dropped
+                    let _ = response_chan.send(());
+                    return;
+                }
             }
 
             // Perform the refresh
             debug!("Requesting topology refresh");
             last_refresh_time = Instant::now();
             let refresh_res = self.perform_refresh().await;
+            self.report_node_state_changes();
 
             // Send refresh result if there was a request
             if let Some(request) = cur_request {
@@ -492,6 +691,35 @@ impl ClusterWorker {
         }
     }
 
+    fn report_node_state_changes(&mut self) {
+        let cluster_data = self.cluster_data.load_full();
+        let mut node_states = HashMap::with_capacity(cluster_data.all_nodes.len());
+
+        for node in &cluster_data.all_nodes {
+            let current = node.update_state();
+            // Nodes seen for the first time don't have a transition to report
+            if let Some(&previous) = self.node_states.get(&node.address) {
+                if previous != current {
+                    debug!(
+                        "Node {} changed state from {:?} to {:?}",
+                        node.address, previous, current
+                    );
+                    // Nobody might be subscribed, which is fine
+                    let _ = self
+                        .cluster_event_sender
+                        .send(ClusterEvent::NodeStateChanged {
+                            address: node.address,
+                            previous,
+                            current,
+                        });
+                }
+            }
+            node_states.insert(node.address, current);
+        }
+
+        self.node_states = node_states;
+    }
+
     fn change_node_down_marker(&mut self, addr: SocketAddr, is_down: bool) {
         let cluster_data = self.cluster_data.load_full();
 
@@ -506,6 +734,21 @@ impl ClusterWorker {
         node.change_down_marker(is_down);
     }
 
+    fn prepare_statements_on_node(&self, addr: SocketAddr) {
+        let prepared_statements = match &self.prepared_statements {
+            Some(prepared_statements) => prepared_statements.clone(),
+            None => return,
+        };
+
+        let node = match self.cluster_data.load().known_peers.get(&addr) {
+            Some(node) if node.is_enabled() => node.clone(),
+            Some(_) => return, // The driver doesn't connect to nodes rejected by the host filter
+            None => return,    // Node will be prepared on when it's added during the next refresh
+        };
+
+        tokio::spawn(async move { prepared_statements.prepare_on_node(&node).await });
+    }
+
     async fn handle_use_keyspace_request(
         cluster_data: Arc<ClusterData>,
         request: UseKeyspaceRequest,
@@ -559,7 +802,21 @@ impl ClusterWorker {
 
     async fn perform_refresh(&mut self) -> Result<(), QueryError> {
         // Read latest Metadata
-        let metadata = self.metadata_reader.read_metadata(false).await?;
+        let previous_control_connection = self.metadata_reader.control_connection_address();
+        let metadata = self.metadata_reader.read_metadata(false).await;
+
+        let current_control_connection = self.metadata_reader.control_connection_address();
+        if current_control_connection != previous_control_connection {
+            // Nobody might be subscribed, which is fine
+            let _ = self
+                .cluster_event_sender
+                .send(ClusterEvent::ControlConnectionChanged {
+                    previous: previous_control_connection,
+                    current: current_control_connection,
+                });
+        }
+
+        let metadata = metadata?;
         let cluster_data: Arc<ClusterData> = self.cluster_data.load_full();
 
         let new_cluster_data = Arc::new(ClusterData::new(
@@ -567,14 +824,27 @@ impl ClusterWorker {
             &self.pool_config,
             &cluster_data.known_peers,
             &self.used_keyspace,
+            &self.host_filter,
         ));
 
         new_cluster_data
             .wait_until_all_pools_are_initialized()
             .await;
 
+        // Nodes which joined the cluster don't know the statements prepared before
+        let new_nodes: Vec<SocketAddr> = new_cluster_data
+            .known_peers
+            .keys()
+            .filter(|addr| !cluster_data.known_peers.contains_key(addr))
+            .copied()
+            .collect();
+
         self.update_cluster_data(new_cluster_data);
 
+        for addr in new_nodes {
+            self.prepare_statements_on_node(addr);
+        }
+
         Ok(())
     }
 
@@ -0,0 +1,145 @@
+//! Running a prepared statement with many values and bounded parallelism
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::stream::{self, BoxStream};
+use futures::{Stream, StreamExt};
+
+use crate::frame::value::{SerializeValuesError, SerializedValues};
+use crate::statement::prepared_statement::PreparedStatement;
+use crate::transport::errors::QueryError;
+use crate::transport::session::Session;
+use crate::QueryResult;
+
+type ValuesIter<'a> =
+    Box<dyn Iterator<Item = Result<SerializedValues, SerializeValuesError>> + Send + 'a>;
+
+/// Result of executing the statement with a single item of values
+#[derive(Debug)]
+pub struct ConcurrentExecutionItem {
+    /// Position of the values in the input iterator
+    pub index: usize,
+    pub result: Result<QueryResult, QueryError>,
+}
+
+/// Stream of results of a prepared statement executed concurrently with many values.\
+/// Returned by [`Session::execute_concurrent`].
+///
+/// Requests are started lazily, when the stream is polled, and at most `concurrency`
+/// of them run at the same time. Each request goes through [`Session::execute`],
+/// so it uses the load balancing, retry and speculative execution settings of the session
+/// and the statement.
+///
+/// By default results are yielded in order of completion, use [`ConcurrentExecution::preserve_order`]
+/// to get them in the order of the input values.
+pub struct ConcurrentExecution<'a> {
+    session: &'a Session,
+    prepared: &'a PreparedStatement,
+    values: Option<ValuesIter<'a>>,
+    concurrency: usize,
+    preserve_order: bool,
+    stop_on_error: bool,
+    results: Option<BoxStream<'a, ConcurrentExecutionItem>>,
+}
+
+impl<'a> ConcurrentExecution<'a> {
+    pub(crate) fn new(
+        session: &'a Session,
+        prepared: &'a PreparedStatement,
+        values: ValuesIter<'a>,
+        concurrency: usize,
+    ) -> Self {
+        Self {
+            session,
+            prepared,
+            values: Some(values),
+            // Zero would never start any request
+            concurrency: concurrency.max(1),
+            preserve_order: false,
+            stop_on_error: false,
+            results: None,
+        }
+    }
+
+    /// Yields results in the order of the input values instead of the order of completion.\
+    /// A slow request holds back the results that come after it, but no more than
+    /// `concurrency` requests are run at once anyway.
+    ///
+    /// Has no effect once the stream was polled.
+    pub fn preserve_order(mut self, preserve_order: bool) -> Self {
+        self.preserve_order = preserve_order;
+        self
+    }
+
+    /// Stops starting new requests after the first failed one.\
+    /// Requests that were already running are finished and their results are still yielded.
+    ///
+    /// Has no effect once the stream was polled.
+    pub fn stop_on_error(mut self, stop_on_error: bool) -> Self {
+        self.stop_on_error = stop_on_error;
+        self
+    }
+
+    /// Runs all requests and returns the errors along with indexes of the values that caused them,
+    /// sorted by index.
+    pub async fn collect_errors(self) -> Vec<(usize, QueryError)> {
+        let mut errors: Vec<(usize, QueryError)> = self
+            .filter_map(|item| async move { item.result.err().map(|err| (item.index, err)) })
+            .collect()
+            .await;
+        errors.sort_by_key(|(index, _)| *index);
+        errors
+    }
+
+    fn start(&mut self, values: ValuesIter<'a>) -> BoxStream<'a, ConcurrentExecutionItem> {
+        let session = self.session;
+        let prepared = self.prepared;
+        let stop_on_error = self.stop_on_error;
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let stopped_ref = stopped.clone();
+        let requests = stream::iter(
+            values
+                .enumerate()
+                .take_while(move |_| !stopped_ref.load(Ordering::Relaxed)),
+        )
+        .map(move |(index, values)| {
+            let stopped = stopped.clone();
+            async move {
+                let result = match values {
+                    Ok(values) => session.execute(prepared, values).await,
+                    Err(err) => Err(err.into()),
+                };
+                if stop_on_error && result.is_err() {
+                    stopped.store(true, Ordering::Relaxed);
+                }
+                ConcurrentExecutionItem { index, result }
+            }
+        });
+
+        if self.preserve_order {
+            requests.buffered(self.concurrency).boxed()
+        } else {
+            requests.buffer_unordered(self.concurrency).boxed()
+        }
+    }
+}
+
+impl<'a> Stream for ConcurrentExecution<'a> {
+    type Item = ConcurrentExecutionItem;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(values) = self.values.take() {
+            let results = self.start(values);
+            self.results = Some(results);
+        }
+
+        match &mut self.results {
+            Some(results) => results.poll_next_unpin(cx),
+            None => Poll::Ready(None),
+        }
+    }
+}
@@ -2,19 +2,18 @@ use bytes::Bytes;
 use futures::{future::RemoteHandle, FutureExt};
 use tokio::io::{split, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
 use tokio::net::{TcpSocket, TcpStream};
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, Notify};
 use tokio::time::Instant;
 use tracing::{debug, error, trace, warn};
 use uuid::Uuid;
 
-#[cfg(feature = "ssl")]
-use openssl::ssl::{Ssl, SslContext};
-#[cfg(feature = "ssl")]
-use std::pin::Pin;
-use std::sync::atomic::AtomicU64;
-#[cfg(feature = "ssl")]
-use tokio_openssl::SslStream;
+#[cfg(any(feature = "ssl", feature = "rustls"))]
+use super::tls::TlsContext;
+#[cfg(feature = "cloud")]
+use crate::cloud::{CloudConfig, CloudNode};
+use std::sync::atomic::{AtomicU64, AtomicUsize};
 
+use std::borrow::Cow;
 use std::collections::{BTreeSet, HashMap};
 use std::convert::TryFrom;
 use std::io::ErrorKind;
@@ -29,7 +28,9 @@ use std::{
 use super::errors::{BadKeyspaceName, BadQuery, DbError, QueryError};
 
 use crate::batch::{Batch, BatchStatement};
+use crate::frame::frame_errors::ParseError;
 use crate::frame::protocol_features::ProtocolFeatures;
+use crate::frame::response::result::ResultMetadata;
 use crate::frame::{
     self,
     request::{self, batch, execute, query, register, Request},
@@ -43,6 +44,8 @@ use crate::routing::ShardInfo;
 use crate::statement::prepared_statement::PreparedStatement;
 use crate::statement::Consistency;
 use crate::transport::session::IntoTypedRows;
+#[cfg(feature = "trace-context")]
+use crate::transport::trace_context::TraceContextPropagator;
 use crate::transport::Authenticator;
 use crate::transport::Authenticator::{
     AllowAllAuthenticator, CassandraAllowAllAuthenticator, CassandraPasswordAuthenticator,
@@ -66,6 +69,9 @@ const LOCAL_VERSION: &str = "SELECT schema_version FROM system.local WHERE key='
 const OLD_ORPHAN_COUNT_THRESHOLD: usize = 1024;
 const OLD_AGE_ORPHAN_THRESHOLD: std::time::Duration = std::time::Duration::from_secs(1);
 
+// Number of stream ids available on a single connection
+const MAX_STREAMS: usize = i16::MAX as usize + 1;
+
 pub struct Connection {
     submit_channel: mpsc::Sender<Task>,
     _worker_handle: RemoteHandle<()>,
@@ -83,6 +89,8 @@ pub struct Connection {
     // pushing values in a synchronous way (without an `.await`), which is
     // needed for pushing values in `Drop` implementations.
     orphan_notification_sender: mpsc::UnboundedSender<RequestId>,
+    // Number of requests waiting for a response, each of them uses a stream id
+    in_flight_requests: AtomicUsize,
 }
 
 #[derive(Default)]
@@ -107,6 +115,36 @@ struct OrphanhoodNotifier<'a> {
     notification_sender: &'a mpsc::UnboundedSender<RequestId>,
 }
 
+// Counts a request as in flight for as long as it exists
+struct InFlightRequest<'a> {
+    counter: &'a AtomicUsize,
+    availability_notify: Option<&'a Notify>,
+}
+
+impl<'a> InFlightRequest<'a> {
+    fn new(counter: &'a AtomicUsize, availability_notify: Option<&'a Notify>) -> Self {
+        counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Self {
+            counter,
+            availability_notify,
+        }
+    }
+}
+
+impl<'a> Drop for InFlightRequest<'a> {
+    fn drop(&mut self) {
+        let in_flight = self
+            .counter
+            .fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+        // A stream id was freed on a connection which had none available
+        if in_flight >= MAX_STREAMS {
+            if let Some(notify) = self.availability_notify {
+                notify.notify_waiters();
+            }
+        }
+    }
+}
+
 impl<'a> OrphanhoodNotifier<'a> {
     fn new(
         request_id: RequestId,
@@ -147,6 +185,9 @@ pub struct QueryResponse {
     pub response: Response,
     pub tracing_id: Option<Uuid>,
     pub warnings: Vec<String>,
+    pub custom_payload: Option<HashMap<String, Bytes>>,
+    // Size of the uncompressed response body
+    pub body_size: usize,
 }
 
 // A QueryResponse in which response can not be Response::Error
@@ -154,6 +195,7 @@ pub struct NonErrorQueryResponse {
     pub response: NonErrorResponse,
     pub tracing_id: Option<Uuid>,
     pub warnings: Vec<String>,
+    pub custom_payload: Option<HashMap<String, Bytes>>,
 }
 
 impl QueryResponse {
@@ -162,6 +204,7 @@ impl QueryResponse {
             response: self.response.into_non_error_response()?,
             tracing_id: self.tracing_id,
             warnings: self.warnings,
+            custom_payload: self.custom_payload,
         })
     }
 
@@ -203,6 +246,7 @@ impl NonErrorQueryResponse {
         Ok(QueryResult {
             rows,
             warnings: self.warnings,
+            custom_payload: self.custom_payload,
             tracing_id: self.tracing_id,
             paging_state,
             col_specs,
@@ -214,14 +258,24 @@ impl NonErrorQueryResponse {
 pub struct ConnectionConfig {
     pub compression: Option<Compression>,
     pub tcp_nodelay: bool,
-    #[cfg(feature = "ssl")]
-    pub ssl_context: Option<SslContext>,
+    #[cfg(any(feature = "ssl", feature = "rustls"))]
+    pub tls_context: Option<TlsContext>,
+    #[cfg(feature = "cloud")]
+    pub cloud_config: Option<Arc<CloudConfig>>,
+    // Node to connect to through the proxy, set in pools of nodes of cloud clusters
+    #[cfg(feature = "cloud")]
+    pub(crate) cloud_node: Option<CloudNode>,
     pub auth_username: Option<String>,
     pub auth_password: Option<String>,
     pub connect_timeout: std::time::Duration,
     // should be Some only in control connections,
     pub event_sender: Option<mpsc::Sender<Event>>,
     pub default_consistency: Consistency,
+    #[cfg(feature = "trace-context")]
+    pub trace_context_propagator: Option<Arc<dyn TraceContextPropagator>>,
+    // Notified when a connection which had no free stream ids frees one,
+    // or when connections of a pool change
+    pub(crate) availability_notify: Option<Arc<Notify>>,
 }
 
 impl Default for ConnectionConfig {
@@ -230,26 +284,44 @@ impl Default for ConnectionConfig {
             compression: None,
             tcp_nodelay: true,
             event_sender: None,
-            #[cfg(feature = "ssl")]
-            ssl_context: None,
+            #[cfg(any(feature = "ssl", feature = "rustls"))]
+            tls_context: None,
+            #[cfg(feature = "cloud")]
+            cloud_config: None,
+            #[cfg(feature = "cloud")]
+            cloud_node: None,
             auth_username: None,
             auth_password: None,
             connect_timeout: std::time::Duration::from_secs(5),
             default_consistency: Default::default(),
+            #[cfg(feature = "trace-context")]
+            trace_context_propagator: None,
+            availability_notify: None,
         }
     }
 }
 
 impl ConnectionConfig {
-    #[cfg(feature = "ssl")]
+    #[cfg(any(feature = "ssl", feature = "rustls"))]
     pub fn is_ssl(&self) -> bool {
-        self.ssl_context.is_some()
+        self.tls_context.is_some() || self.is_cloud()
     }
 
-    #[cfg(not(feature = "ssl"))]
+    #[cfg(not(any(feature = "ssl", feature = "rustls")))]
     pub fn is_ssl(&self) -> bool {
         false
     }
+
+    /// Whether connections go through the SNI proxies of a cloud cluster
+    #[cfg(feature = "cloud")]
+    pub fn is_cloud(&self) -> bool {
+        self.cloud_config.is_some()
+    }
+
+    #[cfg(not(feature = "cloud"))]
+    pub fn is_cloud(&self) -> bool {
+        false
+    }
 }
 
 // Used to listen for fatal error in connection
@@ -262,6 +334,23 @@ impl Connection {
         source_port: Option<u16>,
         config: ConnectionConfig,
     ) -> Result<(Self, ErrorReceiver), QueryError> {
+        // Connections to a cloud cluster go through the SNI proxy of the node's datacenter
+        #[cfg(feature = "cloud")]
+        if let Some(cloud_config) = &config.cloud_config {
+            let stream_connector = tokio::time::timeout(
+                config.connect_timeout,
+                cloud_config.connect(config.cloud_node.as_ref(), config.tcp_nodelay),
+            )
+            .await;
+            let stream = match stream_connector {
+                Ok(stream) => stream?,
+                Err(_) => {
+                    return Err(QueryError::TimeoutError);
+                }
+            };
+            return Ok(Self::with_stream(addr, config, stream));
+        }
+
         let stream_connector = match source_port {
             Some(p) => {
                 tokio::time::timeout(config.connect_timeout, connect_with_source_port(addr, p))
@@ -277,20 +366,36 @@ impl Connection {
         };
         stream.set_nodelay(config.tcp_nodelay)?;
 
+        #[cfg(any(feature = "ssl", feature = "rustls"))]
+        if let Some(context) = &config.tls_context {
+            let stream = context.connect(addr, stream).await?;
+            return Ok(Self::with_stream(addr, config, stream));
+        }
+
+        Ok(Self::with_stream(addr, config, stream))
+    }
+
+    // Spawns the router working on an established stream
+    fn with_stream(
+        addr: SocketAddr,
+        config: ConnectionConfig,
+        stream: impl AsyncRead + AsyncWrite + Send + 'static,
+    ) -> (Self, ErrorReceiver) {
         // TODO: What should be the size of the channel?
         let (sender, receiver) = mpsc::channel(1024);
         let (error_sender, error_receiver) = tokio::sync::oneshot::channel();
         // Unbounded because it allows for synchronous pushes
         let (orphan_notification_sender, orphan_notification_receiver) = mpsc::unbounded_channel();
 
-        let _worker_handle = Self::run_router(
+        let (task, _worker_handle) = Self::router(
             config.clone(),
             stream,
             receiver,
             error_sender,
             orphan_notification_receiver,
         )
-        .await?;
+        .remote_handle();
+        tokio::task::spawn(task);
 
         let connection = Connection {
             submit_channel: sender,
@@ -299,22 +404,23 @@ impl Connection {
             features: Default::default(),
             connect_address: addr,
             request_id_generator: AtomicU64::new(0),
+            in_flight_requests: AtomicUsize::new(0),
             orphan_notification_sender,
         };
 
-        Ok((connection, error_receiver))
+        (connection, error_receiver)
     }
 
     pub async fn startup(&self, options: HashMap<String, String>) -> Result<Response, QueryError> {
         Ok(self
-            .send_request(&request::Startup { options }, false, false)
+            .send_request(&request::Startup { options }, false, false, None)
             .await?
             .response)
     }
 
     pub async fn get_options(&self) -> Result<Response, QueryError> {
         Ok(self
-            .send_request(&request::Options {}, false, false)
+            .send_request(&request::Options {}, false, false, None)
             .await?
             .response)
     }
@@ -327,18 +433,28 @@ impl Connection {
                 },
                 true,
                 query.config.tracing,
+                None,
             )
             .await?;
 
         let mut prepared_statement = match query_response.response {
             Response::Error(err) => return Err(err.into()),
-            Response::Result(result::Result::Prepared(p)) => PreparedStatement::new(
-                p.id,
-                p.prepared_metadata,
-                query.contents.clone(),
-                query.get_page_size(),
-                query.config.clone(),
-            ),
+            Response::Result(result::Result::Prepared(p)) => {
+                let is_lwt = self
+                    .features
+                    .protocol_features
+                    .prepared_flags_contain_lwt_mark(p.prepared_metadata.flags as u32);
+                let mut prepared_statement = PreparedStatement::new(
+                    p.id,
+                    p.prepared_metadata,
+                    p.result_metadata,
+                    query.contents.clone(),
+                    query.get_page_size(),
+                    query.config.clone(),
+                );
+                prepared_statement.set_is_confirmed_lwt(is_lwt);
+                prepared_statement
+            }
             _ => {
                 return Err(QueryError::ProtocolError(
                     "PREPARE: Unexpected server response",
@@ -366,6 +482,8 @@ impl Connection {
                 "Prepared statement Id changed, md5 sum should stay the same",
             ))
         } else {
+            // The schema might have changed, and with it the result metadata
+            previous_prepared.update_result_metadata(reprepared.get_result_metadata());
             Ok(())
         }
     }
@@ -384,6 +502,7 @@ impl Connection {
             },
             false,
             false,
+            None,
         )
         .await
     }
@@ -436,6 +555,25 @@ impl Connection {
         values: impl ValueList,
         consistency: Consistency,
         paging_state: Option<Bytes>,
+    ) -> Result<QueryResponse, QueryError> {
+        self.query_with_page_size(
+            query,
+            values,
+            consistency,
+            query.get_page_size(),
+            paging_state,
+        )
+        .await
+    }
+
+    // Same as query_with_consistency, but overrides the page size set in the query
+    pub(crate) async fn query_with_page_size(
+        &self,
+        query: &Query,
+        values: impl ValueList,
+        consistency: Consistency,
+        page_size: Option<i32>,
+        paging_state: Option<Bytes>,
     ) -> Result<QueryResponse, QueryError> {
         let serialized_values = values.serialized()?;
 
@@ -445,14 +583,21 @@ impl Connection {
                 consistency,
                 serial_consistency: query.get_serial_consistency(),
                 values: &serialized_values,
-                page_size: query.get_page_size(),
+                page_size,
                 paging_state,
                 timestamp: query.get_timestamp(),
+                skip_metadata: false,
             },
         };
 
-        self.send_request(&query_frame, true, query.config.tracing)
-            .await
+        let custom_payload = self.custom_payload(query.config.custom_payload.as_ref());
+        self.send_request(
+            &query_frame,
+            true,
+            query.config.tracing,
+            custom_payload.as_deref(),
+        )
+        .await
     }
 
     /// Performs query_single_page multiple times to query all available pages
@@ -547,39 +692,90 @@ impl Connection {
         values: impl ValueList,
         consistency: Consistency,
         paging_state: Option<Bytes>,
+    ) -> Result<QueryResponse, QueryError> {
+        self.execute_with_page_size(
+            prepared_statement,
+            values,
+            consistency,
+            prepared_statement.get_page_size(),
+            paging_state,
+        )
+        .await
+    }
+
+    // Same as execute_with_consistency, but overrides the page size set in the statement
+    pub(crate) async fn execute_with_page_size(
+        &self,
+        prepared_statement: &PreparedStatement,
+        values: impl ValueList,
+        consistency: Consistency,
+        page_size: Option<i32>,
+        paging_state: Option<Bytes>,
     ) -> Result<QueryResponse, QueryError> {
         let serialized_values = values.serialized()?;
+        let result_metadata = prepared_statement.cached_result_metadata();
 
-        let execute_frame = execute::Execute {
+        let mut execute_frame = execute::Execute {
             id: prepared_statement.get_id().to_owned(),
             parameters: query::QueryParameters {
                 consistency,
                 serial_consistency: prepared_statement.get_serial_consistency(),
                 values: &serialized_values,
-                page_size: prepared_statement.get_page_size(),
+                page_size,
                 timestamp: prepared_statement.get_timestamp(),
                 paging_state,
+                skip_metadata: result_metadata.is_some(),
             },
         };
 
-        let query_response = self
-            .send_request(&execute_frame, true, prepared_statement.config.tracing)
+        let custom_payload = self.custom_payload(prepared_statement.config.custom_payload.as_ref());
+        let task_response = self
+            .send_request_raw(
+                &execute_frame,
+                true,
+                prepared_statement.config.tracing,
+                custom_payload.as_deref(),
+            )
             .await?;
+        let query_response = Self::parse_response_with_result_metadata(
+            task_response,
+            self.config.compression,
+            &self.features.protocol_features,
+            result_metadata.as_deref(),
+        )?;
 
-        match &query_response.response {
-            Response::Error(frame::response::Error {
-                error: DbError::Unprepared { statement_id },
+        match query_response {
+            Some(QueryResponse {
+                response:
+                    Response::Error(frame::response::Error {
+                        error: DbError::Unprepared { statement_id },
+                        ..
+                    }),
                 ..
             }) => {
                 debug!("Connection::execute: Got DbError::Unprepared - repreparing statement with id {:?}", statement_id);
-                // Repreparation of a statement is needed
-                self.reprepare(prepared_statement.get_statement(), prepared_statement)
-                    .await?;
-                self.send_request(&execute_frame, true, prepared_statement.config.tracing)
-                    .await
             }
-            _ => Ok(query_response),
+            Some(query_response) => return Ok(query_response),
+            None => {
+                debug!(
+                    "Connection::execute: Cached result metadata is outdated - repreparing statement with id {:?}",
+                    prepared_statement.get_id()
+                );
+            }
         }
+
+        // Repreparation of a statement is needed. It's sent again along with
+        // the result metadata, in case the schema changes once more in the meantime.
+        self.reprepare(prepared_statement.get_statement(), prepared_statement)
+            .await?;
+        execute_frame.parameters.skip_metadata = false;
+        self.send_request(
+            &execute_frame,
+            true,
+            prepared_statement.config.tracing,
+            custom_payload.as_deref(),
+        )
+        .await
     }
 
     /// Performs execute_single_page multiple times to fetch all available pages
@@ -666,9 +862,15 @@ impl Connection {
             timestamp: batch.get_timestamp(),
         };
 
+        let custom_payload = self.custom_payload(batch.config.custom_payload.as_ref());
         loop {
             let query_response = self
-                .send_request(&batch_frame, true, batch.config.tracing)
+                .send_request(
+                    &batch_frame,
+                    true,
+                    batch.config.tracing,
+                    custom_payload.as_deref(),
+                )
                 .await?;
 
             return match query_response.response {
@@ -741,7 +943,7 @@ impl Connection {
         };
 
         match self
-            .send_request(&register_frame, true, false)
+            .send_request(&register_frame, true, false, None)
             .await?
             .response
         {
@@ -766,24 +968,87 @@ impl Connection {
         Ok(version_id)
     }
 
+    // Custom payload attached to QUERY, EXECUTE and BATCH requests:
+    // the one set on the statement, extended with the trace context
+    #[cfg(feature = "trace-context")]
+    fn custom_payload<'a>(
+        &self,
+        statement_payload: Option<&'a HashMap<String, Bytes>>,
+    ) -> Option<Cow<'a, HashMap<String, Bytes>>> {
+        let propagator = match self.config.trace_context_propagator.as_ref() {
+            Some(propagator) => propagator,
+            None => return statement_payload.map(Cow::Borrowed),
+        };
+        let mut payload = statement_payload.cloned().unwrap_or_default();
+        // Requests are sent from futures instrumented with the span of the attempt
+        propagator.inject(&tracing::Span::current(), &mut payload);
+        if payload.is_empty() && statement_payload.is_none() {
+            None
+        } else {
+            Some(Cow::Owned(payload))
+        }
+    }
+
+    #[cfg(not(feature = "trace-context"))]
+    fn custom_payload<'a>(
+        &self,
+        statement_payload: Option<&'a HashMap<String, Bytes>>,
+    ) -> Option<Cow<'a, HashMap<String, Bytes>>> {
+        statement_payload.map(Cow::Borrowed)
+    }
+
     fn allocate_request_id(&self) -> RequestId {
         self.request_id_generator
             .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
     }
 
+    /// Returns true if a new request can be sent without waiting for a free stream id.
+    /// Stream ids of requests which were cancelled are not taken into account,
+    /// as they are freed once the response arrives.
+    #[cfg(feature = "tower")]
+    pub(crate) fn has_available_streams(&self) -> bool {
+        self.in_flight_requests
+            .load(std::sync::atomic::Ordering::Relaxed)
+            < MAX_STREAMS
+    }
+
     async fn send_request<R: Request>(
         &self,
         request: &R,
         compress: bool,
         tracing: bool,
+        custom_payload: Option<&HashMap<String, Bytes>>,
     ) -> Result<QueryResponse, QueryError> {
+        let task_response = self
+            .send_request_raw(request, compress, tracing, custom_payload)
+            .await?;
+        Self::parse_response(
+            task_response,
+            self.config.compression,
+            &self.features.protocol_features,
+        )
+    }
+
+    // Sends the request and waits for the response, without parsing it
+    async fn send_request_raw<R: Request>(
+        &self,
+        request: &R,
+        compress: bool,
+        tracing: bool,
+        custom_payload: Option<&HashMap<String, Bytes>>,
+    ) -> Result<TaskResponse, QueryError> {
         let compression = if compress {
             self.config.compression
         } else {
             None
         };
-        let serialized_request = SerializedRequest::make(request, compression, tracing)?;
+        let serialized_request =
+            SerializedRequest::make(request, compression, tracing, custom_payload)?;
         let request_id = self.allocate_request_id();
+        let _in_flight = InFlightRequest::new(
+            &self.in_flight_requests,
+            self.config.availability_notify.as_deref(),
+        );
 
         let (response_sender, receiver) = oneshot::channel();
         let response_handler = ResponseHandler {
@@ -820,11 +1085,7 @@ impl Connection {
         // notification about orphaning.
         notifier.disable();
 
-        Self::parse_response(
-            task_response?,
-            self.config.compression,
-            &self.features.protocol_features,
-        )
+        task_response
     }
 
     fn parse_response(
@@ -832,6 +1093,20 @@ impl Connection {
         compression: Option<Compression>,
         features: &ProtocolFeatures,
     ) -> Result<QueryResponse, QueryError> {
+        Self::parse_response_with_result_metadata(task_response, compression, features, None)?
+            .ok_or(QueryError::ProtocolError(
+                "Result metadata was skipped, although it was not requested",
+            ))
+    }
+
+    // Rows sent without metadata are deserialized using the given result metadata.
+    // Returns None if it doesn't match the rows, which means it's outdated.
+    fn parse_response_with_result_metadata(
+        task_response: TaskResponse,
+        compression: Option<Compression>,
+        features: &ProtocolFeatures,
+        result_metadata: Option<&ResultMetadata>,
+    ) -> Result<Option<QueryResponse>, QueryError> {
         let body_with_ext = frame::parse_response_body_extensions(
             task_response.params.flags,
             compression,
@@ -842,51 +1117,24 @@ impl Connection {
             warn!(warning = warn_description.as_str());
         }
 
-        let response =
-            Response::deserialize(features, task_response.opcode, &mut &*body_with_ext.body)?;
+        let response = match Response::deserialize_with_result_metadata(
+            features,
+            task_response.opcode,
+            &mut &*body_with_ext.body,
+            result_metadata,
+        ) {
+            Ok(response) => response,
+            Err(ParseError::ResultMetadataMismatch(_)) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
 
-        Ok(QueryResponse {
+        Ok(Some(QueryResponse {
             response,
             warnings: body_with_ext.warnings,
+            custom_payload: body_with_ext.custom_payload,
             tracing_id: body_with_ext.trace_id,
-        })
-    }
-
-    async fn run_router(
-        config: ConnectionConfig,
-        stream: TcpStream,
-        receiver: mpsc::Receiver<Task>,
-        error_sender: tokio::sync::oneshot::Sender<QueryError>,
-        orphan_notification_receiver: mpsc::UnboundedReceiver<RequestId>,
-    ) -> Result<RemoteHandle<()>, std::io::Error> {
-        #[cfg(feature = "ssl")]
-        if let Some(context) = &config.ssl_context {
-            let ssl = Ssl::new(context)?;
-            let mut stream = SslStream::new(ssl, stream)?;
-            let _pin = Pin::new(&mut stream).connect().await;
-
-            let (task, handle) = Self::router(
-                config,
-                stream,
-                receiver,
-                error_sender,
-                orphan_notification_receiver,
-            )
-            .remote_handle();
-            tokio::task::spawn(task);
-            return Ok(handle);
-        }
-
-        let (task, handle) = Self::router(
-            config,
-            stream,
-            receiver,
-            error_sender,
-            orphan_notification_receiver,
-        )
-        .remote_handle();
-        tokio::task::spawn(task);
-        Ok(handle)
+            body_size: body_with_ext.body.len(),
+        }))
     }
 
     async fn router(
@@ -1144,6 +1392,11 @@ impl Connection {
         self.features.shard_aware_port
     }
 
+    #[cfg(test)]
+    pub(crate) fn get_protocol_features(&self) -> &ProtocolFeatures {
+        &self.features.protocol_features
+    }
+
     fn set_features(&mut self, features: ConnectionFeatures) {
         self.features = features;
     }
@@ -1151,6 +1404,10 @@ impl Connection {
     pub fn get_connect_address(&self) -> SocketAddr {
         self.connect_address
     }
+
+    pub(crate) fn is_cloud(&self) -> bool {
+        self.config.is_cloud()
+    }
 }
 
 pub async fn open_connection(
@@ -1201,7 +1458,9 @@ pub async fn open_named_connection(
         .unwrap_or_default()
         .into_iter()
         .next()
-        .and_then(|p| p.parse::<u16>().ok());
+        .and_then(|p| p.parse::<u16>().ok())
+        // Source ports aren't preserved by the SNI proxies of cloud clusters
+        .filter(|_| !config.is_cloud());
 
     let protocol_features = ProtocolFeatures::parse_from_supported(&supported.options);
 
@@ -1447,7 +1706,7 @@ struct StreamIdSet {
 
 impl StreamIdSet {
     pub fn new() -> Self {
-        const BITMAP_SIZE: usize = (std::i16::MAX as usize + 1) / 64;
+        const BITMAP_SIZE: usize = MAX_STREAMS / 64;
         Self {
             used_bitmap: vec![0; BITMAP_SIZE].into_boxed_slice(),
         }
@@ -3,9 +3,13 @@ use crate::transport::errors::QueryError;
 use crate::transport::{
     connection,
     connection::{Connection, ConnectionConfig, ErrorReceiver, VerifiedKeyspaceName},
+    metrics::Metrics,
+    reconnection_policy::{
+        ExponentialReconnectionPolicy, ReconnectionPolicy, ReconnectionSchedule,
+    },
 };
 
-use arc_swap::ArcSwap;
+use arc_swap::{ArcSwap, ArcSwapOption};
 use futures::{future::RemoteHandle, stream::FuturesUnordered, Future, FutureExt, StreamExt};
 use rand::Rng;
 use std::convert::TryInto;
@@ -15,7 +19,7 @@ use std::num::NonZeroUsize;
 use std::pin::Pin;
 use std::sync::{Arc, Weak};
 use std::time::Duration;
-use tokio::sync::{mpsc, Notify};
+use tokio::sync::{mpsc, oneshot, Notify};
 use tracing::{debug, trace, warn};
 
 /// The target size of a per-node connection pool.
@@ -48,6 +52,8 @@ pub struct PoolConfig {
     pub pool_size: PoolSize,
     pub can_use_shard_aware_port: bool,
     pub keepalive_interval: Option<Duration>,
+    pub reconnection_policy: Arc<dyn ReconnectionPolicy>,
+    pub metrics: Arc<Metrics>,
 }
 
 impl Default for PoolConfig {
@@ -57,10 +63,31 @@ impl Default for PoolConfig {
             pool_size: Default::default(),
             can_use_shard_aware_port: true,
             keepalive_interval: None,
+            reconnection_policy: Arc::new(ExponentialReconnectionPolicy::default()),
+            metrics: Arc::new(Metrics::new()),
         }
     }
 }
 
+/// State of the connection pool to a single node, as returned by [`Session::pool_status`](crate::Session::pool_status)
+#[derive(Debug, Clone)]
+pub struct NodePoolStatus {
+    pub address: SocketAddr,
+    pub datacenter: Option<String>,
+
+    /// Number of connections to each shard of the node.
+    /// Nodes which aren't sharded (e.g. Cassandra nodes) are reported as having one shard.
+    /// Empty if there are no connections, or the node was rejected by the host filter.
+    pub shard_connections: Vec<usize>,
+
+    /// Whether the pool reached its target size, see [`PoolSize`]
+    pub is_full: bool,
+
+    /// The last error which occurred while opening a connection to the node,
+    /// or which broke one of its connections. It's cleared once the pool is full again.
+    pub last_error: Option<QueryError>,
+}
+
 enum MaybePoolConnections {
     // The pool is being filled for the first time
     Initializing,
@@ -138,7 +165,10 @@ impl std::fmt::Debug for PoolConnections {
 
 pub struct NodeConnectionPool {
     conns: Arc<ArcSwap<MaybePoolConnections>>,
+    pool_size: PoolSize,
+    last_error: Arc<ArcSwapOption<QueryError>>,
     use_keyspace_request_sender: mpsc::Sender<UseKeyspaceRequest>,
+    close_request_sender: mpsc::Sender<oneshot::Sender<()>>,
     _refiller_handle: RemoteHandle<()>,
     _keepaliver_handle: Option<RemoteHandle<()>>,
     pool_updated_notify: Arc<Notify>,
@@ -160,9 +190,11 @@ impl NodeConnectionPool {
         current_keyspace: Option<VerifiedKeyspaceName>,
     ) -> Self {
         let (use_keyspace_request_sender, use_keyspace_request_receiver) = mpsc::channel(1);
+        let (close_request_sender, close_request_receiver) = mpsc::channel(1);
         let pool_updated_notify = Arc::new(Notify::new());
 
         let keepalive_interval = pool_config.keepalive_interval;
+        let pool_size = pool_config.pool_size.clone();
 
         let refiller = PoolRefiller::new(
             address,
@@ -171,9 +203,12 @@ impl NodeConnectionPool {
             current_keyspace,
             pool_updated_notify.clone(),
         );
+        let last_error = refiller.get_shared_last_error();
 
         let conns = refiller.get_shared_connections();
-        let (fut, refiller_handle) = refiller.run(use_keyspace_request_receiver).remote_handle();
+        let (fut, refiller_handle) = refiller
+            .run(use_keyspace_request_receiver, close_request_receiver)
+            .remote_handle();
         tokio::spawn(fut);
 
         let keepaliver_handle = if let Some(interval) = keepalive_interval {
@@ -193,7 +228,10 @@ impl NodeConnectionPool {
 
         Self {
             conns,
+            pool_size,
+            last_error,
             use_keyspace_request_sender,
+            close_request_sender,
             _refiller_handle: refiller_handle,
             _keepaliver_handle: keepaliver_handle,
             pool_updated_notify,
@@ -286,18 +324,38 @@ impl NodeConnectionPool {
     ) -> Result<(), QueryError> {
         let (response_sender, response_receiver) = tokio::sync::oneshot::channel();
 
-        self.use_keyspace_request_sender
+        if self
+            .use_keyspace_request_sender
             .send(UseKeyspaceRequest {
                 keyspace_name,
                 response_sender,
             })
             .await
-            .expect("Bug in ConnectionKeeper::use_keyspace sending");
-        // Other end of this channel is in the Refiller, can't be dropped while we have &self to _refiller_handle
+            .is_err()
+        {
+            // Other end of this channel is in the Refiller, which stops only when the pool is closed
+            return Err(QueryError::SessionClosed);
+        }
 
         response_receiver.await.unwrap() // NodePoolRefiller always responds
     }
 
+    // Closes all connections of the pool and stops refilling it.
+    // Connections still used by some requests are closed when the requests finish.
+    pub(crate) async fn close(&self) {
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        // The refiller is already stopped if the pool was closed before
+        if self
+            .close_request_sender
+            .send(response_sender)
+            .await
+            .is_ok()
+        {
+            let _ = response_receiver.await;
+        }
+    }
+
     // Waits until the pool becomes initialized.
     // The pool is considered initialized either if the first connection has been
     // established or after first filling ends, whichever comes first.
@@ -312,6 +370,57 @@ impl NodeConnectionPool {
         }
     }
 
+    // Waits until the pool reaches its target size
+    pub async fn wait_until_full(&self) {
+        loop {
+            // Register for the notification first, so that we don't miss it
+            let notified = self.pool_updated_notify.notified();
+            if self.is_full() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    // Number of connections to each shard, empty if there are no connections
+    pub fn shard_connection_counts(&self) -> Vec<usize> {
+        self.with_connections(|pool_conns| match pool_conns {
+            PoolConnections::NotSharded(conns) => vec![conns.len()],
+            PoolConnections::Sharded { connections, .. } => {
+                connections.iter().map(Vec::len).collect()
+            }
+        })
+        .unwrap_or_default()
+    }
+
+    // Whether the pool is being filled for the first time
+    pub fn is_initializing(&self) -> bool {
+        matches!(**self.conns.load(), MaybePoolConnections::Initializing)
+    }
+
+    // Whether the pool has at least one connection
+    pub fn is_connected(&self) -> bool {
+        matches!(**self.conns.load(), MaybePoolConnections::Ready(_))
+    }
+
+    pub fn is_full(&self) -> bool {
+        let counts = self.shard_connection_counts();
+        if counts.is_empty() {
+            return false;
+        }
+        match self.pool_size {
+            PoolSize::PerHost(target) => counts.iter().sum::<usize>() >= target.get(),
+            PoolSize::PerShard(target) => counts.iter().all(|&count| count >= target.get()),
+        }
+    }
+
+    pub fn last_error(&self) -> Option<QueryError> {
+        self.last_error
+            .load()
+            .as_ref()
+            .map(|err| QueryError::clone(err))
+    }
+
     pub fn get_working_connections(&self) -> Result<Vec<Arc<Connection>>, QueryError> {
         self.with_connections(|pool_conns| match pool_conns {
             PoolConnections::NotSharded(conns) => conns.clone(),
@@ -446,39 +555,6 @@ impl Keepaliver {
 
 const EXCESS_CONNECTION_BOUND_PER_SHARD_MULTIPLIER: usize = 10;
 
-// TODO: Make it configurable through a policy (issue #184)
-const MIN_FILL_BACKOFF: Duration = Duration::from_millis(50);
-const MAX_FILL_BACKOFF: Duration = Duration::from_secs(10);
-const FILL_BACKOFF_MULTIPLIER: u32 = 2;
-
-// A simple exponential strategy for pool fill backoffs.
-struct RefillDelayStrategy {
-    current_delay: Duration,
-}
-
-impl RefillDelayStrategy {
-    fn new() -> Self {
-        Self {
-            current_delay: MIN_FILL_BACKOFF,
-        }
-    }
-
-    fn get_delay(&self) -> Duration {
-        self.current_delay
-    }
-
-    fn on_successful_fill(&mut self) {
-        self.current_delay = MIN_FILL_BACKOFF;
-    }
-
-    fn on_fill_error(&mut self) {
-        self.current_delay = std::cmp::min(
-            MAX_FILL_BACKOFF,
-            self.current_delay * FILL_BACKOFF_MULTIPLIER,
-        );
-    }
-}
-
 struct PoolRefiller {
     // Following information identify the pool and do not change
     address: IpAddr,
@@ -493,11 +569,15 @@ struct PoolRefiller {
     shared_conns: Arc<ArcSwap<MaybePoolConnections>>,
     conns: Vec<Vec<Arc<Connection>>>,
 
+    // The last error of opening or using a connection, reported in the pool status
+    shared_last_error: Arc<ArcSwapOption<QueryError>>,
+
     // Set to true if there was an error since the last refill,
     // set to false when refilling starts.
     had_error_since_last_refill: bool,
 
-    refill_delay_strategy: RefillDelayStrategy,
+    // Delays of refills after errors, created again after a refill without errors
+    reconnection_schedule: Box<dyn ReconnectionSchedule>,
 
     // Receives information about connections becoming ready, i.e. newly connected
     // or after its keyspace was correctly set.
@@ -547,6 +627,7 @@ impl PoolRefiller {
         // and assume that the node is a Cassandra node
         let conns = vec![Vec::new()];
         let shared_conns = Arc::new(ArcSwap::new(Arc::new(MaybePoolConnections::Initializing)));
+        let reconnection_schedule = pool_config.reconnection_policy.new_schedule();
 
         Self {
             address,
@@ -559,8 +640,10 @@ impl PoolRefiller {
             shared_conns,
             conns,
 
+            shared_last_error: Arc::new(ArcSwapOption::empty()),
+
             had_error_since_last_refill: false,
-            refill_delay_strategy: RefillDelayStrategy::new(),
+            reconnection_schedule,
 
             ready_connections: FuturesUnordered::new(),
             connection_errors: FuturesUnordered::new(),
@@ -577,10 +660,15 @@ impl PoolRefiller {
         self.shared_conns.clone()
     }
 
+    pub fn get_shared_last_error(&self) -> Arc<ArcSwapOption<QueryError>> {
+        self.shared_last_error.clone()
+    }
+
     // The main loop of the pool refiller
     pub async fn run(
         mut self,
         mut use_keyspace_request_receiver: mpsc::Receiver<UseKeyspaceRequest>,
+        mut close_request_receiver: mpsc::Receiver<oneshot::Sender<()>>,
     ) {
         debug!("[{}] Started asynchronous pool worker", self.address);
 
@@ -605,6 +693,8 @@ impl PoolRefiller {
                             self.excess_connections.len()
                         );
                         self.excess_connections.clear();
+                        // Errors of the previous attempts don't describe a fully connected pool
+                        self.shared_last_error.store(None);
                     }
                 }
 
@@ -627,6 +717,13 @@ impl PoolRefiller {
                         return;
                     }
                 }
+
+                Some(response_sender) = close_request_receiver.recv() => {
+                    debug!("[{}] Closing the pool", self.address);
+                    self.close();
+                    let _ = response_sender.send(());
+                    return;
+                }
             }
             trace!(
                 pool_state = format!("{:?}", ShardedConnectionVectorWrapper(&self.conns)).as_str()
@@ -634,12 +731,14 @@ impl PoolRefiller {
 
             // Schedule refilling here
             if !refill_scheduled && self.need_filling() {
+                if !self.had_error_since_last_refill {
+                    self.reconnection_schedule =
+                        self.pool_config.reconnection_policy.new_schedule();
+                }
+                let delay = self.reconnection_schedule.next_delay();
                 if self.had_error_since_last_refill {
-                    self.refill_delay_strategy.on_fill_error();
-                } else {
-                    self.refill_delay_strategy.on_successful_fill();
+                    self.pool_config.metrics.log_reconnection_attempt(delay);
                 }
-                let delay = self.refill_delay_strategy.get_delay();
                 debug!(
                     "[{}] Scheduling next refill in {} ms",
                     self.address,
@@ -748,6 +847,7 @@ impl PoolRefiller {
     fn handle_ready_connection(&mut self, evt: OpenedConnectionEvent) {
         match evt.result {
             Err(err) => {
+                self.shared_last_error.store(Some(Arc::new(err.clone())));
                 if evt.requested_shard.is_some() {
                     // If we failed to connect to a shard-aware port,
                     // fall back to the non-shard-aware port.
@@ -981,12 +1081,27 @@ impl PoolRefiller {
 
         // Notify potential waiters
         self.pool_updated_notify.notify_waiters();
+        if let Some(notify) = &self.pool_config.connection_config.availability_notify {
+            notify.notify_waiters();
+        }
+    }
+
+    // Drops all connections of the pool, they are closed when no request uses them anymore
+    fn close(&mut self) {
+        for shard_conns in self.conns.iter_mut() {
+            shard_conns.clear();
+        }
+        self.excess_connections.clear();
+        self.ready_connections = FuturesUnordered::new();
+        self.update_shared_conns(Some(QueryError::SessionClosed));
     }
 
     // Removes given connection from the pool. It looks both into active
     // connections and excess connections.
     fn remove_connection(&mut self, connection: Arc<Connection>, last_error: QueryError) {
         let ptr = Arc::as_ptr(&connection);
+        self.shared_last_error
+            .store(Some(Arc::new(last_error.clone())));
 
         let maybe_remove_in_vec = |v: &mut Vec<Arc<Connection>>| -> bool {
             let maybe_idx = v
@@ -1223,7 +1338,7 @@ async fn open_connection_to_shard_aware_port(
 
 #[cfg(test)]
 mod tests {
-    use super::open_connection_to_shard_aware_port;
+    use super::{open_connection_to_shard_aware_port, NodeConnectionPool};
     use crate::routing::{ShardCount, Sharder};
     use crate::transport::connection::ConnectionConfig;
     use std::net::{SocketAddr, ToSocketAddrs};
@@ -1245,8 +1360,8 @@ mod tests {
         let connection_config = ConnectionConfig {
             compression: None,
             tcp_nodelay: true,
-            #[cfg(feature = "ssl")]
-            ssl_context: None,
+            #[cfg(any(feature = "ssl", feature = "rustls"))]
+            tls_context: None,
             ..Default::default()
         };
 
@@ -1274,4 +1389,16 @@ mod tests {
             res.unwrap();
         }
     }
+
+    #[tokio::test]
+    async fn status_of_unreachable_node() {
+        // Nothing listens on port 1
+        let pool =
+            NodeConnectionPool::new("127.0.0.1".parse().unwrap(), 1, Default::default(), None);
+        pool.wait_until_initialized().await;
+
+        assert!(pool.shard_connection_counts().is_empty());
+        assert!(!pool.is_full());
+        assert!(pool.last_error().is_some());
+    }
 }
@@ -172,11 +172,14 @@ impl RetrySession for DowngradingConsistencyRetrySession {
 
 #[cfg(test)]
 mod tests {
-    use std::{io::ErrorKind, sync::Arc};
+    use std::{io::ErrorKind, sync::Arc, time::Duration};
 
     use bytes::Bytes;
     use scylla_cql::errors::BadQuery;
 
+    use crate::statement::StatementKind;
+    use crate::transport::load_balancing::Statement;
+
     use super::*;
 
     const CONSISTENCY_LEVELS: &[Consistency] = &[
@@ -200,6 +203,17 @@ mod tests {
             error,
             is_idempotent,
             consistency: LegacyConsistency::Regular(cl),
+            node: None,
+            shard: None,
+            attempt: 1,
+            elapsed: Duration::ZERO,
+            request_timeout: None,
+            statement_kind: StatementKind::Query,
+            statement: &Statement {
+                token: None,
+                keyspace: None,
+                is_confirmed_lwt: false,
+            },
         }
     }
 
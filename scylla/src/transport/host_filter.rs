@@ -0,0 +1,159 @@
+//! Host filters decide which nodes the driver is allowed to connect to.
+//!
+//! Nodes rejected by the filter are still present in the cluster metadata (and take part
+//! in computing replicas), but the driver never opens connections to them, they never appear
+//! in query plans, and the control connection never uses them.
+
+use crate::transport::topology::Peer;
+use std::collections::HashSet;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+/// Decides whether the driver should connect to a node.
+pub trait HostFilter: Send + Sync {
+    /// Returns whether the driver is allowed to connect to the peer
+    fn accept(&self, peer: &Peer) -> bool;
+}
+
+/// Accepts all nodes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AcceptAllHostFilter;
+
+impl HostFilter for AcceptAllHostFilter {
+    fn accept(&self, _peer: &Peer) -> bool {
+        true
+    }
+}
+
+/// Accepts only nodes with the given addresses
+#[derive(Debug, Clone)]
+pub struct AllowListHostFilter {
+    allowed: HashSet<SocketAddr>,
+}
+
+impl AllowListHostFilter {
+    /// Creates a filter accepting only nodes with the given addresses.\
+    /// Addresses are resolved once, when the filter is created, and must include the port
+    /// on which nodes accept CQL connections (ex. "10.0.0.1:9042").
+    pub fn new<I, A>(allowed_addresses: I) -> Result<Self, io::Error>
+    where
+        I: IntoIterator<Item = A>,
+        A: ToSocketAddrs,
+    {
+        Ok(Self {
+            allowed: resolve_addresses(allowed_addresses)?,
+        })
+    }
+}
+
+impl HostFilter for AllowListHostFilter {
+    fn accept(&self, peer: &Peer) -> bool {
+        self.allowed.contains(&peer.address)
+    }
+}
+
+/// Rejects nodes with the given addresses
+#[derive(Debug, Clone)]
+pub struct DenyListHostFilter {
+    denied: HashSet<SocketAddr>,
+}
+
+impl DenyListHostFilter {
+    /// Creates a filter rejecting nodes with the given addresses.\
+    /// Addresses are resolved once, when the filter is created, and must include the port
+    /// on which nodes accept CQL connections (ex. "10.0.0.1:9042").
+    pub fn new<I, A>(denied_addresses: I) -> Result<Self, io::Error>
+    where
+        I: IntoIterator<Item = A>,
+        A: ToSocketAddrs,
+    {
+        Ok(Self {
+            denied: resolve_addresses(denied_addresses)?,
+        })
+    }
+}
+
+impl HostFilter for DenyListHostFilter {
+    fn accept(&self, peer: &Peer) -> bool {
+        !self.denied.contains(&peer.address)
+    }
+}
+
+/// Accepts only nodes from the given datacenters.\
+/// Nodes with an unknown datacenter are rejected.
+#[derive(Debug, Clone)]
+pub struct DcHostFilter {
+    datacenters: HashSet<String>,
+}
+
+impl DcHostFilter {
+    /// Creates a filter accepting only nodes from the given datacenters
+    pub fn new(datacenters: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            datacenters: datacenters.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl HostFilter for DcHostFilter {
+    fn accept(&self, peer: &Peer) -> bool {
+        match &peer.datacenter {
+            Some(datacenter) => self.datacenters.contains(datacenter),
+            None => false,
+        }
+    }
+}
+
+fn resolve_addresses<I, A>(addresses: I) -> Result<HashSet<SocketAddr>, io::Error>
+where
+    I: IntoIterator<Item = A>,
+    A: ToSocketAddrs,
+{
+    let mut resolved = HashSet::new();
+    for address in addresses {
+        resolved.extend(address.to_socket_addrs()?);
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(address: &str, datacenter: Option<&str>) -> Peer {
+        Peer {
+            address: address.parse().unwrap(),
+            untranslated_address: None,
+            host_id: None,
+            tokens: Vec::new(),
+            datacenter: datacenter.map(String::from),
+            rack: None,
+        }
+    }
+
+    #[test]
+    fn allow_and_deny_lists() {
+        let allowed = peer("10.0.0.1:9042", None);
+        let other = peer("10.0.0.2:9042", None);
+
+        let allow_list = AllowListHostFilter::new(["10.0.0.1:9042"]).unwrap();
+        assert!(allow_list.accept(&allowed));
+        assert!(!allow_list.accept(&other));
+
+        let deny_list = DenyListHostFilter::new(["10.0.0.1:9042"]).unwrap();
+        assert!(!deny_list.accept(&allowed));
+        assert!(deny_list.accept(&other));
+
+        assert!(AllowListHostFilter::new(["not an address"]).is_err());
+    }
+
+    #[test]
+    fn dc_filter() {
+        let filter = DcHostFilter::new(["eu", "us"]);
+
+        assert!(filter.accept(&peer("10.0.0.1:9042", Some("eu"))));
+        assert!(filter.accept(&peer("10.0.0.2:9042", Some("us"))));
+        assert!(!filter.accept(&peer("10.0.0.3:9042", Some("analytics"))));
+        assert!(!filter.accept(&peer("10.0.0.4:9042", None)));
+    }
+}
@@ -1,11 +1,13 @@
 //! Iterators over rows returned by paged queries
 
+use std::collections::HashMap;
 use std::future::Future;
 use std::mem;
 use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 use bytes::Bytes;
 use futures::Stream;
@@ -26,16 +28,21 @@ use crate::frame::{
     value::SerializedValues,
 };
 use crate::history::{self, HistoryListener};
-use crate::routing::Token;
-use crate::statement::Consistency;
+use crate::routing::{Shard, Token};
 use crate::statement::{prepared_statement::PreparedStatement, query::Query};
+use crate::statement::{Consistency, StatementKind};
+use crate::tracing::SamplingInfo;
 use crate::transport::cluster::ClusterData;
 use crate::transport::connection::{Connection, QueryResponse};
-use crate::transport::load_balancing::{LoadBalancingPolicy, Statement};
+use crate::transport::load_balancing::{self, LoadBalancingPolicy, Statement};
 use crate::transport::metrics::Metrics;
 use crate::transport::node::Node;
+use crate::transport::paging_state::PagingState;
+use crate::transport::request_span::{self, RequestSpan};
+use crate::transport::request_tracker::{RequestGuard, RequestTracker};
 use crate::transport::retry_policy::{QueryInfo, RetryDecision, RetrySession};
-use tracing::{trace, trace_span, warn, Instrument};
+use crate::transport::trace_collector::TracingSampler;
+use tracing::{trace, warn, Instrument};
 use uuid::Uuid;
 
 // #424
@@ -52,20 +59,47 @@ use uuid::Uuid;
 //
 // In order to work around the problem we just set the page size to a default
 // value at the beginning of `query_iter` and `execute_iter`.
-const DEFAULT_ITER_PAGE_SIZE: i32 = 5000;
+// The default value can be changed in `SessionConfig`.
+pub(crate) const DEFAULT_ITER_PAGE_SIZE: i32 = 5000;
 
 /// Iterator over rows returned by paged queries\
 /// Allows to easily access rows without worrying about handling multiple pages
 pub struct RowIterator {
     current_row_idx: usize,
-    current_page: Rows,
-    page_receiver: mpsc::Receiver<Result<ReceivedPage, QueryError>>,
+    current_page: Page,
+    page_receiver: mpsc::Receiver<Result<Page, QueryError>>,
     tracing_ids: Vec<Uuid>,
 }
 
-struct ReceivedPage {
+/// A single page of rows fetched by [`RowIterator`], along with its metadata.\
+/// Returned by the stream created with [`RowIterator::pages`].
+#[derive(Debug)]
+pub struct Page {
+    /// Rows of the page
     pub rows: Rows,
+    /// Tracing id of the request which fetched the page, if tracing was enabled
     pub tracing_id: Option<Uuid>,
+    /// Warnings returned by the database along with the page
+    pub warnings: Vec<String>,
+    /// Custom payload returned by the database along with the page, if any
+    pub custom_payload: Option<HashMap<String, Bytes>>,
+    /// Address of the node which coordinated the request
+    pub coordinator: SocketAddr,
+    /// Paging state allowing to fetch the pages following this one, `None` if this is the last page
+    pub paging_state: Option<PagingState>,
+}
+
+/// Paging settings of iterators created by the `Session`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IteratorPagingConfig {
+    // Timeout reported to the retry policy when the statement doesn't set one, pages aren't timed out
+    pub request_timeout: Option<Duration>,
+    // Page size used when the statement doesn't set one
+    pub default_page_size: i32,
+    // Number of pages fetched in advance, before they are consumed
+    pub prefetch_pages: usize,
+    // Upper limit of the size of a page, enforced by adjusting page size of the following requests
+    pub max_page_bytes: Option<usize>,
 }
 
 pub(crate) struct PreparedIteratorConfig {
@@ -77,6 +111,11 @@ pub(crate) struct PreparedIteratorConfig {
     pub load_balancer: Arc<dyn LoadBalancingPolicy>,
     pub cluster_data: Arc<ClusterData>,
     pub metrics: Arc<Metrics>,
+    pub request_span: RequestSpan,
+    pub paging_state: Option<PagingState>,
+    pub paging_config: IteratorPagingConfig,
+    pub tracing_sampler: Option<Arc<TracingSampler>>,
+    pub request_guard: RequestGuard,
 }
 
 /// Fetching pages is asynchronous so `RowIterator` does not implement the `Iterator` trait.\
@@ -90,12 +129,12 @@ impl Stream for RowIterator {
         if s.is_current_page_exhausted() {
             match Pin::new(&mut s.page_receiver).poll_recv(cx) {
                 Poll::Ready(Some(Ok(received_page))) => {
-                    s.current_page = received_page.rows;
-                    s.current_row_idx = 0;
-
                     if let Some(tracing_id) = received_page.tracing_id {
                         s.tracing_ids.push(tracing_id);
                     }
+
+                    s.current_page = received_page;
+                    s.current_row_idx = 0;
                 }
                 Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
                 Poll::Ready(None) => return Poll::Ready(None),
@@ -104,8 +143,8 @@ impl Stream for RowIterator {
         }
 
         let idx = s.current_row_idx;
-        if idx < s.current_page.rows.len() {
-            let row = mem::take(&mut s.current_page.rows[idx]);
+        if idx < s.current_page.rows.rows.len() {
+            let row = mem::take(&mut s.current_page.rows.rows[idx]);
             s.current_row_idx += 1;
             return Poll::Ready(Some(Ok(row)));
         }
@@ -126,6 +165,7 @@ impl RowIterator {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) async fn new_for_query(
         mut query: Query,
         values: SerializedValues,
@@ -134,12 +174,20 @@ impl RowIterator {
         load_balancer: Arc<dyn LoadBalancingPolicy>,
         cluster_data: Arc<ClusterData>,
         metrics: Arc<Metrics>,
+        request_span: RequestSpan,
+        paging_state: Option<PagingState>,
+        paging_config: IteratorPagingConfig,
+        tracing_sampler: Option<Arc<TracingSampler>>,
+        request_guard: RequestGuard,
     ) -> Result<RowIterator, QueryError> {
-        if query.get_page_size().is_none() {
-            query.set_page_size(DEFAULT_ITER_PAGE_SIZE);
-        }
-        let (sender, mut receiver) = mpsc::channel(1);
+        let page_size = query
+            .get_page_size()
+            .unwrap_or(paging_config.default_page_size);
+        query.set_page_size(page_size);
+        let (sender, mut receiver) = mpsc::channel(paging_config.prefetch_pages.max(1));
         let consistency = query.config.determine_consistency(default_consistency);
+        request_span.record_consistency(consistency);
+        let span = request_span.span().clone();
 
         let worker_task = async move {
             let query_ref = &query;
@@ -149,9 +197,16 @@ impl RowIterator {
 
             let page_query = |connection: Arc<Connection>,
                               consistency: Consistency,
+                              page_size: i32,
                               paging_state: Option<Bytes>| async move {
                 connection
-                    .query_with_consistency(query_ref, values_ref, consistency, paging_state)
+                    .query_with_page_size(
+                        query_ref,
+                        values_ref,
+                        consistency,
+                        Some(page_size),
+                        paging_state,
+                    )
                     .await
             };
 
@@ -160,51 +215,71 @@ impl RowIterator {
                 choose_connection,
                 page_query,
                 statement_info: Statement::default(),
+                statement_kind: StatementKind::Query,
                 query_is_idempotent: query.config.is_idempotent,
                 query_consistency: consistency,
                 retry_session,
                 load_balancer,
                 metrics,
-                paging_state: None,
+                paging_state: paging_state.map(PagingState::into_bytes),
+                page_size,
+                max_page_size: page_size,
+                max_page_bytes: paging_config.max_page_bytes,
+                page_query_start: Instant::now(),
+                page_attempts: 0,
+                request_timeout: query
+                    .config
+                    .request_timeout
+                    .or(paging_config.request_timeout),
+                tracing_sampler,
+                statement: &query_ref.contents,
                 history_listener: query.config.history_listener.clone(),
                 current_query_id: None,
                 current_attempt_id: None,
+                request_span,
+                request_tracker: request_guard.tracker().clone(),
+                request_guard: Some(request_guard),
             };
 
             worker.work(cluster_data).await;
         };
 
-        tokio::task::spawn(worker_task);
+        tokio::task::spawn(worker_task.instrument(span));
 
         let pages_received = receiver.recv().await.unwrap()?;
 
         Ok(RowIterator {
             current_row_idx: 0,
-            current_page: pages_received.rows,
-            page_receiver: receiver,
             tracing_ids: if let Some(tracing_id) = pages_received.tracing_id {
                 vec![tracing_id]
             } else {
                 Vec::new()
             },
+            current_page: pages_received,
+            page_receiver: receiver,
         })
     }
 
     pub(crate) async fn new_for_prepared_statement(
         mut config: PreparedIteratorConfig,
     ) -> Result<RowIterator, QueryError> {
-        if config.prepared.get_page_size().is_none() {
-            config.prepared.set_page_size(DEFAULT_ITER_PAGE_SIZE);
-        }
-        let (sender, mut receiver) = mpsc::channel(1);
+        let page_size = config
+            .prepared
+            .get_page_size()
+            .unwrap_or(config.paging_config.default_page_size);
+        config.prepared.set_page_size(page_size);
+        let (sender, mut receiver) = mpsc::channel(config.paging_config.prefetch_pages.max(1));
         let consistency = config
             .prepared
             .config
             .determine_consistency(config.default_consistency);
+        config.request_span.record_consistency(consistency);
+        let span = config.request_span.span().clone();
 
         let statement_info = Statement {
             token: config.token,
             keyspace: None,
+            is_confirmed_lwt: config.prepared.is_confirmed_lwt(),
         };
 
         let worker_task = async move {
@@ -221,9 +296,16 @@ impl RowIterator {
 
             let page_query = |connection: Arc<Connection>,
                               consistency: Consistency,
+                              page_size: i32,
                               paging_state: Option<Bytes>| async move {
                 connection
-                    .execute_with_consistency(prepared_ref, values_ref, consistency, paging_state)
+                    .execute_with_page_size(
+                        prepared_ref,
+                        values_ref,
+                        consistency,
+                        Some(page_size),
+                        paging_state,
+                    )
                     .await
             };
 
@@ -232,33 +314,49 @@ impl RowIterator {
                 choose_connection,
                 page_query,
                 statement_info,
+                statement_kind: config.prepared.statement_kind(),
                 query_is_idempotent: config.prepared.config.is_idempotent,
                 query_consistency: consistency,
                 retry_session: config.retry_session,
                 load_balancer: config.load_balancer,
                 metrics: config.metrics,
-                paging_state: None,
+                paging_state: config.paging_state.map(PagingState::into_bytes),
+                page_size,
+                max_page_size: page_size,
+                max_page_bytes: config.paging_config.max_page_bytes,
+                page_query_start: Instant::now(),
+                page_attempts: 0,
+                request_timeout: config
+                    .prepared
+                    .config
+                    .request_timeout
+                    .or(config.paging_config.request_timeout),
+                tracing_sampler: config.tracing_sampler,
+                statement: prepared_ref.get_statement(),
                 history_listener: config.prepared.config.history_listener.clone(),
                 current_query_id: None,
                 current_attempt_id: None,
+                request_span: config.request_span,
+                request_tracker: config.request_guard.tracker().clone(),
+                request_guard: Some(config.request_guard),
             };
 
             worker.work(config.cluster_data).await;
         };
 
-        tokio::task::spawn(worker_task);
+        tokio::task::spawn(worker_task.instrument(span));
 
         let pages_received = receiver.recv().await.unwrap()?;
 
         Ok(RowIterator {
             current_row_idx: 0,
-            current_page: pages_received.rows,
-            page_receiver: receiver,
             tracing_ids: if let Some(tracing_id) = pages_received.tracing_id {
                 vec![tracing_id]
             } else {
                 Vec::new()
             },
+            current_page: pages_received,
+            page_receiver: receiver,
         })
     }
 
@@ -269,28 +367,57 @@ impl RowIterator {
 
     /// Returns specification of row columns
     pub fn get_column_specs(&self) -> &[ColumnSpec] {
-        &self.current_page.metadata.col_specs
+        &self.current_page.rows.metadata.col_specs
+    }
+
+    /// Converts this iterator into a stream of whole pages, along with their metadata.\
+    /// Rows of the current page which weren't returned yet are yielded as the first page.
+    pub fn pages(mut self) -> PageIterator {
+        let current_page = if self.current_row_idx > 0 && self.is_current_page_exhausted() {
+            None
+        } else {
+            self.current_page.rows.rows.drain(..self.current_row_idx);
+            Some(self.current_page)
+        };
+
+        PageIterator {
+            current_page,
+            page_receiver: self.page_receiver,
+            tracing_ids: self.tracing_ids,
+        }
+    }
+
+    /// Returns the paging state which allows to fetch pages following the current one,
+    /// or `None` if the current page is the last one.\
+    /// It can be passed to [`Session::resume_query_iter`](crate::Session::resume_query_iter)
+    /// or [`Session::resume_execute_iter`](crate::Session::resume_execute_iter) with the same statement
+    /// and values to resume the scan, also in another process. Rows of the current page which weren't
+    /// returned yet will not be returned by the resumed iterator - use [`pages`](RowIterator::pages)
+    /// to read whole pages along with their paging states.
+    pub fn paging_state(&self) -> Option<&PagingState> {
+        self.current_page.paging_state.as_ref()
     }
 
-    fn is_current_page_exhausted(&self) -> bool {
-        self.current_row_idx >= self.current_page.rows.len()
+    pub(crate) fn is_current_page_exhausted(&self) -> bool {
+        self.current_row_idx >= self.current_page.rows.rows.len()
     }
 }
 
 // RowIteratorWorker works in the background to fetch pages
 // RowIterator receives them through a channel
 struct RowIteratorWorker<'a, ConnFunc, QueryFunc> {
-    sender: mpsc::Sender<Result<ReceivedPage, QueryError>>,
+    sender: mpsc::Sender<Result<Page, QueryError>>,
 
     // Closure used to choose a connection from a node
     // AsyncFn(Arc<Node>) -> Result<Arc<Connection>, QueryError>
     choose_connection: ConnFunc,
 
     // Closure used to perform a single page query
-    // AsyncFn(Arc<Connection>, Option<Bytes>) -> Result<QueryResponse, QueryError>
+    // AsyncFn(Arc<Connection>, Consistency, i32, Option<Bytes>) -> Result<QueryResponse, QueryError>
     page_query: QueryFunc,
 
     statement_info: Statement<'a>,
+    statement_kind: StatementKind,
     query_is_idempotent: bool,
     query_consistency: Consistency,
 
@@ -300,20 +427,42 @@ struct RowIteratorWorker<'a, ConnFunc, QueryFunc> {
 
     paging_state: Option<Bytes>,
 
+    // Page size of the next request, lowered below max_page_size when pages exceed max_page_bytes
+    page_size: i32,
+    max_page_size: i32,
+    max_page_bytes: Option<usize>,
+
+    // When fetching of the current page started and how many attempts it took so far
+    page_query_start: Instant,
+    page_attempts: usize,
+    // Timeout reported to the retry policy, fetching pages isn't limited by it
+    request_timeout: Option<Duration>,
+
+    // Set if the iterator was sampled for tracing, traces of all pages are collected then
+    tracing_sampler: Option<Arc<TracingSampler>>,
+    statement: &'a str,
+
     history_listener: Option<Arc<dyn HistoryListener>>,
     current_query_id: Option<history::QueryId>,
     current_attempt_id: Option<history::AttemptId>,
+
+    request_span: RequestSpan,
+
+    // Held while a page is being fetched, so that closing the session waits for it
+    request_guard: Option<RequestGuard>,
+    request_tracker: Arc<RequestTracker>,
 }
 
 impl<ConnFunc, ConnFut, QueryFunc, QueryFut> RowIteratorWorker<'_, ConnFunc, QueryFunc>
 where
     ConnFunc: Fn(Arc<Node>) -> ConnFut,
     ConnFut: Future<Output = Result<Arc<Connection>, QueryError>>,
-    QueryFunc: Fn(Arc<Connection>, Consistency, Option<Bytes>) -> QueryFut,
+    QueryFunc: Fn(Arc<Connection>, Consistency, i32, Option<Bytes>) -> QueryFut,
     QueryFut: Future<Output = Result<QueryResponse, QueryError>>,
 {
     async fn work(mut self, cluster_data: Arc<ClusterData>) {
-        let query_plan = self.load_balancer.plan(&self.statement_info, &cluster_data);
+        let query_plan =
+            load_balancing::query_plan(&*self.load_balancer, &self.statement_info, &cluster_data);
 
         let mut last_error: QueryError =
             QueryError::ProtocolError("Empty query plan - driver bug!");
@@ -322,10 +471,10 @@ where
         self.log_query_start();
 
         'nodes_in_plan: for node in query_plan {
-            let span = trace_span!("Executing query", node = node.address.to_string().as_str());
+            let span = request_span::attempt_span(&node, self.page_attempts + 1);
             // For each node in the plan choose a connection to use
             // This connection will be reused for same node retries to preserve paging cache on the shard
-            let connection: Arc<Connection> = match (self.choose_connection)(node)
+            let connection: Arc<Connection> = match (self.choose_connection)(node.clone())
                 .instrument(span.clone())
                 .await
             {
@@ -341,12 +490,19 @@ where
                     continue 'nodes_in_plan;
                 }
             };
+            request_span::record_attempt_shard(
+                &span,
+                connection
+                    .get_shard_info()
+                    .as_ref()
+                    .map(|shard_info| Shard::from(shard_info.shard)),
+            );
 
             'same_node_retries: loop {
                 trace!(parent: &span, "Execution started");
                 // Query pages until an error occurs
                 let queries_result: Result<(), QueryError> = self
-                    .query_pages(&connection, current_consistency)
+                    .query_pages(&node, &connection, current_consistency)
                     .instrument(span.clone())
                     .await;
 
@@ -361,6 +517,8 @@ where
                             error = error.to_string().as_str(),
                             "Query failed"
                         );
+                        request_span::record_attempt_error(&span, &error);
+                        node.log_request_error(&error);
                         error
                     }
                 };
@@ -370,6 +528,16 @@ where
                     error: &last_error,
                     is_idempotent: self.query_is_idempotent,
                     consistency: LegacyConsistency::Regular(self.query_consistency),
+                    node: Some(&node),
+                    shard: connection
+                        .get_shard_info()
+                        .as_ref()
+                        .map(|shard_info| Shard::from(shard_info.shard)),
+                    attempt: self.page_attempts,
+                    elapsed: self.page_query_start.elapsed(),
+                    request_timeout: self.request_timeout,
+                    statement_kind: self.statement_kind,
+                    statement: &self.statement_info,
                 };
 
                 let retry_decision = self.retry_session.decide_should_retry(query_info);
@@ -381,11 +549,13 @@ where
                 match retry_decision {
                     RetryDecision::RetrySameNode(cl) => {
                         self.metrics.inc_retries_num();
+                        self.request_span.record_retry();
                         current_consistency = cl;
                         continue 'same_node_retries;
                     }
                     RetryDecision::RetryNextNode(cl) => {
                         self.metrics.inc_retries_num();
+                        self.request_span.record_retry();
                         current_consistency = cl;
                         continue 'nodes_in_plan;
                     }
@@ -400,43 +570,72 @@ where
 
         // Send last_error to RowIterator - query failed fully
         self.log_query_error(&last_error);
+        self.request_span.record_error(&last_error);
         let _ = self.sender.send(Err(last_error)).await;
     }
 
     // Given a working connection query as many pages as possible until the first error
     async fn query_pages(
         &mut self,
+        node: &Node,
         connection: &Arc<Connection>,
         consistency: Consistency,
     ) -> Result<(), QueryError> {
         loop {
             self.metrics.inc_total_paged_queries();
             let query_start = std::time::Instant::now();
+            self.page_attempts += 1;
 
             trace!(
                 connection = connection.get_connect_address().to_string().as_str(),
                 "Sending"
             );
             self.log_attempt_start(connection.get_connect_address());
-            let query_response: QueryResponse =
-                (self.page_query)(connection.clone(), consistency, self.paging_state.clone())
-                    .await?;
+            let query_response: QueryResponse = (self.page_query)(
+                connection.clone(),
+                consistency,
+                self.page_size,
+                self.paging_state.clone(),
+            )
+            .await?;
 
             match query_response.response {
                 Response::Result(result::Result::Rows(mut rows)) => {
-                    let _ = self
-                        .metrics
-                        .log_query_latency(query_start.elapsed().as_millis() as u64);
+                    let latency = query_start.elapsed();
+                    let _ = self.metrics.log_query_latency(latency.as_millis() as u64);
+                    node.log_request_success(latency);
                     self.log_attempt_success();
                     self.log_query_success();
 
+                    if let Some(sampler) = &self.tracing_sampler {
+                        let info = SamplingInfo {
+                            statement_kind: self.statement_kind,
+                            statement: self.statement,
+                        };
+                        sampler.collect(
+                            query_response.tracing_id,
+                            &info,
+                            self.page_query_start.elapsed(),
+                        );
+                    }
+
                     self.paging_state = rows.metadata.paging_state.take();
+                    self.request_span.record_rows(rows.rows.len());
+                    self.adjust_page_size(rows.rows.len(), query_response.body_size);
 
-                    let received_page = ReceivedPage {
+                    let received_page = Page {
                         rows,
                         tracing_id: query_response.tracing_id,
+                        warnings: query_response.warnings,
+                        custom_payload: query_response.custom_payload,
+                        coordinator: connection.get_connect_address(),
+                        paging_state: self.paging_state.clone().map(PagingState::new),
                     };
 
+                    // An iterator which isn't consumed mustn't block closing the session,
+                    // so the guard is released while waiting until there is room for the page
+                    self.request_guard = None;
+
                     // Send next page to RowIterator
                     if self.sender.send(Ok(received_page)).await.is_err() {
                         // channel was closed, RowIterator was dropped - should shutdown
@@ -448,8 +647,19 @@ where
                         return Ok(());
                     }
 
+                    match self.request_tracker.start_request() {
+                        Ok(guard) => self.request_guard = Some(guard),
+                        Err(err) => {
+                            // The session was closed, the next page won't be fetched
+                            let _ = self.sender.send(Err(err)).await;
+                            return Ok(());
+                        }
+                    }
+
                     // Query succeeded, reset retry policy for future retries
                     self.retry_session.reset();
+                    self.page_query_start = Instant::now();
+                    self.page_attempts = 0;
                     self.log_query_start();
                 }
                 Response::Error(err) => {
@@ -467,6 +677,19 @@ where
         }
     }
 
+    // Chooses the page size of the next request, so that pages with rows
+    // of the same size as in the last one don't exceed max_page_bytes
+    fn adjust_page_size(&mut self, rows_num: usize, page_bytes: usize) {
+        if let Some(max_page_bytes) = self.max_page_bytes {
+            if let Some(page_size) =
+                page_size_for_limit(rows_num, page_bytes, max_page_bytes, self.max_page_size)
+            {
+                trace!(page_size = page_size, "Adjusted page size");
+                self.page_size = page_size;
+            }
+        }
+    }
+
     fn log_query_start(&mut self) {
         let history_listener: &dyn HistoryListener = match &self.history_listener {
             Some(hl) => &**hl,
@@ -568,6 +791,58 @@ impl<RowT> TypedRowIterator<RowT> {
     }
 }
 
+// Number of rows of the size observed in the last page which fit in max_page_bytes,
+// but no more than max_page_size. None if the last page had no rows to measure.
+fn page_size_for_limit(
+    rows_num: usize,
+    page_bytes: usize,
+    max_page_bytes: usize,
+    max_page_size: i32,
+) -> Option<i32> {
+    if rows_num == 0 {
+        return None;
+    }
+
+    let row_bytes = (page_bytes / rows_num).max(1);
+    let fitting_rows = (max_page_bytes / row_bytes).clamp(1, i32::MAX as usize) as i32;
+    Some(fitting_rows.min(max_page_size))
+}
+
+/// Stream of whole pages returned by paged queries\
+/// Returned by `RowIterator::pages`
+pub struct PageIterator {
+    current_page: Option<Page>,
+    page_receiver: mpsc::Receiver<Result<Page, QueryError>>,
+    tracing_ids: Vec<Uuid>,
+}
+
+impl PageIterator {
+    /// If tracing was enabled returns tracing ids of all finished page queries
+    pub fn get_tracing_ids(&self) -> &[Uuid] {
+        &self.tracing_ids
+    }
+}
+
+/// Fetching pages is asynchronous so `PageIterator` does not implement the `Iterator` trait.\
+/// Instead it uses the asynchronous `Stream` trait
+impl Stream for PageIterator {
+    type Item = Result<Page, QueryError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(page) = self.current_page.take() {
+            return Poll::Ready(Some(Ok(page)));
+        }
+
+        let next_page = self.page_receiver.poll_recv(cx);
+        if let Poll::Ready(Some(Ok(page))) = &next_page {
+            if let Some(tracing_id) = page.tracing_id {
+                self.tracing_ids.push(tracing_id);
+            }
+        }
+        next_page
+    }
+}
+
 /// Couldn't get next typed row from the iterator
 #[derive(Error, Debug, Clone)]
 pub enum NextRowError {
@@ -606,3 +881,20 @@ impl<RowT: FromRow> Stream for TypedRowIterator<RowT> {
 
 // TypedRowIterator can be moved freely for any RowT so it's Unpin
 impl<RowT> Unpin for TypedRowIterator<RowT> {}
+
+#[cfg(test)]
+mod tests {
+    use super::page_size_for_limit;
+
+    #[test]
+    fn page_size_fits_byte_limit() {
+        // 100 rows of 1000 bytes each, 10kB limit
+        assert_eq!(page_size_for_limit(100, 100_000, 10_000, 5000), Some(10));
+        // Small rows don't raise the page size above the one set in the statement
+        assert_eq!(page_size_for_limit(100, 1000, 10_000_000, 5000), Some(5000));
+        // A single row bigger than the limit still has to be fetched
+        assert_eq!(page_size_for_limit(1, 1_000_000, 10_000, 5000), Some(1));
+        // An empty page doesn't tell anything about the size of rows
+        assert_eq!(page_size_for_limit(0, 100, 10_000, 5000), None);
+    }
+}
@@ -87,7 +87,11 @@ impl LoadBalancingPolicy for DcAwareRoundRobinPolicy {
                     .as_str(),
                 "DC Aware"
             );
-            Box::new(rotated_local_nodes.chain(rotated_remote_nodes))
+            // Remote nodes stay after local ones, even if some local nodes aren't up
+            Box::new(
+                super::up_nodes_first(Box::new(rotated_local_nodes))
+                    .chain(super::up_nodes_first(Box::new(rotated_remote_nodes))),
+            )
         } else {
             trace!(
                 local_nodes = rotated_local_nodes
@@ -98,7 +102,7 @@ impl LoadBalancingPolicy for DcAwareRoundRobinPolicy {
                     .as_str(),
                 "DC Aware"
             );
-            Box::new(rotated_local_nodes)
+            super::up_nodes_first(Box::new(rotated_local_nodes))
         }
     }
 
@@ -135,6 +139,21 @@ impl ChildLoadBalancingPolicy for DcAwareRoundRobinPolicy {
         };
         Box::new(plan.into_iter())
     }
+
+    fn apply_child_policy_in_order(
+        &self,
+        plan: Vec<Arc<Node>>,
+    ) -> Box<dyn Iterator<Item = Arc<Node>> + Send + Sync> {
+        let (local_nodes, remote_nodes): (Vec<_>, Vec<_>) = plan
+            .into_iter()
+            .partition(|node| DcAwareRoundRobinPolicy::is_local_node(node, &self.local_dc));
+
+        if self.include_remote_nodes {
+            Box::new(local_nodes.into_iter().chain(remote_nodes))
+        } else {
+            Box::new(local_nodes.into_iter())
+        }
+    }
 }
 
 #[cfg(test)]
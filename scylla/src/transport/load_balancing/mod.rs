@@ -3,10 +3,18 @@
 //! Policies which implement the `ChildLoadBalancingPolicy` can be wrapped in some other policies\
 //! See [the book](https://rust-driver.docs.scylladb.com/stable/load-balancing/load-balancing.html) for more information
 
-use super::{cluster::ClusterData, node::Node};
+use super::{
+    cluster::ClusterData,
+    node::{Node, NodeState},
+};
 use crate::routing::Token;
 
-use std::{collections::hash_map::DefaultHasher, hash::Hasher, sync::Arc};
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet, VecDeque},
+    hash::Hasher,
+    net::SocketAddr,
+    sync::Arc,
+};
 
 mod dc_aware_round_robin;
 mod round_robin;
@@ -18,18 +26,34 @@ pub use token_aware::TokenAwarePolicy;
 
 /// Represents info about statement that can be used by load balancing policies.
 #[derive(Default)]
+#[non_exhaustive]
 pub struct Statement<'a> {
     pub token: Option<Token>,
     pub keyspace: Option<&'a str>,
+    /// Whether the statement is a lightweight transaction,
+    /// see [`PreparedStatement::is_confirmed_lwt`](crate::prepared_statement::PreparedStatement::is_confirmed_lwt)
+    pub is_confirmed_lwt: bool,
 }
 
 impl<'a> Statement<'a> {
-    fn empty() -> Self {
+    /// Creates info about a statement which isn't a lightweight transaction
+    pub fn new(token: Option<Token>, keyspace: Option<&'a str>) -> Self {
         Self {
-            token: None,
-            keyspace: None,
+            token,
+            keyspace,
+            is_confirmed_lwt: false,
         }
     }
+
+    /// Sets whether the statement is a lightweight transaction
+    pub fn with_confirmed_lwt(mut self, is_confirmed_lwt: bool) -> Self {
+        self.is_confirmed_lwt = is_confirmed_lwt;
+        self
+    }
+
+    fn empty() -> Self {
+        Self::new(None, None)
+    }
 }
 
 pub type Plan<'a> = Box<dyn Iterator<Item = Arc<Node>> + Send + Sync + 'a>;
@@ -43,6 +67,50 @@ pub trait LoadBalancingPolicy: Send + Sync + std::fmt::Debug {
     fn name(&self) -> String;
 }
 
+// Plan made by the policy, without nodes rejected by the host filter
+pub(crate) fn query_plan<'a>(
+    policy: &dyn LoadBalancingPolicy,
+    statement: &Statement,
+    cluster: &'a ClusterData,
+) -> Plan<'a> {
+    Box::new(
+        policy
+            .plan(statement, cluster)
+            .filter(|node| node.is_enabled()),
+    )
+}
+
+/// Moves nodes which aren't [`Up`](NodeState::Up) to the end of the plan, keeping the order
+/// of the others, so that they are tried only if all up nodes fail.\
+/// Built-in policies apply it to their plans, custom policies can use it
+/// or check [`Node::state`] themselves.
+pub fn up_nodes_first<'a>(plan: Plan<'a>) -> Plan<'a> {
+    Box::new(UpNodesFirst {
+        plan,
+        deferred: VecDeque::new(),
+    })
+}
+
+// Yields nodes of the plan which are up, in order, and then all the others
+struct UpNodesFirst<I> {
+    plan: I,
+    deferred: VecDeque<Arc<Node>>,
+}
+
+impl<I: Iterator<Item = Arc<Node>>> Iterator for UpNodesFirst<I> {
+    type Item = Arc<Node>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for node in self.plan.by_ref() {
+            if node.state() == NodeState::Up {
+                return Some(node);
+            }
+            self.deferred.push_back(node);
+        }
+        self.deferred.pop_front()
+    }
+}
+
 /// This trait is used to apply policy to plan made by parent policy.
 ///
 /// For example, this enables RoundRobinPolicy to process plan made by TokenAwarePolicy.
@@ -51,6 +119,25 @@ pub trait ChildLoadBalancingPolicy: LoadBalancingPolicy {
         &self,
         plan: Vec<Arc<Node>>,
     ) -> Box<dyn Iterator<Item = Arc<Node>> + Send + Sync>;
+
+    /// Applies the policy to a plan whose order must be kept, apart from moving preferred nodes
+    /// (e.g. from the local datacenter) first.
+    /// Used for replicas of LWT statements, which all clients should try in the same order.\
+    /// By default, keeps the nodes returned by [`apply_child_policy`](Self::apply_child_policy)
+    /// in the order of the original plan.
+    fn apply_child_policy_in_order(
+        &self,
+        plan: Vec<Arc<Node>>,
+    ) -> Box<dyn Iterator<Item = Arc<Node>> + Send + Sync> {
+        let accepted: HashSet<SocketAddr> = self
+            .apply_child_policy(plan.clone())
+            .map(|node| node.address)
+            .collect();
+        Box::new(
+            plan.into_iter()
+                .filter(move |node| accepted.contains(&node.address)),
+        )
+    }
 }
 
 // Hashing round robin's index is a mitigation to problems that occur when a
@@ -94,6 +181,7 @@ fn slice_rotated_left<T>(slice: &[T], mid: usize) -> impl Iterator<Item = &T> +
 mod tests {
     use super::*;
 
+    use crate::transport::host_filter::{DcHostFilter, HostFilter};
     use crate::transport::topology::Metadata;
     use crate::transport::topology::Peer;
     use std::collections::HashMap;
@@ -142,6 +230,7 @@ mod tests {
                 address: tests::id_to_invalid_addr(*id),
                 tokens: Vec::new(),
                 untranslated_address: Some(tests::id_to_invalid_addr(*id)),
+                host_id: None,
             })
             .collect::<Vec<_>>();
 
@@ -150,12 +239,100 @@ mod tests {
             keyspaces: HashMap::new(),
         };
 
-        ClusterData::new(info, &Default::default(), &HashMap::new(), &None)
+        ClusterData::new(info, &Default::default(), &HashMap::new(), &None, &None)
+    }
+
+    // ConnectionKeeper (which lives in Node) requires context of Tokio runtime
+    #[tokio::test]
+    async fn test_plans_skip_filtered_nodes() {
+        let mut cluster = mock_cluster_data_for_round_robin_tests();
+        let peers = cluster
+            .all_nodes
+            .iter()
+            .map(|node| Peer {
+                datacenter: node.datacenter.clone(),
+                rack: None,
+                address: node.address,
+                tokens: Vec::new(),
+                untranslated_address: Some(node.address),
+                host_id: None,
+            })
+            .collect();
+        let info = Metadata {
+            peers,
+            keyspaces: HashMap::new(),
+        };
+        let host_filter: Option<Arc<dyn HostFilter>> = Some(Arc::new(DcHostFilter::new(["eu"])));
+        cluster = ClusterData::new(
+            info,
+            &Default::default(),
+            &cluster.known_peers,
+            &None,
+            &host_filter,
+        );
+
+        // Filtered nodes are still present in the metadata
+        assert_eq!(cluster.all_nodes.len(), 5);
+        assert_eq!(cluster.datacenters["us"].nodes.len(), 2);
+        assert!(cluster.datacenters["us"]
+            .nodes
+            .iter()
+            .all(|node| !node.is_enabled()));
+
+        let policy = RoundRobinPolicy::new();
+        for _ in 0..5 {
+            let mut plan: Vec<u16> = query_plan(&policy, &EMPTY_STATEMENT, &cluster)
+                .map(|node| node.address.port())
+                .collect();
+            plan.sort_unstable();
+            assert_eq!(plan, vec![1, 2, 3]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_node_states() {
+        let cluster = mock_cluster_data_for_round_robin_tests();
+        let node = |id: u16| cluster.known_peers[&id_to_invalid_addr(id)].clone();
+
+        // Nodes without working connections are never up
+        assert!(cluster
+            .all_nodes
+            .iter()
+            .all(|node| node.state() != NodeState::Up));
+
+        node(2).change_down_marker(true);
+        assert_eq!(node(2).state(), NodeState::Down);
+        node(2).change_down_marker(false);
+        assert_ne!(node(2).state(), NodeState::Down);
+
+        // Nodes which aren't up are still tried, after the others
+        let policy = RoundRobinPolicy::new();
+        node(2).change_down_marker(true);
+        let mut plan: Vec<u16> = query_plan(&policy, &EMPTY_STATEMENT, &cluster)
+            .map(|node| node.address.port())
+            .collect();
+        plan.sort_unstable();
+        assert_eq!(plan, vec![1, 2, 3, 4, 5]);
+
+        // Nodes rejected by the host filter have their own state, whatever the cluster reports
+        let disabled = Node::new(
+            id_to_invalid_addr(6),
+            None,
+            Default::default(),
+            None,
+            None,
+            None,
+            false,
+        );
+        assert_eq!(disabled.state(), NodeState::Disabled);
+        disabled.change_down_marker(true);
+        assert_eq!(disabled.state(), NodeState::Disabled);
     }
 
     pub const EMPTY_STATEMENT: Statement = Statement {
         token: None,
         keyspace: None,
+        is_confirmed_lwt: false,
     };
 
     pub fn get_plan_and_collect_node_identifiers<L: LoadBalancingPolicy>(
@@ -45,7 +45,7 @@ impl LoadBalancingPolicy for RoundRobinPolicy {
             "RoundRobin"
         );
 
-        Box::new(rotated_nodes)
+        super::up_nodes_first(Box::new(rotated_nodes))
     }
 
     fn name(&self) -> String {
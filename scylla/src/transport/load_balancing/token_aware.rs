@@ -154,11 +154,16 @@ impl LoadBalancingPolicy for TokenAwarePolicy {
                         .filter(move |node| !replicas_set.contains(&node.address))
                 };
 
-                let plan = self
-                    .child_policy
-                    .apply_child_policy(replicas)
-                    .chain(fallback_plan);
-                Box::new(plan)
+                // Replicas of LWT statements are tried in ring order, starting with the primary replica,
+                // so that concurrent transactions on the same partition don't compete for different coordinators
+                let replicas = if statement.is_confirmed_lwt {
+                    self.child_policy.apply_child_policy_in_order(replicas)
+                } else {
+                    // Replicas which aren't up are tried after the others, but before non-replicas
+                    super::up_nodes_first(self.child_policy.apply_child_policy(replicas))
+                };
+
+                Box::new(replicas.chain(fallback_plan))
             }
             // fallback to child policy
             None => {
@@ -181,6 +186,7 @@ mod tests {
     use super::*;
 
     use crate::transport::load_balancing::tests;
+    use crate::transport::load_balancing::DcAwareRoundRobinPolicy;
     use crate::transport::topology::Keyspace;
     use crate::transport::topology::Metadata;
     use crate::transport::topology::Peer;
@@ -202,6 +208,7 @@ mod tests {
                 statement: Statement {
                     token: Some(Token { value: 160 }),
                     keyspace: Some("keyspace_with_simple_strategy_replication_factor_2"),
+                    is_confirmed_lwt: false,
                 },
                 expected_plan: vec![3, 1],
             },
@@ -209,6 +216,7 @@ mod tests {
                 statement: Statement {
                     token: Some(Token { value: 60 }),
                     keyspace: Some("keyspace_with_simple_strategy_replication_factor_3"),
+                    is_confirmed_lwt: false,
                 },
                 expected_plan: vec![1, 2, 3],
             },
@@ -216,6 +224,7 @@ mod tests {
                 statement: Statement {
                     token: Some(Token { value: 500 }),
                     keyspace: Some("keyspace_with_simple_strategy_replication_factor_3"),
+                    is_confirmed_lwt: false,
                 },
                 expected_plan: vec![1, 2, 3],
             },
@@ -223,6 +232,7 @@ mod tests {
                 statement: Statement {
                     token: Some(Token { value: 60 }),
                     keyspace: Some("invalid"),
+                    is_confirmed_lwt: false,
                 },
                 expected_plan: vec![1],
             },
@@ -230,6 +240,7 @@ mod tests {
                 statement: Statement {
                     token: Some(Token { value: 60 }),
                     keyspace: None,
+                    is_confirmed_lwt: false,
                 },
                 expected_plan: vec![1],
             },
@@ -253,6 +264,7 @@ mod tests {
         let statement = Statement {
             token: Some(Token { value: 0 }),
             keyspace: Some("keyspace_with_nts"),
+            is_confirmed_lwt: false,
         };
 
         let plan = tests::get_plan_and_collect_node_identifiers(&policy, &statement, &cluster);
@@ -260,6 +272,32 @@ mod tests {
         assert_eq!(plan, expected_plan);
     }
 
+    #[tokio::test]
+    async fn test_token_aware_policy_with_lwt() {
+        let cluster = mock_cluster_data_for_nts_token_aware_tests();
+
+        let policy =
+            TokenAwarePolicy::new(Box::new(DcAwareRoundRobinPolicy::new("her".to_string())));
+        let mut statement = Statement {
+            token: Some(Token { value: 0 }),
+            keyspace: Some("keyspace_with_nts"),
+            is_confirmed_lwt: true,
+        };
+
+        // Replicas of LWT statements are always tried in ring order, local ones first
+        for _ in 0..8 {
+            let plan = tests::get_plan_and_collect_node_identifiers(&policy, &statement, &cluster);
+            assert_eq!(plan[..5], [5, 6, 8, 1, 4]);
+        }
+
+        // Replicas of other statements are rotated
+        statement.is_confirmed_lwt = false;
+        let first_replicas = (0..8)
+            .map(|_| tests::get_plan_and_collect_node_identifiers(&policy, &statement, &cluster)[0])
+            .collect::<HashSet<_>>();
+        assert!(first_replicas.len() > 1);
+    }
+
     #[tokio::test]
     async fn test_token_aware_fallback_policy() {
         let cluster = mock_cluster_data_for_token_aware_tests();
@@ -291,6 +329,7 @@ mod tests {
                     Token { value: 500 },
                 ],
                 untranslated_address: Some(tests::id_to_invalid_addr(1)),
+                host_id: None,
             },
             Peer {
                 datacenter: Some("eu".into()),
@@ -302,6 +341,7 @@ mod tests {
                     Token { value: 300 },
                 ],
                 untranslated_address: Some(tests::id_to_invalid_addr(2)),
+                host_id: None,
             },
             Peer {
                 datacenter: Some("us".into()),
@@ -309,6 +349,7 @@ mod tests {
                 address: tests::id_to_invalid_addr(3),
                 tokens: vec![Token { value: 200 }, Token { value: 400 }],
                 untranslated_address: Some(tests::id_to_invalid_addr(3)),
+                host_id: None,
             },
         ];
 
@@ -345,7 +386,7 @@ mod tests {
             keyspaces,
         };
 
-        ClusterData::new(info, &Default::default(), &HashMap::new(), &None)
+        ClusterData::new(info, &Default::default(), &HashMap::new(), &None, &None)
     }
 
     // creates ClusterData with info about 8 nodes living in two different datacenters
@@ -369,6 +410,7 @@ mod tests {
                 address: tests::id_to_invalid_addr(1),
                 tokens: vec![Token { value: 50 }, Token { value: 200 }],
                 untranslated_address: Some(tests::id_to_invalid_addr(1)),
+                host_id: None,
             },
             Peer {
                 datacenter: Some("waw".into()),
@@ -376,6 +418,7 @@ mod tests {
                 address: tests::id_to_invalid_addr(2),
                 tokens: vec![Token { value: 150 }],
                 untranslated_address: Some(tests::id_to_invalid_addr(2)),
+                host_id: None,
             },
             Peer {
                 datacenter: Some("waw".into()),
@@ -383,6 +426,7 @@ mod tests {
                 address: tests::id_to_invalid_addr(3),
                 tokens: vec![Token { value: 510 }],
                 untranslated_address: Some(tests::id_to_invalid_addr(3)),
+                host_id: None,
             },
             Peer {
                 datacenter: Some("waw".into()),
@@ -390,6 +434,7 @@ mod tests {
                 address: tests::id_to_invalid_addr(4),
                 tokens: vec![Token { value: 300 }],
                 untranslated_address: Some(tests::id_to_invalid_addr(4)),
+                host_id: None,
             },
             Peer {
                 datacenter: Some("her".into()),
@@ -397,6 +442,7 @@ mod tests {
                 address: tests::id_to_invalid_addr(5),
                 tokens: vec![Token { value: 100 }],
                 untranslated_address: Some(tests::id_to_invalid_addr(5)),
+                host_id: None,
             },
             Peer {
                 datacenter: Some("her".into()),
@@ -404,6 +450,7 @@ mod tests {
                 address: tests::id_to_invalid_addr(6),
                 tokens: vec![Token { value: 250 }],
                 untranslated_address: Some(tests::id_to_invalid_addr(6)),
+                host_id: None,
             },
             Peer {
                 datacenter: Some("her".into()),
@@ -411,6 +458,7 @@ mod tests {
                 address: tests::id_to_invalid_addr(7),
                 tokens: vec![Token { value: 500 }],
                 untranslated_address: Some(tests::id_to_invalid_addr(7)),
+                host_id: None,
             },
             Peer {
                 datacenter: Some("her".into()),
@@ -418,6 +466,7 @@ mod tests {
                 address: tests::id_to_invalid_addr(8),
                 tokens: vec![Token { value: 400 }],
                 untranslated_address: Some(tests::id_to_invalid_addr(8)),
+                host_id: None,
             },
         ];
 
@@ -444,7 +493,7 @@ mod tests {
             keyspaces,
         };
 
-        ClusterData::new(info, &Default::default(), &HashMap::new(), &None)
+        ClusterData::new(info, &Default::default(), &HashMap::new(), &None, &None)
     }
 
     // Used as child policy for TokenAwarePolicy tests
@@ -1,6 +1,7 @@
 use histogram::Histogram;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
 
 const ORDER_TYPE: Ordering = Ordering::Relaxed;
 
@@ -35,6 +36,10 @@ pub struct Metrics {
     errors_iter_num: AtomicU64,
     queries_iter_num: AtomicU64,
     retries_num: AtomicU64,
+    speculative_executions_num: AtomicU64,
+    speculative_wins_num: AtomicU64,
+    reconnection_attempts_num: AtomicU64,
+    reconnection_delays_ms: AtomicU64,
     histogram: Arc<Mutex<Histogram>>,
 }
 
@@ -46,6 +51,10 @@ impl Metrics {
             errors_iter_num: AtomicU64::new(0),
             queries_iter_num: AtomicU64::new(0),
             retries_num: AtomicU64::new(0),
+            speculative_executions_num: AtomicU64::new(0),
+            speculative_wins_num: AtomicU64::new(0),
+            reconnection_attempts_num: AtomicU64::new(0),
+            reconnection_delays_ms: AtomicU64::new(0),
             histogram: Arc::new(Mutex::new(Histogram::new())),
         }
     }
@@ -76,6 +85,25 @@ impl Metrics {
         self.retries_num.fetch_add(1, ORDER_TYPE);
     }
 
+    /// Increments counter for speculative executions started by a speculative execution policy
+    pub(crate) fn inc_speculative_executions_num(&self) {
+        self.speculative_executions_num.fetch_add(1, ORDER_TYPE);
+    }
+
+    /// Increments counter for queries whose result was provided by a speculative execution
+    /// instead of the original one
+    pub(crate) fn inc_speculative_wins_num(&self) {
+        self.speculative_wins_num.fetch_add(1, ORDER_TYPE);
+    }
+
+    /// Increments counter for attempts to reconnect to a node after a connection error,
+    /// and adds the delay which preceded the attempt to the total
+    pub(crate) fn log_reconnection_attempt(&self, delay: Duration) {
+        self.reconnection_attempts_num.fetch_add(1, ORDER_TYPE);
+        self.reconnection_delays_ms
+            .fetch_add(delay.as_millis() as u64, ORDER_TYPE);
+    }
+
     /// Saves to histogram latency of completing single query.
     /// For paged queries it should log latency for every page.
     ///
@@ -127,4 +155,259 @@ impl Metrics {
     pub fn get_retries_num(&self) -> u64 {
         self.retries_num.load(ORDER_TYPE)
     }
+
+    /// Returns counter for speculative executions started by a speculative execution policy
+    pub fn get_speculative_executions_num(&self) -> u64 {
+        self.speculative_executions_num.load(ORDER_TYPE)
+    }
+
+    /// Returns counter for queries whose result was provided by a speculative execution
+    /// instead of the original one
+    pub fn get_speculative_wins_num(&self) -> u64 {
+        self.speculative_wins_num.load(ORDER_TYPE)
+    }
+
+    /// Returns counter for attempts to reconnect to nodes after connection errors,
+    /// including the control connection
+    pub fn get_reconnection_attempts_num(&self) -> u64 {
+        self.reconnection_attempts_num.load(ORDER_TYPE)
+    }
+
+    /// Returns the total time in milliseconds waited before reconnection attempts,
+    /// as decided by the reconnection policy
+    pub fn get_reconnection_delays_ms(&self) -> u64 {
+        self.reconnection_delays_ms.load(ORDER_TYPE)
+    }
+}
+
+/// Latency histogram which takes into account only recent measurements.\
+/// Latencies are collected in windows of a fixed length. Percentiles are computed
+/// from the last completed window, or from the current one if no window was completed yet.
+#[derive(Debug)]
+pub(crate) struct RollingLatencyHistogram {
+    window: Duration,
+    inner: Mutex<RollingLatencyHistogramInner>,
+}
+
+#[derive(Debug)]
+struct RollingLatencyHistogramInner {
+    current: Histogram,
+    current_start: Instant,
+    previous: Option<Histogram>,
+}
+
+impl RollingLatencyHistogram {
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            inner: Mutex::new(RollingLatencyHistogramInner {
+                current: Self::new_histogram(),
+                current_start: Instant::now(),
+                previous: None,
+            }),
+        }
+    }
+
+    // Latencies are stored in microseconds, up to one minute.
+    // Two significant figures are enough to decide whether a request is slow
+    // and keep the histogram small, as there is one per node.
+    fn new_histogram() -> Histogram {
+        Histogram::configure()
+            .max_value(60_000_000)
+            .precision(2)
+            .build()
+            .unwrap()
+    }
+
+    pub(crate) fn log_latency(&self, latency: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        self.rotate(&mut inner, Instant::now());
+        // Values above the maximum are not recorded, they are not useful for percentiles anyway
+        let _ = inner.current.increment(latency.as_micros() as u64);
+    }
+
+    /// Returns latency for a given percentile (0.0 - 100.0),
+    /// or `None` if there were no recent measurements.
+    pub(crate) fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let mut inner = self.inner.lock().unwrap();
+        self.rotate(&mut inner, Instant::now());
+
+        let histogram = match &inner.previous {
+            Some(previous) if previous.entries() > 0 => previous,
+            _ => &inner.current,
+        };
+        if histogram.entries() == 0 {
+            return None;
+        }
+
+        histogram
+            .percentile(percentile)
+            .ok()
+            .map(Duration::from_micros)
+    }
+
+    fn rotate(&self, inner: &mut RollingLatencyHistogramInner, now: Instant) {
+        let elapsed = now.saturating_duration_since(inner.current_start);
+        if elapsed < self.window {
+            return;
+        }
+
+        let completed = std::mem::replace(&mut inner.current, Self::new_histogram());
+        // If more than a whole window has passed without rotation,
+        // the completed window is outdated and shouldn't be used
+        inner.previous = if elapsed < self.window * 2 {
+            Some(completed)
+        } else {
+            None
+        };
+        inner.current_start = now;
+    }
+}
+
+/// Ratio of failed requests which takes into account only recent requests.\
+/// Requests are counted in windows of a fixed length, the ratio is computed
+/// from the current window and the last completed one.
+#[derive(Debug)]
+pub(crate) struct RollingErrorRate {
+    window: Duration,
+    inner: Mutex<RollingErrorRateInner>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct RequestCounts {
+    requests: u64,
+    errors: u64,
+}
+
+#[derive(Debug)]
+struct RollingErrorRateInner {
+    current: RequestCounts,
+    current_start: Instant,
+    previous: RequestCounts,
+}
+
+impl RollingErrorRate {
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            inner: Mutex::new(RollingErrorRateInner {
+                current: RequestCounts::default(),
+                current_start: Instant::now(),
+                previous: RequestCounts::default(),
+            }),
+        }
+    }
+
+    pub(crate) fn log_request(&self, failed: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        self.rotate(&mut inner, Instant::now());
+        inner.current.requests += 1;
+        if failed {
+            inner.current.errors += 1;
+        }
+    }
+
+    /// Returns the ratio of failed requests (0.0 - 1.0),
+    /// or `None` if there were fewer than `min_requests` recent requests.
+    pub(crate) fn error_rate(&self, min_requests: u64) -> Option<f64> {
+        let mut inner = self.inner.lock().unwrap();
+        self.rotate(&mut inner, Instant::now());
+
+        let requests = inner.current.requests + inner.previous.requests;
+        let errors = inner.current.errors + inner.previous.errors;
+        if requests == 0 || requests < min_requests {
+            return None;
+        }
+        Some(errors as f64 / requests as f64)
+    }
+
+    fn rotate(&self, inner: &mut RollingErrorRateInner, now: Instant) {
+        let elapsed = now.saturating_duration_since(inner.current_start);
+        if elapsed < self.window {
+            return;
+        }
+
+        let completed = std::mem::take(&mut inner.current);
+        // If more than a whole window has passed without rotation,
+        // the completed window is outdated and shouldn't be used
+        inner.previous = if elapsed < self.window * 2 {
+            completed
+        } else {
+            RequestCounts::default()
+        };
+        inner.current_start = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RollingErrorRate, RollingLatencyHistogram};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn rolling_histogram_percentiles() {
+        let histogram = RollingLatencyHistogram::new(Duration::from_secs(3600));
+        assert_eq!(histogram.percentile(99.0), None);
+
+        for ms in 1..=100 {
+            histogram.log_latency(Duration::from_millis(ms));
+        }
+
+        // The histogram has limited precision, so allow for some error
+        let p50 = histogram.percentile(50.0).unwrap();
+        assert!(p50 >= Duration::from_millis(48) && p50 <= Duration::from_millis(52));
+        let p99 = histogram.percentile(99.0).unwrap();
+        assert!(p99 >= Duration::from_millis(96) && p99 <= Duration::from_millis(102));
+    }
+
+    #[test]
+    fn rolling_histogram_forgets_old_windows() {
+        let window = Duration::from_secs(10);
+        let histogram = RollingLatencyHistogram::new(window);
+        histogram.log_latency(Duration::from_millis(500));
+
+        // Window completed - its measurements are still used
+        let mut inner = histogram.inner.lock().unwrap();
+        let start = inner.current_start;
+        histogram.rotate(&mut inner, start + window);
+        assert!(inner.previous.is_some());
+        drop(inner);
+        assert!(histogram.percentile(50.0).unwrap() >= Duration::from_millis(490));
+
+        // No measurements for more than a whole window - forget everything
+        let mut inner = histogram.inner.lock().unwrap();
+        let start: Instant = inner.current_start;
+        histogram.rotate(&mut inner, start + window * 3);
+        assert!(inner.previous.is_none());
+        drop(inner);
+        assert_eq!(histogram.percentile(50.0), None);
+    }
+
+    #[test]
+    fn rolling_error_rate() {
+        let window = Duration::from_secs(10);
+        let error_rate = RollingErrorRate::new(window);
+        assert_eq!(error_rate.error_rate(1), None);
+
+        for i in 0..10 {
+            error_rate.log_request(i % 4 == 0);
+        }
+        assert_eq!(error_rate.error_rate(20), None);
+        assert_eq!(error_rate.error_rate(10), Some(0.3));
+
+        // The completed window is still taken into account
+        let mut inner = error_rate.inner.lock().unwrap();
+        let start = inner.current_start;
+        error_rate.rotate(&mut inner, start + window);
+        drop(inner);
+        error_rate.log_request(true);
+        assert_eq!(error_rate.error_rate(1), Some(4.0 / 11.0));
+
+        // No requests for more than a whole window - forget everything
+        let mut inner = error_rate.inner.lock().unwrap();
+        let start = inner.current_start;
+        error_rate.rotate(&mut inner, start + window * 3);
+        drop(inner);
+        assert_eq!(error_rate.error_rate(1), None);
+    }
 }
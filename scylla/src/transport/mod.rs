@@ -1,19 +1,33 @@
+pub mod bulk_writer;
 pub(crate) mod caching_session;
 mod cluster;
+pub mod concurrent_execution;
 pub(crate) mod connection;
 mod connection_pool;
 pub mod downgrading_consistency_retry_policy;
+pub mod host_filter;
 pub mod iterator;
 pub mod load_balancing;
 pub(crate) mod metrics;
 mod node;
+pub mod paging_state;
 pub mod partitioner;
+mod prepared_statement_registry;
 pub mod query_result;
+pub mod reconnection_policy;
+pub(crate) mod request_span;
+mod request_tracker;
 pub mod retry_policy;
 pub mod session;
 pub mod session_builder;
+pub mod session_like;
 pub mod speculative_execution;
+#[cfg(any(feature = "ssl", feature = "rustls"))]
+pub mod tls;
 pub mod topology;
+mod trace_collector;
+#[cfg(feature = "trace-context")]
+pub mod trace_context;
 pub use crate::frame::{Authenticator, Compression};
 pub use scylla_cql::errors;
 
@@ -23,11 +37,13 @@ mod authenticate_test;
 mod cql_collections_test;
 #[cfg(test)]
 mod session_test;
+#[cfg(all(test, any(feature = "ssl", feature = "rustls")))]
+mod tls_test;
 
 #[cfg(test)]
 mod cql_types_test;
 #[cfg(test)]
 mod cql_value_test;
 
-pub use cluster::ClusterData;
-pub use node::Node;
+pub use cluster::{ClusterData, ClusterEvent};
+pub use node::{Node, NodeState};
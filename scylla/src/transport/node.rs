@@ -1,29 +1,104 @@
+#[cfg(feature = "cloud")]
+use crate::cloud::CloudNode;
 /// Node represents a cluster node along with it's data and connections
 use crate::routing::{Sharder, Token};
 use crate::transport::connection::Connection;
 use crate::transport::connection::VerifiedKeyspaceName;
-use crate::transport::connection_pool::{NodeConnectionPool, PoolConfig};
-use crate::transport::errors::QueryError;
+use crate::transport::connection_pool::{NodeConnectionPool, NodePoolStatus, PoolConfig};
+use crate::transport::errors::{DbError, QueryError};
+use crate::transport::metrics::{RollingErrorRate, RollingLatencyHistogram};
 
 use std::{
     hash::{Hash, Hasher},
     net::SocketAddr,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU8, Ordering},
         Arc,
     },
+    time::Duration,
 };
+use uuid::Uuid;
+
+// Length of the window in which latencies of requests to a node are collected
+const LATENCY_WINDOW: Duration = Duration::from_secs(10);
+
+// A node is quarantined if at least QUARANTINE_ERROR_RATE of at least QUARANTINE_MIN_REQUESTS
+// recent requests failed with errors suggesting that the node is unhealthy
+const ERROR_RATE_WINDOW: Duration = Duration::from_secs(10);
+const QUARANTINE_MIN_REQUESTS: u64 = 20;
+const QUARANTINE_ERROR_RATE: f64 = 0.5;
+
+/// State of a node, combining status events sent by the cluster, connectivity of the driver
+/// and errors of recent requests sent to the node.\
+/// Built-in load balancing policies try nodes which aren't [`Up`](NodeState::Up) last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum NodeState {
+    /// The driver has working connections to the node and recent requests mostly succeeded
+    Up,
+
+    /// The cluster reported that the node is down
+    Down,
+
+    /// The driver has no connections to the node, but the cluster didn't report it as down,
+    /// e.g. because of a network partition
+    Unknown,
+
+    /// The driver is opening the first connections to the node
+    Connecting,
+
+    /// The driver has connections to the node, but most of recent requests failed
+    /// with errors suggesting that the node is unhealthy, like broken connections,
+    /// timeouts or overload
+    Quarantined,
+
+    /// The node was rejected by the host filter, the driver never connects to it
+    Disabled,
+}
+
+impl NodeState {
+    fn from_u8(state: u8) -> Self {
+        match state {
+            0 => NodeState::Up,
+            1 => NodeState::Down,
+            2 => NodeState::Unknown,
+            3 => NodeState::Connecting,
+            4 => NodeState::Quarantined,
+            _ => NodeState::Disabled,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            NodeState::Up => 0,
+            NodeState::Down => 1,
+            NodeState::Unknown => 2,
+            NodeState::Connecting => 3,
+            NodeState::Quarantined => 4,
+            NodeState::Disabled => 5,
+        }
+    }
+}
 
 /// Node represents a cluster node along with it's data and connections
 #[derive(Debug)]
 pub struct Node {
     pub address: SocketAddr,
+    pub host_id: Option<Uuid>,
     pub datacenter: Option<String>,
     pub rack: Option<String>,
 
-    pool: NodeConnectionPool,
+    // None if the node was rejected by the host filter
+    pool: Option<NodeConnectionPool>,
 
     down_marker: AtomicBool,
+
+    // NodeState computed by `update_state`, cached so that load balancing policies can check it cheaply
+    state: AtomicU8,
+
+    latencies: RollingLatencyHistogram,
+
+    error_rate: RollingErrorRate,
 }
 
 impl Node {
@@ -31,30 +106,75 @@ impl Node {
     /// # Arguments
     ///
     /// `address` - address to connect to
+    /// `host_id` - optional host id, used to connect through the proxy of a cloud cluster
     /// `compression` - preferred compression to use
     /// `datacenter` - optional datacenter name
     /// `rack` - optional rack name
+    /// `enabled` - whether the node was accepted by the host filter, disabled nodes get no connections
     pub(crate) fn new(
         address: SocketAddr,
-        pool_config: PoolConfig,
+        host_id: Option<Uuid>,
+        #[allow(unused_mut)] mut pool_config: PoolConfig,
         datacenter: Option<String>,
         rack: Option<String>,
         keyspace_name: Option<VerifiedKeyspaceName>,
+        enabled: bool,
     ) -> Self {
-        let pool =
-            NodeConnectionPool::new(address.ip(), address.port(), pool_config, keyspace_name);
+        #[cfg(feature = "cloud")]
+        if let Some(host_id) = host_id {
+            pool_config.connection_config.cloud_node = Some(CloudNode {
+                host_id,
+                datacenter: datacenter.clone(),
+            });
+        }
+
+        let state = if enabled {
+            NodeState::Connecting
+        } else {
+            NodeState::Disabled
+        };
+
+        let pool = if enabled {
+            Some(NodeConnectionPool::new(
+                address.ip(),
+                address.port(),
+                pool_config,
+                keyspace_name,
+            ))
+        } else {
+            None
+        };
 
         Node {
             address,
+            host_id,
             datacenter,
             rack,
             pool,
             down_marker: false.into(),
+            state: state.to_u8().into(),
+            latencies: RollingLatencyHistogram::new(LATENCY_WINDOW),
+            error_rate: RollingErrorRate::new(ERROR_RATE_WINDOW),
         }
     }
 
     pub fn sharder(&self) -> Option<Sharder> {
-        self.pool.sharder()
+        self.pool.as_ref()?.sharder()
+    }
+
+    /// Returns whether the node was accepted by the host filter.\
+    /// The driver doesn't connect to disabled nodes and they never appear in query plans.
+    pub fn is_enabled(&self) -> bool {
+        self.pool.is_some()
+    }
+
+    fn get_pool(&self) -> Result<&NodeConnectionPool, QueryError> {
+        self.pool.as_ref().ok_or_else(|| {
+            QueryError::IoError(Arc::new(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("Node {} is rejected by the host filter", self.address),
+            )))
+        })
     }
 
     /// Get connection which should be used to connect using given token
@@ -63,35 +183,136 @@ impl Node {
         &self,
         token: Token,
     ) -> Result<Arc<Connection>, QueryError> {
-        self.pool.connection_for_token(token)
+        self.get_pool()?.connection_for_token(token)
     }
 
     /// Get random connection
     pub(crate) async fn random_connection(&self) -> Result<Arc<Connection>, QueryError> {
-        self.pool.random_connection()
+        self.get_pool()?.random_connection()
     }
 
+    /// Returns whether the cluster reported that the node is down.\
+    /// See [`state`](Node::state) for the state which also takes connectivity of the driver into account.
     pub fn is_down(&self) -> bool {
         self.down_marker.load(Ordering::Relaxed)
     }
 
+    /// Returns the state of the node.\
+    /// It's cheap to call, e.g. for each request in a load balancing policy, because the driver
+    /// updates it in the background every second and whenever the cluster reports a status change.
+    pub fn state(&self) -> NodeState {
+        NodeState::from_u8(self.state.load(Ordering::Relaxed))
+    }
+
+    // Computes the current state of the node and caches it
+    pub(crate) fn update_state(&self) -> NodeState {
+        let state = self.compute_state();
+        self.state.store(state.to_u8(), Ordering::Relaxed);
+        state
+    }
+
+    fn compute_state(&self) -> NodeState {
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => return NodeState::Disabled,
+        };
+
+        if self.is_down() {
+            return NodeState::Down;
+        }
+        if pool.is_initializing() {
+            return NodeState::Connecting;
+        }
+        if !pool.is_connected() {
+            return NodeState::Unknown;
+        }
+
+        match self.error_rate.error_rate(QUARANTINE_MIN_REQUESTS) {
+            Some(rate) if rate >= QUARANTINE_ERROR_RATE => NodeState::Quarantined,
+            _ => NodeState::Up,
+        }
+    }
+
     pub(crate) fn change_down_marker(&self, is_down: bool) {
         self.down_marker.store(is_down, Ordering::Relaxed);
+        self.update_state();
+    }
+
+    /// Returns latency of successful requests to this node for a given percentile (0.0 - 100.0).\
+    /// Only requests finished in the last few seconds are taken into account.
+    /// Returns `None` if there were no such requests.
+    pub fn get_latency_percentile(&self, percentile: f64) -> Option<Duration> {
+        self.latencies.percentile(percentile)
+    }
+
+    pub(crate) fn log_request_success(&self, latency: Duration) {
+        self.latencies.log_latency(latency);
+        self.error_rate.log_request(false);
+    }
+
+    pub(crate) fn log_request_error(&self, error: &QueryError) {
+        // Other errors are caused by the request, the node itself responded correctly
+        let is_node_unhealthy = matches!(
+            error,
+            QueryError::IoError(_)
+                | QueryError::TimeoutError
+                | QueryError::RequestTimeout(_)
+                | QueryError::DbError(
+                    DbError::Overloaded | DbError::IsBootstrapping | DbError::ServerError,
+                    _
+                )
+        );
+        self.error_rate.log_request(is_node_unhealthy);
     }
 
     pub(crate) async fn use_keyspace(
         &self,
         keyspace_name: VerifiedKeyspaceName,
     ) -> Result<(), QueryError> {
-        self.pool.use_keyspace(keyspace_name).await
+        match &self.pool {
+            Some(pool) => pool.use_keyspace(keyspace_name).await,
+            None => Ok(()),
+        }
     }
 
     pub(crate) fn get_working_connections(&self) -> Result<Vec<Arc<Connection>>, QueryError> {
-        self.pool.get_working_connections()
+        self.get_pool()?.get_working_connections()
+    }
+
+    /// Returns the state of the connection pool to this node
+    pub fn pool_status(&self) -> NodePoolStatus {
+        NodePoolStatus {
+            address: self.address,
+            datacenter: self.datacenter.clone(),
+            shard_connections: self
+                .pool
+                .as_ref()
+                .map(NodeConnectionPool::shard_connection_counts)
+                .unwrap_or_default(),
+            is_full: match &self.pool {
+                Some(pool) => pool.is_full(),
+                None => false,
+            },
+            last_error: self.pool.as_ref().and_then(NodeConnectionPool::last_error),
+        }
+    }
+
+    pub(crate) async fn wait_until_pool_full(&self) {
+        if let Some(pool) = &self.pool {
+            pool.wait_until_full().await
+        }
     }
 
     pub(crate) async fn wait_until_pool_initialized(&self) {
-        self.pool.wait_until_initialized().await
+        if let Some(pool) = &self.pool {
+            pool.wait_until_initialized().await
+        }
+    }
+
+    pub(crate) async fn close_pool(&self) {
+        if let Some(pool) = &self.pool {
+            pool.close().await
+        }
     }
 }
 
@@ -0,0 +1,159 @@
+//! Paging state allowing to resume fetching pages of a query, possibly in another process
+
+use bytes::{BufMut, Bytes, BytesMut};
+use thiserror::Error;
+
+// Version of the token format, bumped whenever the format changes
+const TOKEN_VERSION: u8 = 1;
+
+// Version byte before the state, CRC-32 after it
+const VERSION_LEN: usize = 1;
+const CHECKSUM_LEN: usize = 4;
+
+// URL-safe base64 without padding, as defined in RFC 4648
+const BASE64_CONFIG: base64::Config = base64::URL_SAFE_NO_PAD;
+
+/// Paging state returned by the database along with a page of results.\
+/// Passing it back with the same statement and values makes the database return the next page.
+///
+/// The paging state can be stored as a token with [`to_token`](PagingState::to_token)
+/// and restored with [`from_token`](PagingState::from_token), e.g. to implement cursor based
+/// pagination in a stateless service. The token is URL-safe and carries a checksum, so tokens
+/// which were corrupted or modified are rejected. The checksum does not protect against forging,
+/// tokens received from untrusted parties should be additionally signed.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PagingState {
+    raw: Bytes,
+}
+
+/// Error returned when a paging state token can't be decoded
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PagingStateError {
+    #[error("Paging state token is not valid URL-safe base64")]
+    InvalidEncoding,
+    #[error("Paging state token is too short")]
+    TooShort,
+    #[error("Unsupported paging state token version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("Paging state token checksum mismatch - the token was corrupted or modified")]
+    ChecksumMismatch,
+}
+
+impl PagingState {
+    /// Wraps a paging state received from the database
+    pub fn new(raw: Bytes) -> PagingState {
+        PagingState { raw }
+    }
+
+    /// Returns the raw paging state, as sent to the database
+    pub fn as_bytes(&self) -> &Bytes {
+        &self.raw
+    }
+
+    /// Converts into the raw paging state, which can be passed to
+    /// [`Session::query_paged`](crate::Session::query_paged) and
+    /// [`Session::execute_paged`](crate::Session::execute_paged)
+    pub fn into_bytes(self) -> Bytes {
+        self.raw
+    }
+
+    /// Encodes the paging state as a URL-safe token
+    pub fn to_token(&self) -> String {
+        let mut data = BytesMut::with_capacity(VERSION_LEN + self.raw.len() + CHECKSUM_LEN);
+        data.put_u8(TOKEN_VERSION);
+        data.put_slice(&self.raw);
+        let checksum = crc32fast::hash(&data);
+        data.put_u32(checksum);
+
+        base64::encode_config(&data, BASE64_CONFIG)
+    }
+
+    /// Decodes a token created with [`to_token`](PagingState::to_token)
+    pub fn from_token(token: &str) -> Result<PagingState, PagingStateError> {
+        let data = base64::decode_config(token, BASE64_CONFIG)
+            .map_err(|_| PagingStateError::InvalidEncoding)?;
+        if data.len() < VERSION_LEN + CHECKSUM_LEN {
+            return Err(PagingStateError::TooShort);
+        }
+
+        let (content, checksum) = data.split_at(data.len() - CHECKSUM_LEN);
+        let mut checksum_bytes = [0u8; CHECKSUM_LEN];
+        checksum_bytes.copy_from_slice(checksum);
+        if crc32fast::hash(content) != u32::from_be_bytes(checksum_bytes) {
+            return Err(PagingStateError::ChecksumMismatch);
+        }
+
+        if content[0] != TOKEN_VERSION {
+            return Err(PagingStateError::UnsupportedVersion(content[0]));
+        }
+
+        Ok(PagingState {
+            raw: Bytes::copy_from_slice(&content[VERSION_LEN..]),
+        })
+    }
+}
+
+impl From<Bytes> for PagingState {
+    fn from(raw: Bytes) -> PagingState {
+        PagingState::new(raw)
+    }
+}
+
+impl From<PagingState> for Bytes {
+    fn from(paging_state: PagingState) -> Bytes {
+        paging_state.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PagingState, PagingStateError};
+    use bytes::Bytes;
+
+    #[test]
+    fn token_format_is_stable() {
+        // Tokens might be stored by users, the format must not change without bumping the version
+        let paging_state = PagingState::new(Bytes::from_static(b"paging state"));
+        assert_eq!(paging_state.to_token(), "AXBhZ2luZyBzdGF0ZU1kn88");
+    }
+
+    #[test]
+    fn token_roundtrip() {
+        let raw_states: [&[u8]; 4] = [b"", b"a", b"ab", b"\x00\x01\x02\xff\xfe paging state"];
+        for raw in raw_states.iter() {
+            let paging_state = PagingState::new(Bytes::copy_from_slice(raw));
+            let token = paging_state.to_token();
+            assert!(token
+                .bytes()
+                .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_'));
+            assert_eq!(PagingState::from_token(&token), Ok(paging_state));
+        }
+    }
+
+    #[test]
+    fn invalid_tokens_are_rejected() {
+        let token = PagingState::new(Bytes::from_static(b"paging state")).to_token();
+
+        // Change a single character of the token
+        let mut modified = token.into_bytes();
+        modified[5] = if modified[5] == b'A' { b'B' } else { b'A' };
+        let modified = String::from_utf8(modified).unwrap();
+        assert_eq!(
+            PagingState::from_token(&modified),
+            Err(PagingStateError::ChecksumMismatch)
+        );
+
+        assert_eq!(
+            PagingState::from_token("not a token"),
+            Err(PagingStateError::InvalidEncoding)
+        );
+        assert_eq!(
+            PagingState::from_token("AXBh+g"),
+            Err(PagingStateError::InvalidEncoding)
+        );
+        assert_eq!(
+            PagingState::from_token("AAA"),
+            Err(PagingStateError::TooShort)
+        );
+    }
+}
@@ -0,0 +1,253 @@
+/// Registry of statements prepared by the session, used to prepare them
+/// on nodes which come back up or join the cluster
+use crate::prepared_statement::PreparedStatement;
+use crate::query::Query;
+use crate::transport::connection::{Connection, VerifiedKeyspaceName};
+use crate::transport::node::Node;
+
+use bytes::Bytes;
+use futures::future::join_all;
+use std::collections::HashMap;
+use std::sync::{Mutex, Weak};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+// A node which came back up might not have its connections reopened yet
+const CONNECTIONS_WAIT_ATTEMPTS: u32 = 10;
+const CONNECTIONS_WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+struct RegisteredStatement {
+    contents: String,
+    // Keyspace used by the session when the statement was prepared,
+    // unqualified table names in the statement refer to it
+    keyspace: Option<VerifiedKeyspaceName>,
+    // One for every separately prepared copy of the statement, e.g. when the same statement
+    // is prepared twice. The statement doesn't need to be prepared once all of them are dropped.
+    statement_refs: Vec<Weak<()>>,
+}
+
+impl RegisteredStatement {
+    fn is_dropped(&self) -> bool {
+        self.statement_refs
+            .iter()
+            .all(|statement_ref| statement_ref.strong_count() == 0)
+    }
+
+    fn add_ref(&mut self, statement_ref: Weak<()>) {
+        self.statement_refs
+            .retain(|statement_ref| statement_ref.strong_count() > 0);
+        if !self
+            .statement_refs
+            .iter()
+            .any(|registered| registered.ptr_eq(&statement_ref))
+        {
+            self.statement_refs.push(statement_ref);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PreparedStatementRegistry {
+    // Statements by id
+    statements: Mutex<HashMap<Bytes, RegisteredStatement>>,
+    // Keyspace set on connections with `USE <keyspace>`
+    used_keyspace: Mutex<Option<VerifiedKeyspaceName>>,
+}
+
+impl PreparedStatementRegistry {
+    pub(crate) fn new() -> Self {
+        Default::default()
+    }
+
+    pub(crate) fn register(&self, prepared: &PreparedStatement) {
+        let keyspace = self.used_keyspace.lock().unwrap().clone();
+        let mut statements = self.statements.lock().unwrap();
+
+        if let Some(statement) = statements.get_mut(prepared.get_id()) {
+            statement.add_ref(prepared.downgrade());
+            return;
+        }
+
+        // Statements dropped by the user are forgotten, so that the registry doesn't grow indefinitely.
+        // The registry grows only when a new statement is inserted, so it's enough to check then.
+        statements.retain(|_, statement| !statement.is_dropped());
+        statements.insert(
+            prepared.get_id().clone(),
+            RegisteredStatement {
+                contents: prepared.get_statement().to_owned(),
+                keyspace,
+                statement_refs: vec![prepared.downgrade()],
+            },
+        );
+    }
+
+    pub(crate) fn set_used_keyspace(&self, keyspace: VerifiedKeyspaceName) {
+        *self.used_keyspace.lock().unwrap() = Some(keyspace);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.statements.lock().unwrap().len()
+    }
+
+    // Statements which are still in use and were prepared in the keyspace currently set on connections.
+    // Statements prepared in another keyspace would get a different id, they are prepared
+    // again when they're executed.
+    pub(crate) fn statements_to_prepare(&self) -> Vec<String> {
+        let used_keyspace = self.used_keyspace.lock().unwrap().clone();
+        self.statements
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|statement| !statement.is_dropped() && statement.keyspace == used_keyspace)
+            .map(|statement| statement.contents.clone())
+            .collect()
+    }
+
+    /// Prepares all registered statements on all connections to the node
+    pub(crate) async fn prepare_on_node(&self, node: &Node) {
+        node.wait_until_pool_initialized().await;
+
+        let mut attempts = CONNECTIONS_WAIT_ATTEMPTS;
+        let connections = loop {
+            match node.get_working_connections() {
+                Ok(connections) => break connections,
+                Err(err) if attempts <= 1 => {
+                    warn!(
+                        "Could not prepare statements on node {}: {}",
+                        node.address, err
+                    );
+                    return;
+                }
+                Err(_) => {
+                    attempts -= 1;
+                    tokio::time::sleep(CONNECTIONS_WAIT_INTERVAL).await;
+                }
+            }
+        };
+
+        // Cloned, so that the lock is not held across await points
+        let statements = self.statements_to_prepare();
+        if statements.is_empty() {
+            return;
+        }
+
+        debug!(
+            "Preparing {} statements on node {}",
+            statements.len(),
+            node.address
+        );
+        join_all(
+            connections
+                .iter()
+                .map(|connection| Self::prepare_on_connection(connection, &statements)),
+        )
+        .await;
+    }
+
+    async fn prepare_on_connection(connection: &Connection, statements: &[String]) {
+        let results = join_all(statements.iter().map(|statement| async move {
+            connection.prepare(&Query::new(statement.clone())).await
+        }))
+        .await;
+
+        for (statement, result) in statements.iter().zip(results) {
+            if let Err(err) = result {
+                // The statement will be prepared again when it's executed
+                debug!("Failed to prepare statement {:?}: {}", statement, err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PreparedStatementRegistry;
+    use crate::frame::response::result::{PreparedMetadata, ResultMetadata};
+    use crate::prepared_statement::PreparedStatement;
+    use crate::statement::StatementConfig;
+    use crate::transport::connection::VerifiedKeyspaceName;
+    use bytes::Bytes;
+
+    fn prepared(id: &'static [u8], statement: &str) -> PreparedStatement {
+        PreparedStatement::new(
+            Bytes::from_static(id),
+            PreparedMetadata::default(),
+            ResultMetadata::default(),
+            statement.to_owned(),
+            None,
+            StatementConfig::default(),
+        )
+    }
+
+    #[test]
+    fn statements_are_registered_once() {
+        let registry = PreparedStatementRegistry::new();
+        assert_eq!(registry.len(), 0);
+
+        let first = prepared(b"1", "SELECT a FROM ks.t");
+        let second = prepared(b"2", "SELECT b FROM ks.t");
+        registry.register(&first);
+        registry.register(&second);
+        registry.register(&first.clone());
+        assert_eq!(registry.len(), 2);
+    }
+
+    #[test]
+    fn dropped_statements_are_forgotten() {
+        let registry = PreparedStatementRegistry::new();
+
+        let first = prepared(b"1", "SELECT a FROM ks.t");
+        let first_clone = first.clone();
+        registry.register(&first);
+        drop(first);
+        assert_eq!(registry.statements_to_prepare(), vec!["SELECT a FROM ks.t"]);
+
+        drop(first_clone);
+        assert!(registry.statements_to_prepare().is_empty());
+
+        // Dropped statements are removed when another one is registered
+        let second = prepared(b"2", "SELECT b FROM ks.t");
+        registry.register(&second);
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn statements_prepared_twice_are_kept_while_any_copy_is_used() {
+        let registry = PreparedStatementRegistry::new();
+
+        let first = prepared(b"1", "SELECT a FROM ks.t");
+        let second = prepared(b"1", "SELECT a FROM ks.t");
+        registry.register(&first);
+        registry.register(&second);
+        registry.register(&second.clone());
+
+        drop(first);
+        assert_eq!(registry.statements_to_prepare(), vec!["SELECT a FROM ks.t"]);
+
+        // A copy prepared after all previous ones were dropped is registered as well
+        drop(second);
+        assert!(registry.statements_to_prepare().is_empty());
+        let third = prepared(b"1", "SELECT a FROM ks.t");
+        registry.register(&third);
+        assert_eq!(registry.statements_to_prepare(), vec!["SELECT a FROM ks.t"]);
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn statements_are_prepared_in_their_keyspace() {
+        let registry = PreparedStatementRegistry::new();
+
+        let no_keyspace = prepared(b"1", "SELECT a FROM ks.t");
+        registry.register(&no_keyspace);
+
+        registry.set_used_keyspace(VerifiedKeyspaceName::new("ks".to_owned(), false).unwrap());
+        let in_keyspace = prepared(b"2", "SELECT a FROM t");
+        registry.register(&in_keyspace);
+        assert_eq!(registry.statements_to_prepare(), vec!["SELECT a FROM t"]);
+
+        registry.set_used_keyspace(VerifiedKeyspaceName::new("other".to_owned(), false).unwrap());
+        assert!(registry.statements_to_prepare().is_empty());
+    }
+}
@@ -3,6 +3,7 @@ use crate::frame::response::result::ColumnSpec;
 use crate::frame::response::result::Row;
 use crate::transport::session::{IntoTypedRows, TypedRowIter};
 use bytes::Bytes;
+use std::collections::HashMap;
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -16,6 +17,8 @@ pub struct QueryResult {
     pub rows: Option<Vec<Row>>,
     /// Warnings returned by the database
     pub warnings: Vec<String>,
+    /// Custom payload returned by the database, if any
+    pub custom_payload: Option<HashMap<String, Bytes>>,
     /// CQL Tracing uuid - can only be Some if tracing is enabled for this query
     pub tracing_id: Option<Uuid>,
     /// Paging state returned from the server
@@ -25,6 +28,17 @@ pub struct QueryResult {
 }
 
 impl QueryResult {
+    /// Returns warnings returned by the database, e.g. about a batch being too large
+    /// or too many tombstones being read.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Returns the custom payload returned by the database, if any.
+    pub fn custom_payload(&self) -> Option<&HashMap<String, Bytes>> {
+        self.custom_payload.as_ref()
+    }
+
     /// Returns the number of received rows.\
     /// Fails when the query isn't of a type that could return rows, same as [`rows()`](QueryResult::rows).
     pub fn rows_num(&self) -> Result<usize, RowsExpectedError> {
@@ -124,6 +138,50 @@ impl QueryResult {
         Ok(self.single_row()?.into_typed::<RowT>()?)
     }
 
+    /// Returns whether a lightweight transaction (a statement with an `IF` clause) was applied,
+    /// read from the `[applied]` column of the first row.\
+    /// Fails if the result doesn't come from a lightweight transaction.
+    pub fn lwt_applied(&self) -> Result<bool, LwtResultError> {
+        let rows = self.rows.as_ref().ok_or(RowsExpectedError)?;
+        let first_row = rows.first().ok_or(LwtResultError::RowsEmpty)?;
+        let (applied_idx, _) = self
+            .get_column_spec(LWT_APPLIED_COLUMN)
+            .ok_or(LwtResultError::NotLwt)?;
+
+        match first_row.columns.get(applied_idx) {
+            Some(Some(value)) => value.as_boolean().ok_or(LwtResultError::NotLwt),
+            _ => Err(LwtResultError::NotLwt),
+        }
+    }
+
+    /// Returns the result of a lightweight transaction (a statement with an `IF` clause):
+    /// whether it was applied and the current row, parsed as the given type
+    /// from all columns other than `[applied]`.\
+    /// Fails if the result doesn't come from a lightweight transaction.
+    pub fn lwt_result<RowT: FromRow>(self) -> Result<LwtResult<RowT>, LwtResultError> {
+        let applied = self.lwt_applied()?;
+        let (applied_idx, _) = self
+            .get_column_spec(LWT_APPLIED_COLUMN)
+            .ok_or(LwtResultError::NotLwt)?;
+
+        // lwt_applied() already checked that the first row exists
+        let mut row = self.rows.and_then(|rows| rows.into_iter().next()).unwrap();
+        row.columns.remove(applied_idx);
+
+        // The database doesn't return the current row if the transaction was applied (Cassandra),
+        // and returns only nulls if there is no current row (Scylla)
+        let current_row = if row.columns.iter().all(Option::is_none) {
+            None
+        } else {
+            Some(row.into_typed::<RowT>()?)
+        };
+
+        Ok(LwtResult {
+            applied,
+            current_row,
+        })
+    }
+
     /// Returns a column specification for a column with given name, or None if not found
     pub fn get_column_spec<'a>(&'a self, name: &str) -> Option<(usize, &'a ColumnSpec)> {
         self.col_specs
@@ -144,12 +202,32 @@ impl QueryResult {
         };
 
         self.warnings.extend(other.warnings);
+        if let Some(other_payload) = other.custom_payload {
+            self.custom_payload
+                .get_or_insert_with(HashMap::new)
+                .extend(other_payload);
+        }
         self.tracing_id = other.tracing_id;
         self.paging_state = other.paging_state;
         self.col_specs = other.col_specs;
     }
 }
 
+// Name of the column telling whether a lightweight transaction was applied
+const LWT_APPLIED_COLUMN: &str = "[applied]";
+
+/// Result of a lightweight transaction (a statement with an `IF` clause),
+/// returned by [`QueryResult::lwt_result()`](QueryResult::lwt_result)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LwtResult<RowT> {
+    /// Whether the condition was met and the statement was applied
+    pub applied: bool,
+    /// The current values of the row, read while evaluating the condition.\
+    /// `None` if the row doesn't exist, or if the database didn't return it
+    /// (Cassandra returns it only when the statement wasn't applied).
+    pub current_row: Option<RowT>,
+}
+
 /// [`QueryResult::rows()`](QueryResult::rows) or a similar function called on a bad QueryResult.\
 /// Expected `QueryResult.rows` to be `Some`, but it was `None`.\
 /// `QueryResult.rows` is `Some` for queries that can return rows (e.g `SELECT`).\
@@ -254,6 +332,28 @@ pub enum SingleRowTypedError {
     FromRowError(#[from] FromRowError),
 }
 
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum LwtResultError {
+    /// [`QueryResult::lwt_result()`](QueryResult::lwt_result) or a similar function called on a bad QueryResult.\
+    /// Expected `QueryResult.rows` to be `Some`, but it was `None`.\
+    /// `QueryResult.rows` is `Some` for queries that can return rows (e.g `SELECT`).\
+    /// It is `None` for queries that can't return rows (e.g `INSERT`).
+    #[error(transparent)]
+    RowsExpected(#[from] RowsExpectedError),
+
+    /// Rows in `QueryResult` are empty
+    #[error("Rows in QueryResult are empty")]
+    RowsEmpty,
+
+    /// The result has no boolean `[applied]` column, so it doesn't come from a lightweight transaction
+    #[error("QueryResult has no [applied] column, it isn't a result of a lightweight transaction")]
+    NotLwt,
+
+    /// Parsing the current row as the given type failed
+    #[error(transparent)]
+    FromRowError(#[from] FromRowError),
+}
+
 impl From<FirstRowError> for FirstRowTypedError {
     fn from(err: FirstRowError) -> FirstRowTypedError {
         match err {
@@ -318,6 +418,7 @@ mod tests {
         QueryResult {
             rows: None,
             warnings: vec![],
+            custom_payload: None,
             tracing_id: None,
             paging_state: None,
             col_specs: vec![column_spec],
@@ -600,4 +701,61 @@ mod tests {
             Err(SingleRowTypedError::FromRowError(_))
         ));
     }
+
+    fn make_lwt_query_result(applied: bool, value: Option<i32>) -> QueryResult {
+        let column_spec = |name: &str, typ: ColumnType| ColumnSpec {
+            table_spec: TableSpec {
+                ks_name: "some_keyspace".to_string(),
+                table_name: "some_table".to_string(),
+            },
+            name: name.to_string(),
+            typ,
+        };
+
+        let mut res = make_not_rows_query_result();
+        res.col_specs = vec![
+            column_spec("[applied]", ColumnType::Boolean),
+            column_spec("v", ColumnType::Int),
+        ];
+        res.rows = Some(vec![Row {
+            columns: vec![Some(CqlValue::Boolean(applied)), value.map(CqlValue::Int)],
+        }]);
+        res
+    }
+
+    #[test]
+    fn lwt_result_test() {
+        assert_eq!(make_lwt_query_result(true, None).lwt_applied(), Ok(true));
+        assert_eq!(
+            make_lwt_query_result(true, None).lwt_result::<(i32,)>(),
+            Ok(LwtResult {
+                applied: true,
+                current_row: None
+            })
+        );
+        assert_eq!(
+            make_lwt_query_result(false, Some(7)).lwt_result::<(i32,)>(),
+            Ok(LwtResult {
+                applied: false,
+                current_row: Some((7,))
+            })
+        );
+
+        assert_eq!(
+            make_not_rows_query_result().lwt_applied(),
+            Err(LwtResultError::RowsExpected(RowsExpectedError))
+        );
+        assert_eq!(
+            make_rows_query_result(0).lwt_applied(),
+            Err(LwtResultError::RowsEmpty)
+        );
+        assert_eq!(
+            make_rows_query_result(1).lwt_result::<(i32,)>(),
+            Err(LwtResultError::NotLwt)
+        );
+        assert!(matches!(
+            make_lwt_query_result(false, Some(7)).lwt_result::<(String,)>(),
+            Err(LwtResultError::FromRowError(_))
+        ));
+    }
 }
@@ -0,0 +1,210 @@
+//! Policies deciding how long the driver waits before reconnecting to a node.
+//!
+//! Connection pools, including the control connection, reconnect using a schedule
+//! created by the policy each time a series of reconnection attempts starts.
+
+use rand::Rng;
+use std::time::Duration;
+
+/// The policy that decides how long to wait before each attempt to reconnect to a node.
+pub trait ReconnectionPolicy: std::fmt::Debug + Send + Sync {
+    /// Creates the schedule of delays for a new series of reconnection attempts.
+    /// It's created again after the driver successfully connects.
+    fn new_schedule(&self) -> Box<dyn ReconnectionSchedule>;
+}
+
+/// Delays of consecutive reconnection attempts
+pub trait ReconnectionSchedule: Send + Sync {
+    /// The delay before the next reconnection attempt
+    fn next_delay(&mut self) -> Duration;
+}
+
+/// A ReconnectionPolicy that always waits the same delay.
+#[derive(Debug, Clone)]
+pub struct ConstantReconnectionPolicy {
+    /// The delay before each reconnection attempt
+    pub delay: Duration,
+}
+
+/// A ReconnectionPolicy whose delay is doubled after each failed attempt,
+/// starting from `base_delay` up to `max_delay`.
+#[derive(Debug, Clone)]
+pub struct ExponentialReconnectionPolicy {
+    /// The delay before the first reconnection attempt
+    pub base_delay: Duration,
+
+    /// The upper bound of delays
+    pub max_delay: Duration,
+}
+
+/// Same as [`ExponentialReconnectionPolicy`], but each delay is shortened by a random
+/// fraction of itself, up to `jitter`. This spreads reconnection attempts of many clients
+/// in time, e.g. when a whole rack restarts.
+#[derive(Debug, Clone)]
+pub struct ExponentialJitterReconnectionPolicy {
+    /// The delay before the first reconnection attempt, before applying the jitter
+    pub base_delay: Duration,
+
+    /// The upper bound of delays
+    pub max_delay: Duration,
+
+    /// The largest fraction of a delay removed at random, between 0.0 and 1.0 (ex: 0.5).
+    /// Values out of this range are clamped to it, NaN disables the jitter.
+    pub jitter: f64,
+}
+
+impl Default for ExponentialReconnectionPolicy {
+    /// Starts with 50 ms and waits at most 10 s
+    fn default() -> Self {
+        Self {
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl ReconnectionPolicy for ConstantReconnectionPolicy {
+    fn new_schedule(&self) -> Box<dyn ReconnectionSchedule> {
+        Box::new(ConstantSchedule { delay: self.delay })
+    }
+}
+
+impl ReconnectionPolicy for ExponentialReconnectionPolicy {
+    fn new_schedule(&self) -> Box<dyn ReconnectionSchedule> {
+        Box::new(ExponentialSchedule::new(self.base_delay, self.max_delay))
+    }
+}
+
+impl ReconnectionPolicy for ExponentialJitterReconnectionPolicy {
+    fn new_schedule(&self) -> Box<dyn ReconnectionSchedule> {
+        Box::new(JitterSchedule {
+            exponential: ExponentialSchedule::new(self.base_delay, self.max_delay),
+            jitter: if self.jitter.is_nan() {
+                0.0
+            } else {
+                self.jitter.clamp(0.0, 1.0)
+            },
+        })
+    }
+}
+
+struct ConstantSchedule {
+    delay: Duration,
+}
+
+impl ReconnectionSchedule for ConstantSchedule {
+    fn next_delay(&mut self) -> Duration {
+        self.delay
+    }
+}
+
+struct ExponentialSchedule {
+    next_delay: Duration,
+    max_delay: Duration,
+}
+
+impl ExponentialSchedule {
+    fn new(base_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            next_delay: std::cmp::min(base_delay, max_delay),
+            max_delay,
+        }
+    }
+}
+
+impl ReconnectionSchedule for ExponentialSchedule {
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.next_delay;
+        self.next_delay = std::cmp::min(self.max_delay, delay.saturating_mul(2));
+        delay
+    }
+}
+
+struct JitterSchedule {
+    exponential: ExponentialSchedule,
+    jitter: f64,
+}
+
+impl ReconnectionSchedule for JitterSchedule {
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.exponential.next_delay();
+        let removed_fraction = rand::thread_rng().gen_range(0.0..=self.jitter);
+        delay.mul_f64(1.0 - removed_fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delays(policy: &dyn ReconnectionPolicy, count: usize) -> Vec<Duration> {
+        let mut schedule = policy.new_schedule();
+        (0..count).map(|_| schedule.next_delay()).collect()
+    }
+
+    #[test]
+    fn constant_policy() {
+        let policy = ConstantReconnectionPolicy {
+            delay: Duration::from_millis(100),
+        };
+        assert_eq!(delays(&policy, 3), vec![Duration::from_millis(100); 3]);
+    }
+
+    #[test]
+    fn exponential_policy() {
+        let policy = ExponentialReconnectionPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+        };
+        let expected: Vec<Duration> = [100, 200, 400, 500, 500]
+            .iter()
+            .map(|ms| Duration::from_millis(*ms))
+            .collect();
+        assert_eq!(delays(&policy, 5), expected);
+
+        // A new schedule starts from the base delay again
+        assert_eq!(delays(&policy, 1), vec![Duration::from_millis(100)]);
+    }
+
+    #[test]
+    fn exponential_jitter_policy() {
+        let policy = ExponentialJitterReconnectionPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            jitter: 0.5,
+        };
+        let upper_bounds = [100, 200, 400, 500, 500];
+
+        for _ in 0..100 {
+            for (delay, upper_bound) in delays(&policy, 5).into_iter().zip(upper_bounds) {
+                let upper_bound = Duration::from_millis(upper_bound);
+                assert!(delay <= upper_bound);
+                assert!(delay >= upper_bound / 2);
+            }
+        }
+    }
+
+    #[test]
+    fn exponential_jitter_policy_sanitizes_jitter() {
+        let expected: Vec<Duration> = [100, 200, 400]
+            .iter()
+            .map(|ms| Duration::from_millis(*ms))
+            .collect();
+        for jitter in [f64::NAN, f64::NEG_INFINITY, -1.0, 0.0] {
+            let policy = ExponentialJitterReconnectionPolicy {
+                base_delay: Duration::from_millis(100),
+                max_delay: Duration::from_millis(500),
+                jitter,
+            };
+            assert_eq!(delays(&policy, 3), expected);
+        }
+
+        // The whole delay can be removed at most
+        let policy = ExponentialJitterReconnectionPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            jitter: f64::INFINITY,
+        };
+        assert!(delays(&policy, 3).iter().all(|delay| *delay <= expected[2]));
+    }
+}
@@ -0,0 +1,286 @@
+//! Structured `tracing` spans describing requests sent by the driver.
+//!
+//! Field names follow the OpenTelemetry semantic conventions for database clients,
+//! so that the spans can be exported as-is by `tracing-opentelemetry`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tracing::field::Empty;
+use tracing::{info_span, Span};
+
+use crate::batch::{Batch, BatchStatement};
+use crate::frame::response::{result, NonErrorResponse};
+use crate::frame::types::Consistency;
+use crate::prepared_statement::PreparedStatement;
+use crate::query::Query;
+use crate::routing::{Shard, Token};
+use crate::statement::StatementKind;
+use crate::transport::connection::NonErrorQueryResponse;
+use crate::transport::errors::{DbError, QueryError};
+use crate::transport::node::Node;
+
+/// Value of the `db.system` field - Scylla is reported as Cassandra,
+/// as there is no dedicated identifier for it in the conventions.
+const DB_SYSTEM: &str = "cassandra";
+
+/// Span covering a whole request, including all its retries and speculative executions
+pub(crate) struct RequestSpan {
+    span: Span,
+    retries: AtomicUsize,
+    rows: AtomicUsize,
+    internal: bool,
+}
+
+impl RequestSpan {
+    pub(crate) fn new_query(query: &Query) -> Self {
+        let span = Self::make_span(
+            StatementKind::Query,
+            &query.contents,
+            query.config.is_idempotent,
+        );
+        if let Some(page_size) = query.get_page_size() {
+            span.record("db.cassandra.page_size", page_size);
+        }
+        Self::from_span(span)
+    }
+
+    pub(crate) fn new_prepared(prepared: &PreparedStatement, token: Option<Token>) -> Self {
+        let span = Self::make_span(
+            prepared.statement_kind(),
+            prepared.get_statement(),
+            prepared.config.is_idempotent,
+        );
+        if !span.is_disabled() {
+            span.record(
+                "scylla.prepared_id",
+                format!("{:X}", prepared.get_id()).as_str(),
+            );
+        }
+        if let Some(keyspace) = prepared.get_keyspace_name() {
+            span.record("db.name", keyspace);
+        }
+        if let Some(page_size) = prepared.get_page_size() {
+            span.record("db.cassandra.page_size", page_size);
+        }
+        if let Some(token) = token {
+            span.record("scylla.token", token.value);
+        }
+        Self::from_span(span)
+    }
+
+    pub(crate) fn new_batch(batch: &Batch, token: Option<Token>) -> Self {
+        // Only the first statement is recorded, so that large batches don't cost an allocation
+        let first_statement = batch
+            .statements
+            .first()
+            .map(|statement| match statement {
+                BatchStatement::Query(query) => query.contents.as_str(),
+                BatchStatement::PreparedStatement(prepared) => prepared.get_statement(),
+            })
+            .unwrap_or_default();
+
+        let span = Self::make_span(
+            StatementKind::Batch,
+            first_statement,
+            batch.config.is_idempotent,
+        );
+        span.record("scylla.batch_size", batch.statements.len());
+        if let Some(keyspace) = batch
+            .statements
+            .iter()
+            .find_map(|statement| match statement {
+                BatchStatement::PreparedStatement(prepared) => prepared.get_keyspace_name(),
+                BatchStatement::Query(_) => None,
+            })
+        {
+            span.record("db.name", keyspace);
+        }
+        if let Some(token) = token {
+            span.record("scylla.token", token.value);
+        }
+        Self::from_span(span)
+    }
+
+    /// Request span which records nothing - used for requests issued internally by the driver.\
+    /// Such requests are not reported to the history listener of the session either.
+    pub(crate) fn internal() -> Self {
+        Self {
+            internal: true,
+            ..Self::from_span(Span::none())
+        }
+    }
+
+    fn make_span(kind: StatementKind, statement: &str, is_idempotent: bool) -> Span {
+        info_span!(
+            "Request",
+            otel.kind = "client",
+            otel.status_code = Empty,
+            db.system = DB_SYSTEM,
+            db.statement = statement,
+            db.name = Empty,
+            db.cassandra.consistency_level = Empty,
+            db.cassandra.idempotence = is_idempotent,
+            db.cassandra.page_size = Empty,
+            db.cassandra.speculative_execution_count = Empty,
+            db.response.returned_rows = Empty,
+            scylla.statement_kind = statement_kind_name(kind),
+            scylla.prepared_id = Empty,
+            scylla.batch_size = Empty,
+            scylla.token = Empty,
+            scylla.retry_count = 0,
+            error.type = Empty,
+        )
+    }
+
+    fn from_span(span: Span) -> Self {
+        Self {
+            span,
+            retries: AtomicUsize::new(0),
+            rows: AtomicUsize::new(0),
+            internal: false,
+        }
+    }
+
+    pub(crate) fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub(crate) fn is_internal(&self) -> bool {
+        self.internal
+    }
+
+    pub(crate) fn record_consistency(&self, consistency: Consistency) {
+        self.span.record(
+            "db.cassandra.consistency_level",
+            consistency_name(consistency),
+        );
+    }
+
+    pub(crate) fn record_retry(&self) {
+        let retries = self.retries.fetch_add(1, Ordering::Relaxed) + 1;
+        self.span.record("scylla.retry_count", retries);
+    }
+
+    pub(crate) fn record_rows(&self, rows: usize) {
+        let rows = self.rows.fetch_add(rows, Ordering::Relaxed) + rows;
+        self.span.record("db.response.returned_rows", rows);
+    }
+
+    pub(crate) fn record_response(&self, response: &NonErrorQueryResponse) {
+        if let NonErrorResponse::Result(result::Result::Rows(rows)) = &response.response {
+            self.record_rows(rows.rows.len());
+        }
+    }
+
+    pub(crate) fn record_error(&self, error: &QueryError) {
+        self.span.record("otel.status_code", "ERROR");
+        self.span.record("error.type", error_type(error));
+    }
+}
+
+/// Creates a span for a single attempt of sending the request to the given node.\
+/// The span is a child of the current one - the request span or the span of a speculative fiber.
+pub(crate) fn attempt_span(node: &Node, attempt: usize) -> Span {
+    info_span!(
+        "Attempt",
+        otel.kind = "client",
+        server.address = node.address.ip().to_string().as_str(),
+        server.port = node.address.port(),
+        db.cassandra.coordinator.dc = node.datacenter.as_deref(),
+        scylla.shard = Empty,
+        scylla.attempt = attempt,
+        error.type = Empty,
+    )
+}
+
+pub(crate) fn record_attempt_shard(span: &Span, shard: Option<Shard>) {
+    if let Some(shard) = shard {
+        span.record("scylla.shard", shard);
+    }
+}
+
+pub(crate) fn record_attempt_error(span: &Span, error: &QueryError) {
+    span.record("error.type", error_type(error));
+}
+
+fn statement_kind_name(kind: StatementKind) -> &'static str {
+    match kind {
+        StatementKind::Query => "query",
+        StatementKind::Prepared => "prepared",
+        StatementKind::Batch => "batch",
+        StatementKind::Lwt => "lwt",
+    }
+}
+
+fn consistency_name(consistency: Consistency) -> &'static str {
+    match consistency {
+        Consistency::Any => "any",
+        Consistency::One => "one",
+        Consistency::Two => "two",
+        Consistency::Three => "three",
+        Consistency::Quorum => "quorum",
+        Consistency::All => "all",
+        Consistency::LocalQuorum => "local_quorum",
+        Consistency::EachQuorum => "each_quorum",
+        Consistency::LocalOne => "local_one",
+    }
+}
+
+/// Low-cardinality description of an error, suitable for the `error.type` field
+pub(crate) fn error_type(error: &QueryError) -> &'static str {
+    match error {
+        QueryError::DbError(db_error, _) => match db_error {
+            DbError::SyntaxError => "SyntaxError",
+            DbError::Invalid => "Invalid",
+            DbError::AlreadyExists { .. } => "AlreadyExists",
+            DbError::FunctionFailure { .. } => "FunctionFailure",
+            DbError::AuthenticationError => "AuthenticationError",
+            DbError::Unauthorized => "Unauthorized",
+            DbError::ConfigError => "ConfigError",
+            DbError::Unavailable { .. } => "Unavailable",
+            DbError::Overloaded => "Overloaded",
+            DbError::IsBootstrapping => "IsBootstrapping",
+            DbError::TruncateError => "TruncateError",
+            DbError::ReadTimeout { .. } => "ReadTimeout",
+            DbError::WriteTimeout { .. } => "WriteTimeout",
+            DbError::ReadFailure { .. } => "ReadFailure",
+            DbError::WriteFailure { .. } => "WriteFailure",
+            DbError::Unprepared { .. } => "Unprepared",
+            DbError::ServerError => "ServerError",
+            DbError::ProtocolError => "ProtocolError",
+            DbError::RateLimitReached { .. } => "RateLimitReached",
+            DbError::Other(_) => "DbError",
+        },
+        QueryError::BadQuery(_) => "BadQuery",
+        QueryError::IoError(_) => "IoError",
+        QueryError::ProtocolError(_) => "ProtocolError",
+        QueryError::InvalidMessage(_) => "InvalidMessage",
+        QueryError::TimeoutError => "TimeoutError",
+        QueryError::TooManyOrphanedStreamIds(_) => "TooManyOrphanedStreamIds",
+        QueryError::UnableToAllocStreamId => "UnableToAllocStreamId",
+        QueryError::RequestTimeout(_) => "RequestTimeout",
+        QueryError::SessionClosed => "SessionClosed",
+        _ => "QueryError",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::error_type;
+    use crate::transport::errors::{DbError, QueryError};
+
+    #[test]
+    fn error_type_is_low_cardinality() {
+        assert_eq!(
+            error_type(&QueryError::DbError(
+                DbError::Overloaded,
+                "node is overloaded".to_string()
+            )),
+            "Overloaded"
+        );
+        assert_eq!(
+            error_type(&QueryError::RequestTimeout("took 100ms".to_string())),
+            "RequestTimeout"
+        );
+    }
+}
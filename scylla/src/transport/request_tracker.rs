@@ -0,0 +1,115 @@
+//! Tracks requests in progress, so that closing a session can wait for them to finish
+
+use crate::transport::errors::QueryError;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+#[derive(Debug, Default)]
+pub(crate) struct RequestTracker {
+    closed: AtomicBool,
+    in_flight: AtomicUsize,
+    // Signaled when the last request in progress finishes
+    all_finished: Notify,
+}
+
+/// Marks a request as in progress until dropped
+#[derive(Debug)]
+pub(crate) struct RequestGuard {
+    tracker: Arc<RequestTracker>,
+}
+
+impl RequestTracker {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    // Fails if the tracker was closed
+    pub(crate) fn start_request(self: &Arc<Self>) -> Result<RequestGuard, QueryError> {
+        // The counter is incremented before checking the flag, so `close_and_wait`
+        // either sees this request or the request sees the tracker closed
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = RequestGuard {
+            tracker: self.clone(),
+        };
+
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(QueryError::SessionClosed);
+        }
+        Ok(guard)
+    }
+
+    #[cfg(feature = "tower")]
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    // Rejects new requests and waits until all requests in progress finish
+    pub(crate) async fn close_and_wait(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+
+        loop {
+            // Created before checking the counter, so the wakeup can't be missed
+            let all_finished = self.all_finished.notified();
+            if self.in_flight() == 0 {
+                return;
+            }
+            all_finished.await;
+        }
+    }
+}
+
+impl RequestGuard {
+    pub(crate) fn tracker(&self) -> &Arc<RequestTracker> {
+        &self.tracker
+    }
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        if self.tracker.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.tracker.all_finished.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn close_waits_for_requests() {
+        let tracker = RequestTracker::new();
+        let guard = tracker.start_request().unwrap();
+        assert_eq!(tracker.in_flight(), 1);
+
+        let mut closing = tokio::spawn({
+            let tracker = tracker.clone();
+            async move { tracker.close_and_wait().await }
+        });
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), &mut closing)
+                .await
+                .is_err()
+        );
+
+        // New requests are rejected while closing
+        assert!(matches!(
+            tracker.start_request(),
+            Err(QueryError::SessionClosed)
+        ));
+        assert_eq!(tracker.in_flight(), 1);
+
+        drop(guard);
+        tokio::time::timeout(Duration::from_secs(1), closing)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tracker.in_flight(), 0);
+    }
+}
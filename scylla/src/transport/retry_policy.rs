@@ -2,10 +2,17 @@
 //! To decide when to retry a query the `Session` can use any object which implements
 //! the `RetryPolicy` trait
 
+use std::time::Duration;
+
 use crate::frame::types::{Consistency, LegacyConsistency};
+use crate::routing::Shard;
+use crate::statement::StatementKind;
 use crate::transport::errors::{DbError, QueryError, WriteType};
+use crate::transport::load_balancing::Statement;
+use crate::transport::node::Node;
 
 /// Information about a failed query
+#[non_exhaustive]
 pub struct QueryInfo<'a> {
     /// The error with which the query failed
     pub error: &'a QueryError,
@@ -15,6 +22,32 @@ pub struct QueryInfo<'a> {
     pub is_idempotent: bool,
     /// Consistency with which the query failed
     pub consistency: LegacyConsistency,
+    /// Node to which the failed attempt was sent, if known
+    pub node: Option<&'a Node>,
+    /// Shard of the connection used by the failed attempt\
+    /// `None` if the node is not sharded or the shard is unknown
+    pub shard: Option<Shard>,
+    /// Number of attempts performed so far, including the failed one (starting from 1)\
+    /// With speculative execution enabled, attempts are counted separately in each fiber
+    pub attempt: usize,
+    /// Time elapsed since the request was started
+    pub elapsed: Duration,
+    /// Client-side timeout of the whole request, if there is one\
+    /// Paged queries aren't timed out by the driver, for them it is only informational
+    pub request_timeout: Option<Duration>,
+    /// Kind of the failed statement
+    pub statement_kind: StatementKind,
+    /// Routing information about the failed statement
+    pub statement: &'a Statement<'a>,
+}
+
+impl QueryInfo<'_> {
+    /// Returns time left until the request times out on the client side.\
+    /// `None` if the request has no timeout.
+    pub fn remaining_time(&self) -> Option<Duration> {
+        self.request_timeout
+            .map(|timeout| timeout.saturating_sub(self.elapsed))
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -221,20 +254,50 @@ impl RetrySession for DefaultRetrySession {
 mod tests {
     use super::{DefaultRetryPolicy, QueryInfo, RetryDecision, RetryPolicy};
     use crate::frame::types::LegacyConsistency;
-    use crate::statement::Consistency;
+    use crate::statement::{Consistency, StatementKind};
     use crate::transport::errors::{BadQuery, DbError, QueryError, WriteType};
+    use crate::transport::load_balancing::Statement;
     use bytes::Bytes;
     use std::io::ErrorKind;
     use std::sync::Arc;
+    use std::time::Duration;
 
     fn make_query_info(error: &QueryError, is_idempotent: bool) -> QueryInfo<'_> {
         QueryInfo {
             error,
             is_idempotent,
             consistency: LegacyConsistency::Regular(Consistency::One),
+            node: None,
+            shard: None,
+            attempt: 1,
+            elapsed: Duration::ZERO,
+            request_timeout: None,
+            statement_kind: StatementKind::Query,
+            statement: &Statement {
+                token: None,
+                keyspace: None,
+                is_confirmed_lwt: false,
+            },
         }
     }
 
+    #[test]
+    fn query_info_remaining_time() {
+        let error = QueryError::DbError(DbError::Overloaded, String::new());
+        let mut query_info = make_query_info(&error, true);
+        assert_eq!(query_info.remaining_time(), None);
+
+        query_info.elapsed = Duration::from_millis(300);
+        query_info.request_timeout = Some(Duration::from_secs(1));
+        assert_eq!(
+            query_info.remaining_time(),
+            Some(Duration::from_millis(700))
+        );
+
+        query_info.elapsed = Duration::from_secs(2);
+        assert_eq!(query_info.remaining_time(), Some(Duration::ZERO));
+    }
+
     // Asserts that default policy never retries for this Error
     fn default_policy_assert_never_retries(error: QueryError) {
         let mut policy = DefaultRetryPolicy::new().new_session();
@@ -8,43 +8,56 @@ use async_trait::async_trait;
 use bytes::Bytes;
 use futures::future::join_all;
 use futures::future::try_join_all;
+use futures::stream::StreamExt;
 use scylla_cql::frame::response::NonErrorResponse;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::future::Future;
 use std::net::SocketAddr;
+use std::num::NonZeroU32;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::net::lookup_host;
 use tokio::time::timeout;
-use tracing::{debug, error, trace, trace_span, Instrument};
+use tracing::{debug, error, trace, warn, Instrument};
 use uuid::Uuid;
 
 use super::connection::NonErrorQueryResponse;
 use super::connection::QueryResponse;
 use super::errors::{BadQuery, NewSessionError, QueryError};
-use super::topology::UntranslatedPeer;
+use super::topology::{ContactPointHostnames, UntranslatedPeer};
 use crate::cql_to_rust::FromRow;
 use crate::frame::response::cql_to_rust::FromRowError;
+use crate::frame::response::event::SchemaChangeEvent;
 use crate::frame::response::result;
 use crate::frame::value::{
     BatchValues, BatchValuesFirstSerialized, BatchValuesIterator, SerializedValues, ValueList,
 };
 use crate::prepared_statement::{PartitionKeyError, PreparedStatement};
 use crate::query::Query;
-use crate::routing::Token;
+use crate::routing::{Shard, Token};
 use crate::statement::{Consistency, SerialConsistency};
-use crate::tracing::{GetTracingConfig, TracingEvent, TracingInfo};
-use crate::transport::cluster::{Cluster, ClusterData, ClusterNeatDebug};
+use crate::tracing::{
+    GetTracingConfig, SamplingInfo, TraceSink, TracingInfo, TracingSamplingPolicy,
+};
+use crate::transport::cluster::{Cluster, ClusterData, ClusterEvent, ClusterNeatDebug};
+use crate::transport::concurrent_execution::ConcurrentExecution;
 use crate::transport::connection::{Connection, ConnectionConfig, VerifiedKeyspaceName};
 use crate::transport::connection_pool::PoolConfig;
-use crate::transport::iterator::{PreparedIteratorConfig, RowIterator};
+use crate::transport::host_filter::HostFilter;
+use crate::transport::iterator::{
+    IteratorPagingConfig, PreparedIteratorConfig, RowIterator, DEFAULT_ITER_PAGE_SIZE,
+};
 use crate::transport::load_balancing::{
-    LoadBalancingPolicy, RoundRobinPolicy, Statement, TokenAwarePolicy,
+    self, LoadBalancingPolicy, RoundRobinPolicy, Statement, TokenAwarePolicy,
 };
 use crate::transport::metrics::Metrics;
 use crate::transport::node::Node;
+use crate::transport::paging_state::PagingState;
 use crate::transport::query_result::QueryResult;
+use crate::transport::reconnection_policy::{ExponentialReconnectionPolicy, ReconnectionPolicy};
+use crate::transport::request_tracker::RequestTracker;
 use crate::transport::retry_policy::{
     DefaultRetryPolicy, QueryInfo, RetryDecision, RetryPolicy, RetrySession,
 };
@@ -53,14 +66,24 @@ use crate::transport::speculative_execution::SpeculativeExecutionPolicy;
 use crate::transport::Compression;
 use crate::{
     batch::{Batch, BatchStatement},
-    statement::StatementConfig,
+    statement::{StatementConfig, StatementKind},
 };
 
-pub use crate::transport::connection_pool::PoolSize;
+pub use crate::transport::connection_pool::{NodePoolStatus, PoolSize};
 
+#[cfg(any(feature = "ssl", feature = "rustls"))]
+use super::tls::TlsContext;
+#[cfg(feature = "cloud")]
+use crate::cloud::CloudConfig;
 #[cfg(feature = "ssl")]
 use openssl::ssl::SslContext;
 
+#[cfg(feature = "trace-context")]
+use super::trace_context::TraceContextPropagator;
+
+use super::request_span::{self, RequestSpan};
+use super::trace_collector::TracingSampler;
+
 #[derive(Debug, Copy, Clone)]
 pub enum TranslationError {
     NoRuleForAddress,
@@ -110,7 +133,7 @@ impl AddressTranslator for HashMap<&'static str, &'static str> {
 
 /// `Session` manages connections to the cluster and allows to perform queries
 pub struct Session {
-    cluster: Cluster,
+    pub(crate) cluster: Cluster,
     load_balancer: Arc<dyn LoadBalancingPolicy>,
     schema_agreement_interval: Duration,
     retry_policy: Box<dyn RetryPolicy>,
@@ -120,6 +143,14 @@ pub struct Session {
     auto_await_schema_agreement_timeout: Option<Duration>,
     request_timeout: Option<Duration>,
     refresh_metadata_on_auto_schema_agreement: bool,
+    tracing_sampler: Option<Arc<TracingSampler>>,
+    history_listener: Option<Arc<dyn HistoryListener>>,
+    iterator_paging_config: IteratorPagingConfig,
+    prepare_on_all_nodes: bool,
+    request_tracker: Arc<RequestTracker>,
+    // Notified when a connection may have become available, used by tower services waiting for readiness
+    #[cfg(feature = "tower")]
+    availability_notify: Arc<tokio::sync::Notify>,
 }
 
 /// This implementation deliberately omits some details from Cluster in order
@@ -169,9 +200,24 @@ pub struct SessionConfig {
     pub retry_policy: Box<dyn RetryPolicy>,
     pub speculative_execution_policy: Option<Arc<dyn SpeculativeExecutionPolicy>>,
 
+    /// Decides how long connection pools, including the control connection,
+    /// wait before reconnecting to a node. The default is exponential,
+    /// starting with 50 ms and waiting at most 10 s.
+    pub reconnection_policy: Arc<dyn ReconnectionPolicy>,
+
     /// Provide our Session with TLS
-    #[cfg(feature = "ssl")]
-    pub ssl_context: Option<SslContext>,
+    #[cfg(any(feature = "ssl", feature = "rustls"))]
+    pub tls_context: Option<TlsContext>,
+
+    /// Connects to a cluster behind SNI proxies, described by a cloud connection bundle.
+    /// The proxy of the current datacenter is used as the contact point if `known_nodes` is empty.
+    /// The shard-aware port is never used in this mode, because the proxies don't preserve source ports.
+    #[cfg(feature = "cloud")]
+    pub cloud_config: Option<Arc<CloudConfig>>,
+
+    /// Writes trace context of request spans into the custom payload of outgoing requests
+    #[cfg(feature = "trace-context")]
+    pub trace_context_propagator: Option<Arc<dyn TraceContextPropagator>>,
 
     pub auth_username: Option<String>,
     pub auth_password: Option<String>,
@@ -185,6 +231,7 @@ pub struct SessionConfig {
 
     /// If true, prevents the driver from connecting to the shard-aware port, even if the node supports it.
     /// Generally, this options is best left as default (false).
+    /// The shard-aware port is always disallowed when connecting through cloud proxies, see `cloud_config`.
     pub disallow_shard_aware_port: bool,
 
     pub default_consistency: Consistency,
@@ -205,9 +252,77 @@ pub struct SessionConfig {
 
     pub address_translator: Option<Arc<dyn AddressTranslator>>,
 
+    /// Decides which nodes the driver connects to.
+    /// Rejected nodes are kept in the cluster metadata, but get no connections
+    /// and never appear in query plans. The default is None - all nodes are accepted.
+    pub host_filter: Option<Arc<dyn HostFilter>>,
+
+    /// How often hostnames of known nodes are resolved again, during metadata refreshes.
+    /// They are also resolved whenever the control connection fails, so that the driver
+    /// can find the cluster even if all nodes it knew about were replaced.
+    /// None disables periodic resolution. The default is 5 minutes.\
+    /// Each lookup times out after `connect_timeout`, and addresses rejected by the `host_filter` are ignored.
+    pub hostname_resolution_interval: Option<Duration>,
+
     /// If true, full schema metadata is fetched after successfully reaching a schema agreement.
     /// It is true by default but can be disabled if successive schema-altering statements should be performed.
     pub refresh_metadata_on_auto_schema_agreement: bool,
+
+    /// Policy deciding which requests are traced automatically.
+    /// Sampling is enabled only if `trace_sink` is set as well.
+    pub tracing_sampling_policy: Option<Arc<dyn TracingSamplingPolicy>>,
+
+    /// Receives tracing info of requests sampled by `tracing_sampling_policy`
+    pub trace_sink: Option<Arc<dyn TraceSink>>,
+
+    /// Controls fetching of tracing info of sampled requests in the background.
+    /// By default 10 attempts are made, 100ms apart, with consistency `One`.
+    pub trace_collection_config: GetTracingConfig,
+
+    /// Listener receiving history of all queries performed by the session.\
+    /// A listener set on a statement with `set_history_listener` takes precedence over this one.
+    pub history_listener: Option<Arc<dyn HistoryListener>>,
+
+    /// Page size used by `query_iter` and `execute_iter` when the statement doesn't set one.
+    /// Must be larger than 0, the default is 5000 rows.
+    pub iterator_page_size: i32,
+
+    /// Number of pages `query_iter` and `execute_iter` fetch in the background
+    /// before they are consumed. Must be at least 1, the default is 1.
+    pub iterator_prefetch_pages: usize,
+
+    /// Upper limit of the size of a page fetched by `query_iter` and `execute_iter`, in bytes.\
+    /// The database can only limit the number of rows in a page, so the driver lowers the page size
+    /// of the following requests when a page turns out bigger than this limit.
+    /// The first page is always fetched with the page size set in the statement.
+    pub iterator_max_page_bytes: Option<usize>,
+
+    /// If true, statements are prepared on all nodes by `Session::prepare`.
+    /// Otherwise they are prepared on a single node, and prepared on other nodes
+    /// when executed there for the first time. The default is true.
+    pub prepare_on_all_nodes: bool,
+
+    /// If true, the session remembers the prepared statements and prepares them again
+    /// on nodes which come back up (e.g. after a restart) or join the cluster.
+    /// The default is true.
+    pub reprepare_on_node_up: bool,
+
+    /// If set, [`Session::connect`] waits until connection pools are full before returning.
+    /// The default is None - it returns once metadata is read and every pool has
+    /// at least one connection or failed to open it.
+    pub pool_warmup: Option<PoolWarmup>,
+}
+
+/// Configures waiting for connection pools to fill up when the session is created.
+#[derive(Clone, Debug)]
+pub struct PoolWarmup {
+    /// Only pools to nodes in this datacenter are awaited, usually the local one.
+    /// If None, pools to all nodes are awaited.
+    pub datacenter: Option<String>,
+
+    /// The longest time to wait. If pools aren't full by then, the session is created anyway
+    /// and a warning is logged, [`Session::pool_status`] shows which pools are missing connections.
+    pub timeout: Duration,
 }
 
 /// Describes database server known on Session startup.
@@ -239,8 +354,13 @@ impl SessionConfig {
             keyspace_case_sensitive: false,
             retry_policy: Box::new(DefaultRetryPolicy),
             speculative_execution_policy: None,
-            #[cfg(feature = "ssl")]
-            ssl_context: None,
+            reconnection_policy: Arc::new(ExponentialReconnectionPolicy::default()),
+            #[cfg(any(feature = "ssl", feature = "rustls"))]
+            tls_context: None,
+            #[cfg(feature = "cloud")]
+            cloud_config: None,
+            #[cfg(feature = "trace-context")]
+            trace_context_propagator: None,
             auth_username: None,
             auth_password: None,
             connect_timeout: Duration::from_secs(5),
@@ -252,10 +372,42 @@ impl SessionConfig {
             auto_await_schema_agreement_timeout: Some(std::time::Duration::from_secs(60)),
             request_timeout: Some(Duration::from_secs(30)),
             address_translator: None,
+            host_filter: None,
+            hostname_resolution_interval: Some(Duration::from_secs(5 * 60)),
             refresh_metadata_on_auto_schema_agreement: true,
+            tracing_sampling_policy: None,
+            trace_sink: None,
+            trace_collection_config: GetTracingConfig {
+                attempts: NonZeroU32::new(10).unwrap(),
+                interval: Duration::from_millis(100),
+                consistency: Consistency::One,
+            },
+            history_listener: None,
+            iterator_page_size: DEFAULT_ITER_PAGE_SIZE,
+            iterator_prefetch_pages: 1,
+            iterator_max_page_bytes: None,
+            prepare_on_all_nodes: true,
+            reprepare_on_node_up: true,
+            pool_warmup: None,
         }
     }
 
+    /// Returns the OpenSSL context used to encrypt the connections, if any
+    #[cfg(feature = "ssl")]
+    #[deprecated(note = "the `ssl_context` field was replaced by `tls_context`")]
+    pub fn ssl_context(&self) -> Option<&SslContext> {
+        self.tls_context
+            .as_ref()
+            .and_then(TlsContext::get_ssl_context)
+    }
+
+    /// Sets the OpenSSL context used to encrypt the connections
+    #[cfg(feature = "ssl")]
+    #[deprecated(note = "the `ssl_context` field was replaced by `tls_context`")]
+    pub fn set_ssl_context(&mut self, ssl_context: Option<SslContext>) {
+        self.tls_context = ssl_context.map(TlsContext::from);
+    }
+
     /// Adds a known database server with a hostname.
     /// If the port is not explicitly specified, 9042 is used as default
     /// # Example
@@ -314,13 +466,25 @@ impl SessionConfig {
         }
     }
 
+    #[cfg(feature = "cloud")]
+    fn is_cloud(&self) -> bool {
+        self.cloud_config.is_some()
+    }
+
+    #[cfg(not(feature = "cloud"))]
+    fn is_cloud(&self) -> bool {
+        false
+    }
+
     /// Creates a PoolConfig which can be used to create NodeConnectionPools
-    fn get_pool_config(&self) -> PoolConfig {
+    fn get_pool_config(&self, metrics: Arc<Metrics>) -> PoolConfig {
         PoolConfig {
             connection_config: self.get_connection_config(),
             pool_size: self.connection_pool_size.clone(),
-            can_use_shard_aware_port: !self.disallow_shard_aware_port,
+            can_use_shard_aware_port: !self.disallow_shard_aware_port && !self.is_cloud(),
             keepalive_interval: self.keepalive_interval,
+            reconnection_policy: self.reconnection_policy.clone(),
+            metrics,
         }
     }
 
@@ -329,13 +493,20 @@ impl SessionConfig {
         ConnectionConfig {
             compression: self.compression,
             tcp_nodelay: self.tcp_nodelay,
-            #[cfg(feature = "ssl")]
-            ssl_context: self.ssl_context.clone(),
+            #[cfg(any(feature = "ssl", feature = "rustls"))]
+            tls_context: self.tls_context.clone(),
+            #[cfg(feature = "cloud")]
+            cloud_config: self.cloud_config.clone(),
+            #[cfg(feature = "cloud")]
+            cloud_node: None,
             auth_username: self.auth_username.to_owned(),
             auth_password: self.auth_password.to_owned(),
             connect_timeout: self.connect_timeout,
             event_sender: None,
             default_consistency: self.default_consistency,
+            #[cfg(feature = "trace-context")]
+            trace_context_propagator: self.trace_context_propagator.clone(),
+            availability_notify: None,
         }
     }
 }
@@ -379,6 +550,53 @@ impl<RowT: FromRow> Iterator for TypedRowIter<RowT> {
     }
 }
 
+/// Decides how [`Session::batch_by_partition`] splits statements into batches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchGrouping {
+    /// Statements with the same token (i.e. the same partition) go to the same batch
+    Token,
+    /// Statements whose primary replica is the same node go to the same batch
+    Replica,
+}
+
+/// Result of a single batch sent by [`Session::batch_by_partition`]
+#[derive(Debug)]
+pub struct PartitionBatchResult {
+    /// Indexes of the original batch statements that were sent in this batch
+    pub statement_indexes: Vec<usize>,
+    pub result: Result<QueryResult, QueryError>,
+}
+
+/// Aggregated results of [`Session::batch_by_partition`]
+#[derive(Debug)]
+pub struct BatchByPartitionResult {
+    /// One entry for every batch that was sent, in order of the first statement of each batch
+    pub batches: Vec<PartitionBatchResult>,
+}
+
+impl BatchByPartitionResult {
+    /// Returns true if all batches succeeded
+    pub fn is_success(&self) -> bool {
+        self.batches.iter().all(|batch| batch.result.is_ok())
+    }
+
+    /// Returns errors of the failed batches along with indexes of their statements
+    pub fn errors(&self) -> impl Iterator<Item = (&[usize], &QueryError)> {
+        self.batches.iter().filter_map(|batch| match &batch.result {
+            Ok(_) => None,
+            Err(err) => Some((batch.statement_indexes.as_slice(), err)),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum BatchGroupKey {
+    Token(i64),
+    Replica(SocketAddr),
+    // Statements which can't be routed, e.g. unprepared queries
+    Unroutable,
+}
+
 pub enum RunQueryResult<ResT> {
     IgnoredWriteError,
     Completed(ResT),
@@ -411,48 +629,111 @@ impl Session {
     /// # }
     /// ```
     pub async fn connect(config: SessionConfig) -> Result<Session, NewSessionError> {
+        #[allow(unused_mut)]
+        let mut known_nodes = config.known_nodes.clone();
+
+        // Cloud clusters are reached through the proxy of the current datacenter
+        #[cfg(feature = "cloud")]
+        if let (Some(cloud_config), true) = (&config.cloud_config, known_nodes.is_empty()) {
+            known_nodes.push(KnownNode::Hostname(
+                cloud_config.get_contact_point().to_string(),
+            ));
+        }
+
         // Ensure there is at least one known node
-        if config.known_nodes.is_empty() {
+        if known_nodes.is_empty() {
             return Err(NewSessionError::EmptyKnownNodesList);
         }
 
         // Find IP addresses of all known nodes passed in the config
-        let mut node_addresses: Vec<SocketAddr> = Vec::with_capacity(config.known_nodes.len());
+        let mut node_addresses: Vec<SocketAddr> = Vec::with_capacity(known_nodes.len());
 
         let mut to_resolve: Vec<&str> = Vec::new();
 
-        for node in &config.known_nodes {
+        for node in &known_nodes {
             match node {
                 KnownNode::Hostname(hostname) => to_resolve.push(hostname),
                 KnownNode::Address(address) => node_addresses.push(*address),
             };
         }
 
+        let contact_points = ContactPointHostnames {
+            hostnames: to_resolve
+                .iter()
+                .map(|hostname| hostname.to_string())
+                .collect(),
+            resolution_interval: config.hostname_resolution_interval,
+        };
+
         let resolve_futures = to_resolve.into_iter().map(resolve_hostname);
         let resolved: Vec<SocketAddr> = futures::future::try_join_all(resolve_futures).await?;
 
         node_addresses.extend(resolved);
 
+        let metrics = Arc::new(Metrics::new());
+
+        #[allow(unused_mut)]
+        let mut pool_config = config.get_pool_config(metrics.clone());
+        #[cfg(feature = "tower")]
+        let availability_notify = Arc::new(tokio::sync::Notify::new());
+        #[cfg(feature = "tower")]
+        {
+            pool_config.connection_config.availability_notify = Some(availability_notify.clone());
+        }
+
         let cluster = Cluster::new(
             &node_addresses,
-            config.get_pool_config(),
+            contact_points,
+            pool_config,
             config.fetch_schema_metadata,
             &config.address_translator,
+            config.host_filter.clone(),
+            config.reprepare_on_node_up,
         )
         .await?;
 
+        if let Some(warmup) = &config.pool_warmup {
+            Self::wait_for_full_pools(&cluster.get_data(), warmup).await;
+        }
+
+        let request_tracker = RequestTracker::new();
+
+        let tracing_sampler = match (config.tracing_sampling_policy, config.trace_sink) {
+            (Some(policy), Some(sink)) => Some(Arc::new(TracingSampler::new(
+                policy,
+                sink,
+                config.trace_collection_config,
+                cluster.get_data_source(),
+                config.load_balancing.clone(),
+                request_tracker.clone(),
+            ))),
+            _ => None,
+        };
+
         let session = Session {
             cluster,
             load_balancer: config.load_balancing,
             retry_policy: config.retry_policy,
             schema_agreement_interval: config.schema_agreement_interval,
             speculative_execution_policy: config.speculative_execution_policy,
-            metrics: Arc::new(Metrics::new()),
+            metrics,
             default_consistency: config.default_consistency,
             auto_await_schema_agreement_timeout: config.auto_await_schema_agreement_timeout,
             request_timeout: config.request_timeout,
             refresh_metadata_on_auto_schema_agreement: config
                 .refresh_metadata_on_auto_schema_agreement,
+            tracing_sampler,
+            history_listener: config.history_listener,
+            iterator_paging_config: IteratorPagingConfig {
+                request_timeout: config.request_timeout,
+                default_page_size: config.iterator_page_size,
+                prefetch_pages: config.iterator_prefetch_pages,
+                max_page_bytes: config.iterator_max_page_bytes,
+            },
+            prepare_on_all_nodes: config.prepare_on_all_nodes,
+            request_tracker,
+            #[cfg(feature = "tower")]
+            availability_notify,
         };
 
         if let Some(keyspace_name) = config.used_keyspace {
@@ -530,13 +811,23 @@ impl Session {
         values: impl ValueList,
         paging_state: Option<Bytes>,
     ) -> Result<QueryResult, QueryError> {
-        let query: Query = query.into();
+        let _request_guard = self.request_tracker.start_request()?;
+        let mut query: Query = query.into();
         let serialized_values = values.serialized()?;
 
-        let span = trace_span!("Request", query = query.contents.as_str());
+        let sampled =
+            self.sample_tracing(StatementKind::Query, &query.contents, query.config.tracing);
+        if sampled {
+            query.config.tracing = true;
+        }
+
+        let request_start = Instant::now();
+        let request_span = RequestSpan::new_query(&query);
         let run_query_result = self
             .run_query(
+                &request_span,
                 Statement::default(),
+                StatementKind::Query,
                 &query.config,
                 |node: Arc<Node>| async move { node.random_connection().await },
                 |connection: Arc<Connection>, consistency: Consistency| {
@@ -557,7 +848,6 @@ impl Session {
                     }
                 },
             )
-            .instrument(span)
             .await?;
 
         let response = match run_query_result {
@@ -565,9 +855,19 @@ impl Session {
                 response: NonErrorResponse::Result(result::Result::Void),
                 tracing_id: None,
                 warnings: Vec::new(),
+                custom_payload: None,
             },
             RunQueryResult::Completed(response) => response,
         };
+        request_span.record_response(&response);
+        if sampled {
+            self.collect_trace(
+                response.tracing_id,
+                StatementKind::Query,
+                &query.contents,
+                request_start.elapsed(),
+            );
+        }
 
         self.handle_set_keyspace_response(&response).await?;
         self.handle_auto_await_schema_agreement(&query.contents, &response)
@@ -598,8 +898,9 @@ impl Session {
         response: &NonErrorQueryResponse,
     ) -> Result<(), QueryError> {
         if let Some(timeout) = self.auto_await_schema_agreement_timeout {
+            // The statement already holds a request guard, so the agreement isn't tracked separately
             if response.as_schema_change().is_some()
-                && !self.await_timed_schema_agreement(timeout).await?
+                && !self.wait_for_timed_schema_agreement(timeout).await?
             {
                 // TODO: The TimeoutError should allow to provide more context.
                 // For now, print an error to the logs
@@ -658,15 +959,84 @@ impl Session {
         query: impl Into<Query>,
         values: impl ValueList,
     ) -> Result<RowIterator, QueryError> {
-        let query: Query = query.into();
+        self.query_iter_from(query.into(), values, None).await
+    }
+
+    /// Run a simple query with paging, starting from the given paging state\
+    /// Allows to resume a scan stopped by an earlier [query_iter](Session::query_iter),
+    /// also in another process. The query and values must be the same as in the original scan.
+    ///
+    /// # Arguments
+    /// * `query` - query to perform, can be just a `&str` or the [Query](crate::query::Query) struct.
+    /// * `values` - values bound to the query, easiest way is to use a tuple of bound values
+    /// * `paging_state` - paging state obtained from [RowIterator::paging_state](crate::transport::iterator::RowIterator::paging_state)
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use scylla::Session;
+    /// # use std::error::Error;
+    /// # async fn check_only_compiles(session: &Session, token: &str) -> Result<(), Box<dyn Error>> {
+    /// use scylla::transport::paging_state::PagingState;
+    /// use futures::stream::StreamExt;
+    ///
+    /// // Token returned by the previous call of a paginated endpoint
+    /// let paging_state = PagingState::from_token(token)?;
+    ///
+    /// let mut pages = session
+    ///     .resume_query_iter("SELECT a, b FROM ks.t", &[], paging_state)
+    ///     .await?
+    ///     .pages();
+    ///
+    /// // Return a single page along with the token of the next one
+    /// if let Some(page) = pages.next().await {
+    ///     let page = page?;
+    ///     for row in page.rows.rows {
+    ///         println!("{:?}", row);
+    ///     }
+    ///     let next_token: Option<String> = page.paging_state.as_ref().map(PagingState::to_token);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn resume_query_iter(
+        &self,
+        query: impl Into<Query>,
+        values: impl ValueList,
+        paging_state: PagingState,
+    ) -> Result<RowIterator, QueryError> {
+        self.query_iter_from(query.into(), values, Some(paging_state))
+            .await
+    }
+
+    async fn query_iter_from(
+        &self,
+        mut query: Query,
+        values: impl ValueList,
+        paging_state: Option<PagingState>,
+    ) -> Result<RowIterator, QueryError> {
+        let request_guard = self.request_tracker.start_request()?;
         let serialized_values = values.serialized()?;
 
+        if query.config.history_listener.is_none() {
+            query.config.history_listener = self.history_listener.clone();
+        }
+
         let retry_session = match &query.config.retry_policy {
             Some(policy) => policy.new_session(),
             None => self.retry_policy.new_session(),
         };
 
-        let span = trace_span!("Request", query = query.contents.as_str());
+        // Traces of a sampled iterator are collected for each page
+        let tracing_sampler =
+            if self.sample_tracing(StatementKind::Query, &query.contents, query.config.tracing) {
+                query.set_tracing(true);
+                self.tracing_sampler.clone()
+            } else {
+                None
+            };
+
+        let request_span = RequestSpan::new_query(&query);
         RowIterator::new_for_query(
             query,
             serialized_values.into_owned(),
@@ -675,8 +1045,12 @@ impl Session {
             self.load_balancer.clone(),
             self.cluster.get_data(),
             self.metrics.clone(),
+            request_span,
+            paging_state,
+            self.iterator_paging_config,
+            tracing_sampler,
+            request_guard,
         )
-        .instrument(span)
         .await
     }
 
@@ -716,9 +1090,14 @@ impl Session {
     /// # }
     /// ```
     pub async fn prepare(&self, query: impl Into<Query>) -> Result<PreparedStatement, QueryError> {
+        let _request_guard = self.request_tracker.start_request()?;
         let query = query.into();
 
-        let connections = self.cluster.get_working_connections().await?;
+        let connections = if self.prepare_on_all_nodes {
+            self.cluster.get_working_connections().await?
+        } else {
+            self.cluster.get_working_connections_to_random_node()?
+        };
 
         // Prepare statements on all connections concurrently
         let handles = connections.iter().map(|c| c.prepare(&query));
@@ -758,10 +1137,18 @@ impl Session {
         prepared.set_partitioner_name(
             self.extract_partitioner_name(&prepared, &self.cluster.get_data()),
         );
+        self.cluster.register_prepared_statement(&prepared);
 
         Ok(prepared)
     }
 
+    /// Subscribes to schema changes reported by the database
+    pub(crate) fn subscribe_schema_changes(
+        &self,
+    ) -> tokio::sync::broadcast::Receiver<SchemaChangeEvent> {
+        self.cluster.subscribe_schema_changes()
+    }
+
     fn extract_partitioner_name<'a>(
         &self,
         prepared: &PreparedStatement,
@@ -833,24 +1220,44 @@ impl Session {
         values: impl ValueList,
         paging_state: Option<Bytes>,
     ) -> Result<QueryResult, QueryError> {
+        let _request_guard = self.request_tracker.start_request()?;
         let serialized_values = values.serialized()?;
         let values_ref = &serialized_values;
         let paging_state_ref = &paging_state;
 
+        // A sampled statement is executed with tracing enabled on its copy
+        let sampled_prepared: PreparedStatement;
+        let sampled = self.sample_tracing(
+            prepared.statement_kind(),
+            prepared.get_statement(),
+            prepared.config.tracing,
+        );
+        let prepared = if sampled {
+            sampled_prepared = {
+                let mut prepared = prepared.clone();
+                prepared.set_tracing(true);
+                prepared
+            };
+            &sampled_prepared
+        } else {
+            prepared
+        };
+
         let token = self.calculate_token(prepared, &serialized_values)?;
 
         let statement_info = Statement {
             token,
             keyspace: prepared.get_keyspace_name(),
+            is_confirmed_lwt: prepared.is_confirmed_lwt(),
         };
 
-        let span = trace_span!(
-            "Request",
-            prepared_id = format!("{:X}", prepared.get_id()).as_str()
-        );
+        let request_start = Instant::now();
+        let request_span = RequestSpan::new_prepared(prepared, token);
         let run_query_result: RunQueryResult<NonErrorQueryResponse> = self
             .run_query(
+                &request_span,
                 statement_info,
+                prepared.statement_kind(),
                 &prepared.config,
                 |node: Arc<Node>| async move {
                     match token {
@@ -870,7 +1277,6 @@ impl Session {
                         .and_then(QueryResponse::into_non_error_query_response)
                 },
             )
-            .instrument(span)
             .await?;
 
         let response = match run_query_result {
@@ -878,9 +1284,19 @@ impl Session {
                 response: NonErrorResponse::Result(result::Result::Void),
                 tracing_id: None,
                 warnings: Vec::new(),
+                custom_payload: None,
             },
             RunQueryResult::Completed(response) => response,
         };
+        request_span.record_response(&response);
+        if sampled {
+            self.collect_trace(
+                response.tracing_id,
+                prepared.statement_kind(),
+                prepared.get_statement(),
+                request_start.elapsed(),
+            );
+        }
 
         self.handle_set_keyspace_response(&response).await?;
         self.handle_auto_await_schema_agreement(prepared.get_statement(), &response)
@@ -935,9 +1351,93 @@ impl Session {
         prepared: impl Into<PreparedStatement>,
         values: impl ValueList,
     ) -> Result<RowIterator, QueryError> {
-        let prepared = prepared.into();
+        self.execute_iter_from(prepared.into(), values, None).await
+    }
+
+    /// Run a prepared query with paging, starting from the given paging state\
+    /// Allows to resume a scan stopped by an earlier [execute_iter](Session::execute_iter),
+    /// also in another process. The statement and values must be the same as in the original scan.
+    ///
+    /// # Arguments
+    /// * `prepared` - the prepared statement to execute, generated using [`Session::prepare`](Session::prepare)
+    /// * `values` - values bound to the query, easiest way is to use a tuple of bound values
+    /// * `paging_state` - paging state obtained from [RowIterator::paging_state](crate::transport::iterator::RowIterator::paging_state)
+    pub async fn resume_execute_iter(
+        &self,
+        prepared: impl Into<PreparedStatement>,
+        values: impl ValueList,
+        paging_state: PagingState,
+    ) -> Result<RowIterator, QueryError> {
+        self.execute_iter_from(prepared.into(), values, Some(paging_state))
+            .await
+    }
+
+    /// Executes a prepared query once for every item of values, running at most `concurrency`
+    /// requests at the same time.
+    ///
+    /// Returns a stream of results, each one tagged with the index of its values.
+    /// Requests are started only when the stream is polled. Errors don't end the stream,
+    /// unless [`stop_on_error`](ConcurrentExecution::stop_on_error) is set.
+    ///
+    /// See [the book](https://rust-driver.docs.scylladb.com/stable/queries/concurrent.html) for more information
+    ///
+    /// # Arguments
+    /// * `prepared` - the prepared statement to execute, generated using [`Session::prepare`](Session::prepare)
+    /// * `values_iter` - values for each execution, values are serialized when their request is started
+    /// * `concurrency` - maximum number of requests running at the same time
+    ///
+    /// # Example
+    /// ```rust
+    /// # use scylla::Session;
+    /// # use std::error::Error;
+    /// # async fn check_only_compiles(session: &Session) -> Result<(), Box<dyn Error>> {
+    /// use scylla::prepared_statement::PreparedStatement;
+    ///
+    /// let prepared: PreparedStatement = session
+    ///     .prepare("INSERT INTO ks.tab (a, b) VALUES(?, ?)")
+    ///     .await?;
+    ///
+    /// let errors = session
+    ///     .execute_concurrent(&prepared, (0..1000_i32).map(|i| (i, 2 * i)), 256)
+    ///     .collect_errors()
+    ///     .await;
+    ///
+    /// for (index, error) in errors {
+    ///     println!("Insert number {} failed: {}", index, error);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn execute_concurrent<'a, I>(
+        &'a self,
+        prepared: &'a PreparedStatement,
+        values_iter: I,
+        concurrency: usize,
+    ) -> ConcurrentExecution<'a>
+    where
+        I: IntoIterator,
+        I::IntoIter: Send + 'a,
+        I::Item: ValueList,
+    {
+        let values = values_iter
+            .into_iter()
+            .map(|values| values.serialized().map(Cow::into_owned));
+        ConcurrentExecution::new(self, prepared, Box::new(values), concurrency)
+    }
+
+    async fn execute_iter_from(
+        &self,
+        mut prepared: PreparedStatement,
+        values: impl ValueList,
+        paging_state: Option<PagingState>,
+    ) -> Result<RowIterator, QueryError> {
+        let request_guard = self.request_tracker.start_request()?;
         let serialized_values = values.serialized()?;
 
+        if prepared.config.history_listener.is_none() {
+            prepared.config.history_listener = self.history_listener.clone();
+        }
+
         let token = self.calculate_token(&prepared, &serialized_values)?;
 
         let retry_session = match &prepared.config.retry_policy {
@@ -945,10 +1445,19 @@ impl Session {
             None => self.retry_policy.new_session(),
         };
 
-        let span = trace_span!(
-            "Request",
-            prepared_id = format!("{:X}", prepared.get_id()).as_str()
-        );
+        // Traces of a sampled iterator are collected for each page
+        let tracing_sampler = if self.sample_tracing(
+            prepared.statement_kind(),
+            prepared.get_statement(),
+            prepared.config.tracing,
+        ) {
+            prepared.set_tracing(true);
+            self.tracing_sampler.clone()
+        } else {
+            None
+        };
+
+        let request_span = RequestSpan::new_prepared(&prepared, token);
         RowIterator::new_for_prepared_statement(PreparedIteratorConfig {
             prepared,
             values: serialized_values.into_owned(),
@@ -958,8 +1467,12 @@ impl Session {
             load_balancer: self.load_balancer.clone(),
             cluster_data: self.cluster.get_data(),
             metrics: self.metrics.clone(),
+            request_span,
+            paging_state,
+            paging_config: self.iterator_paging_config,
+            tracing_sampler,
+            request_guard,
         })
-        .instrument(span)
         .await
     }
 
@@ -1008,34 +1521,80 @@ impl Session {
         batch: &Batch,
         values: impl BatchValues,
     ) -> Result<QueryResult, QueryError> {
-        // Shard-awareness behavior for batch will be to pick shard based on first batch statement's shard
+        let _request_guard = self.request_tracker.start_request()?;
+        // A sampled batch is executed with tracing enabled on its copy
+        let sampled_batch: Batch;
+        let first_statement = batch
+            .statements
+            .first()
+            .map_or("", |statement| match statement {
+                BatchStatement::Query(query) => query.contents.as_str(),
+                BatchStatement::PreparedStatement(prepared) => prepared.get_statement(),
+            });
+        let sampled =
+            self.sample_tracing(StatementKind::Batch, first_statement, batch.config.tracing);
+        let batch = if sampled {
+            sampled_batch = {
+                let mut batch = batch.clone();
+                batch.set_tracing(true);
+                batch
+            };
+            &sampled_batch
+        } else {
+            batch
+        };
+
+        // Shard-awareness behavior for batch will be to pick shard based on the first prepared statement's shard
         // If users batch statements by shard, they will be rewarded with full shard awareness
 
-        // Extract first serialized_value
-        let mut batch_values_iter_for_first_serialized_value = values.batch_values_iter();
-        let first_serialized_value = batch_values_iter_for_first_serialized_value
-            .next_serialized()
-            .transpose()?;
-        let first_serialized_value = first_serialized_value.as_deref();
-        let statement_info = match (first_serialized_value, batch.statements.first()) {
-            (Some(first_serialized_value), Some(BatchStatement::PreparedStatement(ps))) => {
-                Statement {
-                    token: self.calculate_token(ps, first_serialized_value)?,
-                    keyspace: ps.get_keyspace_name(),
+        // Extract serialized values of the first prepared statement, skipping values of the preceding simple queries
+        let first_prepared = batch
+            .statements
+            .iter()
+            .enumerate()
+            .find_map(|(idx, statement)| match statement {
+                BatchStatement::PreparedStatement(ps) => Some((idx, ps)),
+                BatchStatement::Query(_) => None,
+            });
+        let mut batch_values_iter_for_routing = values.batch_values_iter();
+        let prepared_serialized_value = match first_prepared {
+            Some((idx, _)) => {
+                for _ in 0..idx {
+                    batch_values_iter_for_routing.skip_next();
                 }
+                batch_values_iter_for_routing
+                    .next_serialized()
+                    .transpose()?
             }
+            None => None,
+        };
+        let prepared_serialized_value = prepared_serialized_value.as_deref();
+        let statement_info = match (prepared_serialized_value, first_prepared) {
+            (Some(prepared_serialized_value), Some((_, ps))) => Statement {
+                token: self.calculate_token(ps, prepared_serialized_value)?,
+                keyspace: ps.get_keyspace_name(),
+                is_confirmed_lwt: ps.is_confirmed_lwt(),
+            },
             _ => Statement::default(),
         };
         let first_value_token = statement_info.token;
 
         // Reuse first serialized value when serializing query, and delegate to `BatchValues::write_next_to_request`
         // directly for others (if they weren't already serialized, possibly don't even allocate the `SerializedValues`)
+        let first_serialized_value = match first_prepared {
+            Some((0, _)) => prepared_serialized_value,
+            _ => None,
+        };
         let values = BatchValuesFirstSerialized::new(&values, first_serialized_value);
         let values_ref = &values;
 
+        let request_start = Instant::now();
+        let request_span = RequestSpan::new_batch(batch, first_value_token);
         let run_query_result = self
             .run_query(
+                &request_span,
                 statement_info,
+                StatementKind::Batch,
                 &batch.config,
                 |node: Arc<Node>| async move {
                     match first_value_token {
@@ -1051,13 +1610,172 @@ impl Session {
                         .await
                 },
             )
-            .instrument(trace_span!("Batch"))
             .await?;
 
-        Ok(match run_query_result {
+        let result = match run_query_result {
             RunQueryResult::IgnoredWriteError => QueryResult::default(),
             RunQueryResult::Completed(response) => response,
-        })
+        };
+        if sampled {
+            self.collect_trace(
+                result.tracing_id,
+                StatementKind::Batch,
+                first_statement,
+                request_start.elapsed(),
+            );
+        }
+
+        Ok(result)
+    }
+
+    /// Splits a batch into smaller batches, one for each partition or replica,
+    /// and sends them in parallel, running at most `concurrency` batches at the same time.
+    ///
+    /// Every batch is routed to a replica of its partition, which avoids the cost of a coordinator
+    /// forwarding statements to other nodes. Only prepared statements can be routed -
+    /// unprepared queries are put together in one more batch.
+    ///
+    /// Note that statements from different batches are not applied atomically.
+    /// Every smaller batch has the same type as the original one.
+    /// Batches containing a confirmed LWT statement are always grouped by token,
+    /// because a conditional batch can only modify a single partition.
+    ///
+    /// Errors are collected per batch in the returned [`BatchByPartitionResult`],
+    /// an `Err` is returned only if the values couldn't be matched with the statements.
+    ///
+    /// # Arguments
+    /// * `batch` - [Batch](crate::batch::Batch) to split
+    /// * `values` - List of values for each query, same as in [`Session::batch`]
+    /// * `grouping` - Whether to group statements by token or by their primary replica
+    /// * `concurrency` - maximum number of batches running at the same time
+    ///
+    /// # Example
+    /// ```rust
+    /// # use scylla::Session;
+    /// # use std::error::Error;
+    /// # async fn check_only_compiles(session: &Session) -> Result<(), Box<dyn Error>> {
+    /// use scylla::batch::Batch;
+    /// use scylla::transport::session::BatchGrouping;
+    ///
+    /// let prepared = session.prepare("INSERT INTO ks.tab (a, b) VALUES(?, ?)").await?;
+    ///
+    /// let mut batch: Batch = Default::default();
+    /// batch.append_statement(prepared.clone());
+    /// batch.append_statement(prepared);
+    ///
+    /// let result = session
+    ///     .batch_by_partition(&batch, ((1_i32, 2_i32), (3_i32, 4_i32)), BatchGrouping::Replica, 16)
+    ///     .await?;
+    ///
+    /// for (statement_indexes, error) in result.errors() {
+    ///     println!("Statements {:?} failed: {}", statement_indexes, error);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn batch_by_partition(
+        &self,
+        batch: &Batch,
+        values: impl BatchValues,
+        grouping: BatchGrouping,
+        concurrency: usize,
+    ) -> Result<BatchByPartitionResult, QueryError> {
+        let values_len = values.len();
+        if values_len != batch.statements.len() {
+            return Err(QueryError::BadQuery(BadQuery::ValueLenMismatch(
+                values_len,
+                batch.statements.len(),
+            )));
+        }
+
+        let is_lwt_batch = batch.statements.iter().any(|statement| match statement {
+            BatchStatement::PreparedStatement(ps) => ps.is_confirmed_lwt(),
+            BatchStatement::Query(_) => false,
+        });
+        let grouping = if is_lwt_batch {
+            BatchGrouping::Token
+        } else {
+            grouping
+        };
+
+        let cluster_data = self.cluster.get_data();
+        let mut values_iter = values.batch_values_iter();
+
+        // Groups are kept in order of their first statement
+        let mut group_indexes: HashMap<BatchGroupKey, usize> = HashMap::new();
+        let mut groups: Vec<(Vec<usize>, Vec<SerializedValues>)> = Vec::new();
+
+        for (idx, statement) in batch.statements.iter().enumerate() {
+            let serialized = match values_iter.next_serialized() {
+                Some(serialized) => serialized?.into_owned(),
+                None => {
+                    return Err(QueryError::BadQuery(BadQuery::ValueLenMismatch(
+                        idx,
+                        batch.statements.len(),
+                    )))
+                }
+            };
+
+            let token = match statement {
+                BatchStatement::PreparedStatement(ps) => self
+                    .calculate_token(ps, &serialized)?
+                    .map(|token| (token, ps.get_keyspace_name())),
+                BatchStatement::Query(_) => None,
+            };
+            let key = match (token, grouping) {
+                (Some((token, _)), BatchGrouping::Token) => BatchGroupKey::Token(token.value),
+                (Some((token, keyspace)), BatchGrouping::Replica) => {
+                    match TokenAwarePolicy::replicas_for_token(&cluster_data, &token, keyspace)
+                        .first()
+                    {
+                        Some(replica) => BatchGroupKey::Replica(replica.address),
+                        None => BatchGroupKey::Token(token.value),
+                    }
+                }
+                (None, _) => BatchGroupKey::Unroutable,
+            };
+
+            let group_idx = *group_indexes.entry(key).or_insert_with(|| {
+                groups.push((Vec::new(), Vec::new()));
+                groups.len() - 1
+            });
+            let (statement_indexes, group_values) = &mut groups[group_idx];
+            statement_indexes.push(idx);
+            group_values.push(serialized);
+        }
+
+        let group_batches: Vec<(Vec<usize>, Batch, Vec<SerializedValues>)> = groups
+            .into_iter()
+            .map(|(statement_indexes, group_values)| {
+                let statements = statement_indexes
+                    .iter()
+                    .map(|idx| batch.statements[*idx].clone())
+                    .collect();
+                let mut group_batch = Batch::new_with_statements(batch.get_type(), statements);
+                group_batch.config = batch.config.clone();
+                (statement_indexes, group_batch, group_values)
+            })
+            .collect();
+
+        let results: Vec<Result<QueryResult, QueryError>> = futures::stream::iter(
+            group_batches
+                .iter()
+                .map(|(_, group_batch, group_values)| self.batch(group_batch, group_values)),
+        )
+        .buffered(concurrency.max(1))
+        .collect()
+        .await;
+
+        let batches = group_batches
+            .into_iter()
+            .zip(results)
+            .map(|((statement_indexes, _, _), result)| PartitionBatchResult {
+                statement_indexes,
+                result,
+            })
+            .collect();
+
+        Ok(BatchByPartitionResult { batches })
     }
 
     /// Prepares all statements within the batch and returns a new batch where every
@@ -1181,6 +1899,154 @@ impl Session {
         self.cluster.get_data()
     }
 
+    /// Returns the state of connection pools to all nodes known to the driver,
+    /// e.g. to implement readiness probes
+    pub fn pool_status(&self) -> Vec<NodePoolStatus> {
+        self.cluster
+            .get_data()
+            .get_nodes_info()
+            .iter()
+            .map(|node| node.pool_status())
+            .collect()
+    }
+
+    async fn wait_for_full_pools(cluster_data: &ClusterData, warmup: &PoolWarmup) {
+        let awaited_nodes: Vec<&Arc<Node>> = cluster_data
+            .get_nodes_info()
+            .iter()
+            .filter(|node| {
+                node.is_enabled()
+                    && match &warmup.datacenter {
+                        Some(datacenter) => node.datacenter.as_ref() == Some(datacenter),
+                        None => true,
+                    }
+            })
+            .collect();
+        let wait_futures = awaited_nodes.iter().map(|node| node.wait_until_pool_full());
+
+        if tokio::time::timeout(warmup.timeout, join_all(wait_futures))
+            .await
+            .is_err()
+        {
+            let missing: Vec<String> = awaited_nodes
+                .iter()
+                .map(|node| node.pool_status())
+                .filter(|status| !status.is_full)
+                .map(|status| status.address.to_string())
+                .collect();
+            warn!(
+                "Connection pools weren't full after {:?}, pools to {} are missing connections",
+                warmup.timeout,
+                missing.join(", ")
+            );
+        }
+    }
+
+    /// Subscribes to changes of the cluster state noticed by the driver,
+    /// e.g. the control connection failing over to another node.\
+    /// Only events sent after subscribing are received.
+    pub fn subscribe_cluster_events(&self) -> tokio::sync::broadcast::Receiver<ClusterEvent> {
+        self.cluster.subscribe_cluster_events()
+    }
+
+    /// Closes the session gracefully.\
+    /// New requests are rejected with [`QueryError::SessionClosed`] right away. Then the session waits
+    /// until requests in progress, including pages being fetched by [`RowIterator`]s, finish or `timeout` elapses.
+    /// Finally, it stops refreshing metadata and closes all connections.
+    ///
+    /// A [`RowIterator`] which isn't consumed doesn't block closing, it returns [`QueryError::SessionClosed`]
+    /// once pages fetched before closing are read.
+    /// Requests still in progress after the timeout aren't cancelled: they finish on the connection
+    /// they already use, but can't be retried on other connections.
+    ///
+    /// Returns `true` if all requests finished before the timeout.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use scylla::Session;
+    /// # use std::error::Error;
+    /// # async fn check_only_compiles(session: &Session) -> Result<(), Box<dyn Error>> {
+    /// use std::time::Duration;
+    ///
+    /// if !session.close(Duration::from_secs(10)).await {
+    ///     println!("Some requests didn't finish in time");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn close(&self, timeout_duration: Duration) -> bool {
+        let drained = timeout(timeout_duration, self.request_tracker.close_and_wait())
+            .await
+            .is_ok();
+        if !drained {
+            warn!(
+                "{} requests didn't finish before closing the session",
+                self.request_tracker.in_flight()
+            );
+        }
+
+        self.cluster.close().await;
+        #[cfg(feature = "tower")]
+        self.availability_notify.notify_waiters();
+        drained
+    }
+
+    #[cfg(feature = "tower")]
+    pub(crate) fn is_closed(&self) -> bool {
+        self.request_tracker.is_closed()
+    }
+
+    #[cfg(feature = "tower")]
+    pub(crate) fn get_availability_notify(&self) -> &Arc<tokio::sync::Notify> {
+        &self.availability_notify
+    }
+
+    // Returns true if some node has a working connection with a free stream id
+    #[cfg(feature = "tower")]
+    pub(crate) fn has_available_connections(&self) -> bool {
+        self.cluster.get_data().get_nodes_info().iter().any(|node| {
+            match node.get_working_connections() {
+                Ok(connections) => connections
+                    .iter()
+                    .any(|connection| connection.has_available_streams()),
+                Err(_) => false,
+            }
+        })
+    }
+
+    // Decides whether tracing should be enabled for a request according to the sampling policy.
+    // Requests with tracing enabled explicitly are left to the caller.
+    fn sample_tracing(
+        &self,
+        statement_kind: StatementKind,
+        statement: &str,
+        tracing: bool,
+    ) -> bool {
+        match &self.tracing_sampler {
+            Some(sampler) if !tracing => sampler.should_trace(&SamplingInfo {
+                statement_kind,
+                statement,
+            }),
+            _ => false,
+        }
+    }
+
+    fn collect_trace(
+        &self,
+        tracing_id: Option<Uuid>,
+        statement_kind: StatementKind,
+        statement: &str,
+        latency: Duration,
+    ) {
+        if let Some(sampler) = &self.tracing_sampler {
+            let info = SamplingInfo {
+                statement_kind,
+                statement,
+            };
+            sampler.collect(tracing_id, &info, latency);
+        }
+    }
+
     /// Get [`TracingInfo`] of a traced query performed earlier
     ///
     /// See [the book](https://rust-driver.docs.scylladb.com/stable/tracing/tracing.html)
@@ -1242,47 +2108,7 @@ impl Session {
             self.query(traces_events_query, (tracing_id,))
         )?;
 
-        // Get tracing info
-        let tracing_info_row_res: Option<Result<TracingInfo, _>> = traces_session_res
-            .rows
-            .ok_or(QueryError::ProtocolError(
-                "Response to system_traces.sessions query was not Rows",
-            ))?
-            .into_typed::<TracingInfo>()
-            .next();
-
-        let mut tracing_info: TracingInfo = match tracing_info_row_res {
-            Some(tracing_info_row_res) => tracing_info_row_res.map_err(|_| {
-                QueryError::ProtocolError(
-                    "Columns from system_traces.session have an unexpected type",
-                )
-            })?,
-            None => return Ok(None),
-        };
-
-        // Get tracing events
-        let tracing_event_rows = traces_events_res
-            .rows
-            .ok_or(QueryError::ProtocolError(
-                "Response to system_traces.events query was not Rows",
-            ))?
-            .into_typed::<TracingEvent>();
-
-        for event in tracing_event_rows {
-            let tracing_event: TracingEvent = event.map_err(|_| {
-                QueryError::ProtocolError(
-                    "Columns from system_traces.events have an unexpected type",
-                )
-            })?;
-
-            tracing_info.events.push(tracing_event);
-        }
-
-        if tracing_info.events.is_empty() {
-            return Ok(None);
-        }
-
-        Ok(Some(tracing_info))
+        crate::tracing::parse_tracing_info(traces_session_res, traces_events_res)
     }
 
     // Returns which replicas are likely to take part in handling the query.
@@ -1312,7 +2138,9 @@ impl Session {
     // maybe once async closures get stabilized this can be fixed
     async fn run_query<'a, ConnFut, QueryFut, ResT>(
         &'a self,
+        request_span: &'a RequestSpan,
         statement_info: Statement<'a>,
+        statement_kind: StatementKind,
         statement_config: &'a StatementConfig,
         choose_connection: impl Fn(Arc<Node>) -> ConnFut,
         do_query: impl Fn(Arc<Connection>, Consistency) -> QueryFut,
@@ -1326,11 +2154,25 @@ impl Session {
             statement_config
                 .history_listener
                 .as_ref()
+                .or_else(|| {
+                    if request_span.is_internal() {
+                        None
+                    } else {
+                        self.history_listener.as_ref()
+                    }
+                })
                 .map(|hl| (&**hl, hl.log_query_start()));
 
+        let request_start = Instant::now();
+        let effective_timeout = statement_config.request_timeout.or(self.request_timeout);
+        let statement_info = &statement_info;
+        request_span
+            .record_consistency(statement_config.determine_consistency(self.default_consistency));
+
         let runner = async {
             let cluster_data = self.cluster.get_data();
-            let query_plan = self.load_balancer.plan(&statement_info, &cluster_data);
+            let mut query_plan =
+                load_balancing::query_plan(&*self.load_balancer, statement_info, &cluster_data);
 
             // If a speculative execution policy is used to run query, query_plan has to be shared
             // between different async functions. This struct helps to wrap query_plan in mutex so it
@@ -1366,8 +2208,13 @@ impl Session {
 
             match speculative_policy {
                 Some(speculative) if statement_config.is_idempotent => {
+                    // Take the first node out of the plan, so that the policy
+                    // knows where the original execution is sent
+                    let target_node: Option<Arc<Node>> = query_plan.next();
                     let shared_query_plan = SharedPlan {
-                        iter: std::sync::Mutex::new(query_plan),
+                        iter: std::sync::Mutex::new(
+                            target_node.clone().into_iter().chain(query_plan),
+                        ),
                     };
 
                     let execute_query_generator = |is_speculative: bool| {
@@ -1396,12 +2243,20 @@ impl Session {
                                 consistency: statement_config.consistency,
                                 retry_session: retry_policy.new_session(),
                                 history_data,
+                                statement_info,
+                                statement_kind,
+                                request_start,
+                                request_timeout: effective_timeout,
+                                request_span,
                             },
                         )
                     };
 
                     let context = speculative_execution::Context {
                         metrics: self.metrics.clone(),
+                        statement_kind,
+                        statement: statement_info,
+                        node: target_node.as_deref(),
                     };
 
                     speculative_execution::execute(
@@ -1429,6 +2284,11 @@ impl Session {
                             consistency: statement_config.consistency,
                             retry_session: retry_policy.new_session(),
                             history_data,
+                            statement_info,
+                            statement_kind,
+                            request_start,
+                            request_timeout: effective_timeout,
+                            request_span,
                         },
                     )
                     .await
@@ -1439,7 +2299,7 @@ impl Session {
             }
         };
 
-        let effective_timeout = statement_config.request_timeout.or(self.request_timeout);
+        let runner = runner.instrument(request_span.span().clone());
         let result = match effective_timeout {
             Some(timeout) => tokio::time::timeout(timeout, runner)
                 .await
@@ -1453,6 +2313,10 @@ impl Session {
             None => runner.await,
         };
 
+        if let Err(e) = &result {
+            request_span.record_error(e);
+        }
+
         if let Some((history_listener, query_id)) = history_listener_and_id {
             match &result {
                 Ok(_) => history_listener.log_query_success(query_id),
@@ -1478,10 +2342,11 @@ impl Session {
         let mut last_error: Option<QueryError> = None;
         let mut current_consistency: Consistency =
             context.consistency.unwrap_or(self.default_consistency);
+        let mut attempt: usize = 0;
 
         'nodes_in_plan: for node in query_plan {
-            let span = trace_span!("Executing query", node = node.address.to_string().as_str());
             'same_node_retries: loop {
+                let span = request_span::attempt_span(&node, attempt + 1);
                 trace!(parent: &span, "Execution started");
                 let connection: Arc<Connection> = match choose_connection(node.clone())
                     .instrument(span.clone())
@@ -1502,12 +2367,18 @@ impl Session {
 
                 self.metrics.inc_total_nonpaged_queries();
                 let query_start = std::time::Instant::now();
+                attempt += 1;
 
                 trace!(
                     parent: &span,
                     connection = connection.get_connect_address().to_string().as_str(),
                     "Sending"
                 );
+                let shard: Option<Shard> = connection
+                    .get_shard_info()
+                    .as_ref()
+                    .map(|shard_info| shard_info.shard.into());
+                request_span::record_attempt_shard(&span, shard);
                 let attempt_id: Option<history::AttemptId> =
                     context.log_attempt_start(connection.get_connect_address());
                 let query_result: Result<ResT, QueryError> =
@@ -1518,9 +2389,9 @@ impl Session {
                 last_error = match query_result {
                     Ok(response) => {
                         trace!(parent: &span, "Query succeeded");
-                        let _ = self
-                            .metrics
-                            .log_query_latency(query_start.elapsed().as_millis() as u64);
+                        let latency = query_start.elapsed();
+                        let _ = self.metrics.log_query_latency(latency.as_millis() as u64);
+                        node.log_request_success(latency);
                         context.log_attempt_success(&attempt_id);
                         return Some(Ok(RunQueryResult::Completed(response)));
                     }
@@ -1530,7 +2401,9 @@ impl Session {
                             last_error = e.to_string().as_str(),
                             "Query failed"
                         );
+                        request_span::record_attempt_error(&span, &e);
                         self.metrics.inc_failed_nonpaged_queries();
+                        node.log_request_error(&e);
                         Some(e)
                     }
                 };
@@ -1543,6 +2416,13 @@ impl Session {
                     consistency: LegacyConsistency::Regular(
                         context.consistency.unwrap_or(self.default_consistency),
                     ),
+                    node: Some(&node),
+                    shard,
+                    attempt,
+                    elapsed: context.request_start.elapsed(),
+                    request_timeout: context.request_timeout,
+                    statement_kind: context.statement_kind,
+                    statement: context.statement_info,
                 };
 
                 let retry_decision = context.retry_session.decide_should_retry(query_info);
@@ -1554,11 +2434,13 @@ impl Session {
                 match retry_decision {
                     RetryDecision::RetrySameNode(cl) => {
                         self.metrics.inc_retries_num();
+                        context.request_span.record_retry();
                         current_consistency = cl;
                         continue 'same_node_retries;
                     }
                     RetryDecision::RetryNextNode(cl) => {
                         self.metrics.inc_retries_num();
+                        context.request_span.record_retry();
                         current_consistency = cl;
                         continue 'nodes_in_plan;
                     }
@@ -1575,17 +2457,30 @@ impl Session {
     }
 
     pub async fn await_schema_agreement(&self) -> Result<(), QueryError> {
-        while !self.check_schema_agreement().await? {
+        let _request_guard = self.request_tracker.start_request()?;
+        self.wait_for_schema_agreement().await
+    }
+
+    pub async fn await_timed_schema_agreement(
+        &self,
+        timeout_duration: Duration,
+    ) -> Result<bool, QueryError> {
+        let _request_guard = self.request_tracker.start_request()?;
+        self.wait_for_timed_schema_agreement(timeout_duration).await
+    }
+
+    async fn wait_for_schema_agreement(&self) -> Result<(), QueryError> {
+        while !self.is_schema_in_agreement().await? {
             tokio::time::sleep(self.schema_agreement_interval).await
         }
         Ok(())
     }
 
-    pub async fn await_timed_schema_agreement(
+    async fn wait_for_timed_schema_agreement(
         &self,
         timeout_duration: Duration,
     ) -> Result<bool, QueryError> {
-        timeout(timeout_duration, self.await_schema_agreement())
+        timeout(timeout_duration, self.wait_for_schema_agreement())
             .await
             .map_or(Ok(false), |res| res.and(Ok(true)))
     }
@@ -1605,9 +2500,12 @@ impl Session {
             ..Default::default()
         };
 
+        // Internal requests are not reported as request spans
         match self
             .run_query(
+                &RequestSpan::internal(),
                 info,
+                StatementKind::Query,
                 &config,
                 |node: Arc<Node>| async move { node.random_connection().await },
                 do_query,
@@ -1622,6 +2520,11 @@ impl Session {
     }
 
     pub async fn check_schema_agreement(&self) -> Result<bool, QueryError> {
+        let _request_guard = self.request_tracker.start_request()?;
+        self.is_schema_in_agreement().await
+    }
+
+    async fn is_schema_in_agreement(&self) -> Result<bool, QueryError> {
         let connections = self.cluster.get_working_connections().await?;
 
         let handles = connections.iter().map(|c| c.fetch_schema_version());
@@ -1633,6 +2536,7 @@ impl Session {
     }
 
     pub async fn fetch_schema_version(&self) -> Result<Uuid, QueryError> {
+        let _request_guard = self.request_tracker.start_request()?;
         // We ignore custom Consistency that a retry policy could decide to put here, using the default instead.
         self.schema_agreement_auxilary(
             |connection: Arc<Connection>, _ignored: Consistency| async move {
@@ -1642,7 +2546,7 @@ impl Session {
         .await
     }
 
-    fn calculate_token(
+    pub(crate) fn calculate_token(
         &self,
         prepared: &PreparedStatement,
         serialized_values: &SerializedValues,
@@ -1677,7 +2581,7 @@ fn calculate_partition_key(
 // Resolve the given hostname using a DNS lookup if necessary.
 // The resolution may return multiple IPs and the function returns one of them.
 // It prefers to return IPv4s first, and only if there are none, IPv6s.
-async fn resolve_hostname(hostname: &str) -> Result<SocketAddr, NewSessionError> {
+pub(crate) async fn resolve_hostname(hostname: &str) -> Result<SocketAddr, NewSessionError> {
     let failed_err = NewSessionError::FailedToResolveAddress(hostname.to_string());
     let mut ret = None;
     let addrs: Vec<SocketAddr> = match lookup_host(hostname).await {
@@ -1717,6 +2621,11 @@ struct ExecuteQueryContext<'a> {
     consistency: Option<Consistency>,
     retry_session: Box<dyn RetrySession>,
     history_data: Option<HistoryData<'a>>,
+    statement_info: &'a Statement<'a>,
+    statement_kind: StatementKind,
+    request_start: Instant,
+    request_timeout: Option<Duration>,
+    request_span: &'a RequestSpan,
 }
 
 struct HistoryData<'a> {
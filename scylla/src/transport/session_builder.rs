@@ -1,8 +1,10 @@
 //! SessionBuilder provides an easy way to create new Sessions
 
 use super::errors::NewSessionError;
+use super::host_filter::HostFilter;
 use super::load_balancing::LoadBalancingPolicy;
-use super::session::{AddressTranslator, Session, SessionConfig};
+use super::reconnection_policy::ReconnectionPolicy;
+use super::session::{AddressTranslator, PoolWarmup, Session, SessionConfig};
 use super::speculative_execution::SpeculativeExecutionPolicy;
 use super::Compression;
 use crate::transport::{connection_pool::PoolSize, retry_policy::RetryPolicy};
@@ -10,9 +12,18 @@ use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
+#[cfg(any(feature = "ssl", feature = "rustls"))]
+use super::tls::TlsContext;
+#[cfg(feature = "cloud")]
+use crate::cloud::CloudConfig;
+use crate::history::HistoryListener;
 use crate::statement::Consistency;
+use crate::tracing::{GetTracingConfig, TraceSink, TracingSamplingPolicy};
 #[cfg(feature = "ssl")]
 use openssl::ssl::SslContext;
+
+#[cfg(feature = "trace-context")]
+use super::trace_context::TraceContextPropagator;
 use tracing::warn;
 
 /// SessionBuilder is used to create new Session instances
@@ -295,6 +306,36 @@ impl SessionBuilder {
         self
     }
 
+    /// Set the reconnection policy used by connection pools, including the control connection,
+    /// to decide how long to wait before reconnecting to a node.
+    /// The default is [`ExponentialReconnectionPolicy`](crate::transport::reconnection_policy::ExponentialReconnectionPolicy)
+    /// starting with 50 ms and waiting at most 10 s.
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::{sync::Arc, time::Duration};
+    /// use scylla::transport::reconnection_policy::ExponentialJitterReconnectionPolicy;
+    ///
+    /// let policy = ExponentialJitterReconnectionPolicy {
+    ///     base_delay: Duration::from_millis(100),
+    ///     max_delay: Duration::from_secs(30),
+    ///     jitter: 0.5,
+    /// };
+    ///
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .reconnection_policy(Arc::new(policy))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn reconnection_policy(mut self, policy: Arc<dyn ReconnectionPolicy>) -> Self {
+        self.config.reconnection_policy = policy;
+        self
+    }
+
     /// Sets the [`RetryPolicy`] to use by default on queries
     /// The default is [DefaultRetryPolicy](crate::transport::retry_policy::DefaultRetryPolicy)
     /// It is possible to implement a custom retry policy by implementing the trait [`RetryPolicy`]
@@ -322,7 +363,8 @@ impl SessionBuilder {
     /// Provide SessionBuilder with SslContext from openssl crate that will be
     /// used to create an ssl connection to the database.
     /// If set to None SSL connection won't be used.
-    /// Default is None.
+    /// Default is None.\
+    /// Same as [`tls_context`](SessionBuilder::tls_context) with a context created from the `SslContext`.
     ///
     /// # Example
     /// ```
@@ -346,7 +388,118 @@ impl SessionBuilder {
     /// ```
     #[cfg(feature = "ssl")]
     pub fn ssl_context(mut self, ssl_context: Option<SslContext>) -> Self {
-        self.config.ssl_context = ssl_context;
+        self.config.tls_context = ssl_context.map(TlsContext::from);
+        self
+    }
+
+    /// ssl or rustls feature
+    /// Provide SessionBuilder with the TLS context used to encrypt connections to the database,
+    /// using either OpenSSL or rustls.
+    /// If set to None TLS connection won't be used.
+    /// Default is None.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use scylla::transport::tls::{ServerName, TlsBackend, TlsConfig};
+    /// # #[cfg(feature = "rustls")]
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let tls_context = TlsConfig::new()
+    ///     .add_root_certificates_pem(std::fs::read("./test/tls/ca.crt")?)
+    ///     .server_name(ServerName::Fixed("scylla.test".to_string()))
+    ///     .build(TlsBackend::Rustls)?;
+    ///
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9142")
+    ///     .tls_context(Some(tls_context))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(any(feature = "ssl", feature = "rustls"))]
+    pub fn tls_context(mut self, tls_context: Option<TlsContext>) -> Self {
+        self.config.tls_context = tls_context;
+        self
+    }
+
+    /// cloud feature
+    /// Connects to a cluster behind SNI proxies, described by a cloud connection bundle.
+    /// All connections go through the proxies, using TLS configured in the bundle,
+    /// and the proxy of the current datacenter is used as the contact point.\
+    /// Credentials and default consistency found in the bundle are used as well,
+    /// they can be overridden by calling [`user`](SessionBuilder::user)
+    /// or [`default_consistency`](SessionBuilder::default_consistency) afterwards.
+    ///
+    /// The shard-aware port is never used through the proxies, because they don't preserve
+    /// source ports of connections, so connections to all shards may take a few attempts to open.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use scylla::cloud::CloudConfig;
+    /// # use scylla::transport::tls::TlsBackend;
+    /// # use std::sync::Arc;
+    /// # #[cfg(feature = "rustls")]
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let cloud_config = CloudConfig::from_file("config_data.yaml", TlsBackend::Rustls)?;
+    ///
+    /// let session: Session = SessionBuilder::new()
+    ///     .cloud_config(Arc::new(cloud_config))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "cloud")]
+    pub fn cloud_config(mut self, cloud_config: Arc<CloudConfig>) -> Self {
+        if let Some(username) = cloud_config.get_username() {
+            self.config.auth_username = Some(username.to_string());
+            self.config.auth_password = cloud_config.get_password().map(str::to_string);
+        }
+        if let Some(consistency) = cloud_config.get_default_consistency() {
+            self.config.default_consistency = consistency;
+        }
+        self.config.cloud_config = Some(cloud_config);
+        self
+    }
+
+    /// trace-context feature
+    /// Sets the propagator which writes the trace context of request spans
+    /// into the custom payload of outgoing requests.
+    /// Default is None - no custom payload is sent.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::collections::HashMap;
+    /// # use std::sync::Arc;
+    /// # use bytes::Bytes;
+    /// # use scylla::{Session, SessionBuilder};
+    /// use scylla::transport::trace_context::TraceContextPropagator;
+    ///
+    /// #[derive(Debug)]
+    /// struct SpanIdPropagator;
+    ///
+    /// impl TraceContextPropagator for SpanIdPropagator {
+    ///     fn inject(&self, span: &tracing::Span, payload: &mut HashMap<String, Bytes>) {
+    ///         if let Some(id) = span.id() {
+    ///             payload.insert("span-id".to_string(), id.into_u64().to_string().into());
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .trace_context_propagator(Arc::new(SpanIdPropagator))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "trace-context")]
+    pub fn trace_context_propagator(mut self, propagator: Arc<dyn TraceContextPropagator>) -> Self {
+        self.config.trace_context_propagator = Some(propagator);
         self
     }
 
@@ -438,6 +591,9 @@ impl SessionBuilder {
     /// However, __you should use it as a last resort__. Before you do that, we strongly recommend
     /// that you consider fixing the network issues.
     ///
+    /// The shard-aware port is always disallowed when connecting through cloud proxies
+    /// (`cloud_config`, requires the `cloud` feature).
+    ///
     /// # Example
     /// ```
     /// # use scylla::{Session, SessionBuilder};
@@ -622,6 +778,57 @@ impl SessionBuilder {
         self
     }
 
+    /// Sets the host filter, which decides which nodes the driver connects to.
+    /// Rejected nodes are kept in the cluster metadata, but the driver never opens
+    /// connections to them and they never appear in query plans.
+    /// The default is None - all nodes are accepted.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::sync::Arc;
+    /// use scylla::host_filter::DcHostFilter;
+    ///
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .host_filter(Arc::new(DcHostFilter::new(["dc1"])))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn host_filter(mut self, filter: Arc<dyn HostFilter>) -> Self {
+        self.config.host_filter = Some(filter);
+        self
+    }
+
+    /// Sets how often hostnames of known nodes are resolved again, during metadata refreshes.
+    /// Hostnames are also resolved whenever the control connection fails, so that the driver
+    /// can find the cluster even if all nodes it knew about were replaced.
+    /// None disables periodic resolution. The default is 5 minutes.\
+    /// Each lookup times out after the [connection timeout](SessionBuilder::connection_timeout),
+    /// and addresses of nodes rejected by the [host filter](SessionBuilder::host_filter) during the last
+    /// metadata refresh are ignored. Other addresses are filtered once the node's metadata is fetched.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use std::time::Duration;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("scylla.example.com:9042")
+    ///     .hostname_resolution_interval(Some(Duration::from_secs(60)))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn hostname_resolution_interval(mut self, interval: Option<Duration>) -> Self {
+        self.config.hostname_resolution_interval = interval;
+        self
+    }
+
     /// Set the refresh metadata on schema agreement flag.
     /// The default is true.
     ///
@@ -641,6 +848,236 @@ impl SessionBuilder {
         self.config.refresh_metadata_on_auto_schema_agreement = refresh_metadata;
         self
     }
+
+    /// Enables automatic tracing of requests chosen by the sampling policy.
+    /// Tracing info of sampled requests is fetched in the background and passed to the sink.
+    /// By default no requests are sampled.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use std::sync::Arc;
+    /// # use std::time::Duration;
+    /// use scylla::tracing::{CollectedTrace, SlowQueryTracingSampling, TraceSink};
+    ///
+    /// #[derive(Debug)]
+    /// struct PrintingSink;
+    ///
+    /// impl TraceSink for PrintingSink {
+    ///     fn on_trace(&self, trace: CollectedTrace) {
+    ///         println!("Slow query {}: {:?}", trace.statement, trace.tracing_info);
+    ///     }
+    /// }
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let sampling = SlowQueryTracingSampling {
+    ///     threshold: Duration::from_millis(100),
+    ///     probability: 0.01,
+    /// };
+    ///
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .tracing_sampling(Arc::new(sampling), Arc::new(PrintingSink))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn tracing_sampling(
+        mut self,
+        policy: Arc<dyn TracingSamplingPolicy>,
+        sink: Arc<dyn TraceSink>,
+    ) -> Self {
+        self.config.tracing_sampling_policy = Some(policy);
+        self.config.trace_sink = Some(sink);
+        self
+    }
+
+    /// Changes how tracing info of sampled requests is fetched in the background.
+    /// The default is 10 attempts, 100ms apart, with consistency `One`.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use std::num::NonZeroU32;
+    /// # use std::time::Duration;
+    /// use scylla::statement::Consistency;
+    /// use scylla::tracing::GetTracingConfig;
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .trace_collection_config(GetTracingConfig {
+    ///         attempts: NonZeroU32::new(20).unwrap(),
+    ///         interval: Duration::from_millis(250),
+    ///         consistency: Consistency::One,
+    ///     })
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn trace_collection_config(mut self, config: GetTracingConfig) -> Self {
+        self.config.trace_collection_config = config;
+        self
+    }
+
+    /// Sets a listener receiving history of all queries performed by the session.\
+    /// A listener set on a statement with `set_history_listener` takes precedence over this one.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use std::sync::Arc;
+    /// # use std::time::Duration;
+    /// use scylla::history::SlowQueryLogger;
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .history_listener(Arc::new(SlowQueryLogger::new(Duration::from_millis(500))))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn history_listener(mut self, listener: Arc<dyn HistoryListener>) -> Self {
+        self.config.history_listener = Some(listener);
+        self
+    }
+
+    /// Sets the page size used by `query_iter` and `execute_iter` when the statement doesn't set one.
+    /// The default is 5000 rows. Panics if the page size is not larger than 0.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .iterator_page_size(1000)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn iterator_page_size(mut self, page_size: i32) -> Self {
+        assert!(page_size > 0, "page size must be larger than 0");
+        self.config.iterator_page_size = page_size;
+        self
+    }
+
+    /// Sets the number of pages `query_iter` and `execute_iter` fetch in the background
+    /// before they are consumed. Values below 1 are treated as 1, which is the default.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .iterator_prefetch_pages(4)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn iterator_prefetch_pages(mut self, prefetch_pages: usize) -> Self {
+        self.config.iterator_prefetch_pages = prefetch_pages;
+        self
+    }
+
+    /// Limits the size of pages fetched by `query_iter` and `execute_iter`, in bytes.\
+    /// When a page turns out bigger than the limit, the page size of the following requests
+    /// is lowered, so that pages with rows of a similar size fit in the limit.
+    /// No limit is set by default.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .iterator_max_page_bytes(16 * 1024 * 1024)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn iterator_max_page_bytes(mut self, max_page_bytes: usize) -> Self {
+        self.config.iterator_max_page_bytes = Some(max_page_bytes);
+        self
+    }
+
+    /// Set whether statements are prepared on all nodes, or on a single one.
+    /// Statements prepared on a single node are prepared on other nodes when executed
+    /// there for the first time, at the cost of an additional round trip.
+    /// The default is true.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .prepare_on_all_nodes(false)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn prepare_on_all_nodes(mut self, prepare_on_all_nodes: bool) -> Self {
+        self.config.prepare_on_all_nodes = prepare_on_all_nodes;
+        self
+    }
+
+    /// Set whether the session prepares statements again on nodes which come back up
+    /// (e.g. after a restart, which clears the statements prepared on the node) or join the cluster.
+    /// Otherwise, the statements are prepared again when executed on such nodes.
+    /// The default is true.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .reprepare_on_node_up(false)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn reprepare_on_node_up(mut self, reprepare_on_node_up: bool) -> Self {
+        self.config.reprepare_on_node_up = reprepare_on_node_up;
+        self
+    }
+
+    /// Makes [`Session::connect`] wait until connection pools to nodes in the given
+    /// datacenter (or all nodes, if None) are full, but not longer than `timeout`.
+    /// If pools aren't full by then, the session is created anyway and a warning is logged.
+    /// By default the session doesn't wait for pools to fill up.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use std::time::Duration;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .pool_warmup(Some("dc1".to_string()), Duration::from_secs(10))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn pool_warmup(mut self, datacenter: Option<String>, timeout: Duration) -> Self {
+        self.config.pool_warmup = Some(PoolWarmup {
+            datacenter,
+            timeout,
+        });
+        self
+    }
 }
 
 /// Creates a [`SessionBuilder`] with default configuration, same as [`SessionBuilder::new`]
@@ -822,6 +1259,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn pool_warmup() {
+        let mut builder = SessionBuilder::new();
+        assert!(builder.config.pool_warmup.is_none());
+
+        builder = builder.pool_warmup(Some("dc1".to_string()), std::time::Duration::from_secs(10));
+        let warmup = builder.config.pool_warmup.unwrap();
+        assert_eq!(warmup.datacenter.as_deref(), Some("dc1"));
+        assert_eq!(warmup.timeout, std::time::Duration::from_secs(10));
+    }
+
+    #[test]
+    fn iterator_page_size() {
+        let mut builder = SessionBuilder::new();
+        assert_eq!(builder.config.iterator_page_size, 5000);
+
+        builder = builder.iterator_page_size(100);
+        assert_eq!(builder.config.iterator_page_size, 100);
+    }
+
+    #[test]
+    #[should_panic]
+    fn iterator_page_size_zero() {
+        SessionBuilder::new().iterator_page_size(0);
+    }
+
     #[test]
     fn all_features() {
         let mut builder = SessionBuilder::new();
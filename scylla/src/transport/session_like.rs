@@ -0,0 +1,396 @@
+//! Common interface of [`Session`] and [`CachingSession`]
+
+use crate::batch::Batch;
+use crate::cql_to_rust::FromRow;
+use crate::frame::value::{BatchValues, BatchValuesIterator, SerializedValues, ValueList};
+use crate::prepared_statement::PreparedStatement;
+use crate::query::Query;
+use crate::transport::errors::QueryError;
+use crate::transport::iterator::{RowIterator, TypedRowIterator};
+use crate::transport::paging_state::PagingState;
+use crate::transport::ClusterData;
+use crate::{CachingSession, Metrics, QueryResult, Session};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Operations common to [`Session`] and [`CachingSession`], allowing code to be generic over them.
+///
+/// Methods behave like the methods of [`Session`] with the same names.
+/// [`CachingSession`] implements [`prepare`](SessionLike::prepare) using its cache,
+/// so preparing a statement before each execution is cheap with it. It also applies its statement
+/// configuration template to all executed statements, including prepared ones.
+///
+/// # Example
+/// ```rust
+/// # use scylla::transport::errors::QueryError;
+/// use scylla::transport::session_like::SessionLike;
+///
+/// async fn insert(session: &impl SessionLike, a: i32, b: i32) -> Result<(), QueryError> {
+///     let prepared = session.prepare("INSERT INTO ks.tab (a, b) VALUES (?, ?)").await?;
+///     session.execute(&prepared, (a, b)).await?;
+///     Ok(())
+/// }
+/// ```
+#[async_trait]
+pub trait SessionLike: Send + Sync {
+    /// See [`Session::query`]
+    async fn query(
+        &self,
+        query: impl Into<Query> + Send,
+        values: impl ValueList + Send,
+    ) -> Result<QueryResult, QueryError>;
+
+    /// See [`Session::query_paged`]
+    async fn query_paged(
+        &self,
+        query: impl Into<Query> + Send,
+        values: impl ValueList + Send,
+        paging_state: Option<Bytes>,
+    ) -> Result<QueryResult, QueryError>;
+
+    /// See [`Session::query_iter`]
+    async fn query_iter(
+        &self,
+        query: impl Into<Query> + Send,
+        values: impl ValueList + Send,
+    ) -> Result<RowIterator, QueryError>;
+
+    /// See [`Session::resume_query_iter`]
+    async fn resume_query_iter(
+        &self,
+        query: impl Into<Query> + Send,
+        values: impl ValueList + Send,
+        paging_state: PagingState,
+    ) -> Result<RowIterator, QueryError>;
+
+    /// See [`Session::prepare`]
+    async fn prepare(
+        &self,
+        query: impl Into<Query> + Send,
+    ) -> Result<PreparedStatement, QueryError>;
+
+    /// See [`Session::execute`]
+    async fn execute(
+        &self,
+        prepared: &PreparedStatement,
+        values: impl ValueList + Send,
+    ) -> Result<QueryResult, QueryError>;
+
+    /// See [`Session::execute_paged`]
+    async fn execute_paged(
+        &self,
+        prepared: &PreparedStatement,
+        values: impl ValueList + Send,
+        paging_state: Option<Bytes>,
+    ) -> Result<QueryResult, QueryError>;
+
+    /// See [`Session::execute_iter`]
+    async fn execute_iter(
+        &self,
+        prepared: impl Into<PreparedStatement> + Send,
+        values: impl ValueList + Send,
+    ) -> Result<RowIterator, QueryError>;
+
+    /// See [`Session::resume_execute_iter`]
+    async fn resume_execute_iter(
+        &self,
+        prepared: impl Into<PreparedStatement> + Send,
+        values: impl ValueList + Send,
+        paging_state: PagingState,
+    ) -> Result<RowIterator, QueryError>;
+
+    /// See [`Session::batch`]
+    async fn batch(
+        &self,
+        batch: &Batch,
+        values: impl BatchValues + Send + Sync,
+    ) -> Result<QueryResult, QueryError>;
+
+    /// See [`Session::await_schema_agreement`]
+    async fn await_schema_agreement(&self) -> Result<(), QueryError>;
+
+    /// See [`Session::await_timed_schema_agreement`]
+    async fn await_timed_schema_agreement(
+        &self,
+        timeout_duration: Duration,
+    ) -> Result<bool, QueryError>;
+
+    /// See [`Session::check_schema_agreement`]
+    async fn check_schema_agreement(&self) -> Result<bool, QueryError>;
+
+    /// See [`Session::refresh_metadata`]
+    async fn refresh_metadata(&self) -> Result<(), QueryError>;
+
+    /// See [`Session::get_metrics`]
+    fn get_metrics(&self) -> Arc<Metrics>;
+
+    /// See [`Session::get_cluster_data`]
+    fn get_cluster_data(&self) -> Arc<ClusterData>;
+
+    /// Same as [`query_iter`](SessionLike::query_iter), but parses the rows as `RowT`
+    async fn query_iter_typed<RowT: FromRow>(
+        &self,
+        query: impl Into<Query> + Send,
+        values: impl ValueList + Send,
+    ) -> Result<TypedRowIterator<RowT>, QueryError> {
+        Ok(self.query_iter(query, values).await?.into_typed())
+    }
+
+    /// Same as [`execute_iter`](SessionLike::execute_iter), but parses the rows as `RowT`
+    async fn execute_iter_typed<RowT: FromRow>(
+        &self,
+        prepared: impl Into<PreparedStatement> + Send,
+        values: impl ValueList + Send,
+    ) -> Result<TypedRowIterator<RowT>, QueryError> {
+        Ok(self.execute_iter(prepared, values).await?.into_typed())
+    }
+}
+
+#[async_trait]
+impl SessionLike for Session {
+    async fn query(
+        &self,
+        query: impl Into<Query> + Send,
+        values: impl ValueList + Send,
+    ) -> Result<QueryResult, QueryError> {
+        Session::query(self, query, values).await
+    }
+
+    async fn query_paged(
+        &self,
+        query: impl Into<Query> + Send,
+        values: impl ValueList + Send,
+        paging_state: Option<Bytes>,
+    ) -> Result<QueryResult, QueryError> {
+        Session::query_paged(self, query, values, paging_state).await
+    }
+
+    async fn query_iter(
+        &self,
+        query: impl Into<Query> + Send,
+        values: impl ValueList + Send,
+    ) -> Result<RowIterator, QueryError> {
+        Session::query_iter(self, query, values).await
+    }
+
+    async fn resume_query_iter(
+        &self,
+        query: impl Into<Query> + Send,
+        values: impl ValueList + Send,
+        paging_state: PagingState,
+    ) -> Result<RowIterator, QueryError> {
+        Session::resume_query_iter(self, query, values, paging_state).await
+    }
+
+    async fn prepare(
+        &self,
+        query: impl Into<Query> + Send,
+    ) -> Result<PreparedStatement, QueryError> {
+        Session::prepare(self, query).await
+    }
+
+    async fn execute(
+        &self,
+        prepared: &PreparedStatement,
+        values: impl ValueList + Send,
+    ) -> Result<QueryResult, QueryError> {
+        Session::execute(self, prepared, values).await
+    }
+
+    async fn execute_paged(
+        &self,
+        prepared: &PreparedStatement,
+        values: impl ValueList + Send,
+        paging_state: Option<Bytes>,
+    ) -> Result<QueryResult, QueryError> {
+        Session::execute_paged(self, prepared, values, paging_state).await
+    }
+
+    async fn execute_iter(
+        &self,
+        prepared: impl Into<PreparedStatement> + Send,
+        values: impl ValueList + Send,
+    ) -> Result<RowIterator, QueryError> {
+        Session::execute_iter(self, prepared, values).await
+    }
+
+    async fn resume_execute_iter(
+        &self,
+        prepared: impl Into<PreparedStatement> + Send,
+        values: impl ValueList + Send,
+        paging_state: PagingState,
+    ) -> Result<RowIterator, QueryError> {
+        Session::resume_execute_iter(self, prepared, values, paging_state).await
+    }
+
+    async fn batch(
+        &self,
+        batch: &Batch,
+        values: impl BatchValues + Send + Sync,
+    ) -> Result<QueryResult, QueryError> {
+        let values = serialize_batch_values(&values)?;
+        Session::batch(self, batch, values).await
+    }
+
+    async fn await_schema_agreement(&self) -> Result<(), QueryError> {
+        Session::await_schema_agreement(self).await
+    }
+
+    async fn await_timed_schema_agreement(
+        &self,
+        timeout_duration: Duration,
+    ) -> Result<bool, QueryError> {
+        Session::await_timed_schema_agreement(self, timeout_duration).await
+    }
+
+    async fn check_schema_agreement(&self) -> Result<bool, QueryError> {
+        Session::check_schema_agreement(self).await
+    }
+
+    async fn refresh_metadata(&self) -> Result<(), QueryError> {
+        Session::refresh_metadata(self).await
+    }
+
+    fn get_metrics(&self) -> Arc<Metrics> {
+        Session::get_metrics(self)
+    }
+
+    fn get_cluster_data(&self) -> Arc<ClusterData> {
+        Session::get_cluster_data(self)
+    }
+}
+
+#[async_trait]
+impl SessionLike for CachingSession {
+    async fn query(
+        &self,
+        query: impl Into<Query> + Send,
+        values: impl ValueList + Send,
+    ) -> Result<QueryResult, QueryError> {
+        CachingSession::query(self, query, values).await
+    }
+
+    async fn query_paged(
+        &self,
+        query: impl Into<Query> + Send,
+        values: impl ValueList + Send,
+        paging_state: Option<Bytes>,
+    ) -> Result<QueryResult, QueryError> {
+        CachingSession::query_paged(self, query, values, paging_state).await
+    }
+
+    async fn query_iter(
+        &self,
+        query: impl Into<Query> + Send,
+        values: impl ValueList + Send,
+    ) -> Result<RowIterator, QueryError> {
+        CachingSession::query_iter(self, query, values).await
+    }
+
+    async fn resume_query_iter(
+        &self,
+        query: impl Into<Query> + Send,
+        values: impl ValueList + Send,
+        paging_state: PagingState,
+    ) -> Result<RowIterator, QueryError> {
+        CachingSession::resume_query_iter(self, query, values, paging_state).await
+    }
+
+    async fn prepare(
+        &self,
+        query: impl Into<Query> + Send,
+    ) -> Result<PreparedStatement, QueryError> {
+        CachingSession::prepare(self, query).await
+    }
+
+    async fn execute(
+        &self,
+        prepared: &PreparedStatement,
+        values: impl ValueList + Send,
+    ) -> Result<QueryResult, QueryError> {
+        let prepared = self.apply_statement_config_to_prepared(prepared.clone());
+        self.session.execute(&prepared, values).await
+    }
+
+    async fn execute_paged(
+        &self,
+        prepared: &PreparedStatement,
+        values: impl ValueList + Send,
+        paging_state: Option<Bytes>,
+    ) -> Result<QueryResult, QueryError> {
+        let prepared = self.apply_statement_config_to_prepared(prepared.clone());
+        self.session
+            .execute_paged(&prepared, values, paging_state)
+            .await
+    }
+
+    async fn execute_iter(
+        &self,
+        prepared: impl Into<PreparedStatement> + Send,
+        values: impl ValueList + Send,
+    ) -> Result<RowIterator, QueryError> {
+        let prepared = self.apply_statement_config_to_prepared(prepared);
+        self.session.execute_iter(prepared, values).await
+    }
+
+    async fn resume_execute_iter(
+        &self,
+        prepared: impl Into<PreparedStatement> + Send,
+        values: impl ValueList + Send,
+        paging_state: PagingState,
+    ) -> Result<RowIterator, QueryError> {
+        let prepared = self.apply_statement_config_to_prepared(prepared);
+        self.session
+            .resume_execute_iter(prepared, values, paging_state)
+            .await
+    }
+
+    async fn batch(
+        &self,
+        batch: &Batch,
+        values: impl BatchValues + Send + Sync,
+    ) -> Result<QueryResult, QueryError> {
+        let values = serialize_batch_values(&values)?;
+        CachingSession::batch(self, batch, values).await
+    }
+
+    async fn await_schema_agreement(&self) -> Result<(), QueryError> {
+        CachingSession::await_schema_agreement(self).await
+    }
+
+    async fn await_timed_schema_agreement(
+        &self,
+        timeout_duration: Duration,
+    ) -> Result<bool, QueryError> {
+        CachingSession::await_timed_schema_agreement(self, timeout_duration).await
+    }
+
+    async fn check_schema_agreement(&self) -> Result<bool, QueryError> {
+        CachingSession::check_schema_agreement(self).await
+    }
+
+    async fn refresh_metadata(&self) -> Result<(), QueryError> {
+        CachingSession::refresh_metadata(self).await
+    }
+
+    fn get_metrics(&self) -> Arc<Metrics> {
+        CachingSession::get_metrics(self)
+    }
+
+    fn get_cluster_data(&self) -> Arc<ClusterData> {
+        CachingSession::get_cluster_data(self)
+    }
+}
+
+// Iterators over generic batch values are not guaranteed to be Send,
+// so the values are serialized before the batch is executed
+fn serialize_batch_values(values: &impl BatchValues) -> Result<Vec<SerializedValues>, QueryError> {
+    let mut values_iter = values.batch_values_iter();
+    let mut serialized = Vec::with_capacity(values.len());
+    while let Some(statement_values) = values_iter.next_serialized() {
+        serialized.push(statement_values?.into_owned());
+    }
+    Ok(serialized)
+}
@@ -8,19 +8,23 @@ use crate::routing::Token;
 use crate::statement::Consistency;
 use crate::tracing::TracingInfo;
 use crate::transport::errors::{BadKeyspaceName, BadQuery, DbError, QueryError};
+use crate::transport::paging_state::PagingState;
 use crate::transport::partitioner::{Murmur3Partitioner, Partitioner, PartitionerName};
+use crate::transport::query_result::LwtResultError;
+use crate::transport::session::BatchGrouping;
 use crate::transport::topology::Strategy::SimpleStrategy;
 use crate::transport::topology::{CollectionType, ColumnKind, CqlType, NativeType};
 use crate::utils::test_utils::{supports_feature, unique_keyspace_name};
 use crate::CachingSession;
-use crate::QueryResult;
 use crate::{IntoTypedRows, Session, SessionBuilder};
+use crate::{LwtResult, QueryResult};
 use assert_matches::assert_matches;
 use bytes::Bytes;
 use futures::{FutureExt, StreamExt};
 use itertools::Itertools;
 use std::collections::BTreeSet;
 use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 use tokio::net::TcpListener;
 use uuid::Uuid;
 
@@ -1219,6 +1223,10 @@ async fn test_request_timeout() {
             Ok(_) => panic!("the prepared query should have failed due to a client-side timeout"),
             Err(e) => assert_matches!(e, QueryError::RequestTimeout(_)),
         };
+
+        // Paged queries aren't timed out, the timeout is only reported to the retry policy
+        let mut rows = session.execute_iter(prepared, &[]).await.unwrap();
+        assert!(rows.next().await.unwrap().is_ok());
     }
     {
         let timeouting_session = SessionBuilder::new()
@@ -2227,6 +2235,150 @@ async fn test_prepare_batch() {
     }
 }
 
+#[tokio::test]
+async fn test_pool_warmup_and_status() {
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let session = SessionBuilder::new()
+        .known_node(uri)
+        .pool_warmup(None, std::time::Duration::from_secs(10))
+        .build()
+        .await
+        .unwrap();
+
+    let pool_status = session.pool_status();
+    assert_eq!(
+        pool_status.len(),
+        session.get_cluster_data().get_nodes_info().len()
+    );
+    for node_status in pool_status {
+        assert!(node_status.is_full, "{:?}", node_status);
+        assert!(node_status.last_error.is_none(), "{:?}", node_status);
+        assert!(!node_status.shard_connections.is_empty());
+        assert!(node_status
+            .shard_connections
+            .iter()
+            .all(|&count| count >= 1));
+    }
+}
+
+#[tokio::test]
+async fn test_lwt_result() {
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let session = SessionBuilder::new().known_node(uri).build().await.unwrap();
+    let ks = unique_keyspace_name();
+
+    session.query(format!("CREATE KEYSPACE IF NOT EXISTS {} WITH REPLICATION = {{'class' : 'SimpleStrategy', 'replication_factor' : 1}}", ks), &[]).await.unwrap();
+    session
+        .query(
+            format!(
+                "CREATE TABLE IF NOT EXISTS {}.t (a int primary key, b text)",
+                ks
+            ),
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let insert = session
+        .prepare(format!(
+            "INSERT INTO {}.t (a, b) VALUES (?, ?) IF NOT EXISTS",
+            ks
+        ))
+        .await
+        .unwrap();
+    let select = session
+        .prepare(format!("SELECT b FROM {}.t WHERE a = ?", ks))
+        .await
+        .unwrap();
+    assert!(!select.is_confirmed_lwt());
+    // LWT statements are marked only if the connection negotiated the LWT metadata mark
+    let connection = session.get_cluster_data().get_nodes_info()[0]
+        .random_connection()
+        .await
+        .unwrap();
+    let lwt_mark_negotiated = connection
+        .get_protocol_features()
+        .lwt_optimization_meta_bit_mask
+        .is_some();
+    assert_eq!(insert.is_confirmed_lwt(), lwt_mark_negotiated);
+
+    let first: LwtResult<(i32, String)> = session
+        .execute(&insert, (1, "one"))
+        .await
+        .unwrap()
+        .lwt_result()
+        .unwrap();
+    assert!(first.applied);
+    assert_eq!(first.current_row, None);
+
+    let second = session.execute(&insert, (1, "two")).await.unwrap();
+    assert!(!second.lwt_applied().unwrap());
+    assert_eq!(
+        second.lwt_result::<(i32, String)>().unwrap().current_row,
+        Some((1, "one".to_string()))
+    );
+
+    assert_matches!(
+        session.execute(&select, (1,)).await.unwrap().lwt_applied(),
+        Err(LwtResultError::NotLwt)
+    );
+}
+
+#[tokio::test]
+async fn test_session_close() {
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let session = SessionBuilder::new()
+        .known_node(uri.clone())
+        .build()
+        .await
+        .unwrap();
+
+    // A request started before closing finishes successfully
+    let (result, drained) = tokio::join!(
+        session.query("SELECT * FROM system.local", &[]),
+        session.close(std::time::Duration::from_secs(10))
+    );
+    assert!(result.is_ok());
+    assert!(drained);
+
+    assert_matches!(
+        session.query("SELECT * FROM system.local", &[]).await,
+        Err(QueryError::SessionClosed)
+    );
+    assert_matches!(
+        session.prepare("SELECT * FROM system.local").await,
+        Err(QueryError::SessionClosed)
+    );
+    assert_matches!(
+        session.refresh_metadata().await,
+        Err(QueryError::SessionClosed)
+    );
+    assert_matches!(
+        session.check_schema_agreement().await,
+        Err(QueryError::SessionClosed)
+    );
+    // Closing again does nothing
+    assert!(session.close(std::time::Duration::from_secs(1)).await);
+
+    // An iterator which isn't consumed doesn't block closing,
+    // it returns the rows fetched before and then fails
+    let session = SessionBuilder::new().known_node(uri).build().await.unwrap();
+    let mut query = Query::new("SELECT * FROM system_schema.columns");
+    query.set_page_size(1);
+    let mut rows = session.query_iter(query, &[]).await.unwrap();
+    assert!(session.close(std::time::Duration::from_secs(10)).await);
+
+    let mut fetched_rows = 0;
+    let last = loop {
+        match rows.next().await {
+            Some(Ok(_)) => fetched_rows += 1,
+            other => break other,
+        }
+    };
+    assert!(fetched_rows >= 1);
+    assert_matches!(last, Some(Err(QueryError::SessionClosed)));
+}
+
 #[tokio::test]
 async fn test_refresh_metadata_after_schema_agreement() {
     let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
@@ -2423,3 +2575,684 @@ async fn test_batch_lwts_for_cassandra(session: &Session, batch: &Batch, batch_r
 
     assert_eq!(prepared_batch_res_rows, expected_prepared_batch_res_rows);
 }
+
+#[tokio::test]
+async fn test_resume_iter_from_paging_state() {
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let session = SessionBuilder::new().known_node(uri).build().await.unwrap();
+    let ks = unique_keyspace_name();
+
+    session.query(format!("CREATE KEYSPACE IF NOT EXISTS {} WITH REPLICATION = {{'class' : 'SimpleStrategy', 'replication_factor' : 1}}", ks), &[]).await.unwrap();
+    session.use_keyspace(ks, false).await.unwrap();
+    session
+        .query("CREATE TABLE t (p int, c int, PRIMARY KEY (p, c))", &[])
+        .await
+        .unwrap();
+    for c in 0..10 {
+        session
+            .query("INSERT INTO t (p, c) VALUES (0, ?)", (c,))
+            .await
+            .unwrap();
+    }
+
+    let query = Query::new("SELECT c FROM t WHERE p = 0").with_page_size(4);
+    let prepared = session.prepare(query.clone()).await.unwrap();
+
+    // Read the first page and save the paging state as a token
+    let mut rows_iter = session.query_iter(query.clone(), &[]).await.unwrap();
+    let mut first_page: Vec<i32> = Vec::new();
+    while let Some(row) = rows_iter.next().await {
+        first_page.push(row.unwrap().into_typed::<(i32,)>().unwrap().0);
+        if rows_iter.is_current_page_exhausted() {
+            break;
+        }
+    }
+    assert_eq!(first_page, vec![0, 1, 2, 3]);
+    let token = rows_iter.paging_state().unwrap().to_token();
+    drop(rows_iter);
+
+    // Resume both a simple and a prepared scan from the token
+    let paging_state = PagingState::from_token(&token).unwrap();
+    let resumed_query: Vec<i32> = session
+        .resume_query_iter(query, &[], paging_state.clone())
+        .await
+        .unwrap()
+        .into_typed::<(i32,)>()
+        .map(|row| row.unwrap().0)
+        .collect()
+        .await;
+    let resumed_prepared: Vec<i32> = session
+        .resume_execute_iter(prepared, &[], paging_state)
+        .await
+        .unwrap()
+        .into_typed::<(i32,)>()
+        .map(|row| row.unwrap().0)
+        .collect()
+        .await;
+
+    assert_eq!(resumed_query, (4..10).collect::<Vec<i32>>());
+    assert_eq!(resumed_prepared, (4..10).collect::<Vec<i32>>());
+}
+
+#[tokio::test]
+async fn test_iter_pages() {
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let session = SessionBuilder::new()
+        .known_node(uri)
+        .iterator_prefetch_pages(2)
+        .build()
+        .await
+        .unwrap();
+    let ks = unique_keyspace_name();
+
+    session.query(format!("CREATE KEYSPACE IF NOT EXISTS {} WITH REPLICATION = {{'class' : 'SimpleStrategy', 'replication_factor' : 1}}", ks), &[]).await.unwrap();
+    session.use_keyspace(ks, false).await.unwrap();
+    session
+        .query("CREATE TABLE t (p int, c int, PRIMARY KEY (p, c))", &[])
+        .await
+        .unwrap();
+    for c in 0..10 {
+        session
+            .query("INSERT INTO t (p, c) VALUES (0, ?)", (c,))
+            .await
+            .unwrap();
+    }
+
+    let query = Query::new("SELECT c FROM t WHERE p = 0").with_page_size(4);
+
+    // Rows already returned by the iterator are not repeated by the page stream
+    let mut rows_iter = session.query_iter(query.clone(), &[]).await.unwrap();
+    rows_iter.next().await.unwrap().unwrap();
+    let pages: Vec<_> = rows_iter.pages().map(|page| page.unwrap()).collect().await;
+
+    let page_rows: Vec<Vec<i32>> = pages
+        .iter()
+        .map(|page| {
+            page.rows
+                .rows
+                .iter()
+                .map(|row| row.columns[0].as_ref().unwrap().as_int().unwrap())
+                .collect()
+        })
+        .collect();
+    assert_eq!(page_rows, vec![vec![1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]]);
+
+    assert!(pages[0].paging_state.is_some());
+    assert!(pages[2].paging_state.is_none());
+    assert!(pages.iter().all(|page| page.tracing_id.is_none()));
+
+    // Tracing ids of all fetched pages are kept by the page stream
+    let mut traced_query = query;
+    traced_query.set_tracing(true);
+    let mut page_iter = session.query_iter(traced_query, &[]).await.unwrap().pages();
+    let mut page_tracing_ids = Vec::new();
+    while let Some(page) = page_iter.next().await {
+        page_tracing_ids.push(page.unwrap().tracing_id.unwrap());
+    }
+    assert_eq!(page_tracing_ids.len(), 3);
+    assert_eq!(page_iter.get_tracing_ids(), page_tracing_ids.as_slice());
+}
+
+#[tokio::test]
+async fn test_cached_result_metadata() {
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let session = SessionBuilder::new().known_node(uri).build().await.unwrap();
+    let ks = unique_keyspace_name();
+
+    session.query(format!("CREATE KEYSPACE IF NOT EXISTS {} WITH REPLICATION = {{'class' : 'SimpleStrategy', 'replication_factor' : 1}}", ks), &[]).await.unwrap();
+    session.use_keyspace(ks, false).await.unwrap();
+    session
+        .query("CREATE TABLE t (p int primary key, a int)", &[])
+        .await
+        .unwrap();
+    session
+        .query("INSERT INTO t (p, a) VALUES (0, 1)", &[])
+        .await
+        .unwrap();
+
+    let mut prepared = session.prepare("SELECT * FROM t").await.unwrap();
+    assert!(!prepared.get_use_cached_result_metadata());
+    assert_eq!(prepared.get_result_metadata().col_count(), 2);
+    let result = session.execute(&prepared, &[]).await.unwrap();
+    assert_eq!(result.single_row_typed::<(i32, i32)>().unwrap(), (0, 1));
+
+    // Rows are deserialized using the metadata cached in the statement
+    prepared.set_use_cached_result_metadata(true);
+    let result = session.execute(&prepared, &[]).await.unwrap();
+    assert_eq!(result.col_specs.len(), 2);
+    assert_eq!(result.single_row_typed::<(i32, i32)>().unwrap(), (0, 1));
+
+    // The cached metadata is refreshed after a column is added
+    session
+        .query("ALTER TABLE t ADD b text", &[])
+        .await
+        .unwrap();
+    session.await_schema_agreement().await.unwrap();
+    let result = session.execute(&prepared, &[]).await.unwrap();
+    assert_eq!(result.col_specs.len(), 3);
+    assert_eq!(prepared.get_result_metadata().col_count(), 3);
+
+    // Change the type of column `a`. Columns can't be re-added with a different type,
+    // so the table is created again.
+    let mut uncached = prepared.clone();
+    uncached.set_use_cached_result_metadata(false);
+    session.query("DROP TABLE t", &[]).await.unwrap();
+    session
+        .query("CREATE TABLE t (p int primary key, a text, b text)", &[])
+        .await
+        .unwrap();
+    session.await_schema_agreement().await.unwrap();
+    session
+        .query("INSERT INTO t (p, a, b) VALUES (0, 'abc', 'def')", &[])
+        .await
+        .unwrap();
+
+    // Metadata sent with the result always has the current types
+    let result = session.execute(&uncached, &[]).await.unwrap();
+    assert_eq!(
+        result.single_row_typed::<(i32, String, String)>().unwrap(),
+        (0, "abc".to_string(), "def".to_string())
+    );
+
+    // The statement is invalidated by the database, so the cached metadata is refreshed too
+    let result = session.execute(&prepared, &[]).await.unwrap();
+    assert_eq!(
+        result.single_row_typed::<(i32, String, String)>().unwrap(),
+        (0, "abc".to_string(), "def".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_batch_by_partition() {
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let session = SessionBuilder::new().known_node(uri).build().await.unwrap();
+    let ks = unique_keyspace_name();
+
+    session.query(format!("CREATE KEYSPACE IF NOT EXISTS {} WITH REPLICATION = {{'class' : 'SimpleStrategy', 'replication_factor' : 1}}", ks), &[]).await.unwrap();
+    session.use_keyspace(ks, false).await.unwrap();
+    session
+        .query(
+            "CREATE TABLE t (a int, b int, c text, primary key (a, b))",
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let prepared = session
+        .prepare("INSERT INTO t (a, b, c) VALUES (?, ?, ?)")
+        .await
+        .unwrap();
+    let mut batch: Batch = Default::default();
+    batch.append_statement(prepared.clone());
+    batch.append_statement(prepared.clone());
+    batch.append_statement("INSERT INTO t (a, b, c) VALUES (7, 11, '')");
+    batch.append_statement(prepared);
+    let values = (
+        (1_i32, 2_i32, "abc"),
+        (2_i32, 3_i32, "def"),
+        (),
+        (1_i32, 4_i32, "ghi"),
+    );
+
+    let result = session
+        .batch_by_partition(&batch, values, BatchGrouping::Token, 2)
+        .await
+        .unwrap();
+    assert!(result.is_success());
+    assert_eq!(result.errors().count(), 0);
+    let statement_indexes: Vec<Vec<usize>> = result
+        .batches
+        .into_iter()
+        .map(|batch| batch.statement_indexes)
+        .collect();
+    assert_eq!(statement_indexes, vec![vec![0, 3], vec![1], vec![2]]);
+
+    let mut rows: Vec<(i32, i32, String)> = session
+        .query("SELECT a, b, c FROM t", &[])
+        .await
+        .unwrap()
+        .rows_typed::<(i32, i32, String)>()
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect();
+    rows.sort();
+    assert_eq!(
+        rows,
+        vec![
+            (1, 2, "abc".to_string()),
+            (1, 4, "ghi".to_string()),
+            (2, 3, "def".to_string()),
+            (7, 11, "".to_string()),
+        ]
+    );
+
+    // Statements of the same partition always have the same replica
+    let result = session
+        .batch_by_partition(&batch, values, BatchGrouping::Replica, 2)
+        .await
+        .unwrap();
+    assert!(result.is_success());
+    assert!(result
+        .batches
+        .iter()
+        .any(|batch| batch.statement_indexes.starts_with(&[0])
+            && batch.statement_indexes.contains(&3)));
+    assert_eq!(result.batches.last().unwrap().statement_indexes, vec![2]);
+
+    assert_matches!(
+        session
+            .batch_by_partition(&batch, ((1_i32, 2_i32, "abc"),), BatchGrouping::Token, 2)
+            .await,
+        Err(QueryError::BadQuery(BadQuery::ValueLenMismatch(1, 4)))
+    );
+
+    // Batches with LWT statements are grouped by token even with replica grouping
+    let insert_lwt = session
+        .prepare("INSERT INTO t (a, b, c) VALUES (?, ?, ?) IF NOT EXISTS")
+        .await
+        .unwrap();
+    if insert_lwt.is_confirmed_lwt() {
+        let mut lwt_batch: Batch = Default::default();
+        lwt_batch.append_statement(insert_lwt.clone());
+        lwt_batch.append_statement(insert_lwt.clone());
+        lwt_batch.append_statement(insert_lwt);
+        let lwt_values = (
+            (10_i32, 1_i32, "a"),
+            (11_i32, 1_i32, "b"),
+            (10_i32, 2_i32, "c"),
+        );
+        let result = session
+            .batch_by_partition(&lwt_batch, lwt_values, BatchGrouping::Replica, 2)
+            .await
+            .unwrap();
+        assert!(result.is_success());
+        let statement_indexes: Vec<Vec<usize>> = result
+            .batches
+            .into_iter()
+            .map(|batch| batch.statement_indexes)
+            .collect();
+        assert_eq!(statement_indexes, vec![vec![0, 2], vec![1]]);
+    }
+}
+
+#[tokio::test]
+async fn test_execute_concurrent() {
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let session = SessionBuilder::new().known_node(uri).build().await.unwrap();
+    let ks = unique_keyspace_name();
+
+    session.query(format!("CREATE KEYSPACE IF NOT EXISTS {} WITH REPLICATION = {{'class' : 'SimpleStrategy', 'replication_factor' : 1}}", ks), &[]).await.unwrap();
+    session.use_keyspace(ks, false).await.unwrap();
+    session
+        .query("CREATE TABLE t (a int primary key, b int)", &[])
+        .await
+        .unwrap();
+
+    let insert = session
+        .prepare("INSERT INTO t (a, b) VALUES (?, ?)")
+        .await
+        .unwrap();
+    let errors = session
+        .execute_concurrent(&insert, (0..100_i32).map(|i| (i, 2 * i)), 8)
+        .collect_errors()
+        .await;
+    assert!(errors.is_empty());
+
+    let rows_count = session
+        .query("SELECT COUNT(*) FROM t", &[])
+        .await
+        .unwrap()
+        .single_row_typed::<(i64,)>()
+        .unwrap()
+        .0;
+    assert_eq!(rows_count, 100);
+
+    // Results are yielded in order of the values
+    let select = session
+        .prepare("SELECT b FROM t WHERE a = ?")
+        .await
+        .unwrap();
+    let results: Vec<(usize, i32)> = session
+        .execute_concurrent(&select, (0..20_i32).map(|i| (i,)), 4)
+        .preserve_order(true)
+        .map(|item| {
+            let b = item.result.unwrap().single_row_typed::<(i32,)>().unwrap().0;
+            (item.index, b)
+        })
+        .collect()
+        .await;
+    let expected: Vec<(usize, i32)> = (0..20).map(|i| (i as usize, 2 * i)).collect();
+    assert_eq!(results, expected);
+
+    // A value of a wrong type is rejected, no more requests are started after that
+    let items: Vec<_> = session
+        .execute_concurrent(&insert, vec![(1_i32, None), (2, Some("two")), (3, None)], 1)
+        .preserve_order(true)
+        .stop_on_error(true)
+        .collect()
+        .await;
+    assert_eq!(items.len(), 2);
+    assert!(items[0].result.is_ok());
+    assert_eq!(items[1].index, 1);
+    assert_matches!(items[1].result, Err(QueryError::DbError(_, _)));
+}
+
+#[tokio::test]
+async fn test_bulk_writer() {
+    use crate::transport::bulk_writer::{BulkWriter, BulkWriterConfig, FlushMode};
+    use std::sync::Mutex;
+
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let session = Arc::new(SessionBuilder::new().known_node(uri).build().await.unwrap());
+    let ks = unique_keyspace_name();
+
+    session.query(format!("CREATE KEYSPACE IF NOT EXISTS {} WITH REPLICATION = {{'class' : 'SimpleStrategy', 'replication_factor' : 1}}", ks), &[]).await.unwrap();
+    session.use_keyspace(ks, false).await.unwrap();
+    session
+        .query(
+            "CREATE TABLE t (a int, b int, c text, primary key (a, b))",
+            &[],
+        )
+        .await
+        .unwrap();
+    let insert = session
+        .prepare("INSERT INTO t (a, b, c) VALUES (?, ?, ?)")
+        .await
+        .unwrap();
+
+    let config = BulkWriterConfig {
+        max_flush_rows: 16,
+        max_concurrent_flushes: 4,
+        ..Default::default()
+    };
+    let writer = BulkWriter::new(session.clone(), insert.clone(), config);
+    for i in 0..500_i32 {
+        assert_eq!(writer.write((i % 50, i, "abc")).await.unwrap(), i as u64);
+    }
+    writer.flush().await.unwrap();
+    assert_eq!(writer.progress().written_rows, 500);
+    let progress = writer.finish().await;
+    assert_eq!(progress.written_rows, 500);
+    assert_eq!(progress.failed_rows, 0);
+
+    let rows_count = session
+        .query("SELECT COUNT(*) FROM t", &[])
+        .await
+        .unwrap()
+        .single_row_typed::<(i64,)>()
+        .unwrap()
+        .0;
+    assert_eq!(rows_count, 500);
+
+    // Rows fail independently when pipelined, failed rows are reported through the callback
+    let failed_rows: Arc<Mutex<Vec<u64>>> = Default::default();
+    let config = BulkWriterConfig {
+        flush_mode: FlushMode::Pipelined,
+        on_flush: Some(Arc::new({
+            let failed_rows = failed_rows.clone();
+            move |report| {
+                let mut failed_rows = failed_rows.lock().unwrap();
+                failed_rows.extend(report.failed_rows.iter().map(|row| row.row_number));
+            }
+        })),
+        ..Default::default()
+    };
+    let writer = BulkWriter::new(session.clone(), insert.clone(), config);
+    writer.write((1000_i32, 1_i32, "abc")).await.unwrap();
+    // Text doesn't fit the int column
+    writer.write((1000_i32, "two", "abc")).await.unwrap();
+    writer.write((1000_i32, 3_i32, "abc")).await.unwrap();
+    let progress = writer.finish().await;
+    assert_eq!(progress.written_rows, 2);
+    assert_eq!(progress.failed_rows, 1);
+    assert_eq!(*failed_rows.lock().unwrap(), vec![1]);
+
+    // Values without the partition key can't be routed
+    let writer = BulkWriter::new(session, insert, BulkWriterConfig::default());
+    assert!(writer.write(()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_retry_policy_query_info() {
+    use crate::retry_policy::{QueryInfo, RetryDecision, RetryPolicy, RetrySession};
+    use crate::routing::Shard;
+    use crate::statement::StatementKind;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct RecordedAttempt {
+        node_is_sharded: Option<bool>,
+        shard: Option<Shard>,
+        attempt: usize,
+        statement_kind: StatementKind,
+        request_timeout: Option<Duration>,
+    }
+
+    // Retries once on the same node and records what it was told about each failure
+    #[derive(Debug, Clone, Default)]
+    struct RecordingRetryPolicy {
+        attempts: Arc<Mutex<Vec<RecordedAttempt>>>,
+    }
+
+    impl RetryPolicy for RecordingRetryPolicy {
+        fn new_session(&self) -> Box<dyn RetrySession> {
+            Box::new(self.clone())
+        }
+
+        fn clone_boxed(&self) -> Box<dyn RetryPolicy> {
+            Box::new(self.clone())
+        }
+    }
+
+    impl RetrySession for RecordingRetryPolicy {
+        fn decide_should_retry(&mut self, query_info: QueryInfo) -> RetryDecision {
+            self.attempts.lock().unwrap().push(RecordedAttempt {
+                node_is_sharded: query_info.node.map(|node| node.sharder().is_some()),
+                shard: query_info.shard,
+                attempt: query_info.attempt,
+                statement_kind: query_info.statement_kind,
+                request_timeout: query_info.request_timeout,
+            });
+            match query_info.attempt {
+                1 => RetryDecision::RetrySameNode(Consistency::Three),
+                _ => RetryDecision::DontRetry,
+            }
+        }
+
+        fn reset(&mut self) {}
+    }
+
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let session = SessionBuilder::new()
+        .known_node(uri)
+        .request_timeout(Some(Duration::from_secs(20)))
+        .build()
+        .await
+        .unwrap();
+    let ks = unique_keyspace_name();
+
+    session.query(format!("CREATE KEYSPACE IF NOT EXISTS {} WITH REPLICATION = {{'class' : 'SimpleStrategy', 'replication_factor' : 1}}", ks), &[]).await.unwrap();
+    session.use_keyspace(ks, false).await.unwrap();
+    session
+        .query("CREATE TABLE t (a int primary key, b int)", &[])
+        .await
+        .unwrap();
+
+    // Consistency THREE can't be reached with replication factor 1, so every attempt fails
+    let policy = RecordingRetryPolicy::default();
+    let mut query = Query::new("SELECT b FROM t WHERE a = 1");
+    query.set_consistency(Consistency::Three);
+    query.set_retry_policy(Box::new(policy.clone()));
+    let mut prepared = session.prepare(query.clone()).await.unwrap();
+    prepared.set_consistency(Consistency::Three);
+    prepared.set_retry_policy(Box::new(policy.clone()));
+
+    let assert_attempts = |statement_kind: StatementKind| {
+        let attempts: Vec<RecordedAttempt> = policy.attempts.lock().unwrap().drain(..).collect();
+        assert_eq!(attempts.len(), 2);
+        for (idx, attempt) in attempts.into_iter().enumerate() {
+            let node_is_sharded = attempt.node_is_sharded.expect("node should be known");
+            // Only sharded nodes report the shard
+            assert_eq!(attempt.shard.is_some(), node_is_sharded);
+            assert_eq!(attempt.attempt, idx + 1);
+            assert_eq!(attempt.statement_kind, statement_kind);
+            assert_eq!(attempt.request_timeout, Some(Duration::from_secs(20)));
+        }
+    };
+
+    session.query(query.clone(), &[]).await.unwrap_err();
+    assert_attempts(StatementKind::Query);
+
+    session.execute(&prepared, &[]).await.unwrap_err();
+    assert_attempts(StatementKind::Prepared);
+
+    // Paged queries report the same information when fetching a page fails
+    assert!(session.query_iter(query, &[]).await.is_err());
+    assert_attempts(StatementKind::Query);
+
+    assert!(session.execute_iter(prepared, &[]).await.is_err());
+    assert_attempts(StatementKind::Prepared);
+}
+
+#[tokio::test]
+async fn test_tracing_sampling_iter() {
+    use crate::statement::StatementKind;
+    use crate::tracing::{CollectedTrace, ProbabilisticTracingSampling, TraceSink};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[derive(Debug, Default)]
+    struct CollectingSink {
+        traces: Mutex<Vec<(Uuid, StatementKind)>>,
+    }
+
+    impl TraceSink for CollectingSink {
+        fn on_trace(&self, trace: CollectedTrace) {
+            self.traces
+                .lock()
+                .unwrap()
+                .push((trace.tracing_id, trace.statement_kind));
+        }
+    }
+
+    async fn wait_for_traces(sink: &CollectingSink, expected: &[Uuid]) {
+        for _ in 0..100 {
+            let collected: BTreeSet<Uuid> =
+                sink.traces.lock().unwrap().iter().map(|t| t.0).collect();
+            if expected.iter().all(|id| collected.contains(id)) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("Traces of all pages were not collected");
+    }
+
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let sink = Arc::new(CollectingSink::default());
+    let session = SessionBuilder::new()
+        .known_node(uri)
+        .tracing_sampling(
+            Arc::new(ProbabilisticTracingSampling { probability: 1.0 }),
+            sink.clone(),
+        )
+        .build()
+        .await
+        .unwrap();
+    let ks = unique_keyspace_name();
+
+    session.query(format!("CREATE KEYSPACE IF NOT EXISTS {} WITH REPLICATION = {{'class' : 'SimpleStrategy', 'replication_factor' : 1}}", ks), &[]).await.unwrap();
+    session
+        .query(
+            format!("CREATE TABLE IF NOT EXISTS {}.t (a int primary key)", ks),
+            &[],
+        )
+        .await
+        .unwrap();
+    for i in 0..10_i32 {
+        session
+            .query(format!("INSERT INTO {}.t (a) VALUES (?)", ks), (i,))
+            .await
+            .unwrap();
+    }
+    sink.traces.lock().unwrap().clear();
+
+    // Every page of a sampled query_iter is traced and collected
+    let mut query = Query::new(format!("SELECT a FROM {}.t", ks));
+    query.set_page_size(3);
+    let mut rows = session.query_iter(query, &[]).await.unwrap();
+    while let Some(row) = rows.next().await {
+        row.unwrap();
+    }
+    let tracing_ids = rows.get_tracing_ids().to_vec();
+    assert!(tracing_ids.len() > 1);
+    wait_for_traces(&sink, &tracing_ids).await;
+    assert!(sink
+        .traces
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|t| tracing_ids.contains(&t.0))
+        .all(|t| t.1 == StatementKind::Query));
+
+    // The same goes for execute_iter
+    let mut prepared = session
+        .prepare(format!("SELECT a FROM {}.t", ks))
+        .await
+        .unwrap();
+    prepared.set_page_size(3);
+    let mut rows = session.execute_iter(prepared, &[]).await.unwrap();
+    while let Some(row) = rows.next().await {
+        row.unwrap();
+    }
+    let tracing_ids = rows.get_tracing_ids().to_vec();
+    assert!(tracing_ids.len() > 1);
+    wait_for_traces(&sink, &tracing_ids).await;
+    assert!(sink
+        .traces
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|t| tracing_ids.contains(&t.0))
+        .all(|t| t.1 == StatementKind::Prepared));
+}
+
+#[tokio::test]
+async fn test_prepared_statement_registry() {
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let session = SessionBuilder::new().known_node(uri).build().await.unwrap();
+    let ks = unique_keyspace_name();
+    let other_ks = unique_keyspace_name();
+
+    for keyspace in [&ks, &other_ks] {
+        session.query(format!("CREATE KEYSPACE IF NOT EXISTS {} WITH REPLICATION = {{'class' : 'SimpleStrategy', 'replication_factor' : 1}}", keyspace), &[]).await.unwrap();
+        session
+            .query(
+                format!("CREATE TABLE {}.t (a int primary key, b int)", keyspace),
+                &[],
+            )
+            .await
+            .unwrap();
+    }
+    session.use_keyspace(&ks, false).await.unwrap();
+
+    let registry = session.cluster.get_prepared_statement_registry().unwrap();
+    let select_a = session.prepare("SELECT a FROM t").await.unwrap();
+    let select_b = session.prepare("SELECT b FROM t").await.unwrap();
+    let mut to_prepare = registry.statements_to_prepare();
+    to_prepare.sort();
+    assert_eq!(to_prepare, vec!["SELECT a FROM t", "SELECT b FROM t"]);
+
+    // Dropped statements don't need to be prepared on nodes which come back up
+    drop(select_b);
+    assert_eq!(registry.statements_to_prepare(), vec!["SELECT a FROM t"]);
+
+    // Statements are prepared only in the keyspace they were prepared in
+    session.use_keyspace(&other_ks, false).await.unwrap();
+    assert!(registry.statements_to_prepare().is_empty());
+    let _select_a_other = session.prepare("SELECT a FROM t").await.unwrap();
+    assert_eq!(registry.statements_to_prepare(), vec!["SELECT a FROM t"]);
+    assert_eq!(registry.len(), 2);
+
+    drop(select_a);
+}
@@ -3,13 +3,20 @@ use futures::{
     stream::{FuturesUnordered, StreamExt},
 };
 use std::{future::Future, sync::Arc, time::Duration};
-use tracing::{trace_span, warn, Instrument};
+use tracing::{info_span, warn, Instrument};
 
-use super::{errors::QueryError, metrics::Metrics};
+use super::{errors::QueryError, load_balancing::Statement, metrics::Metrics, node::Node};
+use crate::statement::StatementKind;
 
 /// Context is passed as an argument to `SpeculativeExecutionPolicy` methods
-pub struct Context {
+pub struct Context<'a> {
     pub metrics: Arc<Metrics>,
+    /// Kind of the executed statement
+    pub statement_kind: StatementKind,
+    /// Routing information about the executed statement, including its token
+    pub statement: &'a Statement<'a>,
+    /// Node to which the original (non-speculative) execution is sent, if known
+    pub node: Option<&'a Node>,
 }
 
 /// The policy that decides if the driver will send speculative queries to the
@@ -48,6 +55,24 @@ pub struct PercentileSpeculativeExecutionPolicy {
     pub percentile: f64,
 }
 
+/// A policy that triggers speculative executions when the request to the target
+/// node takes longer than a given percentile of that node's recent latencies.\
+/// Unlike [`PercentileSpeculativeExecutionPolicy`], which uses latencies of all requests
+/// since the session was created, each node is compared only against itself.
+#[derive(Debug, Clone)]
+pub struct NodeLatencySpeculativeExecutionPolicy {
+    /// The maximum number of speculative executions that will be triggered
+    /// for a given request (does not include the initial request)
+    pub max_retry_count: usize,
+
+    /// The percentile of the node's recent latencies that a request's latency
+    /// must exceed to be considered slow (ex: 99.0)
+    pub percentile: f64,
+
+    /// The delay used when there are no recent latency measurements for the target node
+    pub fallback_interval: Duration,
+}
+
 impl SpeculativeExecutionPolicy for SimpleSpeculativeExecutionPolicy {
     fn max_retry_count(&self, _: &Context) -> usize {
         self.max_retry_count
@@ -79,6 +104,19 @@ impl SpeculativeExecutionPolicy for PercentileSpeculativeExecutionPolicy {
     }
 }
 
+impl SpeculativeExecutionPolicy for NodeLatencySpeculativeExecutionPolicy {
+    fn max_retry_count(&self, _: &Context) -> usize {
+        self.max_retry_count
+    }
+
+    fn retry_interval(&self, context: &Context) -> Duration {
+        context
+            .node
+            .and_then(|node| node.get_latency_percentile(self.percentile))
+            .unwrap_or(self.fallback_interval)
+    }
+}
+
 // checks if a result created in a speculative execution branch can be ignored
 fn can_be_ignored<ResT>(result: &Result<ResT, QueryError>) -> bool {
     match result {
@@ -93,7 +131,7 @@ const EMPTY_PLAN_ERROR: QueryError = QueryError::ProtocolError("Empty query plan
 
 pub async fn execute<QueryFut, ResT>(
     policy: &dyn SpeculativeExecutionPolicy,
-    context: &Context,
+    context: &Context<'_>,
     query_runner_generator: impl Fn(bool) -> QueryFut,
 ) -> Result<ResT, QueryError>
 where
@@ -102,11 +140,24 @@ where
     let mut retries_remaining = policy.max_retry_count(context);
     let retry_interval = policy.retry_interval(context);
 
+    // Each fiber's result is tagged with whether it comes from a speculative execution
+    let run_fiber = |is_speculative: bool, span: tracing::Span| {
+        query_runner_generator(is_speculative)
+            .instrument(span)
+            .map(move |res| (res, is_speculative))
+    };
+
     let mut async_tasks = FuturesUnordered::new();
-    async_tasks.push(
-        query_runner_generator(false)
-            .instrument(trace_span!("Speculative execution: original query")),
-    );
+    async_tasks.push(run_fiber(
+        false,
+        info_span!(
+            "Speculative execution: original query",
+            scylla.speculative = false
+        ),
+    ));
+    // Speculative executions are counted in the span of the whole request
+    let request_span = tracing::Span::current();
+    let mut speculative_executions: usize = 0;
 
     let sleep = tokio::time::sleep(retry_interval).fuse();
     tokio::pin!(sleep);
@@ -116,17 +167,27 @@ where
         futures::select! {
             _ = &mut sleep => {
                 if retries_remaining > 0 {
-                    async_tasks.push(query_runner_generator(true).instrument(trace_span!("Speculative execution", retries_remaining = retries_remaining)));
+                    async_tasks.push(run_fiber(true, info_span!(
+                        "Speculative execution",
+                        scylla.speculative = true,
+                        retries_remaining = retries_remaining
+                    )));
+                    context.metrics.inc_speculative_executions_num();
+                    speculative_executions += 1;
+                    request_span.record("db.cassandra.speculative_execution_count", speculative_executions);
                     retries_remaining -= 1;
 
                     // reset the timeout
                     sleep.set(tokio::time::sleep(retry_interval).fuse());
                 }
             }
-            res = async_tasks.select_next_some() => {
+            (res, is_speculative) = async_tasks.select_next_some() => {
                 match res {
                     Some(r) => {
                         if !can_be_ignored(&r) {
+                            if is_speculative {
+                                context.metrics.inc_speculative_wins_num();
+                            }
                             return r;
                         } else {
                             last_error = Some(r)
@@ -144,3 +205,40 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::load_balancing::Statement;
+
+    #[tokio::test]
+    async fn speculative_win_is_counted() {
+        let policy = SimpleSpeculativeExecutionPolicy {
+            max_retry_count: 1,
+            retry_interval: Duration::from_millis(10),
+        };
+        let context = Context {
+            metrics: Arc::new(Metrics::new()),
+            statement_kind: StatementKind::Query,
+            statement: &Statement {
+                token: None,
+                keyspace: None,
+                is_confirmed_lwt: false,
+            },
+            node: None,
+        };
+
+        // The original execution hangs, so the speculative one has to win
+        let result = execute(&policy, &context, |is_speculative| async move {
+            if !is_speculative {
+                futures::future::pending::<()>().await;
+            }
+            Some(Ok::<bool, QueryError>(is_speculative))
+        })
+        .await;
+
+        assert!(result.unwrap());
+        assert_eq!(context.metrics.get_speculative_executions_num(), 1);
+        assert_eq!(context.metrics.get_speculative_wins_num(), 1);
+    }
+}
@@ -0,0 +1,715 @@
+//! TLS configuration independent of the TLS backend.
+//!
+//! Connections can be encrypted using OpenSSL (the `ssl` feature)
+//! or rustls (the `rustls` feature). A [`TlsConfig`] describes the root certificates,
+//! the client certificate and the server names independently of the backend,
+//! and is built into a [`TlsContext`] used by the session.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+
+#[cfg(feature = "ssl")]
+use openssl::{
+    pkey::PKey,
+    ssl::{Ssl, SslContext, SslContextBuilder, SslMethod, SslVerifyMode},
+    x509::X509,
+};
+#[cfg(feature = "ssl")]
+use std::net::IpAddr;
+#[cfg(feature = "ssl")]
+use tokio_openssl::SslStream;
+#[cfg(feature = "rustls")]
+use tokio_rustls::rustls;
+
+/// Backend used to encrypt the connections
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsBackend {
+    /// OpenSSL, enabled by the `ssl` feature
+    #[cfg(feature = "ssl")]
+    OpenSsl,
+    /// rustls, enabled by the `rustls` feature.\
+    /// rustls can't verify certificates issued for IP addresses,
+    /// server names have to be DNS names.
+    #[cfg(feature = "rustls")]
+    Rustls,
+}
+
+/// Name of the server, sent in the SNI extension and verified against its certificate
+#[derive(Clone)]
+pub enum ServerName {
+    /// No name is sent, the certificate chain is verified without checking the name
+    None,
+    /// All nodes use the same name
+    Fixed(String),
+    /// Name of the node with the given address, or no name if `None` is returned
+    PerNode(Arc<dyn Fn(SocketAddr) -> Option<String> + Send + Sync>),
+}
+
+impl ServerName {
+    fn for_node(&self, address: SocketAddr) -> Option<String> {
+        match self {
+            ServerName::None => None,
+            ServerName::Fixed(name) => Some(name.clone()),
+            ServerName::PerNode(resolve) => resolve(address),
+        }
+    }
+}
+
+impl fmt::Debug for ServerName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerName::None => write!(f, "None"),
+            ServerName::Fixed(name) => f.debug_tuple("Fixed").field(name).finish(),
+            ServerName::PerNode(_) => write!(f, "PerNode"),
+        }
+    }
+}
+
+/// Error returned when a [`TlsConfig`] can't be built into a [`TlsContext`]
+#[derive(Error, Debug)]
+pub enum TlsError {
+    #[error("No certificates found in PEM data")]
+    NoCertificates,
+    #[error("No private key found in PEM data")]
+    NoPrivateKey,
+    #[error("Invalid PEM data: {0}")]
+    InvalidPem(io::Error),
+    #[cfg(feature = "ssl")]
+    #[error("OpenSSL error: {0}")]
+    OpenSsl(#[from] openssl::error::ErrorStack),
+    #[cfg(feature = "rustls")]
+    #[error("rustls error: {0}")]
+    Rustls(#[from] rustls::Error),
+    #[cfg(feature = "rustls")]
+    #[error("Invalid root certificate: {0}")]
+    InvalidRootCertificate(webpki::Error),
+}
+
+/// Client certificate presented to the database
+#[derive(Clone)]
+struct ClientIdentity {
+    certificate_chain_pem: Vec<u8>,
+    private_key_pem: Vec<u8>,
+}
+
+/// TLS configuration independent of the backend.
+///
+/// # Example
+/// ```rust
+/// # use scylla::transport::tls::{ServerName, TlsBackend, TlsConfig};
+/// # use scylla::{Session, SessionBuilder};
+/// # #[cfg(feature = "rustls")]
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let tls_context = TlsConfig::new()
+///     .add_root_certificates_pem(std::fs::read("ca.crt")?)
+///     .client_identity_pem(std::fs::read("client.crt")?, std::fs::read("client.key")?)
+///     .server_name(ServerName::Fixed("scylla.example.com".to_string()))
+///     .build(TlsBackend::Rustls)?;
+///
+/// let session: Session = SessionBuilder::new()
+///     .known_node("127.0.0.1:9142")
+///     .tls_context(Some(tls_context))
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct TlsConfig {
+    root_certificates_pem: Vec<Vec<u8>>,
+    client_identity: Option<ClientIdentity>,
+    verify_certificates: bool,
+    server_name: ServerName,
+}
+
+impl TlsConfig {
+    /// Creates a configuration verifying server certificates against no root certificates,
+    /// which have to be added with [`add_root_certificates_pem`](TlsConfig::add_root_certificates_pem)
+    pub fn new() -> Self {
+        Self {
+            root_certificates_pem: Vec::new(),
+            client_identity: None,
+            verify_certificates: true,
+            server_name: ServerName::None,
+        }
+    }
+
+    /// Adds root certificates trusted when verifying server certificates.
+    /// The PEM data can contain multiple certificates.
+    pub fn add_root_certificates_pem(mut self, certificates_pem: impl Into<Vec<u8>>) -> Self {
+        self.root_certificates_pem.push(certificates_pem.into());
+        self
+    }
+
+    /// Sets the certificate presented to the database for client authentication.
+    /// The certificate chain starts with the client certificate, the private key
+    /// can be a PKCS#8, RSA or EC key.
+    pub fn client_identity_pem(
+        mut self,
+        certificate_chain_pem: impl Into<Vec<u8>>,
+        private_key_pem: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.client_identity = Some(ClientIdentity {
+            certificate_chain_pem: certificate_chain_pem.into(),
+            private_key_pem: private_key_pem.into(),
+        });
+        self
+    }
+
+    /// Sets whether server certificates are verified.
+    /// Disabling the verification makes connections vulnerable to man-in-the-middle attacks.\
+    /// Default is true.
+    pub fn verify_certificates(mut self, verify_certificates: bool) -> Self {
+        self.verify_certificates = verify_certificates;
+        self
+    }
+
+    /// Sets the name of the server sent in the SNI extension and verified against its certificate.\
+    /// Default is [`ServerName::None`].
+    pub fn server_name(mut self, server_name: ServerName) -> Self {
+        self.server_name = server_name;
+        self
+    }
+
+    /// Builds the context used to encrypt the connections with the given backend
+    pub fn build(&self, backend: TlsBackend) -> Result<TlsContext, TlsError> {
+        let backend = match backend {
+            #[cfg(feature = "ssl")]
+            TlsBackend::OpenSsl => Backend::OpenSsl(self.build_openssl()?),
+            #[cfg(feature = "rustls")]
+            TlsBackend::Rustls => Backend::Rustls(Arc::new(self.build_rustls()?)),
+        };
+
+        Ok(TlsContext {
+            backend,
+            server_name: self.server_name.clone(),
+        })
+    }
+
+    #[cfg(feature = "ssl")]
+    fn build_openssl(&self) -> Result<SslContext, TlsError> {
+        let mut builder = SslContextBuilder::new(SslMethod::tls_client())?;
+
+        for certificates_pem in &self.root_certificates_pem {
+            let certificates = X509::stack_from_pem(certificates_pem)?;
+            if certificates.is_empty() {
+                return Err(TlsError::NoCertificates);
+            }
+            for certificate in certificates {
+                builder.cert_store_mut().add_cert(certificate)?;
+            }
+        }
+
+        if let Some(identity) = &self.client_identity {
+            let mut chain = X509::stack_from_pem(&identity.certificate_chain_pem)?.into_iter();
+            let certificate = chain.next().ok_or(TlsError::NoCertificates)?;
+            builder.set_certificate(&certificate)?;
+            for intermediate in chain {
+                builder.add_extra_chain_cert(intermediate)?;
+            }
+            let private_key = PKey::private_key_from_pem(&identity.private_key_pem)?;
+            builder.set_private_key(&private_key)?;
+            builder.check_private_key()?;
+        }
+
+        builder.set_verify(if self.verify_certificates {
+            SslVerifyMode::PEER
+        } else {
+            SslVerifyMode::NONE
+        });
+
+        Ok(builder.build())
+    }
+
+    #[cfg(feature = "rustls")]
+    fn build_rustls(&self) -> Result<rustls::ClientConfig, TlsError> {
+        let mut root_certificates = Vec::new();
+        for certificates_pem in &self.root_certificates_pem {
+            let certificates = rustls_pemfile::certs(&mut certificates_pem.as_slice())
+                .map_err(TlsError::InvalidPem)?;
+            if certificates.is_empty() {
+                return Err(TlsError::NoCertificates);
+            }
+            root_certificates.extend(certificates);
+        }
+
+        let verifier =
+            rustls_verifier::CertificateVerifier::new(root_certificates, self.verify_certificates)?;
+        let builder = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(verifier));
+
+        let config = match &self.client_identity {
+            Some(identity) => {
+                let chain = rustls_pemfile::certs(&mut identity.certificate_chain_pem.as_slice())
+                    .map_err(TlsError::InvalidPem)?;
+                if chain.is_empty() {
+                    return Err(TlsError::NoCertificates);
+                }
+                let key = read_rustls_private_key(&identity.private_key_pem)?;
+                builder
+                    .with_single_cert(chain.into_iter().map(rustls::Certificate).collect(), key)?
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        Ok(config)
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "rustls")]
+fn read_rustls_private_key(private_key_pem: &[u8]) -> Result<rustls::PrivateKey, TlsError> {
+    use rustls_pemfile::Item;
+
+    let items = rustls_pemfile::read_all(&mut &*private_key_pem).map_err(TlsError::InvalidPem)?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => {
+                Some(rustls::PrivateKey(key))
+            }
+            _ => None,
+        })
+        .ok_or(TlsError::NoPrivateKey)
+}
+
+#[cfg(feature = "rustls")]
+mod rustls_verifier {
+    use super::TlsError;
+    use std::time::SystemTime;
+    use tokio_rustls::rustls::client::{
+        ServerCertVerified, ServerCertVerifier, ServerName, WebPkiVerifier,
+    };
+    use tokio_rustls::rustls::{Certificate, Error, RootCertStore};
+
+    // The same algorithms as supported by rustls
+    static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+        &webpki::ECDSA_P256_SHA256,
+        &webpki::ECDSA_P256_SHA384,
+        &webpki::ECDSA_P384_SHA256,
+        &webpki::ECDSA_P384_SHA384,
+        &webpki::ED25519,
+        &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+        &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+        &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+        &webpki::RSA_PKCS1_2048_8192_SHA256,
+        &webpki::RSA_PKCS1_2048_8192_SHA384,
+        &webpki::RSA_PKCS1_2048_8192_SHA512,
+        &webpki::RSA_PKCS1_3072_8192_SHA384,
+    ];
+
+    // Verifies the certificate chain, and the name of the server if it was given.
+    // Without a server name the driver connects using the IP address of the node,
+    // which rustls can't verify, so only the chain is verified then.
+    pub(super) struct CertificateVerifier {
+        trust_anchors: Vec<OwnedTrustAnchor>,
+        webpki_verifier: WebPkiVerifier,
+        verify_certificates: bool,
+    }
+
+    // Trust anchor parsed from a root certificate once, when the verifier is created
+    struct OwnedTrustAnchor {
+        subject: Vec<u8>,
+        spki: Vec<u8>,
+        name_constraints: Option<Vec<u8>>,
+    }
+
+    impl OwnedTrustAnchor {
+        fn from_cert_der(certificate: &[u8]) -> Result<Self, webpki::Error> {
+            let trust_anchor = webpki::TrustAnchor::try_from_cert_der(certificate)?;
+            Ok(Self {
+                subject: trust_anchor.subject.to_vec(),
+                spki: trust_anchor.spki.to_vec(),
+                name_constraints: trust_anchor.name_constraints.map(<[u8]>::to_vec),
+            })
+        }
+
+        fn as_trust_anchor(&self) -> webpki::TrustAnchor<'_> {
+            webpki::TrustAnchor {
+                subject: &self.subject,
+                spki: &self.spki,
+                name_constraints: self.name_constraints.as_deref(),
+            }
+        }
+    }
+
+    impl CertificateVerifier {
+        pub(super) fn new(
+            root_certificates: Vec<Vec<u8>>,
+            verify_certificates: bool,
+        ) -> Result<Self, TlsError> {
+            let mut root_store = RootCertStore::empty();
+            let mut trust_anchors = Vec::with_capacity(root_certificates.len());
+            for certificate in root_certificates {
+                trust_anchors.push(
+                    OwnedTrustAnchor::from_cert_der(&certificate)
+                        .map_err(TlsError::InvalidRootCertificate)?,
+                );
+                root_store
+                    .add(&Certificate(certificate))
+                    .map_err(TlsError::InvalidRootCertificate)?;
+            }
+
+            Ok(Self {
+                trust_anchors,
+                webpki_verifier: WebPkiVerifier::new(root_store, None),
+                verify_certificates,
+            })
+        }
+
+        fn verify_chain(
+            &self,
+            end_entity: &Certificate,
+            intermediates: &[Certificate],
+            now: SystemTime,
+        ) -> Result<(), Error> {
+            let certificate_error =
+                |err: webpki::Error| Error::InvalidCertificateData(err.to_string());
+
+            let trust_anchors: Vec<webpki::TrustAnchor> = self
+                .trust_anchors
+                .iter()
+                .map(OwnedTrustAnchor::as_trust_anchor)
+                .collect();
+            let intermediates: Vec<&[u8]> = intermediates
+                .iter()
+                .map(|certificate| certificate.0.as_slice())
+                .collect();
+            let now = webpki::Time::try_from(now).map_err(|_| Error::FailedToGetCurrentTime)?;
+
+            webpki::EndEntityCert::try_from(end_entity.0.as_slice())
+                .and_then(|certificate| {
+                    certificate.verify_is_valid_tls_server_cert(
+                        SUPPORTED_SIG_ALGS,
+                        &webpki::TlsServerTrustAnchors(&trust_anchors),
+                        &intermediates,
+                        now,
+                    )
+                })
+                .map_err(certificate_error)
+        }
+    }
+
+    impl ServerCertVerifier for CertificateVerifier {
+        fn verify_server_cert(
+            &self,
+            end_entity: &Certificate,
+            intermediates: &[Certificate],
+            server_name: &ServerName,
+            scts: &mut dyn Iterator<Item = &[u8]>,
+            ocsp_response: &[u8],
+            now: SystemTime,
+        ) -> Result<ServerCertVerified, Error> {
+            if !self.verify_certificates {
+                return Ok(ServerCertVerified::assertion());
+            }
+
+            match server_name {
+                ServerName::DnsName(_) => self.webpki_verifier.verify_server_cert(
+                    end_entity,
+                    intermediates,
+                    server_name,
+                    scts,
+                    ocsp_response,
+                    now,
+                ),
+                _ => self
+                    .verify_chain(end_entity, intermediates, now)
+                    .map(|_| ServerCertVerified::assertion()),
+            }
+        }
+    }
+}
+
+/// Context used to encrypt the connections, created from a [`TlsConfig`]
+/// or from a context of the backend.
+#[derive(Clone)]
+pub struct TlsContext {
+    backend: Backend,
+    server_name: ServerName,
+}
+
+#[derive(Clone)]
+enum Backend {
+    #[cfg(feature = "ssl")]
+    OpenSsl(SslContext),
+    #[cfg(feature = "rustls")]
+    Rustls(Arc<rustls::ClientConfig>),
+}
+
+impl TlsContext {
+    /// Sets the name of the server sent in the SNI extension and verified against its certificate
+    pub fn with_server_name(mut self, server_name: ServerName) -> Self {
+        self.server_name = server_name;
+        self
+    }
+
+    /// Returns the backend used to encrypt the connections
+    pub fn get_backend(&self) -> TlsBackend {
+        match &self.backend {
+            #[cfg(feature = "ssl")]
+            Backend::OpenSsl(_) => TlsBackend::OpenSsl,
+            #[cfg(feature = "rustls")]
+            Backend::Rustls(_) => TlsBackend::Rustls,
+        }
+    }
+
+    /// Returns the name of the server
+    pub fn get_server_name(&self) -> &ServerName {
+        &self.server_name
+    }
+
+    /// Returns the OpenSSL context, if the context uses OpenSSL
+    #[cfg(feature = "ssl")]
+    pub fn get_ssl_context(&self) -> Option<&SslContext> {
+        match &self.backend {
+            Backend::OpenSsl(context) => Some(context),
+            #[cfg(feature = "rustls")]
+            Backend::Rustls(_) => None,
+        }
+    }
+
+    /// Encrypts the connection to the node with the given address
+    pub(crate) async fn connect(
+        &self,
+        address: SocketAddr,
+        stream: TcpStream,
+    ) -> Result<TlsStream, io::Error> {
+        self.connect_with_server_name(address, self.server_name.for_node(address), stream)
+            .await
+    }
+
+    /// Encrypts the connection using the given server name instead of the configured one
+    // The address is only used by rustls, to verify nodes without a server name
+    #[cfg_attr(not(feature = "rustls"), allow(unused_variables))]
+    pub(crate) async fn connect_with_server_name(
+        &self,
+        address: SocketAddr,
+        server_name: Option<String>,
+        stream: TcpStream,
+    ) -> Result<TlsStream, io::Error> {
+        match &self.backend {
+            #[cfg(feature = "ssl")]
+            Backend::OpenSsl(context) => {
+                let mut ssl = Ssl::new(context).map_err(io_error)?;
+                if let Some(name) = &server_name {
+                    match name.parse::<IpAddr>() {
+                        Ok(ip) => ssl.param_mut().set_ip(ip).map_err(io_error)?,
+                        Err(_) => {
+                            ssl.set_hostname(name).map_err(io_error)?;
+                            ssl.param_mut().set_host(name).map_err(io_error)?;
+                        }
+                    }
+                }
+
+                let mut stream = SslStream::new(ssl, stream).map_err(io_error)?;
+                Pin::new(&mut stream).connect().await.map_err(io_error)?;
+                Ok(TlsStream::OpenSsl(stream))
+            }
+            #[cfg(feature = "rustls")]
+            Backend::Rustls(config) => {
+                let server_name = match &server_name {
+                    Some(name) => match rustls::ServerName::try_from(name.as_str()) {
+                        Ok(rustls::ServerName::DnsName(dns_name)) => {
+                            rustls::ServerName::DnsName(dns_name)
+                        }
+                        _ => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                format!(
+                                "Invalid server name for rustls: {}, only DNS names are supported",
+                                name
+                            ),
+                            ))
+                        }
+                    },
+                    // Connecting using an IP address doesn't send the SNI extension
+                    None => rustls::ServerName::IpAddress(address.ip()),
+                };
+
+                let stream = tokio_rustls::TlsConnector::from(config.clone())
+                    .connect(server_name, stream)
+                    .await?;
+                Ok(TlsStream::Rustls(Box::new(stream)))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for TlsContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsContext")
+            .field("backend", &self.get_backend())
+            .field("server_name", &self.server_name)
+            .finish()
+    }
+}
+
+#[cfg(feature = "ssl")]
+impl From<SslContext> for TlsContext {
+    /// Uses the OpenSSL context without a server name
+    fn from(context: SslContext) -> Self {
+        Self {
+            backend: Backend::OpenSsl(context),
+            server_name: ServerName::None,
+        }
+    }
+}
+
+#[cfg(feature = "rustls")]
+impl From<Arc<rustls::ClientConfig>> for TlsContext {
+    /// Uses the rustls configuration without a server name.\
+    /// Connections without a server name use IP addresses of the nodes, which the default rustls
+    /// certificate verifier rejects, so a server name has to be set with
+    /// [`with_server_name`](TlsContext::with_server_name), unless the configuration
+    /// uses a custom verifier.
+    fn from(config: Arc<rustls::ClientConfig>) -> Self {
+        Self {
+            backend: Backend::Rustls(config),
+            server_name: ServerName::None,
+        }
+    }
+}
+
+#[cfg(feature = "ssl")]
+fn io_error(err: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+/// Connection encrypted by one of the backends
+pub(crate) enum TlsStream {
+    #[cfg(feature = "ssl")]
+    OpenSsl(SslStream<TcpStream>),
+    #[cfg(feature = "rustls")]
+    Rustls(Box<tokio_rustls::client::TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for TlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            #[cfg(feature = "ssl")]
+            TlsStream::OpenSsl(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "rustls")]
+            TlsStream::Rustls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for TlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            #[cfg(feature = "ssl")]
+            TlsStream::OpenSsl(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "rustls")]
+            TlsStream::Rustls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            #[cfg(feature = "ssl")]
+            TlsStream::OpenSsl(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "rustls")]
+            TlsStream::Rustls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            #[cfg(feature = "ssl")]
+            TlsStream::OpenSsl(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "rustls")]
+            TlsStream::Rustls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ServerName, TlsBackend, TlsConfig};
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    const CA_CERTIFICATE: &[u8] = include_bytes!("../../../test/tls/ca.crt");
+    const CLIENT_CERTIFICATE: &[u8] = include_bytes!("../../../test/tls/client.crt");
+    const CLIENT_KEY: &[u8] = include_bytes!("../../../test/tls/client.key");
+
+    fn backends() -> Vec<TlsBackend> {
+        vec![
+            #[cfg(feature = "ssl")]
+            TlsBackend::OpenSsl,
+            #[cfg(feature = "rustls")]
+            TlsBackend::Rustls,
+        ]
+    }
+
+    #[test]
+    fn build_with_all_backends() {
+        let config = TlsConfig::new()
+            .add_root_certificates_pem(CA_CERTIFICATE)
+            .client_identity_pem(CLIENT_CERTIFICATE, CLIENT_KEY)
+            .server_name(ServerName::Fixed("scylla.test".to_string()));
+
+        for backend in backends() {
+            let context = config.build(backend).unwrap();
+            assert_eq!(context.get_backend(), backend);
+            assert!(
+                matches!(context.get_server_name(), ServerName::Fixed(name) if name == "scylla.test")
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_pem_is_rejected() {
+        for backend in backends() {
+            let no_roots = TlsConfig::new().add_root_certificates_pem(&b"not a certificate"[..]);
+            assert!(no_roots.build(backend).is_err());
+
+            let no_key = TlsConfig::new().client_identity_pem(CLIENT_CERTIFICATE, CA_CERTIFICATE);
+            assert!(no_key.build(backend).is_err());
+        }
+
+        #[cfg(feature = "rustls")]
+        assert!(matches!(
+            TlsConfig::new()
+                .client_identity_pem(CLIENT_CERTIFICATE, CA_CERTIFICATE)
+                .build(TlsBackend::Rustls),
+            Err(super::TlsError::NoPrivateKey)
+        ));
+    }
+
+    #[test]
+    fn per_node_server_names() {
+        let server_name = ServerName::PerNode(Arc::new(|address: SocketAddr| {
+            Some(format!("node-{}.scylla.test", address.port()))
+        }));
+
+        let address: SocketAddr = "127.0.0.1:9142".parse().unwrap();
+        assert_eq!(
+            server_name.for_node(address),
+            Some("node-9142.scylla.test".to_string())
+        );
+        assert_eq!(ServerName::None.for_node(address), None);
+    }
+}
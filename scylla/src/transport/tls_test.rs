@@ -0,0 +1,110 @@
+use crate::transport::tls::{ServerName, TlsBackend, TlsConfig};
+use crate::{Session, SessionBuilder};
+
+// These tests require a node with client encryption enabled using the certificates from test/tls,
+// see .github/workflows/tls.yml. They are skipped unless SCYLLA_TLS_URI points to such a node.
+
+const CA_CERTIFICATE: &[u8] = include_bytes!("../../../test/tls/ca.crt");
+const CLIENT_CERTIFICATE: &[u8] = include_bytes!("../../../test/tls/client.crt");
+const CLIENT_KEY: &[u8] = include_bytes!("../../../test/tls/client.key");
+
+fn backends() -> Vec<TlsBackend> {
+    vec![
+        #[cfg(feature = "ssl")]
+        TlsBackend::OpenSsl,
+        #[cfg(feature = "rustls")]
+        TlsBackend::Rustls,
+    ]
+}
+
+fn tls_uri() -> Option<String> {
+    let uri = std::env::var("SCYLLA_TLS_URI").ok();
+    if uri.is_none() {
+        tracing::info!("SCYLLA_TLS_URI is not set, skipping TLS test");
+    }
+    uri
+}
+
+async fn connect(uri: &str, config: TlsConfig, backend: TlsBackend) -> Result<Session, String> {
+    let tls_context = config.build(backend).map_err(|err| err.to_string())?;
+
+    let session = SessionBuilder::new()
+        .known_node(uri)
+        .tls_context(Some(tls_context))
+        .build()
+        .await
+        .map_err(|err| err.to_string())?;
+    session
+        .query("SELECT host_id FROM system.local", &[])
+        .await
+        .map_err(|err| err.to_string())?;
+
+    Ok(session)
+}
+
+#[tokio::test]
+async fn tls_server_name_verification() {
+    let uri = match tls_uri() {
+        Some(uri) => uri,
+        None => return,
+    };
+    for backend in backends() {
+        tracing::info!("Connecting using {:?} backend ...", backend);
+
+        let config = TlsConfig::new()
+            .add_root_certificates_pem(CA_CERTIFICATE)
+            .server_name(ServerName::Fixed("scylla.test".to_string()));
+        connect(&uri, config, backend).await.unwrap();
+
+        let config = TlsConfig::new()
+            .add_root_certificates_pem(CA_CERTIFICATE)
+            .server_name(ServerName::Fixed("wrong-name.test".to_string()));
+        assert!(connect(&uri, config, backend).await.is_err());
+
+        let config = TlsConfig::new()
+            .add_root_certificates_pem(CA_CERTIFICATE)
+            .server_name(ServerName::PerNode(std::sync::Arc::new(|_| {
+                Some("localhost".to_string())
+            })));
+        connect(&uri, config, backend).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn tls_certificate_chain_verification() {
+    let uri = match tls_uri() {
+        Some(uri) => uri,
+        None => return,
+    };
+    for backend in backends() {
+        tracing::info!("Connecting using {:?} backend ...", backend);
+
+        // Without a server name only the certificate chain is verified
+        let config = TlsConfig::new().add_root_certificates_pem(CA_CERTIFICATE);
+        connect(&uri, config, backend).await.unwrap();
+
+        // The node certificate isn't trusted without the root certificate
+        let config = TlsConfig::new();
+        assert!(connect(&uri, config, backend).await.is_err());
+
+        let config = TlsConfig::new().verify_certificates(false);
+        connect(&uri, config, backend).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn tls_client_certificate() {
+    let uri = match tls_uri() {
+        Some(uri) => uri,
+        None => return,
+    };
+    for backend in backends() {
+        tracing::info!("Connecting using {:?} backend ...", backend);
+
+        let config = TlsConfig::new()
+            .add_root_certificates_pem(CA_CERTIFICATE)
+            .client_identity_pem(CLIENT_CERTIFICATE, CLIENT_KEY)
+            .server_name(ServerName::Fixed("scylla.test".to_string()));
+        connect(&uri, config, backend).await.unwrap();
+    }
+}
@@ -1,17 +1,18 @@
 use crate::frame::response::event::Event;
 use crate::routing::Token;
 use crate::statement::query::Query;
-use crate::transport::connection::{Connection, ConnectionConfig};
+use crate::transport::connection::Connection;
 use crate::transport::connection_pool::{NodeConnectionPool, PoolConfig, PoolSize};
 use crate::transport::errors::{DbError, QueryError};
-use crate::transport::session::{AddressTranslator, IntoTypedRows};
+use crate::transport::host_filter::HostFilter;
+use crate::transport::session::{resolve_hostname, AddressTranslator, IntoTypedRows};
 use crate::utils::parse::{ParseErrorCause, ParseResult, ParserState};
 
-use futures::future::try_join_all;
+use futures::future::{join_all, try_join_all};
 use rand::seq::SliceRandom;
 use rand::{thread_rng, Rng};
 use std::borrow::BorrowMut;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fmt::Formatter;
 use std::net::{IpAddr, SocketAddr};
@@ -21,12 +22,13 @@ use std::sync::Arc;
 use std::time::Duration;
 use strum_macros::EnumString;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
 use tracing::{debug, error, trace, warn};
+use uuid::Uuid;
 
 /// Allows to read current metadata from the cluster
 pub(crate) struct MetadataReader {
-    connection_config: ConnectionConfig,
-    keepalive_interval: Option<Duration>,
+    pool_config: PoolConfig,
 
     control_connection_address: SocketAddr,
     control_connection: NodeConnectionPool,
@@ -36,6 +38,26 @@ pub(crate) struct MetadataReader {
     fetch_schema: bool,
 
     address_translator: Option<Arc<dyn AddressTranslator>>,
+
+    // peers rejected by the host filter are never used for the control connection
+    host_filter: Option<Arc<dyn HostFilter>>,
+    rejected_peers: HashSet<SocketAddr>,
+
+    // hostnames of the initial contact points are re-resolved to find the cluster again
+    // in case all known peers were replaced
+    contact_points: ContactPointHostnames,
+    resolved_contact_points: Vec<SocketAddr>,
+    last_hostname_resolution: Instant,
+}
+
+/// Hostnames of the initial contact points, which are re-resolved periodically
+/// and whenever the control connection fails
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ContactPointHostnames {
+    pub(crate) hostnames: Vec<String>,
+
+    // None disables periodic re-resolution, hostnames are still resolved when the control connection fails
+    pub(crate) resolution_interval: Option<Duration>,
 }
 
 /// Describes all metadata retrieved from the cluster
@@ -47,6 +69,7 @@ pub struct Metadata {
 pub struct Peer {
     pub address: SocketAddr,
     pub untranslated_address: Option<SocketAddr>,
+    pub host_id: Option<Uuid>,
     pub tokens: Vec<Token>,
     pub datacenter: Option<String>,
     pub rack: Option<String>,
@@ -199,6 +222,7 @@ impl Metadata {
                     datacenter: None,
                     rack: None,
                     untranslated_address: None,
+                    host_id: None,
                 }
             })
             .collect();
@@ -214,11 +238,12 @@ impl MetadataReader {
     /// Creates new MetadataReader, which connects to known_peers in the background
     pub fn new(
         known_peers: &[SocketAddr],
-        mut connection_config: ConnectionConfig,
-        keepalive_interval: Option<Duration>,
+        mut pool_config: PoolConfig,
         server_event_sender: mpsc::Sender<Event>,
         fetch_schema: bool,
         address_translator: &Option<Arc<dyn AddressTranslator>>,
+        host_filter: Option<Arc<dyn HostFilter>>,
+        contact_points: ContactPointHostnames,
     ) -> Self {
         let control_connection_address = *known_peers
             .choose(&mut thread_rng())
@@ -227,33 +252,55 @@ impl MetadataReader {
         // setting event_sender field in connection config will cause control connection to
         // - send REGISTER message to receive server events
         // - send received events via server_event_sender
-        connection_config.event_sender = Some(server_event_sender);
+        pool_config.connection_config.event_sender = Some(server_event_sender);
 
-        let control_connection = Self::make_control_connection_pool(
-            control_connection_address,
-            connection_config.clone(),
-            keepalive_interval,
-        );
+        let control_connection =
+            Self::make_control_connection_pool(control_connection_address, pool_config.clone());
 
         MetadataReader {
             control_connection_address,
             control_connection,
-            keepalive_interval,
-            connection_config,
+            pool_config,
             known_peers: known_peers.into(),
             fetch_schema,
             address_translator: address_translator.clone(),
+            host_filter,
+            rejected_peers: HashSet::new(),
+            contact_points,
+            resolved_contact_points: Vec::new(),
+            // Contact points were resolved right before creating the MetadataReader
+            last_hostname_resolution: Instant::now(),
         }
     }
 
+    /// Address of the node used by the control connection
+    pub(crate) fn control_connection_address(&self) -> SocketAddr {
+        self.control_connection_address
+    }
+
+    /// Whether the control connection broke and couldn't be reopened
+    pub(crate) fn is_control_connection_broken(&self) -> bool {
+        !self.control_connection.is_initializing() && !self.control_connection.is_connected()
+    }
+
     /// Fetches current metadata from the cluster
     pub async fn read_metadata(&mut self, initial: bool) -> Result<Metadata, QueryError> {
+        if let Some(interval) = self.contact_points.resolution_interval {
+            if self.last_hostname_resolution.elapsed() >= interval {
+                self.resolve_contact_points().await;
+            }
+        }
+
         let mut result = self.fetch_metadata(initial).await;
         if let Ok(metadata) = result {
             self.update_known_peers(&metadata);
             return Ok(metadata);
         }
 
+        // Hostnames of the contact points may point to different nodes by now,
+        // e.g. if all nodes known to the driver were replaced
+        self.resolve_contact_points().await;
+
         // shuffle known_peers to iterate through them in random order later
         self.known_peers.shuffle(&mut thread_rng());
         debug!(
@@ -266,14 +313,17 @@ impl MetadataReader {
         );
 
         let address_of_failed_control_connection = self.control_connection_address;
-        let filtered_known_peers = self
-            .known_peers
-            .iter()
-            .filter(|&peer| peer != &address_of_failed_control_connection);
+        let mut failover_candidates: Vec<SocketAddr> = self.known_peers.clone();
+        for contact_point in &self.resolved_contact_points {
+            if !failover_candidates.contains(contact_point) {
+                failover_candidates.push(*contact_point);
+            }
+        }
+        failover_candidates.retain(|&peer| peer != address_of_failed_control_connection);
 
         // if fetching metadata on current control connection failed,
         // try to fetch metadata from other known peer
-        for peer in filtered_known_peers {
+        for peer in &failover_candidates {
             let err = match result {
                 Ok(_) => break,
                 Err(err) => err,
@@ -288,8 +338,7 @@ impl MetadataReader {
             self.control_connection_address = *peer;
             self.control_connection = Self::make_control_connection_pool(
                 self.control_connection_address,
-                self.connection_config.clone(),
-                self.keepalive_interval,
+                self.pool_config.clone(),
             );
 
             debug!(
@@ -301,6 +350,10 @@ impl MetadataReader {
 
         match &result {
             Ok(metadata) => {
+                warn!(
+                    "Control connection failed over from {} to {}",
+                    address_of_failed_control_connection, self.control_connection_address
+                );
                 self.update_known_peers(metadata);
                 debug!("Fetched new metadata");
             }
@@ -313,6 +366,58 @@ impl MetadataReader {
         result
     }
 
+    async fn resolve_contact_points(&mut self) {
+        self.last_hostname_resolution = Instant::now();
+        if self.contact_points.hostnames.is_empty() {
+            return;
+        }
+
+        // A lookup can hang if the DNS server is unreachable, it shouldn't block the failover
+        let resolution_timeout = self.pool_config.connection_config.connect_timeout;
+        let resolve_futures =
+            self.contact_points.hostnames.iter().map(|hostname| {
+                tokio::time::timeout(resolution_timeout, resolve_hostname(hostname))
+            });
+        let mut resolved: Vec<SocketAddr> = Vec::new();
+        let mut lookup_succeeded = false;
+        for (hostname, result) in self
+            .contact_points
+            .hostnames
+            .iter()
+            .zip(join_all(resolve_futures).await)
+        {
+            lookup_succeeded |= matches!(result, Ok(Ok(_)));
+            match result {
+                Ok(Ok(address)) if self.is_accepted_contact_point(address) => {
+                    resolved.push(address)
+                }
+                Ok(Ok(address)) => debug!(
+                    "Contact point {} resolved to {}, which was rejected by the host filter",
+                    hostname, address
+                ),
+                Ok(Err(err)) => warn!("Failed to resolve contact point {}: {}", hostname, err),
+                Err(_) => warn!(
+                    "Failed to resolve contact point {}: timed out after {:?}",
+                    hostname, resolution_timeout
+                ),
+            }
+        }
+
+        if resolved != self.resolved_contact_points {
+            debug!("Contact points resolved to: {:?}", resolved);
+        }
+        // Keep the previous addresses if the DNS is unavailable
+        if lookup_succeeded {
+            self.resolved_contact_points = resolved;
+        }
+    }
+
+    // The filter needs the metadata of a node, e.g. its datacenter, so only addresses of nodes
+    // rejected during the last refresh are dropped. New addresses are checked once they are connected to.
+    fn is_accepted_contact_point(&self, address: SocketAddr) -> bool {
+        !self.rejected_peers.contains(&address)
+    }
+
     async fn fetch_metadata(&self, initial: bool) -> Result<Metadata, QueryError> {
         // TODO: Timeouts?
         self.control_connection.wait_until_initialized().await;
@@ -343,24 +448,62 @@ impl MetadataReader {
     }
 
     fn update_known_peers(&mut self, metadata: &Metadata) {
-        self.known_peers = metadata.peers.iter().map(|peer| peer.address).collect();
+        let host_filter = self.host_filter.as_deref();
+        let is_accepted = |peer: &Peer| match host_filter {
+            Some(filter) => filter.accept(peer),
+            None => true,
+        };
+
+        let accepted_peers: Vec<SocketAddr> = metadata
+            .peers
+            .iter()
+            .filter(|peer| is_accepted(peer))
+            .map(|peer| peer.address)
+            .collect();
+
+        self.rejected_peers = metadata
+            .peers
+            .iter()
+            .filter(|peer| !is_accepted(peer))
+            .map(|peer| peer.address)
+            .collect();
+
+        if accepted_peers.is_empty() {
+            warn!("The host filter rejected all nodes, keeping the previous list of known peers");
+            return;
+        }
+        self.known_peers = accepted_peers;
+
+        // The control connection may have been opened to a rejected node, e.g. one of the initial peers
+        let control_connection_rejected = metadata
+            .peers
+            .iter()
+            .any(|peer| peer.address == self.control_connection_address && !is_accepted(peer));
+        if control_connection_rejected {
+            let new_address = *self.known_peers.choose(&mut thread_rng()).unwrap(); // known_peers is nonempty
+            debug!(
+                "Node {} was rejected by the host filter, moving the control connection to {}",
+                self.control_connection_address, new_address
+            );
+            self.control_connection_address = new_address;
+            self.control_connection =
+                Self::make_control_connection_pool(new_address, self.pool_config.clone());
+        }
     }
 
     fn make_control_connection_pool(
         addr: SocketAddr,
-        connection_config: ConnectionConfig,
-        keepalive_interval: Option<Duration>,
+        pool_config: PoolConfig,
     ) -> NodeConnectionPool {
         let pool_config = PoolConfig {
-            connection_config,
-            keepalive_interval,
-
             // We want to have only one connection to receive events from
             pool_size: PoolSize::PerHost(NonZeroUsize::new(1).unwrap()),
 
             // The shard-aware port won't be used with PerHost pool size anyway,
             // so explicitly disable it here
             can_use_shard_aware_port: false,
+
+            ..pool_config
         };
 
         NodeConnectionPool::new(addr.ip(), addr.port(), pool_config, None)
@@ -401,12 +544,12 @@ async fn query_peers(
     address_translator: Option<&dyn AddressTranslator>,
 ) -> Result<Vec<Peer>, QueryError> {
     let mut peers_query =
-        Query::new("select rpc_address, data_center, rack, tokens from system.peers");
+        Query::new("select rpc_address, host_id, data_center, rack, tokens from system.peers");
     peers_query.set_page_size(1024);
     let peers_query_future = conn.query_all(&peers_query, &[]);
 
     let mut local_query =
-        Query::new("select rpc_address, data_center, rack, tokens from system.local");
+        Query::new("select rpc_address, host_id, data_center, rack, tokens from system.local");
     local_query.set_page_size(1024);
     let local_query_future = conn.query_all(&local_query, &[]);
 
@@ -420,26 +563,44 @@ async fn query_peers(
         "system.local query response was not Rows",
     ))?;
 
-    let typed_peers_rows =
-        peers_rows.into_typed::<(IpAddr, Option<String>, Option<String>, Option<Vec<String>>)>();
+    type PeerRow = (
+        IpAddr,
+        Option<Uuid>,
+        Option<String>,
+        Option<String>,
+        Option<Vec<String>>,
+    );
+
+    let typed_peers_rows = peers_rows.into_typed::<PeerRow>();
 
     let local_ip: IpAddr = conn.get_connect_address().ip();
     let local_address = SocketAddr::new(local_ip, connect_port);
 
-    let typed_local_rows =
-        local_rows.into_typed::<(IpAddr, Option<String>, Option<String>, Option<Vec<String>>)>();
+    let typed_local_rows = local_rows.into_typed::<PeerRow>();
+    let is_cloud = conn.is_cloud();
 
     let untranslated_rows = typed_peers_rows
         .map(|res| res.map(|peer_row| (false, peer_row)))
         .chain(typed_local_rows.map(|res| res.map(|local_row| (true, local_row))));
 
     let translated_peers_futures = untranslated_rows.map(|untranslated_row| async {
-        let (is_local, (untranslated_ip_addr, datacenter, rack, tokens)) = untranslated_row.map_err(
+        let (is_local, (untranslated_ip_addr, host_id, datacenter, rack, tokens)) = untranslated_row.map_err(
             |_| QueryError::ProtocolError("system.peers or system.local has invalid column type")
         )?;
         let untranslated_address = SocketAddr::new(untranslated_ip_addr, connect_port);
 
+        if is_cloud && host_id.is_none() {
+            // Connections to the node are routed by the proxy using its host id, it can't be reached without one.
+            warn!("Node {} has no host id and can't be reached through the cloud proxy; node therefore skipped.",
+                    untranslated_address);
+            return Ok::<Option<Peer>, QueryError>(None);
+        }
+
         let (untranslated_address, address) = match (is_local, address_translator) {
+            (true, None) if is_cloud => {
+                // The control connection goes through a proxy, so its address doesn't identify the node.
+                (Some(untranslated_address), untranslated_address)
+            },
             (true, None) => {
                 // We need to replace rpc_address with control connection address.
                 (Some(untranslated_address), local_address)
@@ -491,6 +652,7 @@ async fn query_peers(
         Ok(Some(Peer {
             untranslated_address,
             address,
+            host_id,
             tokens,
             datacenter,
             rack,
@@ -975,6 +1137,7 @@ fn strategy_from_string_map(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::transport::host_filter::DcHostFilter;
 
     #[test]
     fn test_cql_type_parsing() {
@@ -1135,4 +1298,68 @@ mod tests {
             assert_eq!(parsed, expected);
         }
     }
+
+    // ConnectionKeeper (which lives in NodeConnectionPool) requires context of Tokio runtime
+    #[tokio::test]
+    async fn test_contact_points_resolution() {
+        let (server_event_sender, _) = mpsc::channel(1);
+        let unreachable: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let mut reader = MetadataReader::new(
+            &[unreachable],
+            Default::default(),
+            server_event_sender,
+            false,
+            &None,
+            None,
+            ContactPointHostnames {
+                hostnames: vec!["localhost:9042".to_string()],
+                resolution_interval: None,
+            },
+        );
+        assert!(reader.resolved_contact_points.is_empty());
+
+        reader.resolve_contact_points().await;
+        assert_eq!(reader.resolved_contact_points.len(), 1);
+        assert_eq!(reader.resolved_contact_points[0].port(), 9042);
+        assert!(reader.resolved_contact_points[0].ip().is_loopback());
+        assert_eq!(reader.control_connection_address(), unreachable);
+    }
+
+    #[tokio::test]
+    async fn test_contact_points_respect_host_filter() {
+        let (server_event_sender, _) = mpsc::channel(1);
+        let unreachable: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let mut reader = MetadataReader::new(
+            &[unreachable],
+            Default::default(),
+            server_event_sender,
+            false,
+            &None,
+            Some(Arc::new(DcHostFilter::new(["dc1"]))),
+            ContactPointHostnames {
+                hostnames: vec!["127.0.0.1:9042".to_string(), "127.0.0.1:9043".to_string()],
+                resolution_interval: None,
+            },
+        );
+
+        // Datacenters of unknown addresses are not known yet, they're filtered after connecting
+        reader.resolve_contact_points().await;
+        assert_eq!(
+            reader.resolved_contact_points,
+            vec![
+                "127.0.0.1:9042".parse::<SocketAddr>().unwrap(),
+                "127.0.0.1:9043".parse::<SocketAddr>().unwrap()
+            ]
+        );
+
+        // Nodes rejected during the last refresh stay rejected
+        reader
+            .rejected_peers
+            .insert("127.0.0.1:9043".parse().unwrap());
+        reader.resolve_contact_points().await;
+        assert_eq!(
+            reader.resolved_contact_points,
+            vec!["127.0.0.1:9042".parse::<SocketAddr>().unwrap()]
+        );
+    }
 }
@@ -0,0 +1,209 @@
+//! Automatic tracing of sampled requests, with tracing info fetched in the background
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use futures::future::RemoteHandle;
+use futures::{FutureExt, StreamExt};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::query::Query;
+use crate::statement::StatementKind;
+use crate::tracing::{
+    parse_tracing_info, CollectedTrace, GetTracingConfig, SamplingInfo, TraceSink, TracingInfo,
+    TracingSamplingPolicy, TRACES_EVENTS_QUERY_STR, TRACES_SESSION_QUERY_STR,
+};
+use crate::transport::cluster::ClusterData;
+use crate::transport::connection::Connection;
+use crate::transport::errors::QueryError;
+use crate::transport::load_balancing::{self, LoadBalancingPolicy, Statement};
+use crate::transport::request_tracker::{RequestGuard, RequestTracker};
+
+// Maximum number of traces waiting to be fetched, traces sampled above this limit are dropped
+const PENDING_TRACES_LIMIT: usize = 1024;
+
+// Maximum number of traces fetched concurrently
+const CONCURRENT_FETCHES: usize = 16;
+
+/// Applies the [`TracingSamplingPolicy`] to requests made by the `Session`
+/// and hands their traces over to a background worker, which delivers them to the [`TraceSink`]
+pub(crate) struct TracingSampler {
+    policy: Arc<dyn TracingSamplingPolicy>,
+    pending_traces: mpsc::Sender<PendingTrace>,
+    request_tracker: Arc<RequestTracker>,
+    _worker_handle: RemoteHandle<()>,
+}
+
+struct PendingTrace {
+    tracing_id: Uuid,
+    statement_kind: StatementKind,
+    statement: String,
+    latency: Duration,
+    // Closing the session waits until the trace is fetched
+    _request_guard: RequestGuard,
+}
+
+impl TracingSampler {
+    pub(crate) fn new(
+        policy: Arc<dyn TracingSamplingPolicy>,
+        sink: Arc<dyn TraceSink>,
+        fetch_config: GetTracingConfig,
+        cluster_data: Arc<ArcSwap<ClusterData>>,
+        load_balancer: Arc<dyn LoadBalancingPolicy>,
+        request_tracker: Arc<RequestTracker>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(PENDING_TRACES_LIMIT);
+
+        let worker = TraceCollectorWorker {
+            sink,
+            fetch_config,
+            cluster_data,
+            load_balancer,
+        };
+
+        let (fut, worker_handle) = worker.work(receiver).remote_handle();
+        tokio::spawn(fut);
+
+        TracingSampler {
+            policy,
+            pending_traces: sender,
+            request_tracker,
+            _worker_handle: worker_handle,
+        }
+    }
+
+    pub(crate) fn should_trace(&self, info: &SamplingInfo) -> bool {
+        self.policy.should_trace(info)
+    }
+
+    // Called after a sampled request succeeded
+    pub(crate) fn collect(&self, tracing_id: Option<Uuid>, info: &SamplingInfo, latency: Duration) {
+        let tracing_id = match tracing_id {
+            Some(id) => id,
+            None => return,
+        };
+
+        if !self.policy.should_collect(info, latency) {
+            return;
+        }
+
+        let request_guard = match self.request_tracker.start_request() {
+            Ok(guard) => guard,
+            Err(_) => {
+                debug!("The session is closed, dropping trace {}", tracing_id);
+                return;
+            }
+        };
+
+        let pending = PendingTrace {
+            tracing_id,
+            statement_kind: info.statement_kind,
+            statement: info.statement.to_owned(),
+            latency,
+            _request_guard: request_guard,
+        };
+
+        // Requests must not wait for the worker, so the trace is dropped if the queue is full
+        if self.pending_traces.try_send(pending).is_err() {
+            debug!(
+                "Too many traces waiting to be fetched, dropping trace {}",
+                tracing_id
+            );
+        }
+    }
+}
+
+struct TraceCollectorWorker {
+    sink: Arc<dyn TraceSink>,
+    fetch_config: GetTracingConfig,
+    cluster_data: Arc<ArcSwap<ClusterData>>,
+    load_balancer: Arc<dyn LoadBalancingPolicy>,
+}
+
+impl TraceCollectorWorker {
+    async fn work(self, receiver: mpsc::Receiver<PendingTrace>) {
+        let pending_traces = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|pending| (pending, receiver))
+        });
+
+        pending_traces
+            .for_each_concurrent(CONCURRENT_FETCHES, |pending| self.handle(pending))
+            .await;
+    }
+
+    async fn handle(&self, pending: PendingTrace) {
+        match self.fetch(pending.tracing_id).await {
+            Ok(tracing_info) => self.sink.on_trace(CollectedTrace {
+                tracing_id: pending.tracing_id,
+                statement_kind: pending.statement_kind,
+                statement: pending.statement,
+                latency: pending.latency,
+                tracing_info,
+            }),
+            Err(error) => {
+                warn!(
+                    "Failed to fetch tracing info of sampled request {}: {}",
+                    pending.tracing_id, error
+                );
+                self.sink.on_fetch_error(pending.tracing_id, error);
+            }
+        }
+    }
+
+    async fn fetch(&self, tracing_id: Uuid) -> Result<TracingInfo, QueryError> {
+        // fetch_config.attempts is NonZeroU32 so at least one attempt will be made
+        for _ in 0..self.fetch_config.attempts.get() {
+            if let Some(tracing_info) = self.try_fetch(tracing_id).await? {
+                return Ok(tracing_info);
+            }
+            tokio::time::sleep(self.fetch_config.interval).await;
+        }
+
+        Err(QueryError::ProtocolError(
+            "All tracing queries returned an empty result, \
+            consider a bigger interval in the trace collection config",
+        ))
+    }
+
+    async fn try_fetch(&self, tracing_id: Uuid) -> Result<Option<TracingInfo>, QueryError> {
+        let connection = self.connection().await?;
+        let consistency = self.fetch_config.consistency;
+
+        let mut traces_session_query = Query::new(TRACES_SESSION_QUERY_STR);
+        traces_session_query.set_page_size(1024);
+
+        let mut traces_events_query = Query::new(TRACES_EVENTS_QUERY_STR);
+        traces_events_query.set_page_size(1024);
+
+        let (traces_session_res, traces_events_res) = tokio::try_join!(
+            connection.query_all_with_consistency(
+                &traces_session_query,
+                (tracing_id,),
+                consistency
+            ),
+            connection.query_all_with_consistency(&traces_events_query, (tracing_id,), consistency)
+        )?;
+
+        parse_tracing_info(traces_session_res, traces_events_res)
+    }
+
+    // Chooses a connection to the first reachable node in the load balancing plan
+    async fn connection(&self) -> Result<Arc<Connection>, QueryError> {
+        let cluster_data = self.cluster_data.load_full();
+        let mut last_error = QueryError::ProtocolError("Empty query plan - driver bug!");
+
+        for node in
+            load_balancing::query_plan(&*self.load_balancer, &Statement::default(), &cluster_data)
+        {
+            match node.random_connection().await {
+                Ok(connection) => return Ok(connection),
+                Err(e) => last_error = e,
+            }
+        }
+
+        Err(last_error)
+    }
+}
@@ -0,0 +1,20 @@
+//! Propagation of the client-side trace context to the cluster.
+//!
+//! Enabled with the `trace-context` feature. A [`TraceContextPropagator`] configured in
+//! [`SessionConfig`](crate::SessionConfig) is asked to write the context of the span of every
+//! attempt into the custom payload of the outgoing `QUERY`, `EXECUTE` and `BATCH` requests.
+
+use std::collections::HashMap;
+
+use bytes::Bytes;
+
+/// Writes the trace context of a span into the custom payload of a request.
+///
+/// The driver does not depend on any particular tracing backend - implementations
+/// usually obtain the context with `tracing-opentelemetry` and encode it with
+/// the propagator used by the rest of the system (e.g. W3C `traceparent`).
+pub trait TraceContextPropagator: std::fmt::Debug + Send + Sync {
+    /// Called right before a request is sent, with the span of the current attempt.\
+    /// If nothing is inserted into `payload`, the request is sent without a custom payload.
+    fn inject(&self, span: &tracing::Span, payload: &mut HashMap<String, Bytes>);
+}